@@ -0,0 +1,117 @@
+//! `--output-archive`: outputs go into one zip or tar.zst instead of a tree of loose
+//! files. Handlers still write into the target dir, which only holds the outputs of the
+//! file in progress; they are streamed into the archive and removed as soon as it is done.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+enum Writer {
+    Zip(Box<zip::ZipWriter<File>>),
+    TarZst(Box<tar::Builder<zstd::Encoder<'static, File>>>),
+}
+
+pub struct Archive {
+    writer: Writer,
+    /// the target dir, entries are named relative to it
+    staging: PathBuf,
+}
+
+impl Archive {
+    /// Creates the archive at `path`, a zip or tar.zst by its extension. An existing
+    /// archive is never appended to.
+    pub fn create(path: &Path, staging: &Path) -> Result<Self> {
+        let name = path.to_string_lossy().to_lowercase();
+        if !name.ends_with(".zip") && !name.ends_with(".tar.zst") {
+            return Err(anyhow!(
+                "{}: --output-archive must end in .zip or .tar.zst",
+                path.display()
+            ));
+        }
+        if path.exists() {
+            return Err(anyhow!(
+                "{} already exists; archives are written from scratch and not resumed, \
+                 remove it or choose another path",
+                path.display()
+            ));
+        }
+        let file = File::create(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let writer = if name.ends_with(".zip") {
+            Writer::Zip(Box::new(zip::ZipWriter::new(file)))
+        } else {
+            Writer::TarZst(Box::new(tar::Builder::new(zstd::Encoder::new(file, 0)?)))
+        };
+        Ok(Archive {
+            writer,
+            staging: staging.to_path_buf(),
+        })
+    }
+
+    /// Moves `outputs` from the target dir into the archive, removing the directories
+    /// they leave empty.
+    pub fn add(&mut self, outputs: &[PathBuf]) -> Result<()> {
+        for output in outputs {
+            let name = output.strip_prefix(&self.staging).unwrap_or(output);
+            self.append(output, name)?;
+            std::fs::remove_file(output)?;
+            let mut dir = output.parent();
+            while let Some(parent) =
+                dir.filter(|d| d.starts_with(&self.staging) && *d != self.staging)
+            {
+                if std::fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the file at `path` into the archive as `name`.
+    pub fn append(&mut self, path: &Path, name: &Path) -> Result<()> {
+        let name = name.to_string_lossy().replace('\\', "/");
+        match &mut self.writer {
+            Writer::Zip(zip) => {
+                let metadata = std::fs::metadata(path)?;
+                let mut options = SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(metadata.len() > u32::MAX as u64);
+                if let Some(time) = metadata.modified().ok().and_then(zip_time) {
+                    options = options.last_modified_time(time);
+                }
+                zip.start_file(name, options)?;
+                std::io::copy(&mut File::open(path)?, zip.as_mut())?;
+            }
+            Writer::TarZst(tar) => tar.append_path_with_name(path, name)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            Writer::Zip(mut zip) => {
+                zip.finish()?;
+            }
+            Writer::TarZst(tar) => {
+                tar.into_inner()?.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Local time of `time` as zip stores it, `None` outside of 1980 to 2107.
+fn zip_time(time: std::time::SystemTime) -> Option<zip::DateTime> {
+    let time: DateTime<Local> = time.into();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
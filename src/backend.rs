@@ -0,0 +1,774 @@
+//! Client for the LibreTranslate server.
+//!
+//! `libretranslate::translate_url` builds a new HTTP client, and with it a new connection,
+//! for every call, and only knows a handful of languages. The backend keeps one pooled
+//! client for the whole run instead and talks to the API itself.
+
+use crate::batch::{Batcher, Batching};
+use crate::bundle::Deferral;
+use crate::echo::EchoCheck;
+use crate::error::{error, ErrorKind};
+use crate::filter::Script;
+use crate::guard::Guardrails;
+use crate::instances::{Instance, Instances};
+use crate::keys::Pause;
+use crate::locale::Conversions;
+use crate::metrics;
+use crate::mixed::{self, Piece};
+use crate::network::Network;
+use crate::normalize;
+use crate::output::Segment;
+use crate::pii::Pii;
+use crate::qa::QaSampler;
+use crate::qa_sample::QaSample;
+use crate::usage::Usage;
+use crate::{PII_MARKER, SOURCE_LANG, SUSPECT_MARKER, TARGET_LANG, UNTRANSLATED_MARKER};
+use anyhow::{anyhow, Result};
+use futures_util::stream::{self, StreamExt};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Longest input LibreTranslate accepts in one request.
+const MAX_INPUT_CHARS: usize = 5000;
+pub const DEFAULT_POOL_SIZE: usize = 8;
+
+pub struct Backend {
+    /// the servers requests are spread over, see [`crate::instances`]
+    pub instances: Instances,
+    /// language code segments are translated from
+    pub source_lang: String,
+    /// source language codes the server offers with their target codes, fetched on first use
+    languages: Option<Vec<(String, Vec<String>)>>,
+    pub usage: Usage,
+    /// segments skipped because the character budget was used up
+    pub untranslated: usize,
+    /// requests sent to the server and the time spent waiting for them
+    pub requests: usize,
+    pub request_time: Duration,
+    pub guardrails: Guardrails,
+    /// translations that failed the guardrails twice and were kept with [`SUSPECT_MARKER`]
+    pub suspect: usize,
+    /// ID numbers translations must keep
+    pub pii: Pii,
+    /// segments kept untranslated with [`PII_MARKER`]
+    pub pii_preserved: usize,
+    /// `Some` with `--back-translate-sample`
+    pub qa: Option<QaSampler>,
+    /// `Some` with `--qa-sample`
+    pub qa_sample: Option<QaSample>,
+    /// server each text of the last [`Backend::translate_all`] was sent to, `None` for
+    /// those copied or failed without a request
+    served: Vec<Option<usize>>,
+    /// scripts of the source languages, overriding [`Script::of`]'s defaults
+    pub scripts: BTreeMap<String, Script>,
+    /// abbreviations of the source languages, which don't end a sentence
+    pub abbreviations: BTreeMap<String, Vec<String>>,
+    /// send segments without a letter of the source script too
+    pub translate_everything: bool,
+    /// segments without a letter of the source script, copied instead of translated
+    pub passed_through: usize,
+    /// only translate the runs of a segment in the source script, `--split-mixed-segments`
+    pub split_mixed: bool,
+    /// clean segments up before sending them, off with `--no-normalize`
+    pub normalize: bool,
+    /// segments changed by [`normalize::normalize`]
+    pub normalized: usize,
+    /// `--normalize-numbers` and `--normalize-dates`
+    pub conversions: Conversions,
+    /// requests in flight at most, `pool_size` of config.toml or `--translate-workers`
+    /// for each server
+    workers: usize,
+    /// `Some` with `[batching]` in config.toml
+    batcher: Option<Batcher>,
+    /// `/frontend/settings` was asked for the server's limit
+    limit_checked: bool,
+    /// print the batching parameters as they change
+    pub verbose: bool,
+    /// no request is sent while it is set, see [`crate::keys`]
+    pub pause: Pause,
+    /// translations identical to their source, see [`crate::echo`]
+    pub echo: EchoCheck,
+    /// set while [`Backend::translate_html`] sends markup
+    html: bool,
+    /// the server refused `format=html`
+    html_refused: AtomicBool,
+    /// set by `extract`: segments are kept for `apply-translations` instead of sent
+    pub deferral: Option<Deferral>,
+}
+
+/// Why a batch failed.
+enum BatchError {
+    /// the server refused it as too long, with its limit if it said
+    TooLong(Option<usize>),
+    Failed(anyhow::Error),
+}
+
+impl Backend {
+    pub fn new(
+        urls: &[String],
+        usage: Usage,
+        pool_size: usize,
+        guardrails: Guardrails,
+        batching: Option<Batching>,
+        network: &Network,
+    ) -> Result<Self> {
+        let instances = Instances::new(urls, pool_size, network)?;
+        Ok(Backend {
+            workers: pool_size.max(1) * instances.len(),
+            instances,
+            source_lang: SOURCE_LANG.as_code().to_string(),
+            languages: None,
+            usage,
+            untranslated: 0,
+            requests: 0,
+            request_time: Duration::ZERO,
+            guardrails,
+            suspect: 0,
+            pii: Pii::default(),
+            pii_preserved: 0,
+            qa: None,
+            qa_sample: None,
+            served: Vec::new(),
+            scripts: BTreeMap::new(),
+            abbreviations: BTreeMap::new(),
+            translate_everything: false,
+            passed_through: 0,
+            split_mixed: false,
+            normalize: true,
+            normalized: 0,
+            conversions: Conversions::default(),
+            batcher: batching.map(Batcher::new),
+            limit_checked: false,
+            verbose: false,
+            pause: Pause::default(),
+            echo: EchoCheck::default(),
+            html: false,
+            html_refused: AtomicBool::new(false),
+            deferral: None,
+        })
+    }
+
+    /// Fails with [`ErrorKind::BackendUnavailable`] if no server can be reached, those
+    /// that can't are left out. Any answer counts, a server without `/languages` is fine.
+    pub async fn ping(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (i, instance) in self.instances.all().iter().enumerate() {
+            let response = instance
+                .client
+                .get(format!("{}/languages", instance.url))
+                .await;
+            if let Err(e) = response {
+                let e = anyhow!(
+                    "translation server {}: {}",
+                    instance.url,
+                    instance.connection_error(e)
+                );
+                if self.instances.len() > 1 {
+                    self.instances.eject(i, &e);
+                }
+                errors.push(e.to_string());
+            }
+        }
+        if errors.len() < self.instances.len() {
+            return Ok(());
+        }
+        let e = anyhow!("{}", errors.join("\n"));
+        Err(error(ErrorKind::BackendUnavailable, e))
+    }
+
+    /// Checks that the server can translate from `source_lang` into the target language.
+    /// Passes if the server doesn't list its languages.
+    pub async fn check_pair(&mut self, source_lang: &str) -> Result<()> {
+        let target = TARGET_LANG.as_code();
+        if source_lang == target {
+            let e = anyhow!("source and target language are both {}", target);
+            return Err(error(ErrorKind::ConfigError, e));
+        }
+        if self.deferral.is_some() {
+            return Ok(());
+        }
+        if self.languages.is_none() {
+            let instance = self.instances.any();
+            let response = instance
+                .client
+                .get(format!("{}/languages", instance.url))
+                .recv_string()
+                .await;
+            let languages: Vec<Value> = response
+                .ok()
+                .and_then(|r| serde_json::from_str(&r).ok())
+                .unwrap_or_default();
+            let languages = languages
+                .iter()
+                .filter_map(|l| {
+                    let code = l["code"].as_str()?.to_string();
+                    let targets = l["targets"]
+                        .as_array()
+                        .map(|t| {
+                            t.iter()
+                                .filter_map(|t| t.as_str())
+                                .map(String::from)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    Some((code, targets))
+                })
+                .collect();
+            self.languages = Some(languages);
+        }
+        let languages = self.languages.as_ref().unwrap();
+        if languages.is_empty() {
+            return Ok(());
+        }
+        let e = match languages.iter().find(|(code, _)| code == source_lang) {
+            None => anyhow!("the server doesn't offer {}", source_lang),
+            Some((_, targets)) if !targets.is_empty() && !targets.iter().any(|t| t == target) => {
+                anyhow!("the server can't translate {} to {}", source_lang, target)
+            }
+            Some(_) => return Ok(()),
+        };
+        Err(error(ErrorKind::ConfigError, e))
+    }
+
+    /// Requests that can be in flight at the same time.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Average round trip of the requests sent so far.
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.requests > 0).then(|| self.request_time / self.requests as u32)
+    }
+
+    /// Translates a segment, see [`Backend::translate_all`].
+    pub async fn translate(&mut self, text: &str) -> Result<String> {
+        self.translate_all(&[text]).await.pop().unwrap()
+    }
+
+    /// Translates HTML blocks with `format=html`, the server keeping their markup, see
+    /// [`crate::html`]. `None` if the server doesn't take HTML; nothing is sent as HTML
+    /// after that.
+    pub async fn translate_html(&mut self, blocks: &[&str]) -> Option<Vec<Result<String>>> {
+        // deferred segments are translated as text
+        if self.html_refused.load(Ordering::Relaxed) || self.deferral.is_some() {
+            return None;
+        }
+        self.html = true;
+        let results = self.translate_all(blocks).await;
+        self.html = false;
+        if self.html_refused.load(Ordering::Relaxed) {
+            eprintln!("the translation server doesn't take format=html, HTML is sent as text");
+            return None;
+        }
+        Some(results)
+    }
+
+    /// The request body for `q`, with the format if it is HTML.
+    fn body(&self, q: Value, source: &str, target: &str) -> Value {
+        let mut body = serde_json::json!({
+            "q": q,
+            "source": source,
+            "target": target,
+        });
+        if self.html {
+            body["format"] = "html".into();
+        }
+        body
+    }
+
+    /// Records that the server refused HTML if `error` is about the format.
+    fn check_refused(&self, error: &str) {
+        if self.html && error.to_lowercase().contains("format") {
+            self.html_refused.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Translates segments with up to `workers` requests in flight, returning the results
+    /// in the order of `texts`. Segments without a letter of the source language's script
+    /// are returned unchanged without a request. Once the character budget is used up a
+    /// segment is returned as is, prefixed with [`UNTRANSLATED_MARKER`]. A translation
+    /// failing the guardrails is requested once more and, if it fails again, prefixed with
+    /// [`SUSPECT_MARKER`]. With `split_mixed` only the runs of a bilingual segment in the
+    /// source script are sent, see [`mixed::split`]. Segments are sent as
+    /// [`normalize::normalize`] cleans them up unless `normalize` is off. With a
+    /// [`Deferral`], for `extract`, every segment is kept and answered with its marker.
+    pub async fn translate_all(&mut self, texts: &[&str]) -> Vec<Result<String>> {
+        if let Some(deferral) = &mut self.deferral {
+            return texts
+                .iter()
+                .map(|text| Ok(deferral.defer(text, None, true)))
+                .collect();
+        }
+        if !self.normalize {
+            return self.translate_pieces(texts).await;
+        }
+        let normalized: Vec<String> = texts.iter().map(|t| normalize::normalize(t)).collect();
+        self.normalized += normalized
+            .iter()
+            .zip(texts)
+            .filter(|(n, t)| n != *t)
+            .count();
+        let normalized: Vec<&str> = normalized.iter().map(String::as_str).collect();
+        self.translate_pieces(&normalized).await
+    }
+
+    /// [`Backend::translate_all`] without the cleanup.
+    async fn translate_pieces(&mut self, texts: &[&str]) -> Vec<Result<String>> {
+        if !self.split_mixed {
+            return self.translate_segments(texts).await;
+        }
+        let source = Script::of(&self.source_lang, &self.scripts);
+        let target = Script::of(TARGET_LANG.as_code(), &self.scripts);
+        let split: Vec<Vec<Piece>> = texts
+            .iter()
+            .map(|text| mixed::split(text, source, target))
+            .collect();
+        let runs: Vec<&str> = split
+            .iter()
+            .flatten()
+            .filter(|piece| piece.translate)
+            .map(|piece| piece.text)
+            .collect();
+        let mut results = self.translate_segments(&runs).await.into_iter();
+        // a segment was served where its first run was
+        let mut served = std::mem::take(&mut self.served).into_iter();
+        self.served = split
+            .iter()
+            .map(|pieces| {
+                let runs = pieces.iter().filter(|piece| piece.translate).count();
+                let runs: Vec<Option<usize>> = served.by_ref().take(runs).collect();
+                runs.into_iter().flatten().next()
+            })
+            .collect();
+        split
+            .iter()
+            .map(|pieces| mixed::splice(pieces, &mut results))
+            .collect()
+    }
+
+    async fn translate_segments(&mut self, texts: &[&str]) -> Vec<Result<String>> {
+        self.served = vec![None; texts.len()];
+        let mut results: Vec<Option<Result<String>>> =
+            texts.iter().map(|text| self.precheck(text)).collect();
+        let pending: Vec<usize> = (0..texts.len()).filter(|&i| results[i].is_none()).collect();
+        if self.echo.tripped() {
+            for &i in &pending {
+                results[i] = Some(Err(self.echo.error()));
+            }
+            return results.into_iter().map(Option::unwrap).collect();
+        }
+        if self.batcher.is_some() {
+            self.translate_batched(texts, &pending, &mut results).await;
+            return results.into_iter().map(Option::unwrap).collect();
+        }
+        let source = self.source_lang.clone();
+        let target = TARGET_LANG.as_code();
+        let source = source.as_str();
+        let this = &*self;
+        // responses come back in any order, a slow segment doesn't hold up the slots of
+        // the ones after it
+        let mut responses: Vec<_> = stream::iter(
+            pending
+                .iter()
+                .map(|&i| async move { (i, this.send(texts[i], source, target).await) }),
+        )
+        .buffer_unordered(self.workers)
+        .collect()
+        .await;
+        responses.sort_by_key(|(i, _)| *i);
+        for (i, (response, elapsed, instance)) in responses {
+            self.served[i] = Some(instance);
+            self.requests += 1;
+            self.request_time += elapsed;
+            results[i] = Some(match response {
+                Ok(output) => {
+                    metrics::segment_translated(texts[i].chars().count());
+                    self.echo.record(texts[i], &output);
+                    self.finish(texts[i], output).await
+                }
+                Err(e) => {
+                    metrics::segment_failed();
+                    Err(e)
+                }
+            });
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Translates the `pending` segments of `texts` in batches, see [`crate::batch`]. A
+    /// batch that fails is split and sent again until the segments that fail on their own
+    /// are found.
+    async fn translate_batched(
+        &mut self,
+        texts: &[&str],
+        pending: &[usize],
+        results: &mut [Option<Result<String>>],
+    ) {
+        self.check_limit().await;
+        let chars = |batch: &[usize]| -> Vec<(usize, usize)> {
+            batch
+                .iter()
+                .map(|&i| (i, texts[i].chars().count()))
+                .collect()
+        };
+        let mut batches = self.batcher.as_ref().unwrap().pack(&chars(pending));
+        let source = self.source_lang.clone();
+        let target = TARGET_LANG.as_code();
+        while !batches.is_empty() {
+            let this = &*self;
+            let source = source.as_str();
+            let responses: Vec<_> = stream::iter(batches.drain(..).map(|batch| async move {
+                let batch_texts: Vec<&str> = batch.iter().map(|&i| texts[i]).collect();
+                let started = Instant::now();
+                let (result, instance) = this.send_batch(&batch_texts, source, target).await;
+                let elapsed = started.elapsed();
+                metrics::backend_request(elapsed);
+                (batch, result, elapsed, instance)
+            }))
+            .buffer_unordered(self.workers)
+            .collect()
+            .await;
+            for (batch, result, elapsed, instance) in responses {
+                for &i in &batch {
+                    self.served[i] = Some(instance);
+                }
+                self.requests += 1;
+                self.request_time += elapsed;
+                let batch_chars: usize = chars(&batch).iter().map(|(_, c)| c).sum();
+                let batcher = self.batcher.as_mut().unwrap();
+                let described = batcher.describe();
+                match result {
+                    Ok(outputs) => {
+                        batcher.succeeded(elapsed);
+                        self.log_batching(&described);
+                        for (&i, output) in batch.iter().zip(outputs) {
+                            metrics::segment_translated(texts[i].chars().count());
+                            self.echo.record(texts[i], &output);
+                            results[i] = Some(self.finish(texts[i], output).await);
+                        }
+                    }
+                    Err(BatchError::TooLong(limit)) if batch.len() > 1 => {
+                        batcher.too_long(batch_chars, limit);
+                        let mut repacked = batcher.pack(&chars(&batch));
+                        if repacked.len() == 1 {
+                            repacked = halves(batch);
+                        }
+                        batches.extend(repacked);
+                        self.log_batching(&described);
+                    }
+                    Err(BatchError::Failed(_)) if batch.len() > 1 => {
+                        batcher.failed();
+                        batches.extend(halves(batch));
+                        self.log_batching(&described);
+                    }
+                    Err(e) => {
+                        metrics::segment_failed();
+                        results[batch[0]] = Some(Err(match e {
+                            BatchError::TooLong(_) => anyhow!(
+                                "segment of {} characters is over the server's limit",
+                                batch_chars
+                            ),
+                            BatchError::Failed(e) => e,
+                        }));
+                    }
+                }
+            }
+            batches.sort_by_key(|batch| batch[0]);
+        }
+    }
+
+    /// Prints the batching parameters with `--verbose` if they aren't `described` anymore.
+    fn log_batching(&self, described: &str) {
+        let described_now = self.batcher.as_ref().unwrap().describe();
+        if self.verbose && described_now != described {
+            eprintln!("batching: {}", described_now);
+        }
+    }
+
+    /// Asks the server for its character limit once, before the first batch.
+    async fn check_limit(&mut self) {
+        if self.limit_checked {
+            return;
+        }
+        self.limit_checked = true;
+        let instance = self.instances.any();
+        let settings: Option<Value> = instance
+            .client
+            .get(format!("{}/frontend/settings", instance.url))
+            .recv_string()
+            .await
+            .ok()
+            .and_then(|r| serde_json::from_str(&r).ok());
+        // -1 for none
+        let limit = settings
+            .and_then(|s| s["charLimit"].as_i64())
+            .filter(|&limit| limit > 0)
+            .map(|limit| limit as usize);
+        let batcher = self.batcher.as_mut().unwrap();
+        batcher.set_limit(limit);
+        if self.verbose {
+            eprintln!("batching: {}", batcher.describe());
+        }
+    }
+
+    /// The result for `text` if it doesn't need a request, `None` if it does.
+    fn precheck(&mut self, text: &str) -> Option<Result<String>> {
+        // numbers, part codes, ASCII tables and the like have nothing to translate
+        if !self.translate_everything
+            && !Script::of(&self.source_lang, &self.scripts).found_in(text)
+        {
+            if !text.trim().is_empty() {
+                self.passed_through += 1;
+            }
+            return Some(Ok(text.to_string()));
+        }
+        let chars = text.chars().count();
+        if chars >= MAX_INPUT_CHARS {
+            return Some(Err(anyhow!("segment of {} characters is too long", chars)));
+        }
+        if !self.usage.spend(chars) {
+            self.untranslated += 1;
+            return Some(Ok(format!("{}{}", UNTRANSLATED_MARKER, text)));
+        }
+        None
+    }
+
+    /// Checks the translation of `text` against the guardrails, retrying once, and keeps
+    /// `text` with [`PII_MARKER`] instead if it altered an ID number.
+    async fn finish(&mut self, text: &str, mut output: String) -> Result<String> {
+        let target = TARGET_LANG.as_code();
+        let source = self.source_lang.clone();
+        if self
+            .guardrails
+            .check(text, &output, &source, target, &self.scripts)
+            .is_some()
+            && self.usage.spend(text.chars().count())
+        {
+            // a failed retry leaves the first answer, marked below
+            match self.request(text, &source, target).await {
+                Ok(retried) => output = retried,
+                Err(e) => eprintln!("retry of a suspect translation failed: {}", e),
+            }
+        }
+        let altered = self.pii.altered(text, &output);
+        if !altered.is_empty() {
+            eprintln!(
+                "translation altered {}, kept untranslated: {:?}",
+                altered.join(", "),
+                text.trim()
+            );
+            self.pii_preserved += 1;
+            return Ok(format!("{}{}", PII_MARKER, text));
+        }
+        if self.conversions.enabled() {
+            output = self.conversions.apply(&output, &self.pii);
+        }
+        self.sample(text, &output).await;
+        match self
+            .guardrails
+            .check(text, &output, &source, target, &self.scripts)
+        {
+            None => Ok(output),
+            Some(reason) => {
+                eprintln!("suspect translation, {}: {:?}", reason, text.trim());
+                self.suspect += 1;
+                Ok(format!("{}{}", SUSPECT_MARKER, output))
+            }
+        }
+    }
+
+    /// Offers text `index` of the last [`Backend::translate_all`], written as `segment`, to
+    /// the `--qa-sample`.
+    pub fn offer_sample(&mut self, segment: &Segment, translated: &str, index: usize) {
+        let Some(sample) = self.qa_sample.as_mut().filter(|_| self.deferral.is_none()) else {
+            return;
+        };
+        let served = self.served.get(index).copied().flatten();
+        let url = served.map_or("", |i| self.instances.all()[i].url.as_str());
+        sample.offer(segment, translated, url);
+    }
+
+    /// Translates `output` back for a sampled segment and records the three texts.
+    /// Failures are only printed, the spot check never holds up a run.
+    async fn sample(&mut self, text: &str, output: &str) {
+        if !self.qa.as_ref().is_some_and(|qa| qa.picks(text))
+            || !self.usage.spend(output.chars().count())
+        {
+            return;
+        }
+        let source = self.source_lang.clone();
+        let back = match self.request(output, TARGET_LANG.as_code(), &source).await {
+            Ok(back) => back,
+            Err(e) => {
+                eprintln!("back-translation failed: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.qa.as_mut().unwrap().record(text, output, &back) {
+            eprintln!("could not write the QA sample: {}", e);
+        }
+    }
+
+    async fn request(&mut self, text: &str, source: &str, target: &str) -> Result<String> {
+        let (result, elapsed, _) = self.send(text, source, target).await;
+        self.requests += 1;
+        self.request_time += elapsed;
+        result
+    }
+
+    /// Sends one request, to another server once more if the first one is down, returning
+    /// its result and round trip. Takes `&self` so several can be in flight.
+    async fn send(
+        &self,
+        text: &str,
+        source: &str,
+        target: &str,
+    ) -> (Result<String>, Duration, usize) {
+        let started = Instant::now();
+        let mut avoid = None;
+        let (result, i) = loop {
+            self.pause.wait().await;
+            let (i, instance) = self.instances.start(avoid);
+            let result = self.post(instance, text, source, target).await;
+            if self.instances.finish(i, result.as_ref().err()) && avoid.is_none() {
+                avoid = Some(i);
+                continue;
+            }
+            break (result, i);
+        };
+        let elapsed = started.elapsed();
+        metrics::backend_request(elapsed);
+        (result, elapsed, i)
+    }
+
+    /// [`Backend::send`] for a batch.
+    async fn send_batch(
+        &self,
+        texts: &[&str],
+        source: &str,
+        target: &str,
+    ) -> (Result<Vec<String>, BatchError>, usize) {
+        let mut avoid = None;
+        loop {
+            self.pause.wait().await;
+            let (i, instance) = self.instances.start(avoid);
+            let result = self.post_batch(instance, texts, source, target).await;
+            let failed = match &result {
+                Err(BatchError::Failed(e)) => Some(e),
+                _ => None,
+            };
+            if self.instances.finish(i, failed) && avoid.is_none() {
+                avoid = Some(i);
+                continue;
+            }
+            return (result, i);
+        }
+    }
+
+    /// Sends `texts` in one request to `instance`, returning their translations in order.
+    async fn post_batch(
+        &self,
+        instance: &Instance,
+        texts: &[&str],
+        source: &str,
+        target: &str,
+    ) -> Result<Vec<String>, BatchError> {
+        let body = self.body(texts.into(), source, target);
+        let failed = |e: anyhow::Error| BatchError::Failed(e);
+        let mut response = instance
+            .client
+            .post(format!("{}/translate", instance.url))
+            .body_json(&body)
+            .map_err(|e| failed(anyhow!("{}", e)))?
+            .await
+            .map_err(|e| failed(instance.connection_error(e)))?;
+        let status = response.status();
+        if let Some(e) = instance.unavailable(status) {
+            return Err(failed(e));
+        }
+        let response = response
+            .body_string()
+            .await
+            .map_err(|e| failed(instance.connection_error(e)))?;
+        let response: Value = serde_json::from_str(&response)
+            .map_err(|e| failed(anyhow!("{} answered {}: {}", instance.url, status, e)))?;
+        if let Value::String(error) = &response["error"] {
+            self.check_refused(error);
+            // LibreTranslate says "... exceeds text limit (5000)" with a 400
+            let too_long = status == surf::StatusCode::PayloadTooLarge
+                || (status == surf::StatusCode::BadRequest && error.contains("limit"));
+            if too_long {
+                return Err(BatchError::TooLong(reported_limit(error)));
+            }
+            return Err(failed(anyhow!("{}", error)));
+        }
+        if status == surf::StatusCode::PayloadTooLarge {
+            return Err(BatchError::TooLong(None));
+        }
+        let outputs: Option<Vec<String>> = response["translatedText"].as_array().map(|outputs| {
+            outputs
+                .iter()
+                .filter_map(|output| output.as_str().map(String::from))
+                .collect()
+        });
+        match outputs {
+            Some(outputs) if outputs.len() == texts.len() => Ok(outputs),
+            _ => Err(failed(anyhow!(
+                "no translatedText for each of the {} segments in the response",
+                texts.len()
+            ))),
+        }
+    }
+
+    async fn post(
+        &self,
+        instance: &Instance,
+        text: &str,
+        source: &str,
+        target: &str,
+    ) -> Result<String> {
+        let body = self.body(text.into(), source, target);
+        let mut response = instance
+            .client
+            .post(format!("{}/translate", instance.url))
+            .body_json(&body)
+            .map_err(|e| anyhow!("{}", e))?
+            .await
+            .map_err(|e| instance.connection_error(e))?;
+        if let Some(e) = instance.unavailable(response.status()) {
+            return Err(e);
+        }
+        let response = response
+            .body_string()
+            .await
+            .map_err(|e| instance.connection_error(e))?;
+        let response: Value = serde_json::from_str(&response)?;
+        if let Value::String(error) = &response["error"] {
+            self.check_refused(error);
+            return Err(anyhow!("{}", error));
+        }
+        match &response["translatedText"] {
+            Value::String(output) => Ok(output.clone()),
+            _ => Err(anyhow!("no translatedText in the response")),
+        }
+    }
+}
+
+/// `batch` cut in two, for one of the halves to show which segment fails.
+fn halves(mut batch: Vec<usize>) -> Vec<Vec<usize>> {
+    let second = batch.split_off(batch.len() / 2);
+    vec![batch, second]
+}
+
+/// The number following "limit" in an error message of the server.
+fn reported_limit(error: &str) -> Option<usize> {
+    let after = &error[error.find("limit")? + "limit".len()..];
+    let digits: String = after
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
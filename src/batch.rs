@@ -0,0 +1,207 @@
+//! `[batching]`: several segments go to the server in one request, packed up to a
+//! character budget. The budget adapts as the run goes: it is halved when a request
+//! takes longer than the target latency or fails, grows back by a quarter after each
+//! fast one, and never exceeds the server's limit, read from `/frontend/settings` or
+//! learned from the requests it refused as too long.
+
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Smallest budget shrinking goes down to, a batch holds at least one segment whatever
+/// it is.
+const MIN_CHARS: usize = 50;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Batching {
+    /// characters packed into one request at most
+    pub max_chars: usize,
+    /// requests slower than this shrink the batches
+    pub target_latency_ms: u64,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            max_chars: 2000,
+            target_latency_ms: 3000,
+        }
+    }
+}
+
+/// The current budget of a run and what it learned about the server.
+pub struct Batcher {
+    settings: Batching,
+    /// characters of the next batches
+    chars: usize,
+    /// characters the server accepts in one request, as far as known
+    limit: Option<usize>,
+}
+
+impl Batcher {
+    pub fn new(settings: Batching) -> Self {
+        Batcher {
+            chars: settings.max_chars.max(1),
+            settings,
+            limit: None,
+        }
+    }
+
+    /// Groups the segments with `chars` characters each into batches of consecutive
+    /// segments within the budget; returns the indices of each batch, in order.
+    pub fn pack(&self, chars: &[(usize, usize)]) -> Vec<Vec<usize>> {
+        let budget = self.budget();
+        let mut batches: Vec<Vec<usize>> = Vec::new();
+        let mut current = Vec::new();
+        let mut current_chars = 0;
+        for &(index, chars) in chars {
+            if !current.is_empty() && current_chars + chars > budget {
+                batches.push(std::mem::take(&mut current));
+                current_chars = 0;
+            }
+            current.push(index);
+            current_chars += chars;
+        }
+        if !current.is_empty() {
+            batches.push(current);
+        }
+        batches
+    }
+
+    /// Characters of the next batches.
+    pub fn budget(&self) -> usize {
+        self.limit.map_or(self.chars, |limit| self.chars.min(limit))
+    }
+
+    /// The server's limit from its settings, `None` if it has none.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// A batch went through in `elapsed`.
+    pub fn succeeded(&mut self, elapsed: Duration) {
+        if elapsed > Duration::from_millis(self.settings.target_latency_ms) {
+            self.shrink();
+        } else {
+            let grown = self.chars + (self.chars / 4).max(1);
+            self.chars = grown.min(self.settings.max_chars.max(1));
+        }
+    }
+
+    /// The server refused a batch of `chars` characters as too long, saying it takes
+    /// `limit` if it said.
+    pub fn too_long(&mut self, chars: usize, limit: Option<usize>) {
+        let limit = limit.unwrap_or(chars.saturating_sub(1)).max(1);
+        self.limit = Some(self.limit.map_or(limit, |known| known.min(limit)));
+    }
+
+    /// A batch failed otherwise.
+    pub fn failed(&mut self) {
+        self.shrink();
+    }
+
+    fn shrink(&mut self) {
+        self.chars = (self.chars / 2).max(MIN_CHARS.min(self.chars));
+    }
+
+    /// The parameters for `--verbose`.
+    pub fn describe(&self) -> String {
+        let limit = match self.limit {
+            Some(limit) => format!("server limit {}", limit),
+            None => "no known server limit".to_string(),
+        };
+        format!(
+            "batches of up to {} characters, {}, target latency {} ms",
+            self.budget(),
+            limit,
+            self.settings.target_latency_ms
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batcher(max_chars: usize) -> Batcher {
+        Batcher::new(Batching {
+            max_chars,
+            target_latency_ms: 3000,
+        })
+    }
+
+    const FAST: Duration = Duration::from_millis(100);
+    const SLOW: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn segments_are_packed_in_order_within_the_budget() {
+        let batcher = batcher(100);
+        let chars = [(0, 40), (1, 50), (2, 20), (3, 150), (4, 10)];
+        assert_eq!(
+            batcher.pack(&chars),
+            [vec![0, 1], vec![2], vec![3], vec![4]]
+        );
+        assert!(batcher.pack(&[]).is_empty());
+    }
+
+    #[test]
+    fn slow_and_failed_requests_shrink_the_batches_and_fast_ones_grow_them() {
+        let mut batcher = batcher(2000);
+        batcher.succeeded(SLOW);
+        assert_eq!(batcher.budget(), 1000);
+        batcher.succeeded(FAST);
+        assert_eq!(batcher.budget(), 1250);
+        for _ in 0..5 {
+            batcher.succeeded(FAST);
+        }
+        assert_eq!(batcher.budget(), 2000);
+        for _ in 0..10 {
+            batcher.failed();
+        }
+        assert_eq!(batcher.budget(), MIN_CHARS);
+        // a budget below the floor to begin with stays as it is
+        let mut small = self::batcher(30);
+        small.failed();
+        assert_eq!(small.budget(), 30);
+    }
+
+    #[test]
+    fn the_server_limit_caps_the_budget() {
+        let mut batcher = batcher(2000);
+        batcher.set_limit(Some(1500));
+        assert_eq!(batcher.budget(), 1500);
+        batcher.too_long(1200, None);
+        assert_eq!(batcher.budget(), 1199);
+        // a limit the server names is taken, a larger one doesn't raise the known one
+        batcher.too_long(1100, Some(800));
+        assert_eq!(batcher.budget(), 800);
+        batcher.too_long(900, Some(1000));
+        assert_eq!(batcher.budget(), 800);
+        assert!(batcher.describe().contains("server limit 800"));
+    }
+
+    /// A run against a server refusing requests over 700 characters without saying its
+    /// limit, and taking 5 ms per character.
+    #[test]
+    fn a_run_adapts_to_a_server_with_a_limit_and_slowdowns() {
+        let mut batcher = batcher(2000);
+        let mut pending: Vec<(usize, usize)> = (0..40).map(|i| (i, 100)).collect();
+        let mut sent = Vec::new();
+        let mut refused = 0;
+        while !pending.is_empty() {
+            let batch = batcher.pack(&pending).remove(0);
+            let chars = batch.len() * 100;
+            if chars > 700 {
+                batcher.too_long(chars, None);
+                refused += 1;
+                continue;
+            }
+            batcher.succeeded(Duration::from_millis(chars as u64 * 5));
+            pending.drain(..batch.len());
+            sent.extend(batch);
+        }
+        assert_eq!(sent, (0..40).collect::<Vec<_>>());
+        assert_eq!(refused, 13);
+        assert!(batcher.describe().contains("server limit 799"));
+    }
+}
@@ -0,0 +1,330 @@
+//! `extract` and `apply-translations`: a run split between a machine that can read the
+//! files, but not reach the server, and one that can. `extract` is `translate` with a
+//! backend that answers every segment with a marker and keeps the segment; the outputs
+//! written with the markers go into `extraction.jsonl` with the segments. There
+//! `apply-translations` sends the segments through the backend as `translate` would and
+//! writes the outputs with the markers replaced, like `retry-failures` does with failed
+//! segments. See the README for the format.
+
+use crate::diff;
+use crate::output::{self, Origin, Segment, TextFormat};
+use crate::post_edit::{Granularity, PostEdit};
+use crate::translator::Translator;
+use crate::{FileReport, Report, TARGET_LANG};
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const BUNDLE_FILE: &str = "extraction.jsonl";
+/// Raised when a bundle changes in a way older versions can't read.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// First line of a bundle.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    target_lang: String,
+    /// encoding and line endings the outputs are written in
+    format: TextFormat,
+}
+
+/// A segment the backend was asked for, standing as `marker` in the outputs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Deferred {
+    pub marker: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// the text stays if the translation fails, as for table cells and email headers,
+    /// instead of a failure marker
+    #[serde(default)]
+    pub keep_on_failure: bool,
+}
+
+/// The segments of a run of `extract`, numbered across the run. Their markers hold a
+/// random number of the run too, so a source text that happens to hold a marker isn't
+/// taken for one.
+pub struct Deferral {
+    count: usize,
+    nonce: u64,
+    pub segments: Vec<Deferred>,
+}
+
+impl Default for Deferral {
+    fn default() -> Self {
+        Deferral {
+            count: 0,
+            nonce: RandomState::new().build_hasher().finish(),
+            segments: Vec::new(),
+        }
+    }
+}
+
+impl Deferral {
+    /// Keeps `text` and returns the marker standing for its translation.
+    pub fn defer(&mut self, text: &str, origin: Option<Origin>, keep_on_failure: bool) -> String {
+        self.count += 1;
+        let marker = format!(
+            "[[dir-translate: segment {:016x}-{}]]",
+            self.nonce, self.count
+        );
+        self.segments.push(Deferred {
+            marker: marker.clone(),
+            text: text.to_string(),
+            origin,
+            keep_on_failure,
+        });
+        marker
+    }
+}
+
+/// A marker of [`Deferral::defer`], capturing the number of its segment. Bundles of
+/// older versions have no nonce in them.
+fn marker() -> &'static Regex {
+    static MARKER: OnceLock<Regex> = OnceLock::new();
+    MARKER.get_or_init(|| {
+        Regex::new(r"\[\[dir-translate: segment (?:[0-9a-f]{16}-)?(\d+)\]\]").unwrap()
+    })
+}
+
+/// An output of a file, relative to the target dir: the text written with the markers, or
+/// the bytes of a picture in base64.
+#[derive(Serialize, Deserialize)]
+struct BundledOutput {
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+/// `path` if it stays below the dir it is joined to, failing if it is absolute or has a
+/// `..` in it.
+fn below(path: &Path) -> Result<&Path> {
+    if path.components().all(|c| matches!(c, Component::Normal(_))) {
+        Ok(path)
+    } else {
+        Err(anyhow!(
+            "{} is not a path below the target dir",
+            path.display()
+        ))
+    }
+}
+
+/// Whether `path` is the `.src.txt` or `.src.md` of `--keep-source-text`.
+fn is_source_text(path: &Path) -> bool {
+    path.file_stem()
+        .is_some_and(|stem| stem.to_string_lossy().ends_with(".src"))
+}
+
+/// One line of a bundle after the header: a source file, relative to the source dir.
+#[derive(Serialize, Deserialize)]
+struct BundledFile {
+    source: PathBuf,
+    handler: String,
+    source_lang: String,
+    outputs: Vec<BundledOutput>,
+    segments: Vec<Deferred>,
+}
+
+/// The bundle of an `extract` run, opened for writing.
+pub struct Bundle {
+    file: File,
+    format: TextFormat,
+}
+
+impl Bundle {
+    pub fn create(path: &Path, format: &TextFormat) -> Result<Self> {
+        let mut bundle = Bundle {
+            file: File::create(path)?,
+            format: format.clone(),
+        };
+        let header = Header {
+            version: BUNDLE_VERSION,
+            target_lang: TARGET_LANG.as_code().to_string(),
+            format: format.clone(),
+        };
+        bundle.write_line(&header)?;
+        Ok(bundle)
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Moves the outputs of `source`, below `out`, into the bundle with the segments
+    /// they stand for. Returns the outputs relative to `out`.
+    pub fn append(
+        &mut self,
+        source: &Path,
+        handler: &str,
+        source_lang: &str,
+        out: &Path,
+        outputs: &[PathBuf],
+        segments: Vec<Deferred>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut bundled = Vec::new();
+        for output in outputs {
+            let bytes = std::fs::read(output)?;
+            let path = output
+                .strip_prefix(out)
+                .map_err(|_| anyhow!("{} is not below {}", output.display(), out.display()))?;
+            let path = below(path)?.to_path_buf();
+            let (text, data) = if diff::is_text(output) {
+                (Some(self.format.decode(&bytes)), None)
+            } else {
+                (None, Some(base64::encode(&bytes)))
+            };
+            bundled.push(BundledOutput { path, text, data });
+        }
+        let file = BundledFile {
+            source: source.to_path_buf(),
+            handler: handler.to_string(),
+            source_lang: source_lang.to_string(),
+            outputs: bundled,
+            segments,
+        };
+        self.write_line(&file)?;
+        for output in outputs {
+            std::fs::remove_file(output)?;
+        }
+        Ok(file.outputs.into_iter().map(|o| o.path).collect())
+    }
+}
+
+/// Translates the segments of the bundle at `path` and writes its outputs into
+/// `target_dir`, adding a file to `report` for each source.
+pub async fn apply(
+    translator: &mut Translator,
+    path: &Path,
+    target_dir: &Path,
+    post_edit: Option<PostEdit>,
+    report: &mut Report,
+) -> Result<()> {
+    let file = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines
+        .next()
+        .ok_or_else(|| anyhow!("{}: empty", path.display()))??;
+    let header: Header = serde_json::from_str(&header)?;
+    if header.version != BUNDLE_VERSION {
+        return Err(anyhow!(
+            "{}: bundle version {}, this dir-translate reads version {}",
+            path.display(),
+            header.version,
+            BUNDLE_VERSION
+        ));
+    }
+    if header.target_lang != TARGET_LANG.as_code() {
+        return Err(anyhow!(
+            "{}: extracted for {}, this dir-translate translates into {}",
+            path.display(),
+            header.target_lang,
+            TARGET_LANG.as_code()
+        ));
+    }
+    let format = header.format;
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let file: BundledFile = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("{}: line {}: {}", path.display(), number + 2, e))?;
+        // a bundle from elsewhere must not write outside the target dir
+        for output in &file.outputs {
+            below(&output.path)
+                .map_err(|e| anyhow!("{}: line {}: {}", path.display(), number + 2, e))?;
+        }
+        if let Err(e) = translator.backend.check_pair(&file.source_lang).await {
+            eprintln!("{}: skipped, {}", file.source.display(), e);
+            report.summary.failed += 1;
+            report.push(FileReport::failed(file.source, &file.handler, &e));
+            continue;
+        }
+        translator.backend.source_lang = file.source_lang.clone();
+        let mut post_edit = post_edit.clone();
+        if let Some(post_edit) = &mut post_edit {
+            post_edit.source = file.source.clone();
+            post_edit.source_lang = file.source_lang.clone();
+        }
+        let texts: Vec<&str> = file.segments.iter().map(|s| s.text.as_str()).collect();
+        let results = translator.backend.translate_all(&texts).await;
+        // the segment and translation of each marker
+        let mut translations = HashMap::new();
+        for (segment, result) in file.segments.iter().zip(results) {
+            let translated = match result {
+                Ok(translated) => Some(translated),
+                Err(_) if segment.keep_on_failure => None,
+                Err(e) => {
+                    eprintln!("{}: {}", file.source.display(), e);
+                    None
+                }
+            };
+            translations.insert(segment.marker.as_str(), (segment, translated));
+        }
+        let mut outputs = Vec::new();
+        let mut failures = Vec::new();
+        for output in &file.outputs {
+            let path = target_dir.join(&output.path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            let bytes = match (&output.text, &output.data) {
+                (Some(text), _) => {
+                    // placed as a run places them, only the markers of the bundle
+                    let text = marker().replace_all(text, |captures: &regex::Captures| {
+                        let Some((segment, translated)) = translations.get(&captures[0]) else {
+                            return captures[0].to_string();
+                        };
+                        if segment.keep_on_failure {
+                            return output::or_source(translated.clone(), &segment.text);
+                        }
+                        output::place(
+                            &Segment::new(segment.text.as_str(), segment.origin),
+                            translated.as_deref(),
+                            captures[1].parse().unwrap_or_default(),
+                            &path,
+                            post_edit.as_ref(),
+                            None,
+                            &mut failures,
+                        )
+                    });
+                    let document_edit = post_edit
+                        .as_ref()
+                        .filter(|p| p.granularity == Granularity::Document)
+                        .filter(|_| !is_source_text(&output.path));
+                    let text = match document_edit {
+                        Some(post_edit) => post_edit.edit_document(&text, &path),
+                        None => text.into_owned(),
+                    };
+                    format.encode_file(&text)
+                }
+                (None, Some(data)) => base64::decode(data)?,
+                (None, None) => Vec::new(),
+            };
+            std::fs::write(&path, bytes)?;
+            outputs.push(path);
+        }
+        report.summary.processed += 1;
+        report.summary.failed_segments += failures.len();
+        report.push(FileReport {
+            outputs,
+            error: None,
+            failures,
+            ..FileReport::skipped(file.source, &file.handler, String::new())
+        });
+        translator.backend.usage.save()?;
+    }
+    report.format = format;
+    Ok(())
+}
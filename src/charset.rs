@@ -0,0 +1,69 @@
+//! Charset fallback for text with unreliable labels, mostly Russian and Ukrainian email
+//! sent as koi8-r or cp1251 but labeled as something else or not at all.
+
+use encoding_rs::{Encoding, KOI8_R, UTF_8, WINDOWS_1251};
+
+/// Letter counts of a decoded text.
+#[derive(Default)]
+struct Letters {
+    ascii: usize,
+    cyrillic_lower: usize,
+    cyrillic_upper: usize,
+    /// letters that are neither ASCII nor Cyrillic
+    other: usize,
+}
+
+impl Letters {
+    fn of(text: &str) -> Self {
+        let mut letters = Letters::default();
+        for c in text.chars().filter(|c| c.is_alphabetic()) {
+            if c.is_ascii() {
+                letters.ascii += 1;
+            } else if ('\u{0400}'..='\u{04FF}').contains(&c) {
+                if c.is_lowercase() {
+                    letters.cyrillic_lower += 1;
+                } else {
+                    letters.cyrillic_upper += 1;
+                }
+            } else {
+                letters.other += 1;
+            }
+        }
+        letters
+    }
+
+    /// Whether the text reads like a real decoding: mostly ASCII, or mostly Cyrillic with
+    /// more lower than upper case. cp1251 read as koi8-r and the other way round swap the
+    /// case, and either read as a Latin charset gives accented Latin letters.
+    fn plausible(&self) -> bool {
+        let non_ascii = self.cyrillic_lower + self.cyrillic_upper + self.other;
+        if non_ascii * 10 < (self.ascii + non_ascii) * 3 {
+            return true;
+        }
+        self.cyrillic_lower > self.cyrillic_upper && self.other * 5 < non_ascii
+    }
+}
+
+/// Decodes `bytes` with the encoding of `label` if that gives a plausible text, otherwise
+/// with the first of UTF-8, cp1251 and koi8-r that does. Falls back to the label, or
+/// UTF-8 without one, with replacement characters.
+pub fn decode(bytes: &[u8], label: Option<&str>) -> String {
+    let labeled = label.and_then(|l| Encoding::for_label(l.trim().as_bytes()));
+    let candidates = labeled.into_iter().chain([UTF_8, WINDOWS_1251, KOI8_R]);
+    for encoding in candidates {
+        let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+        if !had_errors && Letters::of(&text).plausible() {
+            return text.into_owned();
+        }
+    }
+    labeled
+        .unwrap_or(UTF_8)
+        .decode_without_bom_handling(bytes)
+        .0
+        .into_owned()
+}
+
+/// Whether `text`, as decoded by someone else, looks wrong enough to decode it again.
+pub fn suspicious(text: &str) -> bool {
+    text.contains('\u{FFFD}') || !Letters::of(text).plausible()
+}
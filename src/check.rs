@@ -0,0 +1,185 @@
+//! `check` subcommand: verifies everything a run needs before starting one.
+
+use crate::backend::Backend;
+use crate::presets::{self, Preset};
+use crate::translator::{bind_pdfium, pdfium_error, tesseract_error};
+use crate::usage::Usage;
+use crate::{Config, TARGET_LANG};
+use anyhow::{anyhow, Result};
+use pdfium_render::prelude::*;
+use std::path::Path;
+
+struct Check {
+    name: &'static str,
+    hint: String,
+    result: Result<String>,
+}
+
+impl Check {
+    fn print(&self) {
+        match &self.result {
+            Ok(detail) => println!("[ok]   {}: {}", self.name, detail),
+            Err(e) => {
+                println!("[FAIL] {}: {}", self.name, e);
+                println!("       hint: {}", self.hint);
+            }
+        }
+    }
+}
+
+fn check_pdfium(config: &Config) -> Result<String> {
+    let path = config.pdfium_path.as_deref();
+    let bindings = bind_pdfium(path).map_err(|e| anyhow!("{}", pdfium_error(path, e)))?;
+    let _ = Pdfium::new(bindings);
+    Ok("library bound".to_string())
+}
+
+fn check_tesseract(config: &Config) -> Result<String> {
+    let lang = &config.preset.ocr_lang;
+    leptess::LepTess::new(Some(&config.tesserac_data), lang)
+        .map_err(|e| anyhow!("{}", tesseract_error(&config.tesserac_data, lang, e)))?;
+    Ok(format!("{} in {}", lang, config.tesserac_data))
+}
+
+fn backend(config: &Config, cache_dir: &Path) -> Result<Backend> {
+    Backend::new(
+        &config.libretranslate_url.list(),
+        Usage::load(cache_dir, None)?,
+        1,
+        config.guardrails.clone(),
+        config.batching.clone(),
+        &config.network,
+    )
+}
+
+/// Translates a word through the same client as a run, proxy and TLS settings included.
+/// The characters are not counted against the budget.
+async fn check_backend(config: &Config, cache_dir: &Path) -> Result<String> {
+    let mut backend = backend(config, cache_dir)?;
+    backend.source_lang = config.preset.source_lang.clone();
+    let output = backend.translate("привет").await?;
+    Ok(format!("\"привет\" -> \"{}\"", output))
+}
+
+/// Fails with what is missing unless both tesseract and the server know the languages of
+/// `preset`. The server is taken to know them if it doesn't list its languages.
+async fn check_preset(preset: &Preset, config: &Config, backend: &mut Backend) -> Result<()> {
+    let mut problems = Vec::new();
+    let missing = presets::missing_traineddata(preset, &config.tesserac_data);
+    if !missing.is_empty() {
+        problems.push(format!(
+            "no {} traineddata in {}",
+            missing.join(", "),
+            config.tesserac_data
+        ));
+    }
+    if let Err(e) = backend.check_pair(&preset.source_lang).await {
+        problems.push(e.to_string());
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!("{}", problems.join("; ")));
+    }
+    Ok(())
+}
+
+async fn check_presets(config: &Config, cache_dir: &Path) -> Result<String> {
+    let mut backend = backend(config, cache_dir)?;
+    let mut usable = Vec::new();
+    for (name, preset) in presets::all(&config.presets) {
+        if check_preset(&preset, config, &mut backend).await.is_ok() {
+            usable.push(name);
+        }
+    }
+    if usable.is_empty() {
+        return Err(anyhow!("none usable, see `languages`"));
+    }
+    Ok(format!("usable: {}", usable.join(", ")))
+}
+
+/// `languages` subcommand: prints every preset with its languages and whether it can be
+/// used on this machine.
+pub async fn languages(config: &Config, cache_dir: &Path) -> Result<()> {
+    let mut backend = backend(config, cache_dir)?;
+    for (name, preset) in presets::all(&config.presets) {
+        let languages = format!("{}, {}", preset.source_lang, preset.ocr_lang);
+        match check_preset(&preset, config, &mut backend).await {
+            Ok(()) => println!("[ok]   {}: {}", name, languages),
+            Err(e) => println!("[FAIL] {}: {}: {}", name, languages, e),
+        }
+    }
+    Ok(())
+}
+
+fn check_source_dir(source_dir: &Path) -> Result<String> {
+    if !source_dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", source_dir.display()));
+    }
+    std::fs::read_dir(source_dir)?;
+    Ok(source_dir.display().to_string())
+}
+
+fn check_target_dir(target_dir: &Path) -> Result<String> {
+    std::fs::create_dir_all(target_dir)?;
+    let probe = target_dir.join(".dir-translate-check");
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)?;
+    Ok(target_dir.display().to_string())
+}
+
+/// Runs all checks, printing one line per item. Returns false if any of them failed.
+pub async fn run(
+    config: &Config,
+    source_dir: &Path,
+    target_dir: Option<&Path>,
+    cache_dir: &Path,
+) -> bool {
+    let mut checks = vec![
+        Check {
+            name: "pdfium",
+            hint: format!(
+                "install {} next to the executable or a system library path, or point pdfium_path in config.toml at it",
+                Pdfium::pdfium_platform_library_name().to_string_lossy()
+            ),
+            result: check_pdfium(config),
+        },
+        Check {
+            name: "tesseract",
+            hint: format!(
+                "install the {} traineddata (e.g. tesseract-ocr-{}) and point tesserac_data in config.toml at its tessdata dir",
+                config.preset.ocr_lang, config.preset.ocr_lang
+            ),
+            result: check_tesseract(config),
+        },
+        Check {
+            name: "libretranslate",
+            hint: format!(
+                "start LibreTranslate with the {}->{} model loaded or fix libretranslate_url in config.toml",
+                config.preset.source_lang,
+                TARGET_LANG.as_code()
+            ),
+            result: check_backend(config, cache_dir).await,
+        },
+        Check {
+            name: "presets",
+            hint: "install the traineddata and server model of a preset, see `languages`"
+                .to_string(),
+            result: check_presets(config, cache_dir).await,
+        },
+        Check {
+            name: "source dir",
+            hint: "pass an existing, readable directory to --source-dir".to_string(),
+            result: check_source_dir(source_dir),
+        },
+    ];
+    if let Some(target_dir) = target_dir {
+        checks.push(Check {
+            name: "target dir",
+            hint: "pick a target directory you have write access to".to_string(),
+            result: check_target_dir(target_dir),
+        });
+    }
+    for check in &checks {
+        check.print();
+    }
+    checks.iter().all(|c| c.result.is_ok())
+}
@@ -0,0 +1,237 @@
+//! Column detection for multi-column scans. Word boxes are projected onto the x axis and
+//! the vertical whitespace valleys between them are the gutters; the page is split into
+//! one region per column and recognized region by region, so lines of neighbouring
+//! columns are never joined. Words crossing a gutter, like a title over both columns,
+//! get a full-width band of their own.
+
+use clap::ValueEnum;
+
+/// Share of the typical projection a valley may still have, for a title or stray marks
+/// across a gutter.
+const VALLEY_TOLERANCE: f32 = 0.25;
+/// Narrowest gutter, in median word heights; wider than the spaces between words, which
+/// rarely line up over a whole page anyway.
+const MIN_GUTTER: f32 = 1.5;
+/// Fewest words on a page for `auto` to look for columns.
+const MIN_WORDS: usize = 30;
+/// Fewest words a column needs, as a share of the page, so ragged gaps inside a single
+/// column don't split it.
+const MIN_COLUMN_SHARE: f32 = 0.15;
+/// Most columns `auto` detects.
+const MAX_COLUMNS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Columns {
+    /// find the columns of every page from the whitespace between them
+    Auto,
+    /// one column, tesseract's own block order
+    #[value(name = "1")]
+    One,
+    /// two columns
+    #[value(name = "2")]
+    Two,
+    /// three columns
+    #[value(name = "3")]
+    Three,
+}
+
+impl Columns {
+    fn count(self) -> Option<usize> {
+        match self {
+            Columns::Auto => None,
+            Columns::One => Some(1),
+            Columns::Two => Some(2),
+            Columns::Three => Some(3),
+        }
+    }
+}
+
+/// Gutter between two columns, as the x range of its whitespace.
+#[derive(Clone, Copy, Debug)]
+struct Gutter {
+    left: i32,
+    right: i32,
+}
+
+impl Gutter {
+    fn center(&self) -> i32 {
+        (self.left + self.right) / 2
+    }
+}
+
+/// Regions of a `width` x `height` page to recognize one after the other, in reading
+/// order, from the boxes (x, y, width, height) of its words. A single region covering
+/// the page if it has one column.
+pub fn regions(words: &[[i32; 4]], width: i32, height: i32, layout: Columns) -> Vec<[i32; 4]> {
+    let page = vec![[0, 0, width, height]];
+    if layout == Columns::One
+        || words.is_empty()
+        || (layout == Columns::Auto && words.len() < MIN_WORDS)
+    {
+        return page;
+    }
+    let gutters = gutters(words, layout.count());
+    if gutters.is_empty() {
+        return page;
+    }
+    // full-width bands where words cross a gutter, sorted top to bottom and merged
+    let mut bands: Vec<(i32, i32)> = words
+        .iter()
+        .filter(|[x, _, w, _]| {
+            gutters
+                .iter()
+                .any(|g| *x < g.center() && x + w > g.center())
+        })
+        .map(|[_, y, _, h]| (*y, y + h))
+        .collect();
+    bands.sort();
+    let mut merged: Vec<(i32, i32)> = Vec::new();
+    for (top, bottom) in bands {
+        match merged.last_mut() {
+            Some(last) if top <= last.1 => last.1 = last.1.max(bottom),
+            _ => merged.push((top, bottom)),
+        }
+    }
+    let mut edges: Vec<i32> = vec![0];
+    edges.extend(gutters.iter().map(Gutter::center));
+    edges.push(width);
+    let mut regions = Vec::new();
+    let mut y = 0;
+    for (top, bottom) in merged.into_iter().chain([(height, height)]) {
+        if top > y {
+            for column in edges.windows(2) {
+                regions.push([column[0], y, column[1] - column[0], top - y]);
+            }
+        }
+        if bottom > top {
+            regions.push([0, top, width, bottom - top]);
+        }
+        y = y.max(bottom);
+    }
+    // stripes without a word of their own, e.g. below the last line of a column
+    regions.retain(|region| words.iter().any(|word| center_in(word, region)));
+    if regions.is_empty() {
+        return page;
+    }
+    regions
+}
+
+fn center_in([x, y, w, h]: &[i32; 4], [rx, ry, rw, rh]: &[i32; 4]) -> bool {
+    let (cx, cy) = (x + w / 2, y + h / 2);
+    cx >= *rx && cx < rx + rw && cy >= *ry && cy < ry + rh
+}
+
+/// The gutters between the columns of the words, the widest ones if there are more than
+/// `count` needs. With a forced count narrower gaps are taken too, and if there still
+/// aren't enough the widest columns are split in the middle.
+fn gutters(words: &[[i32; 4]], count: Option<usize>) -> Vec<Gutter> {
+    let left = words.iter().map(|w| w[0]).min().unwrap();
+    let right = words.iter().map(|w| w[0] + w[2]).max().unwrap();
+    let mut profile = vec![0u32; (right - left).max(0) as usize];
+    for [x, _, w, _] in words {
+        for column in &mut profile[(x - left) as usize..(x + w - left) as usize] {
+            *column += 1;
+        }
+    }
+    let mut heights: Vec<i32> = words.iter().map(|w| w[3]).collect();
+    heights.sort();
+    let min_gutter = (heights[heights.len() / 2] as f32 * MIN_GUTTER).max(1.0) as usize;
+    let mut sorted = profile.clone();
+    sorted.sort();
+    let typical = sorted[sorted.len() * 4 / 5];
+    let ceiling = (typical as f32 * VALLEY_TOLERANCE) as u32;
+    let wanted = count.unwrap_or(MAX_COLUMNS) - 1;
+    let mut chosen = Vec::new();
+    choose(
+        words,
+        valleys(&profile, left, ceiling, min_gutter),
+        wanted,
+        &mut chosen,
+    );
+    if let Some(count) = count {
+        choose(
+            words,
+            valleys(&profile, left, ceiling, 1),
+            wanted,
+            &mut chosen,
+        );
+        while chosen.len() < count - 1 {
+            let mut edges: Vec<i32> = vec![left];
+            edges.extend(chosen.iter().map(Gutter::center));
+            edges.push(right);
+            edges.sort();
+            let widest = edges
+                .windows(2)
+                .max_by_key(|pair| pair[1] - pair[0])
+                .unwrap();
+            let middle = (widest[0] + widest[1]) / 2;
+            chosen.push(Gutter {
+                left: middle,
+                right: middle,
+            });
+        }
+    }
+    chosen.sort_by_key(|g| g.left);
+    chosen
+}
+
+/// Runs of at least `min_width` pixels where `profile` stays at or below `ceiling`.
+fn valleys(profile: &[u32], left: i32, ceiling: u32, min_width: usize) -> Vec<Gutter> {
+    let mut valleys = Vec::new();
+    let mut start = None;
+    for (i, &value) in profile.iter().enumerate() {
+        match (value <= ceiling, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= min_width {
+                    valleys.push(Gutter {
+                        left: left + s as i32,
+                        right: left + i as i32,
+                    });
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    valleys
+}
+
+/// Adds the widest of `candidates` to `chosen` until it has `wanted`, skipping those that
+/// would leave a column with too few words or overlap one already chosen.
+fn choose(
+    words: &[[i32; 4]],
+    mut candidates: Vec<Gutter>,
+    wanted: usize,
+    chosen: &mut Vec<Gutter>,
+) {
+    candidates.sort_by_key(|g| std::cmp::Reverse(g.right - g.left));
+    for candidate in candidates {
+        if chosen.len() == wanted {
+            break;
+        }
+        if chosen
+            .iter()
+            .any(|g| g.left < candidate.right && candidate.left < g.right)
+        {
+            continue;
+        }
+        chosen.push(candidate);
+        if !balanced(words, chosen) {
+            chosen.pop();
+        }
+    }
+}
+
+/// Whether every column between `gutters` holds enough of the words.
+fn balanced(words: &[[i32; 4]], gutters: &[Gutter]) -> bool {
+    let mut edges: Vec<i32> = gutters.iter().map(Gutter::center).collect();
+    edges.sort();
+    let mut counts = vec![0usize; edges.len() + 1];
+    for [x, _, w, _] in words {
+        let center = x + w / 2;
+        counts[edges.iter().filter(|&&edge| edge < center).count()] += 1;
+    }
+    let needed = (words.len() as f32 * MIN_COLUMN_SHARE).ceil() as usize;
+    counts.iter().all(|&count| count >= needed)
+}
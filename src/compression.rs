@@ -0,0 +1,132 @@
+//! Sources compressed once with gzip, zstd or bzip2, like `letter.eml.gz` or `scan.pdf.zst`.
+//! The handler is the one of the name inside, `letter.eml`, or of the first bytes of the
+//! content for a name without another extension. The file is decompressed as a stream into
+//! the workspace, as the handlers need files they can seek in, and removed once it is done,
+//! so a large file takes disk space for the run of it but no memory. Its outputs are
+//! named after the inner file and written uncompressed, or with `--compress-output`
+//! compressed like the source.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+/// Types told by the start of their content, for names without an inner extension.
+const MAGIC: [(&[u8], &str); 4] = [
+    (b"%PDF", "pdf"),
+    (b"PK\x03\x04", "docx"),
+    (b"\x89PNG", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+];
+
+impl Codec {
+    /// The codec of `path` by its extension.
+    pub fn of(path: &Path) -> Option<Codec> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "gz" => Some(Codec::Gzip),
+            "zst" => Some(Codec::Zstd),
+            "bz2" => Some(Codec::Bzip2),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Zstd => "zst",
+            Codec::Bzip2 => "bz2",
+        }
+    }
+
+    fn decoder(self, file: File) -> Result<Box<dyn Read>> {
+        let file = BufReader::new(file);
+        Ok(match self {
+            Codec::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)),
+            Codec::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+            Codec::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(file)),
+        })
+    }
+
+    /// Compresses `path` into `path` with the extension of the codec appended, removing
+    /// `path`. Returns the compressed file.
+    pub fn compress(self, path: &Path) -> Result<PathBuf> {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(self.extension());
+        let compressed = PathBuf::from(name);
+        let file = BufWriter::new(File::create(&compressed)?);
+        let mut source = File::open(path)?;
+        // finishing writes the trailer, dropping an encoder would lose its errors
+        let mut file = match self {
+            Codec::Gzip => {
+                let level = flate2::Compression::default();
+                let mut encoder = flate2::write::GzEncoder::new(file, level);
+                std::io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?
+            }
+            Codec::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                std::io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?
+            }
+            Codec::Bzip2 => {
+                let level = bzip2::Compression::default();
+                let mut encoder = bzip2::write::BzEncoder::new(file, level);
+                std::io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?
+            }
+        };
+        file.flush()?;
+        std::fs::remove_file(path)?;
+        Ok(compressed)
+    }
+}
+
+/// The name of the file inside the compressed `path`, `None` if it isn't compressed or
+/// has neither an inner extension nor content of a known type.
+pub fn inner_name(path: &Path) -> Option<String> {
+    let codec = Codec::of(path)?;
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    if Path::new(&stem).extension().is_some() {
+        return Some(stem);
+    }
+    let mut start = Vec::new();
+    let decoder = codec.decoder(File::open(path).ok()?).ok()?;
+    decoder.take(8).read_to_end(&mut start).ok()?;
+    let (_, extension) = MAGIC.iter().find(|(magic, _)| start.starts_with(magic))?;
+    Some(format!("{}.{}", stem, extension))
+}
+
+/// A decompressed source, removed when dropped.
+pub struct Decompressed {
+    pub path: PathBuf,
+}
+
+impl Drop for Decompressed {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Decompresses `path` into a file named [`inner_name`] in `dir`.
+pub fn decompress(path: &Path, dir: &Path) -> Result<Decompressed> {
+    let codec = Codec::of(path).unwrap();
+    let name =
+        inner_name(path).unwrap_or_else(|| path.file_stem().unwrap().to_string_lossy().to_string());
+    let decompressed = Decompressed {
+        path: dir.join(name),
+    };
+    let mut decoder = codec.decoder(File::open(path)?)?;
+    let mut out = BufWriter::new(File::create(&decompressed.path)?);
+    std::io::copy(&mut decoder, &mut out)?;
+    out.flush()?;
+    Ok(decompressed)
+}
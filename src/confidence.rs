@@ -0,0 +1,85 @@
+//! OCR confidence of every page and file, for finding the originals worth scanning again:
+//! the mean of the confidences of the blocks with text and their 10th percentile, which a
+//! few unreadable lines on an otherwise clean page pull down. `--low-confidence-report`
+//! lists the pages whose mean is below `--rescan-below`, the worst first.
+
+use crate::ocr::OcrBlock;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Mean below which a page is listed by default, tesseract's confidences go to 100.
+pub const DEFAULT_RESCAN_BELOW: f64 = 60.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Confidence {
+    pub mean: f64,
+    pub p10: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PageConfidence {
+    pub page: usize,
+    #[serde(flatten)]
+    pub confidence: Confidence,
+    /// confidences of the blocks, for the one of the file
+    #[serde(skip)]
+    blocks: Vec<i32>,
+}
+
+/// Mean and 10th percentile, nearest rank, of `values`; `None` without any.
+fn aggregate(mut values: Vec<i32>) -> Option<Confidence> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+    let rank = (values.len() as f64 * 0.1).ceil() as usize;
+    Some(Confidence {
+        mean,
+        p10: values[rank.max(1) - 1] as f64,
+    })
+}
+
+/// Confidence of page `page` from its blocks, `None` if none of them holds text.
+pub fn page(page: usize, blocks: &[OcrBlock]) -> Option<PageConfidence> {
+    let values: Vec<i32> = blocks
+        .iter()
+        .filter(|b| !b.text.trim().is_empty())
+        .map(|b| b.confidence)
+        .collect();
+    Some(PageConfidence {
+        page,
+        confidence: aggregate(values.clone())?,
+        blocks: values,
+    })
+}
+
+/// Confidence of a file over the blocks of all its pages.
+pub fn file(pages: &[PageConfidence]) -> Option<Confidence> {
+    aggregate(
+        pages
+            .iter()
+            .flat_map(|p| p.blocks.iter().copied())
+            .collect(),
+    )
+}
+
+#[derive(Serialize)]
+pub struct LowPage {
+    pub source: PathBuf,
+    pub page: usize,
+    pub mean: f64,
+    pub p10: f64,
+}
+
+/// Writes `pages` as CSV, the lowest mean first.
+pub fn write(path: &Path, mut pages: Vec<LowPage>) -> Result<()> {
+    pages.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+    let mut writer = csv::Writer::from_path(path)?;
+    for page in pages {
+        writer.serialize(page)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
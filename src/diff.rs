@@ -0,0 +1,117 @@
+//! `diff`: what changed between the outputs of two runs, e.g. before and after changing
+//! the DPI or a glossary. Files are paired by their path relative to the target dir.
+
+use crate::charset;
+use crate::manifest::{self, MANIFEST_FILE};
+use crate::qa::QA_FILE;
+use crate::stamp;
+use anyhow::Result;
+use similar::TextDiff;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Outputs compared line by line, everything else is compared by hash.
+const TEXT_EXTENSIONS: [&str; 4] = ["txt", "tsv", "csv", "md"];
+/// Lines of context around each change in `--show-content`.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Default)]
+pub struct DirDiff {
+    /// changed files with their unified diff, `None` for binary files
+    pub changed: Vec<(PathBuf, Option<String>)>,
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub unchanged: usize,
+}
+
+/// Relative paths of the files in a target dir, without the run's own bookkeeping.
+fn outputs(dir: &Path) -> BTreeSet<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().strip_prefix(dir).unwrap().to_path_buf())
+        .filter(|p| p != Path::new(MANIFEST_FILE) && p != Path::new(QA_FILE))
+        .collect()
+}
+
+pub fn is_text(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| TEXT_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Unified diff of two text outputs, `None` if they are the same. Outputs written with
+/// `--output-encoding cp1251` are decoded as such.
+fn text_diff(path: &Path, old: &Path, new: &Path) -> Result<Option<String>> {
+    let old_bytes = std::fs::read(old)?;
+    let new_bytes = std::fs::read(new)?;
+    if old_bytes == new_bytes {
+        return Ok(None);
+    }
+    // the stamps of `--stamp-outputs` differ by the time of the run
+    let old_text = charset::decode(&old_bytes, None);
+    let new_text = charset::decode(&new_bytes, None);
+    let (old_text, new_text) = (stamp::strip(&old_text), stamp::strip(&new_text));
+    if old_text == new_text {
+        return Ok(None);
+    }
+    let name = path.display();
+    let diff = TextDiff::from_lines(old_text, new_text)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&format!("old/{}", name), &format!("new/{}", name))
+        .to_string();
+    Ok(Some(diff))
+}
+
+pub fn compare(old_dir: &Path, new_dir: &Path) -> Result<DirDiff> {
+    let old = outputs(old_dir);
+    let new = outputs(new_dir);
+    let mut diff = DirDiff {
+        removed: old.difference(&new).cloned().collect(),
+        added: new.difference(&old).cloned().collect(),
+        ..Default::default()
+    };
+    for path in old.intersection(&new) {
+        let (old_path, new_path) = (old_dir.join(path), new_dir.join(path));
+        if is_text(path) {
+            match text_diff(path, &old_path, &new_path)? {
+                Some(text) => diff.changed.push((path.clone(), Some(text))),
+                None => diff.unchanged += 1,
+            }
+        } else if manifest::hash_file(&old_path)? != manifest::hash_file(&new_path)? {
+            diff.changed.push((path.clone(), None));
+        } else {
+            diff.unchanged += 1;
+        }
+    }
+    Ok(diff)
+}
+
+impl DirDiff {
+    /// Prints one line per changed, added and removed file, with `show_content` followed
+    /// by the diff of changed text files, and the counts to stderr.
+    pub fn print(&self, show_content: bool) {
+        for (path, text) in &self.changed {
+            println!("changed\t{}", path.display());
+            if let Some(text) = text.as_ref().filter(|_| show_content) {
+                print!("{}", text);
+            }
+        }
+        for path in &self.added {
+            println!("added\t{}", path.display());
+        }
+        for path in &self.removed {
+            println!("removed\t{}", path.display());
+        }
+        eprintln!(
+            "{} changed, {} added, {} removed, {} unchanged",
+            self.changed.len(),
+            self.added.len(),
+            self.removed.len(),
+            self.unchanged
+        );
+    }
+}
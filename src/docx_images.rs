@@ -0,0 +1,67 @@
+//! `--docx-ocr-images`: the pictures of a docx, the raster parts under `word/media/`, read
+//! with OCR and translated in a section of their own after the text of the document.
+//! Vector drawings (EMF, WMF, SVG) have no pixels to read and are left out, and so are
+//! small images, which are icons, bullets and rules rather than text.
+
+use anyhow::Result;
+use image::io::Reader as ImageReader;
+use std::io::{Cursor, Read};
+
+pub const IMAGES_HEADING: &str = "## Images";
+
+const MEDIA_DIR: &str = "word/media/";
+const RASTER_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "bmp", "tif", "tiff"];
+
+/// Images smaller than this many bytes or with a side shorter than this many pixels are
+/// taken for decoration.
+const MIN_BYTES: usize = 4 * 1024;
+const MIN_SIDE: u32 = 200;
+
+pub struct MediaImage {
+    /// part name, like `word/media/image1.png`
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+/// The raster images of the docx zip `bytes` large enough to hold text, in part order.
+pub fn read(bytes: &[u8]) -> Result<Vec<MediaImage>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.starts_with(MEDIA_DIR) && is_raster(name))
+        .map(str::to_string)
+        .collect();
+    names.sort_by_key(|name| natural_key(name));
+    let mut images = Vec::new();
+    for name in names {
+        let mut part = archive.by_name(&name)?;
+        if (part.size() as usize) < MIN_BYTES {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        part.read_to_end(&mut bytes)?;
+        let dimensions = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()?
+            .into_dimensions();
+        match dimensions {
+            Ok((width, height)) if width.min(height) >= MIN_SIDE => {
+                images.push(MediaImage { name, bytes })
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("{}: not read, {}", name, e),
+        }
+    }
+    Ok(images)
+}
+
+fn is_raster(name: &str) -> bool {
+    name.rsplit_once('.').is_some_and(|(_, extension)| {
+        RASTER_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+/// `image10.png` after `image9.png`, the order Word numbers them in.
+fn natural_key(name: &str) -> (usize, String) {
+    let digits: String = name.chars().filter(char::is_ascii_digit).collect();
+    (digits.parse().unwrap_or(0), name.to_string())
+}
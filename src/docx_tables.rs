@@ -0,0 +1,261 @@
+//! Table extraction for docx files. docx-rust skips tables in `Body::text()` and doesn't
+//! know about merged or nested cells, so tables are read straight from `word/document.xml`.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read, Seek};
+use std::{fs::File, path::Path};
+
+/// Placeholder for a grid column covered by a horizontally merged cell to its left.
+pub const MERGED_LEFT: &str = "<-";
+/// Placeholder for a cell continuing a vertical merge from the row above.
+pub const MERGED_UP: &str = "^";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TableFormat {
+    /// GitHub-flavored Markdown table
+    Markdown,
+    /// plain columns padded to equal width
+    Aligned,
+    /// one row per line, cells separated by tabs
+    Tsv,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cell {
+    pub text: String,
+    /// number of grid columns the cell covers
+    pub span: usize,
+    /// cell continues a vertical merge, its text lives in the row above
+    pub merged_up: bool,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            text: String::new(),
+            span: 1,
+            merged_up: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Table {
+    pub rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    fn columns(&self) -> usize {
+        self.rows
+            .iter()
+            .map(|row| row.iter().map(|c| c.span).sum())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Expands merges into a rectangular grid so every row has the same number of columns.
+    fn grid(&self) -> Vec<Vec<String>> {
+        let columns = self.columns();
+        self.rows
+            .iter()
+            .map(|row| {
+                let mut line = Vec::with_capacity(columns);
+                for cell in row {
+                    if cell.merged_up {
+                        line.push(MERGED_UP.to_string());
+                    } else {
+                        line.push(cell.text.trim().to_string());
+                    }
+                    for _ in 1..cell.span {
+                        line.push(MERGED_LEFT.to_string());
+                    }
+                }
+                line.resize(columns, String::new());
+                line
+            })
+            .collect()
+    }
+
+    pub fn render(&self, format: TableFormat) -> String {
+        let grid = self.grid();
+        let mut out = String::new();
+        match format {
+            TableFormat::Markdown => {
+                for (index, row) in grid.iter().enumerate() {
+                    let cells: Vec<_> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+                    out.push_str(&format!("| {} |\n", cells.join(" | ")));
+                    if index == 0 {
+                        let rule = vec!["---"; row.len()];
+                        out.push_str(&format!("| {} |\n", rule.join(" | ")));
+                    }
+                }
+            }
+            TableFormat::Aligned => {
+                let mut widths = vec![0; self.columns()];
+                for row in &grid {
+                    for (width, cell) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(cell.chars().count());
+                    }
+                }
+                for row in &grid {
+                    let mut line = String::new();
+                    for (cell, width) in row.iter().zip(&widths) {
+                        line.push_str(cell);
+                        line.push_str(&" ".repeat(width - cell.chars().count() + 2));
+                    }
+                    out.push_str(line.trim_end());
+                    out.push('\n');
+                }
+            }
+            TableFormat::Tsv => {
+                for row in &grid {
+                    let cells: Vec<_> = row.iter().map(|c| c.replace(['\t', '\n'], " ")).collect();
+                    out.push_str(&cells.join("\t"));
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct TableBuilder {
+    rows: Vec<Vec<Cell>>,
+    row: Vec<Cell>,
+    cell: Option<Cell>,
+}
+
+impl TableBuilder {
+    /// Flattens a nested table into a single line of text for its parent cell.
+    fn flatten(self) -> String {
+        self.rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| c.text.trim())
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes()
+        .filter_map(|a| a.ok())
+        .find(|a| a.key.as_ref() == name)
+        .map(|a| String::from_utf8_lossy(&a.value).to_string())
+}
+
+fn current_cell(stack: &mut [TableBuilder]) -> Option<&mut Cell> {
+    stack.last_mut().and_then(|table| table.cell.as_mut())
+}
+
+fn cell_text(stack: &mut [TableBuilder]) -> Option<&mut String> {
+    current_cell(stack).map(|cell| &mut cell.text)
+}
+
+/// Returns the top-level tables of the document in body order. Tables nested inside a
+/// cell are flattened into that cell's text.
+pub fn read_tables(file: &Path) -> Result<Vec<Table>> {
+    read_tables_from(BufReader::new(File::open(file)?))
+}
+
+/// [`read_tables`] of a docx already read or decrypted.
+pub fn read_tables_from(docx: impl Read + Seek) -> Result<Vec<Table>> {
+    let mut archive = zip::ZipArchive::new(docx)?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")?
+        .read_to_string(&mut xml)?;
+    parse_tables(&xml)
+}
+
+fn parse_tables(xml: &str) -> Result<Vec<Table>> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<TableBuilder> = Vec::new();
+    let mut tables = Vec::new();
+    let mut in_text = false;
+    loop {
+        let event = reader.read_event()?;
+        let is_start = matches!(event, Event::Start(_));
+        match &event {
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                b"w:tbl" if is_start => stack.push(TableBuilder::default()),
+                b"w:tc" => {
+                    if let Some(table) = stack.last_mut() {
+                        table.cell = Some(Cell::default());
+                    }
+                }
+                b"w:t" => in_text = is_start,
+                b"w:gridSpan" => {
+                    if let Some(cell) = current_cell(&mut stack) {
+                        cell.span = attribute(e, b"w:val")
+                            .and_then(|v| v.parse().ok())
+                            .unwrap_or(1)
+                            .max(1);
+                    }
+                }
+                b"w:vMerge" => {
+                    if let Some(cell) = current_cell(&mut stack) {
+                        cell.merged_up = attribute(e, b"w:val").as_deref() != Some("restart");
+                    }
+                }
+                b"w:tab" | b"w:br" => {
+                    if let Some(text) = cell_text(&mut stack) {
+                        text.push(' ');
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(t) if in_text => {
+                if let Some(text) = cell_text(&mut stack) {
+                    text.push_str(&t.unescape()?);
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => {
+                    if let Some(text) = cell_text(&mut stack) {
+                        if !text.is_empty() && !text.ends_with(' ') {
+                            text.push(' ');
+                        }
+                    }
+                }
+                b"w:tc" => {
+                    if let Some(table) = stack.last_mut() {
+                        let cell = table.cell.take().unwrap_or_default();
+                        table.row.push(cell);
+                    }
+                }
+                b"w:tr" => {
+                    if let Some(table) = stack.last_mut() {
+                        let row = std::mem::take(&mut table.row);
+                        table.rows.push(row);
+                    }
+                }
+                b"w:tbl" => {
+                    if let Some(table) = stack.pop() {
+                        if stack.is_empty() {
+                            tables.push(Table { rows: table.rows });
+                        } else if let Some(text) = cell_text(&mut stack) {
+                            text.push_str(&table.flatten());
+                            text.push(' ');
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(tables)
+}
@@ -0,0 +1,74 @@
+//! Translations that are their source. A LibreTranslate without the model of a pair can
+//! answer every request with its input, and the run then looks fine while nothing is
+//! translated. If most of the first segments sent come back unchanged, the run stops
+//! after the file in progress; the share of each file goes into the report either way.
+
+use crate::error::{error, ErrorKind};
+use anyhow::anyhow;
+
+pub const ECHO_ERROR: &str =
+    "the translation server appears to be echoing its input, check the language pair and its model";
+
+/// Segments the run is judged by, and the share of them coming back unchanged that stops it.
+const WINDOW: usize = 50;
+const MAX_SHARE: f64 = 0.8;
+
+#[derive(Default)]
+pub struct EchoCheck {
+    /// segments translated in the run, counted up to [`WINDOW`], and echoed of those
+    run_segments: usize,
+    run_echoed: usize,
+    /// set once the first [`WINDOW`] segments were mostly echoed
+    tripped: bool,
+    /// segments translated and echoed since [`EchoCheck::take_file`]
+    file_segments: usize,
+    file_echoed: usize,
+    /// segments echoed in the run
+    pub echoed: usize,
+}
+
+/// Whether `output` is `text`, but for whitespace.
+fn same(text: &str, output: &str) -> bool {
+    text.split_whitespace().eq(output.split_whitespace())
+}
+
+impl EchoCheck {
+    pub fn record(&mut self, text: &str, output: &str) {
+        let echoed = same(text, output);
+        self.file_segments += 1;
+        if echoed {
+            self.file_echoed += 1;
+            self.echoed += 1;
+        }
+        if self.run_segments < WINDOW {
+            self.run_segments += 1;
+            self.run_echoed += usize::from(echoed);
+            if self.run_segments == WINDOW && self.run_echoed as f64 >= MAX_SHARE * WINDOW as f64 {
+                self.tripped = true;
+                eprintln!(
+                    "{} of the first {} segments came back unchanged",
+                    self.run_echoed, WINDOW
+                );
+            }
+        }
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// The error segments fail with once it tripped, instead of being sent.
+    pub fn error(&self) -> anyhow::Error {
+        error(ErrorKind::BackendUnavailable, anyhow!(ECHO_ERROR))
+    }
+
+    /// Share of the segments of the file just done that came back unchanged, `None` if
+    /// none was translated. Starts counting the next file.
+    pub fn take_file(&mut self) -> Option<f64> {
+        let share =
+            (self.file_segments > 0).then(|| self.file_echoed as f64 / self.file_segments as f64);
+        self.file_segments = 0;
+        self.file_echoed = 0;
+        share
+    }
+}
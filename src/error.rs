@@ -0,0 +1,96 @@
+//! Kinds of errors a run or a file can fail with. Each kind a run can end with has its own
+//! exit code, and the report names the kind of every failed file.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// config.toml, a directory override or the command line is wrong
+    ConfigError,
+    /// the translation server can't be reached
+    BackendUnavailable,
+    /// tesseract or its language data is missing
+    OcrInitError,
+    /// a source or target file or dir can't be read or written
+    IoError,
+    /// a source file is broken
+    InputError,
+    /// a source file is password-protected and no or the wrong password was given
+    EncryptedInput,
+    /// a source file is of another format than its extension says, like a `.doc` named
+    /// `.docx`
+    FormatMismatch,
+    /// a source file ends early, like an interrupted copy or download
+    TruncatedInput,
+    /// the run completed but some files failed
+    PartialFailure,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::PartialFailure
+            | ErrorKind::InputError
+            | ErrorKind::EncryptedInput
+            | ErrorKind::FormatMismatch
+            | ErrorKind::TruncatedInput => 2,
+            ErrorKind::ConfigError => 3,
+            ErrorKind::BackendUnavailable => 4,
+            ErrorKind::OcrInitError => 5,
+            ErrorKind::IoError => 6,
+        }
+    }
+
+    /// Name of the kind as in the report, e.g. `input_error`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::ConfigError => "config_error",
+            ErrorKind::BackendUnavailable => "backend_unavailable",
+            ErrorKind::OcrInitError => "ocr_init_error",
+            ErrorKind::IoError => "io_error",
+            ErrorKind::InputError => "input_error",
+            ErrorKind::EncryptedInput => "encrypted_input",
+            ErrorKind::FormatMismatch => "format_mismatch",
+            ErrorKind::TruncatedInput => "truncated_input",
+            ErrorKind::PartialFailure => "partial_failure",
+        }
+    }
+
+    /// The kind `error` was raised with, else `IoError` if an I/O error caused it and
+    /// `InputError` otherwise.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.chain().find_map(|e| e.downcast_ref::<Error>()) {
+            return error.kind;
+        }
+        if error.chain().any(|e| e.is::<std::io::Error>()) {
+            return ErrorKind::IoError;
+        }
+        ErrorKind::InputError
+    }
+}
+
+/// An error with its [`ErrorKind`].
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    source: anyhow::Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// `error` tagged with `kind`.
+pub fn error(kind: ErrorKind, error: impl Into<anyhow::Error>) -> anyhow::Error {
+    Error {
+        kind,
+        source: error.into(),
+    }
+    .into()
+}
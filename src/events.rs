@@ -0,0 +1,104 @@
+//! Progress of a run as a stream of events, for a front end to follow it while it goes.
+//! Every [`subscribe`]r gets every event emitted after it subscribed; while nobody does,
+//! emitting is a lock of an empty list. `--log-format json` writes them all as JSON
+//! lines from such a subscriber. A [`CancellationToken`] stops the run after the file in
+//! progress.
+
+use crate::{FileReport, Summary};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+static SUBSCRIBERS: Mutex<Vec<UnboundedSender<Event>>> = Mutex::new(Vec::new());
+/// threads of the subscribers started by [`consume`], joined by [`close`]
+static CONSUMERS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Written as JSON lines by `--log-format json`, with the variant in an `event` field.
+#[derive(Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    FileStarted {
+        source: PathBuf,
+        handler: String,
+    },
+    /// a page or image was recognized or taken from the OCR cache
+    #[serde(rename = "page")]
+    PageOcrComplete {
+        source: PathBuf,
+        /// counting from 1
+        page: usize,
+        chars: usize,
+        /// tesseract's confidence over the page, weighted by the length of the blocks
+        confidence: i32,
+    },
+    #[serde(rename = "segment")]
+    SegmentTranslated {
+        chars: usize,
+        /// the server failed on it, a placeholder was written
+        failed: bool,
+    },
+    /// a file is done, failed or was skipped, with its entry in the report
+    #[serde(rename = "file")]
+    FileFinished(Box<FileReport>),
+    #[serde(rename = "summary")]
+    RunFinished(Box<Summary>),
+}
+
+/// The events of the run from now on. The stream ends with [`close`].
+pub fn subscribe() -> impl Stream<Item = Event> + Send + Unpin {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((event, receiver))
+    })
+    .boxed()
+}
+
+pub fn emit(event: Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+/// Handles every event with `f` on a thread of its own, so the work of the run never
+/// waits for it. [`close`] waits until it has seen the last one.
+pub fn consume(mut f: impl FnMut(Event) + Send + 'static) {
+    let mut events = subscribe();
+    let runtime = tokio::runtime::Handle::current();
+    let consumer = std::thread::spawn(move || {
+        runtime.block_on(async {
+            while let Some(event) = events.next().await {
+                f(event);
+            }
+        })
+    });
+    CONSUMERS.lock().unwrap().push(consumer);
+}
+
+/// Ends the streams of all subscribers and waits for the consumers. Called before the
+/// process exits.
+pub fn close() {
+    SUBSCRIBERS.lock().unwrap().clear();
+    let consumers = std::mem::take(&mut *CONSUMERS.lock().unwrap());
+    for consumer in consumers {
+        let _ = consumer.join();
+    }
+}
+
+/// Stops a run once the file in progress is done. Clones share the state.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
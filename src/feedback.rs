@@ -0,0 +1,47 @@
+//! Guards against translating the outputs of an earlier run again, e.g. when the source
+//! dir of a run points at the target dir of the last one.
+
+use crate::manifest::{self, MANIFEST_FILE};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Why a run from `source_dir` into `target_dir` would feed on its own outputs, `None` if
+/// it wouldn't. `manifests` are the manifest files found in the source dir.
+pub fn problem(source_dir: &Path, target_dir: &Path, manifests: &[PathBuf]) -> Option<String> {
+    // symlinks and `..` are resolved, both dirs exist by now
+    let source = std::fs::canonicalize(source_dir).ok()?;
+    let target = std::fs::canonicalize(target_dir).ok()?;
+    if source == target {
+        return Some("source and target dir are the same".to_string());
+    }
+    if target.starts_with(&source) {
+        return Some("the target dir is inside the source dir".to_string());
+    }
+    if source.starts_with(&target) {
+        return Some("the source dir is inside the target dir".to_string());
+    }
+    manifests.first().map(|path| {
+        format!(
+            "{} in the source dir is the {} of an earlier run",
+            path.display(),
+            MANIFEST_FILE
+        )
+    })
+}
+
+/// Canonical paths of every output listed in `manifests`.
+pub fn own_outputs(manifests: &[PathBuf]) -> Result<HashSet<PathBuf>> {
+    let mut outputs = HashSet::new();
+    for path in manifests {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for entry in manifest::read(path)? {
+            for output in entry.outputs {
+                if let Ok(output) = std::fs::canonicalize(dir.join(output.path)) {
+                    outputs.insert(output);
+                }
+            }
+        }
+    }
+    Ok(outputs)
+}
@@ -0,0 +1,307 @@
+//! Drops OCR segments that are most likely speckle noise before they cost a request.
+
+use crate::ocr::OcrBlock;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const DEFAULT_MIN_LEN: usize = 3;
+pub const DEFAULT_MIN_LETTER_RATIO: f32 = 0.25;
+pub const DEFAULT_MIN_HEIGHT_RATIO: f32 = 0.3;
+
+/// Blocks read at least this confidently are kept whatever their size.
+const SURE_CONFIDENCE: i32 = 90;
+/// Largest area of a block per character, in squares of the text height of its page; a
+/// line of text takes about one.
+const MAX_AREA_PER_CHAR: f32 = 50.0;
+
+/// Languages written in Cyrillic, for `min_script_ratio`.
+const CYRILLIC_LANGS: [&str; 7] = ["ru", "uk", "be", "kk", "bg", "sr", "mk"];
+
+#[derive(Clone, Debug)]
+pub struct SegmentFilter {
+    /// minimum number of characters after trimming
+    pub min_len: usize,
+    /// minimum share of letters among the non-whitespace characters
+    pub min_letter_ratio: f32,
+    /// minimum share of letters in the source language's script among all letters
+    pub min_script_ratio: Option<f32>,
+    /// minimum height of the words of a block relative to those of its page; `None` with
+    /// `--no-geometry-filter`, which also keeps boxes far too large for their text
+    pub min_height_ratio: Option<f32>,
+    /// print every segment that is dropped
+    pub verbose: bool,
+}
+
+impl Default for SegmentFilter {
+    fn default() -> Self {
+        SegmentFilter {
+            min_len: DEFAULT_MIN_LEN,
+            min_letter_ratio: DEFAULT_MIN_LETTER_RATIO,
+            min_script_ratio: None,
+            min_height_ratio: Some(DEFAULT_MIN_HEIGHT_RATIO),
+            verbose: false,
+        }
+    }
+}
+
+fn is_cyrillic(lang: &str) -> bool {
+    CYRILLIC_LANGS.contains(&lang)
+}
+
+/// Whether the two languages are written in the same script, as far as this module and
+/// `scripts` know.
+pub fn same_script(a: &str, b: &str, scripts: &BTreeMap<String, Script>) -> bool {
+    Script::of(a, scripts) == Script::of(b, scripts)
+}
+
+/// Script a language is written in, set per language under `[scripts]` in config.toml.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Script {
+    Cyrillic,
+    Latin,
+    Greek,
+    /// every letter counts
+    Any,
+}
+
+impl Script {
+    /// The script of `lang`: from `overrides` if listed there, else Cyrillic for the
+    /// Cyrillic languages and Latin for the rest.
+    pub fn of(lang: &str, overrides: &BTreeMap<String, Script>) -> Script {
+        match overrides.get(lang) {
+            Some(script) => *script,
+            None if is_cyrillic(lang) => Script::Cyrillic,
+            None => Script::Latin,
+        }
+    }
+
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            Script::Cyrillic => ('\u{0400}'..='\u{052F}').contains(&c),
+            Script::Latin => c.is_ascii_alphabetic() || ('\u{00C0}'..='\u{024F}').contains(&c),
+            Script::Greek => ('\u{0370}'..='\u{03FF}').contains(&c),
+            Script::Any => c.is_alphabetic(),
+        }
+    }
+
+    /// Whether `text` has at least one letter in this script.
+    pub fn found_in(self, text: &str) -> bool {
+        text.chars().any(|c| c.is_alphabetic() && self.contains(c))
+    }
+}
+
+fn ratio(part: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f32 / total as f32
+    }
+}
+
+/// Height of the text of a page with `blocks`: the median word height of its characters,
+/// 0 if not known.
+pub fn text_height(blocks: &[OcrBlock]) -> i32 {
+    let mut heights: Vec<(i32, usize)> = blocks
+        .iter()
+        .filter(|b| b.text_height > 0)
+        .map(|b| {
+            (
+                b.text_height,
+                b.text.chars().filter(|c| !c.is_whitespace()).count(),
+            )
+        })
+        .collect();
+    heights.sort();
+    let half = heights.iter().map(|(_, chars)| chars).sum::<usize>() / 2;
+    let mut counted = 0;
+    for (height, chars) in heights {
+        counted += chars;
+        if counted > half {
+            return height;
+        }
+    }
+    0
+}
+
+/// Share of the letters of `text` written in the script of `lang`, 0 without letters.
+pub fn script_ratio(text: &str, lang: &str, scripts: &BTreeMap<String, Script>) -> f32 {
+    let letters: Vec<char> = text.chars().filter(|c| c.is_alphabetic()).collect();
+    let lang = Script::of(lang, scripts);
+    let script = letters.iter().filter(|&&c| lang.contains(c)).count();
+    ratio(script, letters.len())
+}
+
+impl SegmentFilter {
+    /// Returns why `text` shouldn't be translated, `None` if it should.
+    pub fn reject(
+        &self,
+        text: &str,
+        source_lang: &str,
+        scripts: &BTreeMap<String, Script>,
+    ) -> Option<String> {
+        let text = text.trim();
+        let chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if text.chars().count() < self.min_len {
+            return Some(format!("shorter than {} characters", self.min_len));
+        }
+        let letters: Vec<char> = chars
+            .iter()
+            .copied()
+            .filter(|c| c.is_alphabetic())
+            .collect();
+        let letter_ratio = ratio(letters.len(), chars.len());
+        if letter_ratio < self.min_letter_ratio {
+            return Some(format!("{:.0}% letters", letter_ratio * 100.0));
+        }
+        if let Some(min_script_ratio) = self.min_script_ratio {
+            let script_ratio = script_ratio(text, source_lang, scripts);
+            if script_ratio < min_script_ratio {
+                return Some(format!(
+                    "{:.0}% letters in the {} script",
+                    script_ratio * 100.0,
+                    source_lang
+                ));
+            }
+        }
+        None
+    }
+
+    /// Returns why `block` of a page with text `page_height` pixels high is noise rather
+    /// than text, `None` if it isn't.
+    pub fn reject_block(&self, block: &OcrBlock, page_height: i32) -> Option<String> {
+        let min_height_ratio = self.min_height_ratio?;
+        if page_height <= 0 || block.confidence >= SURE_CONFIDENCE {
+            return None;
+        }
+        if block.text_height > 0
+            && (block.text_height as f32) < min_height_ratio * page_height as f32
+        {
+            return Some(format!(
+                "{} px high, the text of the page {} px",
+                block.text_height, page_height
+            ));
+        }
+        let chars = block.text.chars().filter(|c| !c.is_whitespace()).count();
+        let [_, _, w, h] = block.bbox;
+        let area = w as f32 * h as f32;
+        if area / chars.max(1) as f32 > MAX_AREA_PER_CHAR * (page_height * page_height) as f32 {
+            return Some(format!("{}x{} px box for {} characters", w, h, chars));
+        }
+        None
+    }
+
+    /// Whether to translate a segment of `file`; dropped ones are printed with `--verbose`.
+    pub fn keep(
+        &self,
+        file: &Path,
+        text: &str,
+        source_lang: &str,
+        scripts: &BTreeMap<String, Script>,
+    ) -> bool {
+        self.report(file, text, self.reject(text, source_lang, scripts))
+    }
+
+    /// [`SegmentFilter::keep`] for the geometry of `block`.
+    pub fn keep_block(&self, file: &Path, block: &OcrBlock, page_height: i32) -> bool {
+        self.report(file, &block.text, self.reject_block(block, page_height))
+    }
+
+    /// Whether a segment without a `reason` to drop it is kept, printing it otherwise.
+    fn report(&self, file: &Path, text: &str, reason: Option<String>) -> bool {
+        match reason {
+            None => true,
+            Some(reason) => {
+                if self.verbose {
+                    eprintln!("{}: dropped {:?}, {}", file.display(), text.trim(), reason);
+                }
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(text: &str, bbox: [i32; 4], confidence: i32, text_height: i32) -> OcrBlock {
+        OcrBlock {
+            text: text.to_string(),
+            bbox,
+            confidence,
+            text_height,
+        }
+    }
+
+    #[test]
+    fn the_text_height_is_the_median_of_the_characters() {
+        let blocks = [
+            block(".", [0, 0, 3, 3], 20, 3),
+            block("Обычный текст", [0, 0, 400, 30], 80, 30),
+            block("Ещё строка", [0, 40, 300, 32], 80, 32),
+            block("без высоты", [0, 80, 300, 30], 80, 0),
+        ];
+        assert_eq!(text_height(&blocks), 30);
+        assert_eq!(text_height(&blocks[..1]), 3);
+        assert_eq!(text_height(&[]), 0);
+    }
+
+    #[test]
+    fn speckles_far_smaller_than_the_text_are_dropped() {
+        let filter = SegmentFilter::default();
+        let speckle = block("ккк", [10, 10, 12, 5], 40, 5);
+        assert!(filter.reject_block(&speckle, 30).is_some());
+        // read for sure, kept whatever its size
+        let footnote = block("сноска", [10, 10, 40, 5], 95, 5);
+        assert_eq!(filter.reject_block(&footnote, 30), None);
+        let line = block("Обычный текст", [0, 0, 400, 30], 80, 28);
+        assert_eq!(filter.reject_block(&line, 30), None);
+    }
+
+    #[test]
+    fn a_giant_box_with_a_few_characters_is_dropped() {
+        let filter = SegmentFilter::default();
+        let stain = block("ab", [0, 0, 1000, 1000], 50, 0);
+        assert!(filter.reject_block(&stain, 30).is_some());
+        let heading = block("Акт", [0, 0, 300, 90], 50, 90);
+        assert_eq!(filter.reject_block(&heading, 30), None);
+    }
+
+    #[test]
+    fn the_geometry_filter_can_be_turned_off() {
+        let filter = SegmentFilter {
+            min_height_ratio: None,
+            ..SegmentFilter::default()
+        };
+        let speckle = block("ккк", [10, 10, 12, 5], 40, 5);
+        assert_eq!(filter.reject_block(&speckle, 30), None);
+        // a page without known heights filters nothing either
+        assert_eq!(SegmentFilter::default().reject_block(&speckle, 0), None);
+    }
+
+    #[test]
+    fn short_and_letterless_segments_are_rejected() {
+        let filter = SegmentFilter::default();
+        let scripts = BTreeMap::new();
+        assert!(filter.reject(" ab ", "ru", &scripts).is_some());
+        assert!(filter.reject("|||---..", "ru", &scripts).is_some());
+        assert_eq!(filter.reject("Акт № 5", "ru", &scripts), None);
+    }
+
+    #[test]
+    fn segments_in_another_script_are_rejected() {
+        let filter = SegmentFilter {
+            min_script_ratio: Some(0.5),
+            ..SegmentFilter::default()
+        };
+        let mut scripts = BTreeMap::new();
+        assert!(filter.reject("Hello world", "ru", &scripts).is_some());
+        assert_eq!(filter.reject("Привет, world", "ru", &scripts), None);
+        scripts.insert("ru".to_string(), Script::Any);
+        assert_eq!(filter.reject("Hello world", "ru", &scripts), None);
+        assert!(same_script("ru", "uk", &BTreeMap::new()));
+        assert!(!same_script("ru", "en", &BTreeMap::new()));
+    }
+}
@@ -0,0 +1,74 @@
+//! Sanity checks on what the server returns. LibreTranslate now and then answers with the
+//! input unchanged, an empty string or a truncated text; such translations are retried
+//! once and then kept with [`SUSPECT_MARKER`] for review.
+
+use crate::filter::{self, Script};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Thresholds of the checks, the `[guardrails]` table of config.toml.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Guardrails {
+    /// shortest translation allowed, relative to the input length
+    pub min_length_ratio: f32,
+    /// longest translation allowed, relative to the input length
+    pub max_length_ratio: f32,
+    /// the length ratio is only checked for inputs with at least this many characters
+    pub min_length: usize,
+    /// largest share of letters still in the source script, if the target uses another one
+    pub max_source_script_ratio: f32,
+}
+
+impl Default for Guardrails {
+    fn default() -> Self {
+        Guardrails {
+            min_length_ratio: 0.3,
+            max_length_ratio: 3.0,
+            min_length: 20,
+            max_source_script_ratio: 0.5,
+        }
+    }
+}
+
+impl Guardrails {
+    /// Returns why `output` doesn't look like a translation of `input`, `None` if it does.
+    pub fn check(
+        &self,
+        input: &str,
+        output: &str,
+        source_lang: &str,
+        target_lang: &str,
+        scripts: &BTreeMap<String, Script>,
+    ) -> Option<String> {
+        let (input, output) = (input.trim(), output.trim());
+        if input.is_empty() {
+            return None;
+        }
+        if output.is_empty() {
+            return Some("empty translation".to_string());
+        }
+        let has_letters = input.chars().any(char::is_alphabetic);
+        if has_letters && input.contains(char::is_whitespace) && output == input {
+            return Some("returned unchanged".to_string());
+        }
+        let input_len = input.chars().count();
+        if input_len >= self.min_length {
+            let ratio = output.chars().count() as f32 / input_len as f32;
+            if ratio < self.min_length_ratio || ratio > self.max_length_ratio {
+                return Some(format!("{:.2} times as long as the input", ratio));
+            }
+        }
+        if !filter::same_script(source_lang, target_lang, scripts) {
+            let ratio = filter::script_ratio(output, source_lang, scripts);
+            if ratio > self.max_source_script_ratio {
+                return Some(format!(
+                    "{:.0}% of the letters still in the {} script",
+                    ratio * 100.0,
+                    source_lang
+                ));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,136 @@
+//! What a handler makes of a file before anything is translated: its parts in reading
+//! order. Handlers only build a [`Document`]; [`Document::translate`] sends it through the
+//! backend, consecutive segments together, and writes it with the separators, provenance
+//! and failure markers of the [`SegmentWriter`].
+
+use super::{overlay_blocks, translate_html, translate_segments, translate_table, translate_toc};
+use crate::backend::Backend;
+use crate::docx_tables::{Table, TableFormat};
+use crate::output::{self, Origin, Segment, SegmentWriter};
+use crate::toc::Toc;
+use anyhow::Result;
+
+/// What a segment is, for the stages that treat kinds differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// docx sentence or email paragraph
+    Paragraph,
+    /// text block of a scan, painted over in the overlay
+    Block,
+    /// handwritten note or rotated text of a scan, not painted over
+    Aside,
+}
+
+pub enum Part {
+    Segment(Segment, Kind),
+    /// cells translated one by one, failed ones keep their text
+    Table(Table, TableFormat),
+    /// table of contents or index, its entries translated like cells
+    Toc(Toc),
+    /// written the same into the source text and the translation, like headings and
+    /// placeholders
+    Verbatim(String),
+    /// block of an HTML body sent as markup, see [`crate::html`]
+    Html(String),
+    /// email header field `name: value, value`: the first half of each value is translated
+    /// alone and kept if that fails, the second half copied, like the address after a name
+    Field {
+        name: String,
+        values: Vec<(String, String)>,
+    },
+}
+
+#[derive(Default)]
+pub struct Document {
+    pub parts: Vec<Part>,
+}
+
+impl Document {
+    pub fn push_segment(&mut self, text: impl Into<String>, origin: Option<Origin>, kind: Kind) {
+        self.parts
+            .push(Part::Segment(Segment::new(text, origin), kind));
+    }
+
+    /// Translates and writes every part. Returns the box and translation of every block
+    /// translated, for the overlay.
+    pub async fn translate(
+        &self,
+        backend: &mut Backend,
+        writer: &mut SegmentWriter,
+    ) -> Result<Vec<([i32; 4], String)>> {
+        let mut blocks = Vec::new();
+        let mut pending: Vec<(Segment, Kind)> = Vec::new();
+        let mut pending_html: Vec<&str> = Vec::new();
+        let mut parts = self.parts.iter().peekable();
+        while let Some(part) = parts.next() {
+            if !matches!(part, Part::Segment(..)) {
+                flush(backend, &mut pending, writer, &mut blocks).await?;
+            }
+            if !matches!(part, Part::Html(..)) && !pending_html.is_empty() {
+                translate_html(backend, &std::mem::take(&mut pending_html), writer).await?;
+            }
+            match part {
+                Part::Segment(segment, kind) => pending.push((segment.clone(), *kind)),
+                Part::Html(html) => pending_html.push(html),
+                Part::Table(table, format) => {
+                    translate_table(backend, table.clone(), *format, writer).await?
+                }
+                Part::Toc(toc) => translate_toc(backend, toc, writer).await?,
+                Part::Verbatim(text) => writer.write_verbatim(text, text)?,
+                Part::Field { name, values } => {
+                    let mut source = Vec::new();
+                    let mut translated = Vec::new();
+                    for (text, rest) in values {
+                        let output = match text.as_str() {
+                            "" => String::new(),
+                            text => output::or_source(backend.translate(text).await.ok(), text),
+                        };
+                        source.push(format!("{}{}", text, rest));
+                        translated.push(format!("{}{}", output, rest));
+                    }
+                    // the fields stay together, a blank line after the last one
+                    let separator = match parts.peek() {
+                        Some(Part::Field { .. }) => "\n",
+                        _ => "\n\n",
+                    };
+                    writer.write_separated(
+                        &format!("{}: {}", name, source.join(", ")),
+                        &format!("{}: {}", name, translated.join(", ")),
+                        separator,
+                    )?;
+                }
+            }
+        }
+        flush(backend, &mut pending, writer, &mut blocks).await?;
+        if !pending_html.is_empty() {
+            translate_html(backend, &pending_html, writer).await?;
+        }
+        Ok(blocks)
+    }
+}
+
+/// Translates and writes the segments gathered so far, adding the blocks among them to
+/// `blocks`.
+async fn flush(
+    backend: &mut Backend,
+    pending: &mut Vec<(Segment, Kind)>,
+    writer: &mut SegmentWriter,
+    blocks: &mut Vec<([i32; 4], String)>,
+) -> Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let (segments, kinds): (Vec<Segment>, Vec<Kind>) = std::mem::take(pending).into_iter().unzip();
+    let translated = translate_segments(backend, &segments, writer).await?;
+    let (boxes, translated): (Vec<[i32; 4]>, Vec<Option<String>>) = segments
+        .iter()
+        .zip(kinds)
+        .zip(translated)
+        .filter_map(|((segment, kind), translated)| match segment.origin {
+            Some(Origin::Block { bbox, .. }) if kind == Kind::Block => Some((bbox, translated)),
+            _ => None,
+        })
+        .unzip();
+    blocks.extend(overlay_blocks(&boxes, translated));
+    Ok(())
+}
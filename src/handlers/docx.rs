@@ -0,0 +1,346 @@
+use super::document::{Document, Kind, Part};
+use super::{translate_ocr, Dependency, FileHandler, HandlerOutput};
+use crate::docx_images::{self, MediaImage, IMAGES_HEADING};
+use crate::docx_tables;
+use crate::error::{error, ErrorKind};
+use crate::ocr::{self, OcrPage};
+use crate::office_crypto::{self, CompoundFile, OLE_MAGIC};
+use crate::output::{Origin, SegmentWriter};
+use crate::translator::{ocr_pool, Translator};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use docx_rust::{document::BodyContent, DocxFile};
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::DynamicImage;
+use std::io::Cursor;
+use std::path::Path;
+
+pub struct DocxHandler;
+
+/// Parts gathered before they are translated and written, so the segments of a long
+/// document are never held all at once.
+const CHUNK_PARTS: usize = 200;
+
+#[async_trait(?Send)]
+impl FileHandler for DocxHandler {
+    fn name(&self) -> &'static str {
+        "docx"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx"]
+    }
+
+    fn dependencies(&self) -> &'static [Dependency] {
+        &[]
+    }
+
+    async fn handle(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        out: &Path,
+    ) -> Result<HandlerOutput> {
+        let bytes = load(file, translator.docx_password.as_deref())?;
+        let docx_file =
+            DocxFile::from_reader(Cursor::new(&bytes)).map_err(|f| anyhow!("{:?}", f))?;
+        let docx = docx_file.parse().map_err(|f| anyhow!("{:?}", f))?;
+
+        let mut new_txt_file = file.file_name().unwrap().to_string_lossy().to_string();
+        new_txt_file.push_str(".txt");
+        let mut writer = SegmentWriter::create(
+            out.join(new_txt_file),
+            &translator.text_format,
+            translator.keep_source_text,
+        )?;
+        // cloned, the backend translates the chunks in between
+        let abbreviations = translator
+            .backend
+            .abbreviations
+            .get(&translator.backend.source_lang)
+            .cloned()
+            .unwrap_or_default();
+        let mut document = Document::default();
+        let mut tables = docx_tables::read_tables_from(Cursor::new(&bytes))?.into_iter();
+        // the paragraphs since the end of the last sentence, with where each one starts
+        // and its number in the document
+        let mut text = String::new();
+        let mut starts = Vec::new();
+        let mut paragraphs = 0;
+        let mut has_text = false;
+        for content in &docx.document.body.content {
+            if translator.preview.is_some() && has_text {
+                break;
+            }
+            match content {
+                BodyContent::Paragraph(para) => {
+                    if paragraphs > 0 {
+                        text.push_str("\r\n");
+                    }
+                    paragraphs += 1;
+                    starts.push((text.len(), paragraphs));
+                    let paragraph = para.text();
+                    has_text |= !paragraph.trim().is_empty();
+                    text.push_str(&paragraph);
+                    push_complete(&mut document, &mut text, &mut starts, &abbreviations);
+                }
+                BodyContent::Table(_) => {
+                    push_sentences(&mut document, &text, &starts, &abbreviations);
+                    text.clear();
+                    starts.clear();
+                    if let Some(table) = tables.next() {
+                        document
+                            .parts
+                            .push(Part::Table(table, translator.table_format));
+                    }
+                }
+                _ => (),
+            }
+            if document.parts.len() >= CHUNK_PARTS {
+                std::mem::take(&mut document)
+                    .translate(&mut translator.backend, &mut writer)
+                    .await?;
+            }
+        }
+        push_sentences(&mut document, &text, &starts, &abbreviations);
+        document
+            .translate(&mut translator.backend, &mut writer)
+            .await?;
+        let mut output = HandlerOutput::default();
+        if translator.docx_ocr_images && translator.preview.is_none() {
+            translate_images(translator, file, &bytes, &mut writer, &mut output).await?;
+        }
+        output.add(writer);
+        Ok(output)
+    }
+
+    async fn excerpt(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        max_chars: usize,
+    ) -> Result<Option<String>> {
+        let bytes = load(file, translator.docx_password.as_deref())?;
+        let docx_file =
+            DocxFile::from_reader(Cursor::new(&bytes)).map_err(|f| anyhow!("{:?}", f))?;
+        let docx = docx_file.parse().map_err(|f| anyhow!("{:?}", f))?;
+        let mut text = String::new();
+        for content in &docx.document.body.content {
+            if text.chars().count() >= max_chars {
+                break;
+            }
+            if let BodyContent::Paragraph(para) = content {
+                text.push_str(&para.text());
+                text.push('\n');
+            }
+        }
+        Ok(Some(text))
+    }
+}
+
+/// Reads the images of the docx `bytes` with OCR and translates them under a heading of
+/// their own, each under its part name. The blocks of an image have its number as page.
+async fn translate_images(
+    translator: &mut Translator,
+    file: &Path,
+    bytes: &[u8],
+    writer: &mut SegmentWriter,
+    output: &mut HandlerOutput,
+) -> Result<()> {
+    let images = docx_images::read(bytes)?;
+    if images.is_empty() {
+        return Ok(());
+    }
+    if let Err(e) = ocr_pool(&translator.ocr) {
+        eprintln!("{}: images not read, {}", file.display(), e);
+        return Ok(());
+    }
+    writer.write_verbatim(IMAGES_HEADING, IMAGES_HEADING)?;
+    let file_hash = translator.ocr_file_hash(file)?;
+    for (number, image) in (1..).zip(images) {
+        let name = image.name.clone();
+        let (page, cached) = recognize(translator, file_hash, number, image).await?;
+        if cached {
+            output.ocr_cache_hits += 1;
+        }
+        output.masked_blocks += page.masked_blocks;
+        let heading = format!("### {}", name);
+        writer.write_verbatim(&heading, &heading)?;
+        writer.set_page(number);
+        translate_ocr(
+            &mut translator.backend,
+            &translator.filter,
+            translator.reflow,
+            translator.toc_handling,
+            file,
+            &page,
+            writer,
+            output,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// The OCR result of the `number`th image of a docx, from the cache if it is there, and
+/// whether it was.
+async fn recognize(
+    translator: &Translator,
+    file_hash: Option<u64>,
+    number: usize,
+    image: MediaImage,
+) -> Result<(OcrPage, bool)> {
+    let max_side = translator.max_image_side;
+    let key = translator
+        .ocr_key(file_hash, number)
+        .map(|key| format!("{} {} max {}", key, image.name, max_side));
+    if let Some(page) = translator.cached_ocr(key.as_deref())? {
+        return Ok((page, true));
+    }
+    let options = translator.ocr_options();
+    let (_, page) = ocr_pool(&translator.ocr)?
+        .run(move |lt| ocr::recognize(lt, decode_capped(&image.bytes, max_side)?, &options))
+        .await?;
+    translator.cache_ocr(key.as_deref(), &page)?;
+    Ok((page, false))
+}
+
+/// Decodes an image, scaled down if a side is longer than `max_side`.
+fn decode_capped(bytes: &[u8], max_side: u32) -> Result<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.no_limits();
+    let image = reader.decode()?;
+    if image.width().max(image.height()) <= max_side {
+        return Ok(image);
+    }
+    Ok(image.resize(max_side, max_side, FilterType::Triangle))
+}
+
+/// Adds the sentences of `text`, the paragraphs starting at the offsets of `starts` joined
+/// by line breaks. A sentence gets the paragraph it starts in as origin.
+fn push_sentences(
+    document: &mut Document,
+    text: &str,
+    starts: &[(usize, usize)],
+    abbreviations: &[String],
+) {
+    for part in sentences(text, abbreviations)
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    {
+        let offset = part.as_ptr() as usize - text.as_ptr() as usize;
+        let paragraph = starts
+            .iter()
+            .take_while(|(start, _)| *start <= offset)
+            .last()
+            .map(|&(_, paragraph)| Origin::Paragraph { paragraph });
+        document.push_segment(part, paragraph, Kind::Paragraph);
+    }
+}
+
+/// Pushes the sentences of `text` that end in it, removing them from `text` and `starts`,
+/// so the next paragraph continues the rest.
+fn push_complete(
+    document: &mut Document,
+    text: &mut String,
+    starts: &mut Vec<(usize, usize)>,
+    abbreviations: &[String],
+) {
+    let Some(&cut) = cuts(text, abbreviations).last() else {
+        return;
+    };
+    push_sentences(document, &text[..cut], starts, abbreviations);
+    text.drain(..cut);
+    // the paragraph the rest starts in
+    let current = starts.iter().take_while(|(start, _)| *start <= cut).last();
+    let current = current.map(|&(_, paragraph)| (0, paragraph));
+    starts.retain(|(start, _)| *start > cut);
+    for (start, _) in starts.iter_mut() {
+        *start -= cut;
+    }
+    starts.splice(0..0, current);
+}
+
+/// Ends of the sentences of `text`: after every period, except those of `abbreviations`
+/// like "г." of "1998 г.", compared in lower case.
+fn cuts(text: &str, abbreviations: &[String]) -> Vec<usize> {
+    let mut cuts = Vec::new();
+    let mut start = 0;
+    for (end, _) in text.match_indices('.') {
+        let word = text[start..end]
+            .rsplit(|c: char| !c.is_alphanumeric() && c != '-')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        if !abbreviations.contains(&word) {
+            start = end + 1;
+            cuts.push(start);
+        }
+    }
+    cuts
+}
+
+/// `text` cut at its [`cuts`].
+fn sentences<'a>(text: &'a str, abbreviations: &[String]) -> Vec<&'a str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for end in cuts(text, abbreviations) {
+        sentences.push(&text[start..end]);
+        start = end;
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// The zip of the docx `file`, decrypted with `password` if it is password-protected.
+/// Encrypted files, files of another format and zips cut short fail with an error kind
+/// of their own before docx-rust gets to them.
+pub fn load(file: &Path, password: Option<&str>) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(file)?;
+    let bytes = if bytes.starts_with(&OLE_MAGIC) {
+        let compound = CompoundFile::parse(&bytes)
+            .map_err(|e| error(ErrorKind::TruncatedInput, anyhow!("broken OLE file: {}", e)))?;
+        if !compound.has_stream("EncryptionInfo") {
+            let e = anyhow!("a Word 97-2003 document or another OLE file, save it as .docx");
+            return Err(error(ErrorKind::FormatMismatch, e));
+        }
+        let Some(password) = password else {
+            let e = anyhow!("password-protected, give its password with --docx-password");
+            return Err(error(ErrorKind::EncryptedInput, e));
+        };
+        office_crypto::decrypt(&compound, password).map_err(|e| {
+            error(
+                ErrorKind::EncryptedInput,
+                anyhow!("could not decrypt: {}", e),
+            )
+        })?
+    } else {
+        bytes
+    };
+    if !bytes.starts_with(b"PK") {
+        return Err(error(
+            ErrorKind::InputError,
+            anyhow!("not a zip, so not a docx"),
+        ));
+    }
+    let mut archive = zip::ZipArchive::new(Cursor::new(&bytes)).map_err(|e| {
+        let e = anyhow!("zip is cut short or damaged, copy it again: {}", e);
+        error(ErrorKind::TruncatedInput, e)
+    })?;
+    if archive.by_name("word/document.xml").is_err() {
+        let what = if archive.by_name("xl/workbook.xml").is_ok() {
+            "an Excel workbook"
+        } else if archive.by_name("ppt/presentation.xml").is_ok() {
+            "a PowerPoint presentation"
+        } else {
+            "a zip"
+        };
+        let e = anyhow!("{} without word/document.xml, not a docx", what);
+        return Err(error(ErrorKind::FormatMismatch, e));
+    }
+    Ok(bytes)
+}
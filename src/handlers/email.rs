@@ -0,0 +1,200 @@
+use super::document::{Document, Kind, Part};
+use super::{save_attachment, Dependency, FileHandler, HandlerOutput};
+use crate::charset;
+use crate::html::{self, HtmlMode};
+use crate::output::SegmentWriter;
+use crate::translator::Translator;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use mail_parser::decoders::quoted_printable::quoted_printable_decode;
+use mail_parser::decoders::{base64::base64_decode, html::html_to_text};
+use mail_parser::{Address, Encoding, Message, MessageParser, MessagePart, PartType};
+use std::path::{Path, PathBuf};
+
+pub struct EmailHandler;
+
+#[async_trait(?Send)]
+impl FileHandler for EmailHandler {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["eml"]
+    }
+
+    fn dependencies(&self) -> &'static [Dependency] {
+        &[]
+    }
+
+    async fn handle(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        out: &Path,
+    ) -> Result<HandlerOutput> {
+        let raw = std::fs::read(file)?;
+        let message = MessageParser::default()
+            .parse(raw.as_slice())
+            .ok_or_else(|| anyhow!("could not parse the email"))?;
+
+        let mut new_txt_file = file.file_name().unwrap().to_string_lossy().to_string();
+        new_txt_file.push_str(".txt");
+        let mut writer = SegmentWriter::create(
+            out.join(new_txt_file),
+            &translator.text_format,
+            translator.keep_source_text,
+        )?;
+        let mut document = Document::default();
+        for (name, address) in [("From", message.from()), ("To", message.to())] {
+            if let Some(address) = address {
+                document.parts.push(address_field(name, address));
+            }
+        }
+        if let Some(subject) = message.subject() {
+            document.parts.push(Part::Field {
+                name: "Subject".to_string(),
+                values: vec![(subject.to_string(), String::new())],
+            });
+        }
+        if let Some(date) = message.header_raw("Date") {
+            document.parts.push(Part::Field {
+                name: "Date".to_string(),
+                values: vec![(String::new(), date.trim().to_string())],
+            });
+        }
+        // a preview only has the first paragraph
+        let mut paragraphs = match translator.preview {
+            Some(_) => 1,
+            None => usize::MAX,
+        };
+        for part in message.text_bodies() {
+            let Some((content, is_html)) = body_content(&message, part) else {
+                continue;
+            };
+            if is_html && translator.html_mode == HtmlMode::Server {
+                for block in html::blocks(&content) {
+                    if paragraphs == 0 {
+                        break;
+                    }
+                    paragraphs -= 1;
+                    document.parts.push(Part::Html(block));
+                }
+                continue;
+            }
+            let text = match is_html {
+                true => html_to_text(&content),
+                false => content,
+            };
+            let text = text.replace("\r\n", "\n");
+            for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+                if paragraphs == 0 {
+                    break;
+                }
+                paragraphs -= 1;
+                document.push_segment(paragraph, None, Kind::Paragraph);
+            }
+        }
+        document
+            .translate(&mut translator.backend, &mut writer)
+            .await?;
+
+        let mut output = HandlerOutput::default();
+        output.add(writer);
+        if translator.email_attachments {
+            let stem = file.file_stem().unwrap().to_string_lossy().to_string();
+            let dir = out.join(stem).join("attachments");
+            for attachment in message.attachments() {
+                if let Some(path) = extract(attachment, &dir, &translator.attachment_extensions)? {
+                    output.outputs.push(path.clone());
+                    output.attachments.push(path);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// The subject, which names the email better than any body could, else the body.
+    async fn excerpt(
+        &self,
+        _translator: &mut Translator,
+        file: &Path,
+        max_chars: usize,
+    ) -> Result<Option<String>> {
+        let raw = std::fs::read(file)?;
+        let message = MessageParser::default()
+            .parse(raw.as_slice())
+            .ok_or_else(|| anyhow!("could not parse the email"))?;
+        if let Some(subject) = message.subject().filter(|s| !s.trim().is_empty()) {
+            return Ok(Some(subject.to_string()));
+        }
+        let text = message
+            .text_bodies()
+            .find_map(|part| body_text(&message, part))
+            .map(|text| text.chars().take(max_chars).collect());
+        Ok(text)
+    }
+}
+
+/// The header field for `address`, with its display names to translate.
+fn address_field(name: &str, address: &Address<'_>) -> Part {
+    let values = address
+        .as_list()
+        .unwrap_or_default()
+        .iter()
+        .map(|addr| {
+            let email = addr.address.as_deref().unwrap_or_default();
+            match addr.name.as_deref() {
+                Some(name) => (name.to_string(), format!(" <{}>", email)),
+                None => (String::new(), email.to_string()),
+            }
+        })
+        .collect();
+    Part::Field {
+        name: name.to_string(),
+        values,
+    }
+}
+
+/// Plain text of a body part, HTML converted to text.
+fn body_text(message: &Message, part: &MessagePart) -> Option<String> {
+    let (content, is_html) = body_content(message, part)?;
+    Some(if is_html {
+        html_to_text(&content)
+    } else {
+        content
+    })
+}
+
+/// Content of a body part and whether it is HTML. If the parser had trouble with the
+/// charset or its result looks garbled, the part is decoded again from the raw message
+/// with [`charset::decode`].
+fn body_content(message: &Message, part: &MessagePart) -> Option<(String, bool)> {
+    let (text, html) = match &part.body {
+        PartType::Text(text) => (text.as_ref(), false),
+        PartType::Html(html) => (html.as_ref(), true),
+        _ => return None,
+    };
+    let mut text = text.to_string();
+    if part.is_encoding_problem || charset::suspicious(&text) {
+        let raw = &message.raw_message()[part.offset_body..part.offset_end];
+        let bytes = match part.encoding {
+            Encoding::None => Some(raw.to_vec()),
+            Encoding::QuotedPrintable => quoted_printable_decode(raw),
+            Encoding::Base64 => base64_decode(raw),
+        };
+        if let Some(bytes) = bytes {
+            let label = part.content_type().and_then(|c| c.attribute("charset"));
+            text = charset::decode(&bytes, label);
+        }
+    }
+    Some((text, html))
+}
+
+/// Writes `attachment` into `dir` if its extension is one of `extensions`.
+fn extract(attachment: &MessagePart, dir: &Path, extensions: &[&str]) -> Result<Option<PathBuf>> {
+    let Some(name) = attachment.attachment_name() else {
+        return Ok(None);
+    };
+    save_attachment(name, attachment.contents(), dir, extensions)
+}
@@ -0,0 +1,250 @@
+use super::{
+    extract_handwriting, ocr_excerpt, page_image_path, save_overlay, save_page_image,
+    translate_ocr, write_page_heading, Dependency, FileHandler, HandlerOutput,
+};
+use crate::ocr::{self, OcrCache, OcrPage};
+use crate::ocr_pool::OcrPool;
+use crate::orientation;
+use crate::output::{OutputFormat, SegmentWriter};
+use crate::photo::{self, PHOTO_REASON};
+use crate::translator::{ocr_pool, Translator};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinHandle;
+
+pub struct ImageHandler;
+
+/// An image being recognized ahead of its turn, with the OCR settings it was started with.
+pub type Prefetched = (String, JoinHandle<Result<OcrPage>>);
+
+/// What an image is recognized with: its OCR cache key, without the file.
+fn ocr_settings(lang: &str, options: &ocr::Options, max_side: u32) -> String {
+    format!("{} max {}", ocr::key(0, 0, lang, options), max_side)
+}
+
+/// Recognizes images ahead of their turn, in the OCR stage of the run, see
+/// [`crate::pipeline`]. Started with the OCR settings of the run; the handler of an image
+/// only takes the result if the settings of the file are still those.
+pub struct Ahead {
+    pool: OcrPool,
+    cache: Option<OcrCache>,
+    lang: String,
+    options: ocr::Options,
+    max_side: u32,
+    force: bool,
+}
+
+impl Ahead {
+    /// `None` without tesseract.
+    pub fn new(translator: &Translator) -> Option<Self> {
+        Some(Ahead {
+            pool: ocr_pool(&translator.ocr).ok()?.clone(),
+            cache: translator.ocr_cache.clone(),
+            lang: translator.ocr_lang().to_string(),
+            options: translator.ocr_options(),
+            max_side: translator.max_image_side,
+            force: translator.force_ocr_images,
+        })
+    }
+
+    /// Starts recognizing `file` on the next free instance. Nothing is started for a file
+    /// in the OCR cache.
+    pub fn start(&self, file: &Path) -> Option<Prefetched> {
+        let (max_side, force) = (self.max_side, self.force);
+        if let Some(cache) = &self.cache {
+            let hash = ocr::hash_file(file).ok()?;
+            let key = ocr::key(hash, 0, &self.lang, &self.options);
+            // cached, or a miss that fails with --retranslate-only anyway
+            if cache.only || cache.get(&format!("{} max {}", key, max_side)).is_some() {
+                return None;
+            }
+        }
+        let options = self.options.clone();
+        let path = file.to_path_buf();
+        let job = self.pool.spawn(move |lt| {
+            let image = open_capped(&path, max_side)?;
+            // the handler skips photos before it asks for their text
+            if !force && photo::is_photo(&image) {
+                return Err(anyhow!(PHOTO_REASON));
+            }
+            ocr::recognize(lt, image, &options)
+        });
+        // only the text is kept until the handler takes it
+        let job = tokio::spawn(async move { job.await?.map(|(_, page)| page) });
+        Some((ocr_settings(&self.lang, &self.options, max_side), job))
+    }
+}
+
+/// The OCR result of `file`, from the cache if it is there, and whether it was.
+async fn recognize(translator: &mut Translator, file: &Path) -> Result<(OcrPage, bool)> {
+    let file_hash = translator.ocr_file_hash(file)?;
+    let max_side = translator.max_image_side;
+    let key = translator
+        .ocr_key(file_hash, 0)
+        .map(|key| format!("{} max {}", key, max_side));
+    if let Some(page) = translator.cached_ocr(key.as_deref())? {
+        return Ok((page, true));
+    }
+    if let Some((settings, job)) = translator.prefetched.remove(file) {
+        let options = translator.ocr_options();
+        if settings == ocr_settings(translator.ocr_lang(), &options, max_side) {
+            let page = job.await??;
+            translator.cache_ocr(key.as_deref(), &page)?;
+            return Ok((page, false));
+        }
+    }
+    let pool = ocr_pool(&translator.ocr)?;
+    let options = translator.ocr_options();
+    let path = file.to_path_buf();
+    // decoded on the OCR thread and dropped as soon as tesseract is done with it
+    let (_, page) = pool
+        .run(move |lt| ocr::recognize(lt, open_capped(&path, max_side)?, &options))
+        .await?;
+    translator.cache_ocr(key.as_deref(), &page)?;
+    Ok((page, false))
+}
+
+/// Opens an image, scaled down right after decoding if a side is longer than `max_side`.
+fn open_capped(file: &Path, max_side: u32) -> Result<DynamicImage> {
+    let (width, height) = ImageReader::open(file)?
+        .with_guessed_format()?
+        .into_dimensions()?;
+    let mut reader = ImageReader::open(file)?.with_guessed_format()?;
+    // huge scans exceed the default allocation limit, they are scaled down right away
+    reader.no_limits();
+    let image = reader.decode()?;
+    let longest = width.max(height);
+    if longest <= max_side {
+        return Ok(image);
+    }
+    eprintln!(
+        "{}: {}x{} is over the {} px cap, scaled by {:.2}",
+        file.display(),
+        width,
+        height,
+        max_side,
+        max_side as f32 / longest as f32
+    );
+    Ok(image.resize(max_side, max_side, FilterType::Triangle))
+}
+
+/// Whether `file` is a photo to be left alone, never with `--force-ocr-images`.
+fn skip_as_photo(translator: &Translator, file: &Path) -> Result<bool> {
+    if translator.force_ocr_images {
+        return Ok(false);
+    }
+    let mut reader = ImageReader::open(file)?.with_guessed_format()?;
+    reader.no_limits();
+    Ok(photo::is_photo(&reader.decode()?))
+}
+
+#[async_trait(?Send)]
+impl FileHandler for ImageHandler {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["png", "jpg"]
+    }
+
+    fn dependencies(&self) -> &'static [Dependency] {
+        &[Dependency::Tesseract]
+    }
+
+    fn existing_outputs(&self, src: &Path, dest: &Path) -> Vec<PathBuf> {
+        let prefix = format!("{}.", src.file_name().unwrap().to_string_lossy());
+        let mut files = super::files_starting_with(dest, &prefix);
+        files.extend(super::files_starting_with(
+            &dest.join(super::ASSETS_DIR),
+            &prefix,
+        ));
+        files
+    }
+
+    async fn handle(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        out: &Path,
+    ) -> Result<HandlerOutput> {
+        let max_side = translator.max_image_side;
+        let mut output = HandlerOutput::default();
+        if skip_as_photo(translator, file)? {
+            output.skipped = Some(PHOTO_REASON.to_string());
+            return Ok(output);
+        }
+        let (mut page, cached) = recognize(translator, file).await?;
+        if cached {
+            output.ocr_cache_hits += 1;
+        } else {
+            output.pages = 1;
+        }
+        if page.degrees != 0 {
+            eprintln!("{}: rotated by {}°", file.display(), page.degrees);
+        }
+        output.masked_blocks = page.masked_blocks;
+        if !page.handwriting.is_empty() {
+            let image = orientation::rotate(&open_capped(file, max_side)?, page.degrees);
+            extract_handwriting(translator, file, out, &image, 1, &mut page, &mut output)?;
+        }
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        let markdown = translator.output_format == OutputFormat::Markdown;
+        let extension = if markdown { "md" } else { "txt" };
+        let mut writer = SegmentWriter::create(
+            out.join(format!("{}.{}", name, extension)),
+            &translator.text_format,
+            translator.keep_source_text,
+        )?;
+        // text outputs of images have no page image, the source is next to them
+        let thumbnail = if markdown {
+            page_image_path(translator, out, format!("{}.jpg", name))
+        } else {
+            None
+        };
+        if markdown {
+            write_page_heading(&mut writer, 1, thumbnail.as_deref())?;
+        }
+        let blocks = translate_ocr(
+            &mut translator.backend,
+            &translator.filter,
+            translator.reflow,
+            translator.toc_handling,
+            file,
+            &page,
+            &mut writer,
+            &mut output,
+        )
+        .await?;
+        output.add(writer);
+        if let Some(thumbnail) = thumbnail {
+            save_page_image(translator, &open_capped(file, max_side)?, &thumbnail)?;
+            output.outputs.push(thumbnail);
+        }
+        if translator.output_format == OutputFormat::Overlay {
+            // the boxes are in the coordinates of the capped image, turned upright
+            let image = orientation::rotate(&open_capped(file, max_side)?, page.degrees);
+            let path = out.join(format!("{}.overlay.png", name));
+            save_overlay(translator, &image, &blocks, &path)?;
+            output.outputs.push(path);
+        }
+        Ok(output)
+    }
+
+    async fn excerpt(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        max_chars: usize,
+    ) -> Result<Option<String>> {
+        if skip_as_photo(translator, file)? {
+            return Ok(None);
+        }
+        let (page, _) = recognize(translator, file).await?;
+        Ok(Some(ocr_excerpt(&page, max_chars)))
+    }
+}
@@ -0,0 +1,660 @@
+//! File handlers, looked up by extension through the [`HandlerRegistry`]. Each one makes a
+//! [`Document`] of its file, which goes through the same translation stage and writer.
+
+use crate::backend::Backend;
+use crate::compression;
+use crate::confidence::{self, PageConfidence};
+use crate::docx_tables::{Table, TableFormat};
+use crate::events::{self, Event};
+use crate::filter::{self, SegmentFilter};
+use crate::handwriting;
+use crate::html;
+use crate::multi_pass::{self, SecondPass};
+use crate::ocr::{OcrBlock, OcrPage};
+use crate::output::{self, Failure, Origin, OutputFormat, Segment, SegmentWriter};
+use crate::reflow::reflow;
+use crate::rotated;
+use crate::toc::{self, Toc, TocHandling};
+use crate::translator::Translator;
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use ::image::imageops::FilterType;
+use ::image::{DynamicImage, ImageFormat};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use document::{Document, Kind, Part};
+use std::path::{Path, PathBuf};
+
+mod document;
+mod docx;
+mod email;
+mod image;
+mod pdf;
+
+pub use image::{Ahead, Prefetched};
+pub use pdf::{render_pages, RENDER_SIZE};
+
+#[derive(Default)]
+pub struct HandlerOutput {
+    pub outputs: Vec<PathBuf>,
+    /// text blocks dropped because they were inside an `--ocr-mask`
+    pub masked_blocks: usize,
+    /// OCR segments dropped as noise by the segment filter
+    pub filtered_segments: usize,
+    /// OCR blocks dropped by the segment filter as too small or too sparse for their box
+    pub geometry_filtered: usize,
+    /// pages, counted from 1, that could not be rendered, left as a placeholder
+    pub failed_pages: Vec<usize>,
+    /// pages whose OCR result came from the cache
+    pub ocr_cache_hits: usize,
+    /// pdf pages and images recognized by tesseract, not taken from the cache
+    pub pages: usize,
+    /// segments left as a marker because the server failed to translate them
+    pub failures: Vec<Failure>,
+    /// files extracted from the source, like email attachments, that `main` hands to
+    /// their own handlers
+    pub attachments: Vec<PathBuf>,
+    /// pages recognized twice with `--ocr-multi-pass`
+    pub second_pass: Vec<SecondPass>,
+    /// why the file was left alone without outputs, like a photo
+    pub skipped: Option<String>,
+    /// the outputs are named otherwise than with `--legacy-names`
+    pub legacy_names_differ: bool,
+    /// OCR confidence of every page recognized with text on it
+    pub page_confidence: Vec<PageConfidence>,
+    /// pages taken for a table of contents or an index
+    pub toc_pages: usize,
+}
+
+impl HandlerOutput {
+    /// Takes over the files and failures of a finished writer.
+    pub fn add(&mut self, writer: SegmentWriter) {
+        let (paths, failures) = writer.finish();
+        self.outputs.extend(paths);
+        self.failures.extend(failures);
+    }
+
+    /// Takes over everything another handler produced, e.g. for an attachment, but for
+    /// the confidence of its pages, which are numbered on their own.
+    pub fn merge(&mut self, other: HandlerOutput) {
+        self.outputs.extend(other.outputs);
+        self.masked_blocks += other.masked_blocks;
+        self.filtered_segments += other.filtered_segments;
+        self.geometry_filtered += other.geometry_filtered;
+        self.failed_pages.extend(other.failed_pages);
+        self.ocr_cache_hits += other.ocr_cache_hits;
+        self.pages += other.pages;
+        self.failures.extend(other.failures);
+        self.attachments.extend(other.attachments);
+        self.second_pass.extend(other.second_pass);
+        self.toc_pages += other.toc_pages;
+    }
+}
+
+/// Makes a [`Document`] of an OCR result of `file`, its blocks reflowed and filtered, and
+/// translates it into `writer`. A table of contents or an index is handled as `toc` says.
+/// Takes the parts of the translator it needs so pdfium stays borrowed in the pdf handler.
+/// Returns the box and translation of every block translated, for the overlay.
+// the parts of the translator, see above
+#[allow(clippy::too_many_arguments)]
+async fn translate_ocr(
+    backend: &mut Backend,
+    filter: &SegmentFilter,
+    reflow_lines: bool,
+    toc: TocHandling,
+    file: &Path,
+    page: &OcrPage,
+    writer: &mut SegmentWriter,
+    output: &mut HandlerOutput,
+) -> Result<Vec<([i32; 4], String)>> {
+    events::emit(Event::PageOcrComplete {
+        source: file.to_path_buf(),
+        page: writer.page(),
+        chars: page
+            .segments
+            .iter()
+            .map(|s| s.text.trim().chars().count())
+            .sum(),
+        confidence: multi_pass::confidence(&page.segments),
+    });
+    output
+        .page_confidence
+        .extend(confidence::page(writer.page(), &page.segments));
+    let mut document = Document::default();
+    let lines = || page.segments.iter().flat_map(|s| s.text.lines());
+    if let Some(entries) = toc::detect(lines()) {
+        output.toc_pages += 1;
+        let part = match toc {
+            TocHandling::Translate => Part::Toc(entries),
+            TocHandling::Skip => Part::Verbatim(toc::SKIPPED.to_string()),
+            TocHandling::Verbatim => Part::Verbatim(lines().collect::<Vec<_>>().join("\n")),
+        };
+        document.parts.push(part);
+        return document.translate(backend, writer).await;
+    }
+    let mut tables = page.tables.iter().peekable();
+    // numbered after the blocks of the page and its rotated text
+    let first_note = page.segments.len() + page.rotated.len() + 1;
+    let mut notes = page.handwriting.iter().zip(first_note..).peekable();
+    let text_height = filter::text_height(&page.segments);
+    for (index, segment) in page.segments.iter().enumerate() {
+        // tables and notes go before the first block below their top edge
+        while let Some(table) = tables.next_if(|t| t.bbox[1] <= segment.bbox[1]) {
+            document
+                .parts
+                .push(Part::Table(table.table.clone(), TableFormat::Tsv));
+        }
+        while let Some((note, block)) = notes.next_if(|(n, _)| n.bbox[1] <= segment.bbox[1]) {
+            push_note(&mut document, note, block, writer.page());
+        }
+        if !filter.keep_block(file, segment, text_height) {
+            output.geometry_filtered += 1;
+            continue;
+        }
+        let mut input = segment.text.clone();
+        if reflow_lines {
+            input = reflow(&input);
+        }
+        if !filter.keep(file, &input, &backend.source_lang, &backend.scripts) {
+            output.filtered_segments += 1;
+            continue;
+        }
+        let origin = Origin::Block {
+            page: writer.page(),
+            block: index + 1,
+            bbox: segment.bbox,
+            confidence: segment.confidence,
+        };
+        document.push_segment(input, Some(origin), Kind::Block);
+    }
+    for table in tables {
+        document
+            .parts
+            .push(Part::Table(table.table.clone(), TableFormat::Tsv));
+    }
+    for (note, block) in notes {
+        push_note(&mut document, note, block, writer.page());
+    }
+    // after the reading order, not painted over
+    if !page.rotated.is_empty() {
+        document
+            .parts
+            .push(Part::Verbatim(rotated::HEADING.to_string()));
+        let first = page.segments.len();
+        for (index, block) in page.rotated.iter().enumerate() {
+            let origin = Origin::Block {
+                page: writer.page(),
+                block: first + index + 1,
+                bbox: block.bbox,
+                confidence: block.confidence,
+            };
+            document.push_segment(block.text.trim(), Some(origin), Kind::Aside);
+        }
+    }
+    document.translate(backend, writer).await
+}
+
+/// Adds a handwritten note as block `block` of page `page`: its recognized text to
+/// translate, or the placeholder if it has none.
+fn push_note(document: &mut Document, note: &OcrBlock, block: usize, page: usize) {
+    if note.text.trim().is_empty() {
+        let placeholder = handwriting::PLACEHOLDER.to_string();
+        document.parts.push(Part::Verbatim(placeholder));
+        return;
+    }
+    let origin = Origin::Block {
+        page,
+        block,
+        bbox: note.bbox,
+        confidence: note.confidence,
+    };
+    document.push_segment(note.text.trim(), Some(origin), Kind::Aside);
+}
+
+/// Pairs boxes with their translations. Failed and untranslated segments are left out so
+/// the overlay keeps their source text visible, suspect ones lose their marker.
+fn overlay_blocks(
+    boxes: &[[i32; 4]],
+    translated: Vec<Option<String>>,
+) -> impl Iterator<Item = ([i32; 4], String)> + '_ {
+    boxes.iter().zip(translated).filter_map(|(bbox, text)| {
+        let text = text.filter(|t| !t.starts_with(UNTRANSLATED_MARKER))?;
+        let text = text
+            .strip_prefix(SUSPECT_MARKER)
+            .unwrap_or(&text)
+            .to_string();
+        Some((*bbox, text))
+    })
+}
+
+/// Translates `segments` together, so several requests are in flight, and writes them in
+/// order. Segments that fail get a placeholder. Returns the translations, `None` for
+/// the failed ones.
+async fn translate_segments(
+    backend: &mut Backend,
+    segments: &[Segment],
+    writer: &mut SegmentWriter,
+) -> Result<Vec<Option<String>>> {
+    let inputs: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+    // deferred with their origin, a failure gets a marker
+    let results = match &mut backend.deferral {
+        Some(deferral) => segments
+            .iter()
+            .map(|s| Ok(deferral.defer(&s.text, s.origin, false)))
+            .collect(),
+        None => backend.translate_all(&inputs).await,
+    };
+    let mut translations = Vec::new();
+    for (i, (segment, result)) in segments.iter().zip(results).enumerate() {
+        events::emit(Event::SegmentTranslated {
+            chars: segment.text.chars().count(),
+            failed: result.is_err(),
+        });
+        match result {
+            Ok(translated) => {
+                writer.write_segment(segment, &translated)?;
+                backend.offer_sample(segment, &translated, i);
+                translations.push(Some(translated));
+            }
+            Err(_) => {
+                writer.write_failed(segment)?;
+                translations.push(None);
+            }
+        }
+    }
+    Ok(translations)
+}
+
+/// Translates HTML blocks as markup if the server takes it, writing their text. A block
+/// whose tags the server changed, or all of them if it doesn't take HTML, is translated
+/// as text instead.
+async fn translate_html(
+    backend: &mut Backend,
+    blocks: &[&str],
+    writer: &mut SegmentWriter,
+) -> Result<()> {
+    let segments: Vec<Segment> = blocks
+        .iter()
+        .map(|block| Segment::new(html::text(block), None))
+        .collect();
+    let Some(results) = backend.translate_html(blocks).await else {
+        translate_segments(backend, &segments, writer).await?;
+        return Ok(());
+    };
+    for ((block, segment), result) in blocks.iter().zip(&segments).zip(results) {
+        match result {
+            Ok(translated) if html::same_tags(block, &translated) => {
+                events::emit(Event::SegmentTranslated {
+                    chars: segment.text.chars().count(),
+                    failed: false,
+                });
+                writer.write_segment(segment, &html::text(&translated))?;
+            }
+            Ok(_) => {
+                eprintln!(
+                    "markup changed in translation, sent as text: {:?}",
+                    segment.text
+                );
+                translate_segments(backend, std::slice::from_ref(segment), writer).await?;
+            }
+            Err(_) => {
+                events::emit(Event::SegmentTranslated {
+                    chars: segment.text.chars().count(),
+                    failed: true,
+                });
+                writer.write_failed(segment)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Translates the cells of a table. Cells that fail to translate keep their source text
+/// so the columns stay intact.
+async fn translate_table(
+    backend: &mut Backend,
+    source: Table,
+    format: TableFormat,
+    writer: &mut SegmentWriter,
+) -> Result<()> {
+    let mut translated = source.clone();
+    let mut cells: Vec<_> = translated
+        .rows
+        .iter_mut()
+        .flatten()
+        .filter(|cell| !cell.merged_up && !cell.text.trim().is_empty())
+        .collect();
+    let inputs: Vec<&str> = source
+        .rows
+        .iter()
+        .flatten()
+        .filter(|cell| !cell.merged_up && !cell.text.trim().is_empty())
+        .map(|cell| cell.text.trim())
+        .collect();
+    for (cell, result) in cells.iter_mut().zip(backend.translate_all(&inputs).await) {
+        cell.text = output::or_source(result.ok(), &cell.text);
+    }
+    writer.write_verbatim(&source.render(format), &translated.render(format))
+}
+
+/// Translates the text of the entries of a table of contents and writes them aligned, see
+/// [`crate::toc`]. Entries that fail to translate keep their text.
+async fn translate_toc(backend: &mut Backend, toc: &Toc, writer: &mut SegmentWriter) -> Result<()> {
+    let sources: Vec<String> = toc.entries.iter().map(|e| e.text.clone()).collect();
+    let inputs: Vec<&str> = sources.iter().map(String::as_str).collect();
+    let translated: Vec<String> = backend
+        .translate_all(&inputs)
+        .await
+        .into_iter()
+        .zip(&sources)
+        .map(|(result, source)| output::or_source(result.ok(), source))
+        .collect();
+    writer.write_verbatim(&toc.render(&sources), &toc.render(&translated))
+}
+
+/// Cuts the handwritten notes of `page`, number `number` of `file`, out of its oriented
+/// `image` into `<stem>/handwriting/` in `out`, reading them with the recognizer if there
+/// is one.
+fn extract_handwriting(
+    translator: &Translator,
+    file: &Path,
+    out: &Path,
+    image: &DynamicImage,
+    number: usize,
+    page: &mut OcrPage,
+    output: &mut HandlerOutput,
+) -> Result<()> {
+    let stem = file.file_stem().unwrap().to_string_lossy().to_string();
+    let dir = out.join(stem).join("handwriting");
+    let recognizer = translator.handwriting_recognizer.as_deref();
+    let crops = handwriting::extract(recognizer, image, number, &mut page.handwriting, &dir)?;
+    output.outputs.extend(crops);
+    Ok(())
+}
+
+/// Subfolder of the output dir holding the page thumbnails of Markdown outputs.
+const ASSETS_DIR: &str = "assets";
+
+/// Where the image of a page named `name` goes, `None` if none is written.
+fn page_image_path(translator: &Translator, out: &Path, name: String) -> Option<PathBuf> {
+    match translator.output_format {
+        OutputFormat::Text | OutputFormat::Overlay => Some(out.join(name)),
+        OutputFormat::Markdown if translator.thumbnail_width > 0 => {
+            Some(out.join(ASSETS_DIR).join(name))
+        }
+        OutputFormat::Markdown => None,
+    }
+}
+
+/// Writes the Markdown heading of page `number`, counting from 1, with a link to its
+/// thumbnail relative to the Markdown file.
+fn write_page_heading(
+    writer: &mut SegmentWriter,
+    number: usize,
+    image: Option<&Path>,
+) -> Result<()> {
+    let mut heading = format!("## Page {}", number);
+    if let Some(name) = image.and_then(|i| i.file_name()) {
+        heading.push_str(&format!(
+            "\n\n![Page {}]({}/{})",
+            number,
+            ASSETS_DIR,
+            name.to_string_lossy()
+        ));
+    }
+    writer.write_verbatim(&heading, &heading)
+}
+
+/// Saves a page image as jpg, scaled down to the thumbnail width for Markdown outputs.
+fn save_page_image(translator: &Translator, image: &DynamicImage, path: &Path) -> Result<()> {
+    let width = translator.thumbnail_width;
+    let thumbnail;
+    let image = if translator.output_format == OutputFormat::Markdown && image.width() > width {
+        thumbnail = image.resize(width, u32::MAX, FilterType::Triangle);
+        &thumbnail
+    } else {
+        image
+    };
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    image.to_rgb8().save_with_format(path, ImageFormat::Jpeg)?;
+    Ok(())
+}
+
+/// Saves a page image as png with `blocks` painted over it.
+fn save_overlay(
+    translator: &Translator,
+    image: &DynamicImage,
+    blocks: &[([i32; 4], String)],
+    path: &Path,
+) -> Result<()> {
+    let overlay = translator
+        .overlay
+        .as_ref()
+        .ok_or_else(|| anyhow!("no font loaded for the overlay"))?;
+    let mut image = image.to_rgb8();
+    overlay.draw(&mut image, blocks);
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    image.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Native libraries a handler needs at runtime.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Dependency {
+    Pdfium,
+    Tesseract,
+}
+
+impl Dependency {
+    fn name(&self) -> &'static str {
+        match self {
+            Dependency::Pdfium => "pdfium",
+            Dependency::Tesseract => "tesseract",
+        }
+    }
+
+    fn available(&self, translator: &Translator) -> bool {
+        match self {
+            Dependency::Pdfium => translator.pdfium.get().is_ok(),
+            Dependency::Tesseract => translator.ocr.is_ok(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+pub trait FileHandler {
+    /// name used by `--disable-handler` and `--enable-only`
+    fn name(&self) -> &'static str;
+    /// lowercase extensions handled, without the dot
+    fn extensions(&self) -> &'static [&'static str];
+    fn dependencies(&self) -> &'static [Dependency];
+    /// Translates `src`, writing outputs into the directory `dest`.
+    async fn handle(
+        &self,
+        translator: &mut Translator,
+        src: &Path,
+        dest: &Path,
+    ) -> Result<HandlerOutput>;
+    /// The start of the text of `src`, about `max_chars` characters, for
+    /// `--names-from-content`. `None` if there is no text to read.
+    async fn excerpt(
+        &self,
+        _translator: &mut Translator,
+        _src: &Path,
+        _max_chars: usize,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
+    /// Files an earlier run wrote for `src` into `dest`, which handling it again overwrites.
+    fn existing_outputs(&self, src: &Path, dest: &Path) -> Vec<PathBuf> {
+        let prefix = format!("{}.", src.file_name().unwrap().to_string_lossy());
+        files_starting_with(dest, &prefix)
+    }
+}
+
+fn files_starting_with(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(prefix))
+        .map(|e| e.path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes an attachment named `name` into `dir` if its extension is one of `extensions`,
+/// for email attachments and files embedded in PDFs. Other attachments are left out like
+/// unsupported files of the source dir.
+fn save_attachment(
+    name: &str,
+    contents: &[u8],
+    dir: &Path,
+    extensions: &[&str],
+) -> Result<Option<PathBuf>> {
+    // only the file name, attachment names can contain paths
+    let Some(name) = Path::new(name).file_name() else {
+        return Ok(None);
+    };
+    let path = dir.join(name);
+    let supported = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.contains(&e.to_lowercase().as_str()));
+    if !supported {
+        return Ok(None);
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&path, contents)?;
+    Ok(Some(path))
+}
+
+/// The recognized text of `page`, block by block, cut after the block that reaches
+/// `max_chars`.
+fn ocr_excerpt(page: &OcrPage, max_chars: usize) -> String {
+    let mut text = String::new();
+    for segment in &page.segments {
+        if text.chars().count() >= max_chars {
+            break;
+        }
+        text.push_str(segment.text.trim());
+        text.push('\n');
+    }
+    text
+}
+
+struct Entry {
+    handler: Box<dyn FileHandler>,
+    enabled: bool,
+}
+
+pub struct HandlerRegistry {
+    entries: Vec<Entry>,
+}
+
+impl Default for HandlerRegistry {
+    fn default() -> Self {
+        let handlers: Vec<Box<dyn FileHandler>> = vec![
+            Box::new(pdf::PdfHandler),
+            Box::new(image::ImageHandler),
+            Box::new(docx::DocxHandler),
+            Box::new(email::EmailHandler),
+        ];
+        HandlerRegistry {
+            entries: handlers
+                .into_iter()
+                .map(|handler| Entry {
+                    handler,
+                    enabled: true,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl HandlerRegistry {
+    /// Disables handlers by name. With `enable_only`, every handler not listed is disabled too.
+    pub fn configure(&mut self, disabled: &[String], enable_only: Option<&[String]>) -> Result<()> {
+        for name in disabled.iter().chain(enable_only.unwrap_or_default()) {
+            if !self.entries.iter().any(|e| e.handler.name() == name) {
+                return Err(anyhow!(
+                    "unknown handler {}, expected one of {}",
+                    name,
+                    self.names().join(", ")
+                ));
+            }
+        }
+        for entry in &mut self.entries {
+            let name = entry.handler.name().to_string();
+            if disabled.contains(&name) || enable_only.is_some_and(|only| !only.contains(&name)) {
+                entry.enabled = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|e| e.handler.name()).collect()
+    }
+
+    /// The enabled handler for the extension of `path`, if any, or of the file inside a
+    /// compressed one, see [`compression::inner_name`].
+    pub fn for_path(&self, path: &Path) -> Option<&dyn FileHandler> {
+        self.find(path, true)
+    }
+
+    /// The name of the disabled handler for `path`, if it has one.
+    pub fn disabled_for(&self, path: &Path) -> Option<&'static str> {
+        self.find(path, false).map(|h| h.name())
+    }
+
+    fn find(&self, path: &Path, enabled: bool) -> Option<&dyn FileHandler> {
+        let inner = compression::inner_name(path);
+        let path = inner.as_deref().map_or(path, Path::new);
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|e| e.enabled == enabled)
+            .map(|e| e.handler.as_ref())
+            .find(|h| h.extensions().contains(&ext.as_str()))
+    }
+
+    /// Extensions of all enabled handlers.
+    pub fn extensions(&self) -> Vec<&'static str> {
+        self.entries
+            .iter()
+            .filter(|e| e.enabled)
+            .flat_map(|e| e.handler.extensions().iter().copied())
+            .collect()
+    }
+
+    /// Prints every handler with its extensions and the state of its dependencies.
+    pub fn print(&self, translator: &Translator) {
+        for entry in &self.entries {
+            let handler = &entry.handler;
+            let mut dependencies: Vec<_> = handler
+                .dependencies()
+                .iter()
+                .map(|d| {
+                    let state = if d.available(translator) {
+                        "ok"
+                    } else {
+                        "missing"
+                    };
+                    format!("{} {}", d.name(), state)
+                })
+                .collect();
+            if dependencies.is_empty() {
+                dependencies.push("no dependencies".to_string());
+            }
+            println!(
+                "{:<6} {:<8} {:<12} {}",
+                handler.name(),
+                if entry.enabled { "enabled" } else { "disabled" },
+                handler.extensions().join(","),
+                dependencies.join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,618 @@
+use super::{
+    extract_handwriting, ocr_excerpt, page_image_path, save_attachment, save_overlay,
+    save_page_image, translate_ocr, write_page_heading, Dependency, FileHandler, HandlerOutput,
+};
+use crate::backend::Backend;
+use crate::hyphenation;
+use crate::multi_pass::{self, SecondPass};
+use crate::ocr;
+use crate::ocr::OcrPage;
+use crate::orientation;
+use crate::outline;
+use crate::output::{self, OutputFormat, SegmentWriter};
+use crate::pdf_forms::{self, PageText, ANNOTATIONS_HEADING, FORM_FIELDS_HEADING};
+use crate::render_workers::{RenderFailed, RenderWorkers};
+use crate::translator::{ocr_pool, Translator};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use image::DynamicImage;
+use pdfium_render::prelude::*;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinHandle;
+
+/// Longest side of a rendered page in pixels.
+pub const RENDER_SIZE: i32 = 2000;
+
+pub struct PdfHandler;
+
+/// Renders a page, retrying once at half the resolution if pdfium fails on it.
+fn render<'a>(page: &'a PdfPage<'a>) -> Result<PdfBitmap<'a>, PdfiumError> {
+    render_at(page, RENDER_SIZE)
+}
+
+/// Renders a page with a longer side of `size` pixels, or half of it if that fails.
+fn render_at<'a>(page: &'a PdfPage<'a>, size: i32) -> Result<PdfBitmap<'a>, PdfiumError> {
+    let config = |size| {
+        PdfRenderConfig::new()
+            .set_target_width(size)
+            .set_maximum_height(size)
+    };
+    page.render_with_config(&config(size))
+        .or_else(|_| page.render_with_config(&config(size / 2)))
+}
+
+/// Recognizes `page` again at `size` if the first pass in `first` wasn't confident
+/// enough, keeping the more confident text of each block.
+async fn second_pass(
+    translator: &Translator,
+    page: &PdfPage<'_>,
+    size: u32,
+    image: &DynamicImage,
+    first: &mut OcrPage,
+) -> Result<()> {
+    if !multi_pass::needs_second_pass(&first.segments) {
+        return Ok(());
+    }
+    let rendered = render_at(page, size as i32)
+        .map_err(|e| anyhow!("second pass render failed: {:?}", e))?
+        .as_image();
+    let options = translator.ocr_options();
+    let (large, second) = ocr_pool(&translator.ocr)?
+        .run(move |lt| ocr::recognize(lt, rendered.clone(), &options))
+        .await?;
+    // tesseract found another orientation, the boxes can't be compared
+    if second.degrees != first.degrees {
+        first.second_pass_blocks = Some(0);
+        return Ok(());
+    }
+    let scale = large.width().max(large.height()) as f32 / image.width().max(image.height()) as f32;
+    let (segments, won) = multi_pass::merge(&first.segments, &second.segments, scale);
+    first.segments = segments;
+    first.second_pass_blocks = Some(won);
+    Ok(())
+}
+
+/// Name of the output of page `index` with extension `ext`, e.g. `scan-page-3.txt`, after
+/// the stem of `file` as it is. `legacy` is `--legacy-names`: the file name lowercased and
+/// every `.pdf` in it replaced, so `Отчёт.PDF.pdf` gave `отчёт-page-3.txt-page-3.txt`.
+fn page_file_name(file: &Path, index: usize, ext: &str, legacy: bool) -> String {
+    if legacy {
+        return file
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_lowercase()
+            .replace(".pdf", &format!("-page-{}.{}", index, ext));
+    }
+    format!("{}{}.{}", page_prefix(file, false), index, ext)
+}
+
+/// Start of the names of the page outputs of `file`.
+fn page_prefix(file: &Path, legacy: bool) -> String {
+    if legacy {
+        return file
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_lowercase()
+            .replace(".pdf", "-page-");
+    }
+    format!("{}-page-", file.file_stem().unwrap().to_string_lossy())
+}
+
+enum PageOcr {
+    RenderFailed(String),
+    /// cached result, with the page image if it has to be written again
+    Cached(Option<DynamicImage>, OcrPage),
+    Running(JoinHandle<Result<(DynamicImage, OcrPage)>>),
+    /// recognized and cached, ahead of its turn for the last page
+    Recognized(Option<DynamicImage>, OcrPage),
+}
+
+struct PageJob {
+    index: PdfPageIndex,
+    key: Option<String>,
+    image_path: Option<PathBuf>,
+    ocr: PageOcr,
+}
+
+/// `render-pages`: renders the pages whose indexes come in on stdin into `dir`, answering
+/// each on stdout, see [`crate::render_workers`].
+pub fn render_pages(pdfium: &Pdfium, file: &Path, dir: &Path, legacy: bool) -> Result<()> {
+    let document = pdfium
+        .load_pdf_from_file(file, None)
+        .map_err(|e| anyhow!("could not open the pdf: {:?}", e))?;
+    let pages = document.pages();
+    let mut stdout = std::io::stdout().lock();
+    for line in std::io::stdin().lines() {
+        let index: PdfPageIndex = line?.trim().parse()?;
+        let path = dir.join(page_file_name(file, index as usize, "png", legacy));
+        let rendered = pages
+            .get(index)
+            .and_then(|page| render(&page).map(|rendered| rendered.as_image()))
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|image| image.save(&path).map_err(|e| e.to_string()));
+        match rendered {
+            Ok(()) => writeln!(stdout, "ok {}", index)?,
+            Err(e) => writeln!(stdout, "failed {} {}", index, e)?,
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Looks up the OCR result of a page, or renders it and starts OCR in the pool. Pages
+/// without a cached result are rendered by `workers` if there are any. The render is
+/// saved into `renders` too with `--keep-temp`.
+#[allow(clippy::too_many_arguments)]
+fn start_page(
+    translator: &Translator,
+    file: &Path,
+    out: &Path,
+    file_hash: Option<u64>,
+    pages: &PdfPages,
+    index: PdfPageIndex,
+    renders: Option<&Path>,
+    workers: Option<&mut RenderWorkers>,
+) -> Result<PageJob> {
+    let page = pages.get(index)?;
+    let overlay = translator.output_format == OutputFormat::Overlay;
+    let extension = if overlay { "png" } else { "jpg" };
+    let image_path = page_image_path(
+        translator,
+        out,
+        page_file_name(file, index as usize, extension, translator.legacy_names),
+    );
+    let key = translator.ocr_key(file_hash, index as usize);
+    let cached = translator.cached_ocr(key.as_deref())?;
+    // with a cached result the page is only rendered for a missing page image, or for the
+    // overlay as the translations may have changed
+    // and for cutting out handwritten notes
+    let notes = cached.as_ref().is_some_and(|c| !c.handwriting.is_empty());
+    if let (Some(workers), None) = (workers, &cached) {
+        let pool = ocr_pool(&translator.ocr)?;
+        let name = page_file_name(file, index as usize, "png", translator.legacy_names);
+        let render = workers.request(index, &name)?;
+        let options = translator.ocr_options();
+        let recognize = move |lt: &mut _| ocr::recognize(lt, render.wait()?, &options);
+        return Ok(PageJob {
+            index,
+            key,
+            image_path,
+            ocr: PageOcr::Running(pool.spawn(recognize)),
+        });
+    }
+    let rendered =
+        if cached.is_some() && !overlay && !notes && image_path.as_ref().is_none_or(|p| p.exists())
+        {
+            Ok(None)
+        } else {
+            render(&page).map(|rendered| Some(rendered.as_image()))
+        };
+    let ocr = match (rendered, cached) {
+        (Err(e), _) => PageOcr::RenderFailed(format!("{:?}", e)),
+        (Ok(rendered), Some(cached)) => PageOcr::Cached(
+            rendered.map(|image| orientation::rotate(&image, cached.degrees)),
+            cached,
+        ),
+        (Ok(Some(rendered)), None) => {
+            if let Some(dir) = renders {
+                let path = dir.join(page_file_name(
+                    file,
+                    index as usize,
+                    "png",
+                    translator.legacy_names,
+                ));
+                if let Err(e) = rendered.save(&path) {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            }
+            let options = translator.ocr_options();
+            let pool = ocr_pool(&translator.ocr)?;
+            PageOcr::Running(pool.spawn(move |lt| ocr::recognize(lt, rendered.clone(), &options)))
+        }
+        (Ok(None), None) => unreachable!(),
+    };
+    Ok(PageJob {
+        index,
+        key,
+        image_path,
+        ocr,
+    })
+}
+
+/// Waits for the OCR of `job` on `page` if it is running, recognizes the page again with
+/// `--ocr-multi-pass` and caches the result.
+async fn finish_ocr(translator: &Translator, page: &PdfPage<'_>, job: &mut PageJob) -> Result<()> {
+    let ocr = std::mem::replace(&mut job.ocr, PageOcr::RenderFailed(String::new()));
+    job.ocr = match ocr {
+        PageOcr::Running(handle) => {
+            let (image, mut ocr_page) = match handle.await? {
+                Ok(recognized) => recognized,
+                Err(e) => match e.downcast::<RenderFailed>() {
+                    Ok(e) => {
+                        job.ocr = PageOcr::RenderFailed(e.0);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                },
+            };
+            if let Some(size) = translator.multi_pass {
+                second_pass(translator, page, size, &image, &mut ocr_page).await?;
+            }
+            translator.cache_ocr(job.key.as_deref(), &ocr_page)?;
+            PageOcr::Recognized(Some(image), ocr_page)
+        }
+        ocr => ocr,
+    };
+    Ok(())
+}
+
+/// Translates a form value or note, keeping the source text if the server fails.
+async fn translate_or_keep(backend: &mut Backend, text: &str) -> String {
+    output::or_source(backend.translate(text).await.ok(), text)
+}
+
+/// Appends the translated form fields and annotations of a page below its OCR text.
+/// Headings go to the source sidecar too so both files stay aligned.
+async fn write_forms(
+    backend: &mut Backend,
+    writer: &mut SegmentWriter,
+    forms: PageText,
+) -> Result<()> {
+    if !forms.fields.is_empty() || forms.unreadable > 0 {
+        writer.write_verbatim(FORM_FIELDS_HEADING, FORM_FIELDS_HEADING)?;
+        for (name, value) in &forms.fields {
+            let translated_name = if name.is_empty() {
+                String::new()
+            } else {
+                translate_or_keep(backend, name).await
+            };
+            let translated_value = translate_or_keep(backend, value).await;
+            writer.write(
+                &format!("{}: {}", name, value),
+                &format!("{}: {}", translated_name, translated_value),
+            )?;
+        }
+        if forms.unreadable > 0 {
+            let note = format!("[{} encrypted fields not readable]", forms.unreadable);
+            writer.write_verbatim(&note, &note)?;
+        }
+    }
+    if !forms.annotations.is_empty() {
+        writer.write_verbatim(ANNOTATIONS_HEADING, ANNOTATIONS_HEADING)?;
+        for contents in &forms.annotations {
+            let translated = translate_or_keep(backend, contents).await;
+            writer.write(contents, &translated)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait(?Send)]
+impl FileHandler for PdfHandler {
+    fn name(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn dependencies(&self) -> &'static [Dependency] {
+        &[Dependency::Pdfium, Dependency::Tesseract]
+    }
+
+    fn existing_outputs(&self, src: &Path, dest: &Path) -> Vec<PathBuf> {
+        // named either way, by a run with or without --legacy-names
+        let mut prefixes = vec![page_prefix(src, false), page_prefix(src, true)];
+        prefixes.dedup();
+        let mut files = Vec::new();
+        for prefix in &prefixes {
+            files.extend(super::files_starting_with(dest, prefix));
+            files.extend(super::files_starting_with(
+                &dest.join(super::ASSETS_DIR),
+                prefix,
+            ));
+        }
+        // the markdown file, the table of contents and their source sidecars
+        let markdown = format!("{}.", src.file_name().unwrap().to_string_lossy());
+        files.extend(super::files_starting_with(dest, &markdown));
+        files
+    }
+
+    async fn handle(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        out: &Path,
+    ) -> Result<HandlerOutput> {
+        let pdfium = translator.pdfium.get()?;
+        let mut output = HandlerOutput {
+            legacy_names_differ: !translator.legacy_names
+                && page_prefix(file, false) != page_prefix(file, true),
+            ..HandlerOutput::default()
+        };
+        let document = pdfium
+            .load_pdf_from_file(file, None)
+            .map_err(|e| anyhow!("could not open the pdf: {:?}", e))?;
+        let encrypted = pdf_forms::is_encrypted(&document);
+        let file_hash = translator.ocr_file_hash(file)?;
+        let pages = document.pages();
+        // later pages are rendered and queued in the OCR pool while earlier ones translate
+        let ahead = translator.ocr.as_ref().map_or(1, |pool| pool.size());
+        let mut queued = VecDeque::new();
+        let selected: Vec<PdfPageIndex> = match &translator.preview {
+            Some(preview) => preview
+                .pages(file, pages.len() as usize)
+                .into_iter()
+                .map(|index| index as PdfPageIndex)
+                .collect(),
+            None => (0..pages.len()).collect(),
+        };
+        let mut next = 0;
+        let renders = match &translator.workspace {
+            Some(workspace) if workspace.keep() => Some(workspace.file_dir(file)?),
+            _ => None,
+        };
+        let mut workers = match (&translator.render_workers, &translator.workspace) {
+            (Some(settings), Some(workspace)) => {
+                let keep = renders.is_some();
+                let dir = match &renders {
+                    Some(dir) => dir.clone(),
+                    None => workspace.file_dir(file)?,
+                };
+                let legacy = translator.legacy_names;
+                Some(RenderWorkers::new(settings, file, legacy, dir, keep))
+            }
+            _ => None,
+        };
+        // markdown puts all pages into one file, text gets a file per page
+        let mut document_writer = match translator.output_format {
+            OutputFormat::Markdown => Some(SegmentWriter::create(
+                out.join(format!(
+                    "{}.md",
+                    file.file_name().unwrap().to_string_lossy()
+                )),
+                &translator.text_format,
+                translator.keep_source_text,
+            )?),
+            OutputFormat::Text | OutputFormat::Overlay => None,
+        };
+        let combined = document_writer.is_some();
+        let bookmarks = outline::read(&document);
+        if !bookmarks.is_empty() {
+            let translated = |page: usize| selected.contains(&((page - 1) as PdfPageIndex));
+            match &mut document_writer {
+                Some(writer) => {
+                    outline::write(
+                        &mut translator.backend,
+                        writer,
+                        &bookmarks,
+                        true,
+                        translated,
+                    )
+                    .await?
+                }
+                None => {
+                    let mut writer = SegmentWriter::create(
+                        out.join(format!(
+                            "{}.contents.txt",
+                            file.file_name().unwrap().to_string_lossy()
+                        )),
+                        &translator.text_format,
+                        translator.keep_source_text,
+                    )?;
+                    outline::write(
+                        &mut translator.backend,
+                        &mut writer,
+                        &bookmarks,
+                        false,
+                        translated,
+                    )
+                    .await?;
+                    output.add(writer);
+                }
+            }
+        }
+        loop {
+            while next < selected.len() && queued.len() < ahead {
+                let job = start_page(
+                    translator,
+                    file,
+                    out,
+                    file_hash,
+                    pages,
+                    selected[next],
+                    renders.as_deref(),
+                    workers.as_mut(),
+                )?;
+                queued.push_back(job);
+                next += 1;
+            }
+            let Some(mut job) = queued.pop_front() else {
+                break;
+            };
+            let index = job.index as usize;
+            let page = pages.get(job.index)?;
+            finish_ocr(translator, &page, &mut job).await?;
+            let mut page_writer = None;
+            let writer = match &mut document_writer {
+                Some(writer) => {
+                    let image = match job.ocr {
+                        PageOcr::RenderFailed(_) => None,
+                        _ => job.image_path.as_deref(),
+                    };
+                    write_page_heading(writer, index + 1, image)?;
+                    writer
+                }
+                None => page_writer.insert(SegmentWriter::create(
+                    out.join(page_file_name(file, index, "txt", translator.legacy_names)),
+                    &translator.text_format,
+                    translator.keep_source_text,
+                )?),
+            };
+            writer.set_page(index + 1);
+            let (image, mut ocr_page) = match job.ocr {
+                PageOcr::RenderFailed(e) => {
+                    let number = index + 1;
+                    eprintln!("{}: page {}: render failed: {}", file.display(), number, e);
+                    let placeholder = format!("[page {}: render failed]", number);
+                    writer.write_verbatim(&placeholder, &placeholder)?;
+                    if let Some(writer) = page_writer {
+                        output.add(writer);
+                    }
+                    output.failed_pages.push(number);
+                    continue;
+                }
+                PageOcr::Cached(image, ocr_page) => {
+                    output.ocr_cache_hits += 1;
+                    (image, ocr_page)
+                }
+                PageOcr::Recognized(image, ocr_page) => {
+                    output.pages += 1;
+                    (image, ocr_page)
+                }
+                PageOcr::Running(_) => unreachable!(),
+            };
+            // one page ahead at most, the next one is recognized now if this one ends in
+            // a broken word
+            if combined && hyphenation::ends_hyphenated(&ocr_page) {
+                if queued.is_empty() && next < selected.len() {
+                    let job = start_page(
+                        translator,
+                        file,
+                        out,
+                        file_hash,
+                        pages,
+                        selected[next],
+                        renders.as_deref(),
+                        workers.as_mut(),
+                    )?;
+                    queued.push_back(job);
+                    next += 1;
+                }
+                if let Some(next_job) = queued.front_mut().filter(|j| j.index as usize == index + 1)
+                {
+                    finish_ocr(translator, &pages.get(next_job.index)?, next_job).await?;
+                    if let PageOcr::Cached(_, next_page) | PageOcr::Recognized(_, next_page) =
+                        &mut next_job.ocr
+                    {
+                        hyphenation::join(&mut ocr_page, next_page);
+                    }
+                }
+            }
+            if let Some(blocks) = ocr_page.second_pass_blocks {
+                output.second_pass.push(SecondPass {
+                    page: index,
+                    blocks,
+                });
+            }
+            if ocr_page.degrees != 0 {
+                eprintln!(
+                    "{}: page {} rotated by {}°",
+                    file.display(),
+                    index,
+                    ocr_page.degrees
+                );
+            }
+            output.masked_blocks += ocr_page.masked_blocks;
+            if let Some(image) = &image {
+                let page = &mut ocr_page;
+                extract_handwriting(translator, file, out, image, index + 1, page, &mut output)?;
+            }
+            let blocks = translate_ocr(
+                &mut translator.backend,
+                &translator.filter,
+                translator.reflow,
+                translator.toc_handling,
+                file,
+                &ocr_page,
+                writer,
+                &mut output,
+            )
+            .await?;
+            let forms = pdf_forms::read_page(&page, encrypted);
+            if forms.unreadable > 0 {
+                eprintln!(
+                    "{}: page {}: {} form fields could not be read, the document is encrypted",
+                    file.display(),
+                    index,
+                    forms.unreadable
+                );
+            }
+            if !forms.is_empty() {
+                write_forms(&mut translator.backend, writer, forms).await?;
+            }
+            if let Some(writer) = page_writer {
+                output.add(writer);
+            }
+            if let Some(image_path) = job.image_path {
+                // the saved page image stays unmasked
+                match image {
+                    Some(image) if translator.output_format == OutputFormat::Overlay => {
+                        save_overlay(translator, &image, &blocks, &image_path)?
+                    }
+                    Some(image) => save_page_image(translator, &image, &image_path)?,
+                    None => {}
+                }
+                output.outputs.push(image_path);
+            }
+        }
+        if let Some(writer) = document_writer {
+            output.add(writer);
+        }
+        if translator.pdf_attachments {
+            let stem = file.file_stem().unwrap().to_string_lossy().to_string();
+            let dir = out.join(stem).join("attachments");
+            for attachment in document.attachments().iter() {
+                let contents = attachment.save_to_bytes().map_err(|e| {
+                    anyhow!("could not read attachment {}: {:?}", attachment.name(), e)
+                })?;
+                let extensions = &translator.attachment_extensions;
+                if let Some(path) =
+                    save_attachment(&attachment.name(), &contents, &dir, extensions)?
+                {
+                    output.outputs.push(path.clone());
+                    output.attachments.push(path);
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// The text layer of the first page, or its OCR for a scan.
+    async fn excerpt(
+        &self,
+        translator: &mut Translator,
+        file: &Path,
+        max_chars: usize,
+    ) -> Result<Option<String>> {
+        let pdfium = translator.pdfium.get()?;
+        let document = pdfium
+            .load_pdf_from_file(file, None)
+            .map_err(|e| anyhow!("could not open the pdf: {:?}", e))?;
+        let Ok(page) = document.pages().get(0) else {
+            return Ok(None);
+        };
+        let text = page.text().map(|text| text.all()).unwrap_or_default();
+        if text.chars().any(char::is_alphabetic) {
+            return Ok(Some(text.chars().take(max_chars).collect()));
+        }
+        let key = translator.ocr_key(translator.ocr_file_hash(file)?, 0);
+        if let Some(cached) = translator.cached_ocr(key.as_deref())? {
+            return Ok(Some(ocr_excerpt(&cached, max_chars)));
+        }
+        let rendered = render(&page)
+            .map_err(|e| anyhow!("could not render page 1: {:?}", e))?
+            .as_image();
+        let options = translator.ocr_options();
+        let (_, recognized) = ocr_pool(&translator.ocr)?
+            .run(move |lt| ocr::recognize(lt, rendered.clone(), &options))
+            .await?;
+        translator.cache_ocr(key.as_deref(), &recognized)?;
+        Ok(Some(ocr_excerpt(&recognized, max_chars)))
+    }
+}
@@ -0,0 +1,88 @@
+//! `--extract-handwriting`: handwritten margin notes come out of tesseract as a few
+//! letters of noise. Blocks it reads with little confidence although they carry a fair
+//! amount of ink are taken for handwriting and left out of the segments. Each is cut out
+//! of the page into `<stem>/handwriting/` for someone to read, and the outputs get a
+//! placeholder in its place, or the translated text of the `handwriting_recognizer`
+//! command of the config file.
+
+use crate::ocr::OcrBlock;
+use crate::profiles;
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageFormat};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Blocks recognized at least this confidently are print.
+const MAX_CONFIDENCE: i32 = 40;
+/// Share of ink from which an unclear block is writing rather than specks or a frame.
+const MIN_INK: f32 = 0.04;
+/// Regions with a side shorter than this many pixels hold no note.
+const MIN_SIDE: i32 = 20;
+
+/// Written for a note that wasn't recognized, in the source text and the translation.
+pub const PLACEHOLDER: &str = "[handwritten note, not recognized — see page image]";
+
+/// Takes the blocks of `segments` that look handwritten out of them and returns them
+/// top to bottom, with their boxes on `page`.
+pub fn detect(page: &DynamicImage, segments: &mut Vec<OcrBlock>) -> Vec<OcrBlock> {
+    let (mut notes, print): (Vec<OcrBlock>, Vec<OcrBlock>) = std::mem::take(segments)
+        .into_iter()
+        .partition(|block| handwritten(page, block));
+    *segments = print;
+    notes.sort_by_key(|b| (b.bbox[1], b.bbox[0]));
+    notes
+}
+
+fn handwritten(page: &DynamicImage, block: &OcrBlock) -> bool {
+    let [x, y, w, h] = block.bbox;
+    if block.confidence >= MAX_CONFIDENCE || w < MIN_SIDE || h < MIN_SIDE {
+        return false;
+    }
+    let region = page.crop_imm(x.max(0) as u32, y.max(0) as u32, w as u32, h as u32);
+    profiles::ink(&region.to_luma8()) >= MIN_INK
+}
+
+/// Cuts `notes` out of `page`, number `page_number` counting from 1, into `dir` as
+/// `page-N-K.png`, and replaces their text with what `recognizer` reads from the crop,
+/// empty for a placeholder. Returns the paths of the crops.
+pub fn extract(
+    recognizer: Option<&[String]>,
+    page: &DynamicImage,
+    page_number: usize,
+    notes: &mut [OcrBlock],
+    dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut crops = Vec::new();
+    for (index, note) in notes.iter_mut().enumerate() {
+        let [x, y, w, h] = note.bbox;
+        let region = page.crop_imm(x.max(0) as u32, y.max(0) as u32, w as u32, h as u32);
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("page-{}-{}.png", page_number, index + 1));
+        region.save_with_format(&path, ImageFormat::Png)?;
+        note.text = match recognizer {
+            Some(command) => recognize(command, &path).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path.display(), e);
+                String::new()
+            }),
+            None => String::new(),
+        };
+        crops.push(path);
+    }
+    Ok(crops)
+}
+
+/// The stdout of `command` run with the path of a crop appended.
+fn recognize(command: &[String], crop: &Path) -> Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("handwriting_recognizer is empty"))?;
+    let output = Command::new(program).args(args).arg(crop).output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
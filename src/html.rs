@@ -0,0 +1,69 @@
+//! `--html-mode server`: HTML bodies sent as markup with LibreTranslate's `format=html`,
+//! one block element at a time, so a sentence with a `<b>` or a link inside is translated
+//! whole and the server keeps the tags where the words went. A translation whose tags
+//! aren't those of its source is translated again as text, and a server refusing the
+//! format gets text for the rest of the run, as with `--html-mode client`, where the body
+//! is turned into text before it is sent.
+
+use clap::ValueEnum;
+use mail_parser::decoders::html::html_to_text;
+use regex::Regex;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HtmlMode {
+    /// turn HTML into text and send the text
+    #[default]
+    Client,
+    /// send the markup of each block element
+    Server,
+}
+
+/// Elements that start a block; the blocks between them are sent.
+const BLOCKS: &str =
+    "p|div|li|ul|ol|h[1-6]|td|th|tr|table|blockquote|pre|br|hr|body|section|article";
+
+fn tag() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"<(/?)([a-zA-Z][a-zA-Z0-9]*)\b[^>]*>").unwrap())
+}
+
+fn block() -> &'static Regex {
+    static BLOCK: OnceLock<Regex> = OnceLock::new();
+    BLOCK.get_or_init(|| Regex::new(&format!(r"(?i)</?(?:{})\b[^>]*>", BLOCKS)).unwrap())
+}
+
+fn unsent() -> &'static Regex {
+    static UNSENT: OnceLock<Regex> = OnceLock::new();
+    UNSENT.get_or_init(|| {
+        Regex::new(r"(?is)<head\b.*?</head>|<style\b.*?</style>|<script\b.*?</script>|<!--.*?-->")
+            .unwrap()
+    })
+}
+
+/// The block elements of `html` with text in them, their inline markup kept.
+pub fn blocks(html: &str) -> Vec<String> {
+    let html = unsent().replace_all(html, "");
+    block()
+        .split(&html)
+        .map(str::trim)
+        .filter(|block| !text(block).is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Text of an HTML block.
+pub fn text(html: &str) -> String {
+    html_to_text(html).trim().to_string()
+}
+
+/// Whether `translated` has the tags of `source`, opened and closed in the same order.
+pub fn same_tags(source: &str, translated: &str) -> bool {
+    let tags = |html: &str| -> Vec<(bool, String)> {
+        tag()
+            .captures_iter(html)
+            .map(|c| (c[1].is_empty(), c[2].to_lowercase()))
+            .collect()
+    };
+    tags(source) == tags(translated)
+}
@@ -0,0 +1,66 @@
+//! Words hyphenated across a page break. In outputs holding all pages of a document, a
+//! page whose last block ends in a word fragment and a hyphen takes the rest of the
+//! sentence from the first block of the next page if that goes on in lowercase, so the
+//! sentence is translated whole, at its place on the earlier page.
+
+use crate::ocr::OcrPage;
+
+/// Hyphens a word is broken with at the end of a line, the soft one included.
+const HYPHENS: [char; 4] = ['-', '\u{AD}', '\u{2010}', '¬'];
+
+/// `text` without its hyphen if it ends in a broken word.
+fn fragment(text: &str) -> Option<&str> {
+    let text = text.trim_end();
+    let stem = text.strip_suffix(HYPHENS)?;
+    stem.chars()
+        .next_back()
+        .is_some_and(char::is_alphabetic)
+        .then_some(stem)
+}
+
+/// Whether the last block of `page` ends in a broken word.
+pub fn ends_hyphenated(page: &OcrPage) -> bool {
+    page.segments
+        .last()
+        .is_some_and(|block| fragment(&block.text).is_some())
+}
+
+/// Length of the sentence `text` starts with, up to its final punctuation; all of it if
+/// it doesn't end.
+fn sentence_len(text: &str) -> usize {
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends = matches!(c, '.' | '!' | '?' | '…');
+        if ends && chars.peek().is_none_or(|(_, next)| next.is_whitespace()) {
+            return i + c.len_utf8();
+        }
+    }
+    text.len()
+}
+
+/// Moves the rest of the sentence broken off at the end of `page` from the start of
+/// `next` onto its last block. Returns whether there was one.
+pub fn join(page: &mut OcrPage, next: &mut OcrPage) -> bool {
+    let Some(first) = next.segments.first() else {
+        return false;
+    };
+    let continuation = first.text.trim_start();
+    if !continuation.chars().next().is_some_and(char::is_lowercase) {
+        return false;
+    }
+    let Some(last) = page.segments.last_mut() else {
+        return false;
+    };
+    let Some(stem) = fragment(&last.text) else {
+        return false;
+    };
+    let len = sentence_len(continuation);
+    last.text = format!("{}{}", stem, &continuation[..len]);
+    let rest = continuation[len..].trim_start().to_string();
+    if rest.is_empty() {
+        next.segments.remove(0);
+    } else {
+        next.segments[0].text = rest;
+    }
+    true
+}
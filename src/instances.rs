@@ -0,0 +1,216 @@
+//! Several translation servers: `libretranslate_url` can be a list, and each request goes
+//! to the server with the fewest requests in flight, in turn when that's a tie. A server
+//! that can't be reached, times out or answers 429, 502, 503 or 504 is left out for
+//! [`EJECT_FOR`]; the next request after that is its probe, which brings it back or
+//! leaves it out again. A request failing that way is sent once more to another server
+//! if there is one that isn't left out.
+
+use crate::error::{error, ErrorKind};
+use crate::network::{self, Network};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a server that failed is left out.
+pub const EJECT_FOR: Duration = Duration::from_secs(30);
+
+/// `libretranslate_url`, one URL or a list of them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ServerUrls {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ServerUrls {
+    pub fn list(&self) -> Vec<String> {
+        match self {
+            ServerUrls::One(url) => vec![url.clone()],
+            ServerUrls::Many(urls) => urls.clone(),
+        }
+    }
+}
+
+/// Requests and failures of one server, for the summary.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InstanceStats {
+    pub url: String,
+    pub requests: usize,
+    /// requests that failed as described above, not those the server refused
+    pub errors: usize,
+    /// times it was left out
+    pub ejections: usize,
+}
+
+pub struct Instance {
+    pub url: String,
+    pub client: surf::Client,
+    /// proxy the client goes through, without its password, for error messages
+    proxy: Option<String>,
+    outstanding: AtomicUsize,
+    requests: AtomicUsize,
+    errors: AtomicUsize,
+    ejections: AtomicUsize,
+    /// left out until then
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Instance {
+    /// Error of a failed request, saying whether it went through a proxy.
+    pub fn connection_error(&self, e: surf::Error) -> anyhow::Error {
+        let e = match &self.proxy {
+            Some(proxy) => anyhow!("{} (via proxy {})", e, proxy),
+            None => anyhow!("{} (no proxy)", e),
+        };
+        error(ErrorKind::BackendUnavailable, e)
+    }
+
+    /// The error for an answer with `status` if it says the server is overloaded or down.
+    pub fn unavailable(&self, status: surf::StatusCode) -> Option<anyhow::Error> {
+        use surf::StatusCode::*;
+        matches!(
+            status,
+            TooManyRequests | BadGateway | ServiceUnavailable | GatewayTimeout
+        )
+        .then(|| {
+            let e = anyhow!("{} answered {}", self.url, status);
+            error(ErrorKind::BackendUnavailable, e)
+        })
+    }
+
+    fn ejected_until(&self) -> Option<Instant> {
+        *self.ejected_until.lock().unwrap()
+    }
+}
+
+pub struct Instances {
+    list: Vec<Instance>,
+    /// where the turn of ties starts next
+    next: AtomicUsize,
+}
+
+impl Instances {
+    pub fn new(urls: &[String], pool_size: usize, network: &Network) -> Result<Self> {
+        if urls.is_empty() {
+            return Err(anyhow!("libretranslate_url is an empty list"));
+        }
+        let list = urls
+            .iter()
+            .map(|url| {
+                Ok(Instance {
+                    proxy: network.proxy(url).map(|p| network::redact(&p)),
+                    url: url.trim_end_matches('/').to_string(),
+                    client: network.client(url, pool_size)?,
+                    outstanding: AtomicUsize::new(0),
+                    requests: AtomicUsize::new(0),
+                    errors: AtomicUsize::new(0),
+                    ejections: AtomicUsize::new(0),
+                    ejected_until: Mutex::new(None),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Instances {
+            list,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn all(&self) -> &[Instance] {
+        &self.list
+    }
+
+    /// The server for a one-off request, like asking for the languages.
+    pub fn any(&self) -> &Instance {
+        &self.list[self.pick(None)]
+    }
+
+    /// Index of the server for the next request: of those not left out and other than
+    /// `avoid` if possible, the one with the fewest requests in flight. If all are left
+    /// out, the one that comes back first.
+    fn pick(&self, avoid: Option<usize>) -> usize {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let order = (0..self.list.len()).map(|k| (start + k) % self.list.len());
+        let available: Vec<usize> = order
+            .filter(|&i| {
+                self.list[i]
+                    .ejected_until()
+                    .is_none_or(|until| until <= now)
+            })
+            .collect();
+        let preferred: Vec<usize> = available
+            .iter()
+            .copied()
+            .filter(|&i| Some(i) != avoid)
+            .collect();
+        let candidates = if preferred.is_empty() {
+            available
+        } else {
+            preferred
+        };
+        let outstanding = |&i: &usize| self.list[i].outstanding.load(Ordering::Relaxed);
+        match candidates.iter().min_by_key(|i| outstanding(i)) {
+            Some(&i) => i,
+            None => (0..self.list.len())
+                .min_by_key(|&i| self.list[i].ejected_until())
+                .unwrap(),
+        }
+    }
+
+    /// Picks the server for a request as [`Instances::pick`] does and counts it in flight.
+    pub fn start(&self, avoid: Option<usize>) -> (usize, &Instance) {
+        let i = self.pick(avoid);
+        let instance = &self.list[i];
+        instance.outstanding.fetch_add(1, Ordering::Relaxed);
+        instance.requests.fetch_add(1, Ordering::Relaxed);
+        (i, instance)
+    }
+
+    /// Ends a request to server `i`, leaving it out if it `failed` as described above.
+    /// Returns whether sending the request to another server might help.
+    pub fn finish(&self, i: usize, failed: Option<&anyhow::Error>) -> bool {
+        let instance = &self.list[i];
+        instance.outstanding.fetch_sub(1, Ordering::Relaxed);
+        let Some(e) = failed.filter(|e| ErrorKind::of(e) == ErrorKind::BackendUnavailable) else {
+            *instance.ejected_until.lock().unwrap() = None;
+            return false;
+        };
+        instance.errors.fetch_add(1, Ordering::Relaxed);
+        if self.list.len() == 1 {
+            return false;
+        }
+        self.eject(i, e);
+        true
+    }
+
+    /// Leaves server `i` out for [`EJECT_FOR`] because of `e`.
+    pub fn eject(&self, i: usize, e: &anyhow::Error) {
+        let instance = &self.list[i];
+        instance.ejections.fetch_add(1, Ordering::Relaxed);
+        *instance.ejected_until.lock().unwrap() = Some(Instant::now() + EJECT_FOR);
+        eprintln!(
+            "{} left out for {} s: {}",
+            instance.url,
+            EJECT_FOR.as_secs(),
+            e
+        );
+    }
+
+    pub fn stats(&self) -> Vec<InstanceStats> {
+        self.list
+            .iter()
+            .map(|instance| InstanceStats {
+                url: instance.url.clone(),
+                requests: instance.requests.load(Ordering::Relaxed),
+                errors: instance.errors.load(Ordering::Relaxed),
+                ejections: instance.ejections.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+//! Keys of a `translate` run in a terminal: `p` pauses it, `r` resumes it, `s` prints where
+//! it is and `q` stops it after the file in progress, like SIGTERM. A paused run starts no
+//! new file and sends no new request; those in flight finish. Without a terminal on stdin
+//! and stderr, with `--interactive` or with a file list on stdin, no key is read.
+
+use crate::events::CancellationToken;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// Whether the run is paused, shared by the file loop and the backend. Clones share it.
+#[derive(Clone)]
+pub struct Pause {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Pause {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+}
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        self.sender.send_replace(paused);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Returns once the run isn't paused.
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        // the sender lives as long as `self`
+        let _ = receiver.wait_for(|paused| !paused).await;
+    }
+}
+
+/// Where the run is, for `s`, as of the start of the file in progress.
+#[derive(Default)]
+pub struct Status {
+    pub file: Option<PathBuf>,
+    pub processed: usize,
+    pub failed: usize,
+    /// files queued after the one in progress
+    pub left: usize,
+    pub characters: usize,
+}
+
+#[cfg(unix)]
+static SAVED: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// The terminal while keys are read, given its settings back when dropped, also as a
+/// panic unwinds the file loop. Ctrl-C and fatal errors call [`restore`].
+pub struct Terminal;
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Reads the keys on a thread of their own if stdin and stderr are a terminal, which gets
+/// them one at a time without echo until the returned [`Terminal`] is dropped.
+pub fn start(
+    pause: Pause,
+    cancellation: CancellationToken,
+    status: Arc<Mutex<Status>>,
+) -> Option<Terminal> {
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() || !raw_mode() {
+        return None;
+    }
+    let started = Instant::now();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut key = [0u8];
+        while let Ok(1) = stdin.read(&mut key) {
+            match key[0] {
+                b'p' if !pause.is_paused() => {
+                    pause.set(true);
+                    eprintln!("paused, the requests in flight finish; r resumes");
+                }
+                b'r' if pause.is_paused() => {
+                    pause.set(false);
+                    eprintln!("resumed");
+                }
+                b's' => print_status(&status.lock().unwrap(), pause.is_paused(), started),
+                b'q' => {
+                    eprintln!("stopping after the file in progress");
+                    cancellation.cancel();
+                    pause.set(false);
+                }
+                _ => (),
+            }
+        }
+    });
+    eprintln!("p pauses, r resumes, s shows the status, q stops after the file in progress");
+    Some(Terminal)
+}
+
+fn print_status(status: &Status, paused: bool, started: Instant) {
+    let minutes = started.elapsed().as_secs() / 60;
+    let state = if paused { "paused" } else { "running" };
+    eprintln!(
+        "{} for {} min: {} files processed, {} failed, {} left, {} characters sent",
+        state, minutes, status.processed, status.failed, status.left, status.characters
+    );
+    if let Some(file) = &status.file {
+        eprintln!("  at {}", file.display());
+    }
+}
+
+/// Turns off line buffering and echo of the terminal on stdin, keeping Ctrl-C.
+#[cfg(unix)]
+fn raw_mode() -> bool {
+    // termios is plain data, filled in by tcgetattr before it is read
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return false;
+        }
+        let saved = termios;
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+            return false;
+        }
+        *SAVED.lock().unwrap() = Some(saved);
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn raw_mode() -> bool {
+    false
+}
+
+/// Gives the terminal its settings back, if [`start`] changed them.
+pub fn restore() {
+    #[cfg(unix)]
+    if let Some(saved) = SAVED.lock().unwrap().take() {
+        // `saved` came from tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! `--normalize-numbers` and `--normalize-dates`: numbers and dates in translations are
+//! written as English readers and tools expect, "1 234,56" as "1,234.56" and "12.03.2021"
+//! as "2021-03-12". The server leaves them as they were in the source. Only numbers and
+//! dates standing on their own are converted, never digits in a word, a code, a path or an
+//! ID number of `[pii_patterns]`. Anything that could be read two ways is left alone:
+//! "1,234" may already be an English thousand, "01.02.03" doesn't say which part is the
+//! year, and "12 300" with an ordinary space may be two numbers, so grouping by ordinary
+//! spaces is only trusted with a decimal part or at least two groups.
+
+use crate::pii::Pii;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use regex::{Captures, Regex};
+use std::ops::Range;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DateFormat {
+    /// leave dates as they are
+    #[default]
+    None,
+    /// 2021-03-12
+    Iso,
+    /// 03/12/2021
+    Us,
+}
+
+/// Characters next to which digits are part of a code, a path or an identifier.
+const CODE_CHARS: &str = "_/\\.:#@=&+-";
+/// Spaces that group digits in thousands besides the ordinary one.
+const GROUP_SPACES: [char; 2] = ['\u{a0}', '\u{202f}'];
+
+fn date() -> &'static Regex {
+    static DATE: OnceLock<Regex> = OnceLock::new();
+    DATE.get_or_init(|| Regex::new(r"(\d{1,2})\.(\d{1,2})\.(\d{2,4})").unwrap())
+}
+
+fn number() -> &'static Regex {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    NUMBER.get_or_init(|| {
+        Regex::new(r"(?P<int>\d{1,3}(?:[ \u{a0}\u{202f}]\d{3})+|\d+)(?:,(?P<frac>\d+))?").unwrap()
+    })
+}
+
+fn code_span() -> &'static Regex {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    CODE.get_or_init(|| Regex::new(r"`[^`\n]*`").unwrap())
+}
+
+/// Conversions asked for and how many were made.
+#[derive(Clone, Debug, Default)]
+pub struct Conversions {
+    pub numbers: bool,
+    pub dates: DateFormat,
+    pub converted_numbers: usize,
+    pub converted_dates: usize,
+}
+
+/// Whether the match at `range` of `text` stands on its own: no letter, digit or code
+/// character touches it. A full stop, comma or colon after it is fine if nothing follows
+/// it.
+fn standalone(text: &str, range: &Range<usize>) -> bool {
+    let code = |c: char| c.is_alphanumeric() || CODE_CHARS.contains(c);
+    let mut before = text[..range.start].chars().rev();
+    match before.next() {
+        // a minus sign at the start of a word
+        Some('-') if before.next().is_none_or(char::is_whitespace) => {}
+        Some(c) if code(c) || c == ',' => return false,
+        _ => {}
+    }
+    let mut after = text[range.end..].chars();
+    match after.next() {
+        Some('.' | ',' | ':') => after.next().is_none_or(|c| !code(c) && c != ','),
+        Some(c) => !code(c),
+        None => true,
+    }
+}
+
+impl Conversions {
+    pub fn enabled(&self) -> bool {
+        self.numbers || self.dates != DateFormat::None
+    }
+
+    /// `text` with its numbers and dates converted, leaving alone the tokens of `pii`.
+    pub fn apply(&mut self, text: &str, pii: &Pii) -> String {
+        let mut text = text.to_string();
+        if self.dates != DateFormat::None {
+            let (converted, count) = replace(&text, pii, date(), |c| self.date(c));
+            text = converted;
+            self.converted_dates += count;
+        }
+        if self.numbers {
+            let (converted, count) = replace(&text, pii, number(), number_in_english);
+            text = converted;
+            self.converted_numbers += count;
+        }
+        text
+    }
+
+    /// The date `12.03.2021` in the format asked for, `None` if it isn't a date or could be
+    /// read otherwise.
+    fn date(&self, captures: &Captures) -> Option<String> {
+        if captures[3].len() != 4 {
+            return None;
+        }
+        let day: u32 = captures[1].parse().ok()?;
+        let month: u32 = captures[2].parse().ok()?;
+        let year: i32 = captures[3].parse().ok()?;
+        let date = NaiveDate::from_ymd_opt(year, month, day)?;
+        match self.dates {
+            DateFormat::Iso => Some(date.format("%Y-%m-%d").to_string()),
+            DateFormat::Us => Some(date.format("%m/%d/%Y").to_string()),
+            DateFormat::None => None,
+        }
+    }
+}
+
+/// The number `1 234,56` as `1,234.56`, `None` if it's written the English way already or
+/// could be.
+fn number_in_english(captures: &Captures) -> Option<String> {
+    let int = &captures["int"];
+    let frac = captures.name("frac").map(|f| f.as_str());
+    let groups = int.split([' ', '\u{a0}', '\u{202f}']).count();
+    let grouped = groups > 1;
+    if grouped && !int.contains(GROUP_SPACES) && frac.is_none() && groups < 3 {
+        return None;
+    }
+    // "0,125" is no thousand
+    let thousand = int.len() <= 3 && !int.starts_with('0') && frac.is_some_and(|f| f.len() == 3);
+    if !grouped && (frac.is_none() || thousand) {
+        return None;
+    }
+    let mut english = int.replace([' ', '\u{a0}', '\u{202f}'], ",");
+    if let Some(frac) = frac {
+        english.push('.');
+        english.push_str(frac);
+    }
+    Some(english)
+}
+
+/// `text` with the matches of `regex` that stand on their own, outside code spans and the
+/// tokens of `pii`, replaced by `convert`, and how many were.
+fn replace(
+    text: &str,
+    pii: &Pii,
+    regex: &Regex,
+    mut convert: impl FnMut(&Captures) -> Option<String>,
+) -> (String, usize) {
+    let mut protected = pii.tokens(text);
+    protected.extend(code_span().find_iter(text).map(|m| m.range()));
+    let mut converted = String::with_capacity(text.len());
+    let mut count = 0;
+    let mut last = 0;
+    for captures in regex.captures_iter(text) {
+        let range = captures.get(0).unwrap().range();
+        let overlaps = protected
+            .iter()
+            .any(|p| p.start < range.end && range.start < p.end);
+        if overlaps || !standalone(text, &range) {
+            continue;
+        }
+        let Some(replacement) = convert(&captures) else {
+            continue;
+        };
+        converted.push_str(&text[last..range.start]);
+        converted.push_str(&replacement);
+        last = range.end;
+        count += 1;
+    }
+    converted.push_str(&text[last..]);
+    (converted, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(text: &str) -> String {
+        let mut conversions = Conversions {
+            numbers: true,
+            ..Conversions::default()
+        };
+        conversions.apply(text, &Pii::default())
+    }
+
+    fn dates(text: &str, format: DateFormat) -> String {
+        let mut conversions = Conversions {
+            dates: format,
+            ..Conversions::default()
+        };
+        conversions.apply(text, &Pii::default())
+    }
+
+    #[test]
+    fn russian_numbers_are_written_the_english_way() {
+        assert_eq!(numbers("1 234,56 rubles"), "1,234.56 rubles");
+        assert_eq!(numbers("12\u{a0}300 pages"), "12,300 pages");
+        assert_eq!(numbers("1\u{202f}000"), "1,000");
+        assert_eq!(numbers("1 234 567 residents"), "1,234,567 residents");
+        assert_eq!(numbers("pi is 3,14."), "pi is 3.14.");
+        assert_eq!(numbers("0,125 l"), "0.125 l");
+        assert_eq!(numbers("-3,5 degrees"), "-3.5 degrees");
+    }
+
+    #[test]
+    fn numbers_that_could_be_read_two_ways_are_left_alone() {
+        // an English thousand already, or two numbers
+        assert_eq!(numbers("1,234 cars"), "1,234 cars");
+        assert_eq!(numbers("in 12 300 cases"), "in 12 300 cases");
+        assert_eq!(numbers("in 2021"), "in 2021");
+        assert_eq!(numbers("1, 2, 3"), "1, 2, 3");
+    }
+
+    #[test]
+    fn digits_in_codes_and_identifiers_are_left_alone() {
+        for text in [
+            "version v1,5",
+            "GOST-1234,5",
+            "file_3,14.txt",
+            "see `3,14` in the log",
+            "path/1 234,56",
+            "passport 45 08 123456",
+            "SNILS 123-456-789 01",
+            "INN 7707083893",
+        ] {
+            assert_eq!(numbers(text), text);
+        }
+    }
+
+    #[test]
+    fn dates_are_converted_to_the_format_asked_for() {
+        assert_eq!(dates("on 12.03.2021.", DateFormat::Iso), "on 2021-03-12.");
+        assert_eq!(dates("1.2.2021", DateFormat::Iso), "2021-02-01");
+        assert_eq!(dates("12.03.2021", DateFormat::Us), "03/12/2021");
+        assert_eq!(dates("12.03.2021", DateFormat::None), "12.03.2021");
+    }
+
+    #[test]
+    fn ambiguous_and_impossible_dates_are_left_alone() {
+        for text in [
+            "01.02.03",
+            "31.02.2021",
+            "12.13.2021",
+            "v12.03.2021",
+            "1.12.03.2021",
+            "12.03.2021:15",
+        ] {
+            assert_eq!(dates(text, DateFormat::Iso), text);
+        }
+    }
+
+    #[test]
+    fn conversions_are_counted() {
+        let mut conversions = Conversions {
+            numbers: true,
+            dates: DateFormat::Iso,
+            ..Conversions::default()
+        };
+        let text = conversions.apply("12.03.2021: 1 234,5 and 2,5 but 01.02.03", &Pii::default());
+        assert_eq!(text, "2021-03-12: 1,234.5 and 2.5 but 01.02.03");
+        assert_eq!(conversions.converted_dates, 1);
+        assert_eq!(conversions.converted_numbers, 2);
+        assert!(conversions.enabled());
+        assert!(!Conversions::default().enabled());
+    }
+}
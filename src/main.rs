@@ -1,21 +1,335 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use archive::Archive;
+use bundle::{Bundle, Deferral};
 use clap::*;
-use docx_rust::DocxFile;
-use image::*;
-use libretranslate::{translate_url, Language};
+use columns::Columns;
+use compression::Codec;
+use confidence::{Confidence, PageConfidence};
+use docx_tables::TableFormat;
+use error::ErrorKind;
+use events::{CancellationToken, Event};
+use filter::{Script, SegmentFilter};
+use futures_util::StreamExt;
+use guard::Guardrails;
+use handlers::{Ahead, Dependency, HandlerRegistry};
+use html::HtmlMode;
+use instances::{InstanceStats, ServerUrls};
+use libretranslate::Language;
+use locale::DateFormat;
+use manifest::Manifest;
+use mapping::{Mapping, MappingFormat, MappingWriter};
+use markers::Markers;
+use mask::Mask;
+use multi_pass::SecondPass;
+use name_case::FilenameCase;
+use names::NamesMode;
+use network::Network;
+use ocr::OcrCache;
+use order::Order;
+use orientation::Orientation;
+use output::{Encoding, Failure, Newline, OutputFormat, TextFormat};
+use overlay::Overlay;
+use overrides::{OverrideTree, Overrides, Settings};
 use pdfium_render::prelude::*;
+use pii::Pii;
+use pipeline::{Pipeline, Walked};
+use post_edit::{Granularity, PostEdit};
+use presets::Preset;
+use preview::Preview;
+use profiles::Profile;
+use prompt::Interactive;
+use qa::QaSampler;
+use qa_sample::QaSample;
+use render_workers::RenderSettings;
 use serde::*;
-use std::io::Write;
-use std::{fs::File, io::Cursor, path::Path};
+use settle::Settle;
+use source::Spill;
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use toc::TocHandling;
+use translator::{bind_pdfium, pdfium_error, Translator};
+use unsupported::Unsupported;
+use usage::Usage;
 use walkdir::*;
+use workspace::TempWorkspace;
+
+mod archive;
+mod backend;
+mod batch;
+mod bundle;
+mod charset;
+mod check;
+mod columns;
+mod compression;
+mod confidence;
+mod diff;
+mod docx_images;
+mod docx_tables;
+mod echo;
+mod error;
+mod events;
+mod feedback;
+mod filter;
+mod guard;
+mod handlers;
+mod handwriting;
+mod html;
+mod hyphenation;
+mod instances;
+mod keys;
+mod locale;
+mod manifest;
+mod mapping;
+mod markers;
+mod mask;
+mod metrics;
+mod mixed;
+mod multi_pass;
+mod name_case;
+mod names;
+mod network;
+mod normalize;
+mod ocr;
+mod ocr_pool;
+mod office_crypto;
+mod order;
+mod orientation;
+mod outline;
+mod output;
+mod overlay;
+mod overrides;
+mod pdf_forms;
+mod photo;
+mod pii;
+mod pipeline;
+mod plan;
+mod post_edit;
+mod presets;
+mod preview;
+mod probe;
+mod profiles;
+mod prompt;
+mod qa;
+mod qa_sample;
+mod reflow;
+mod render_workers;
+mod retry;
+mod rotated;
+mod scan_tables;
+mod schedule;
+mod serve;
+mod settle;
+mod source;
+mod stamp;
+mod stats;
+mod times;
+mod titles;
+mod toc;
+mod translator;
+mod unsupported;
+mod usage;
+mod workspace;
 
 const TARGET_LANG: Language = Language::English;
 const SOURCE_LANG: Language = Language::Russian;
+/// Prefix for segments that were not sent to the backend because the budget ran out.
+const UNTRANSLATED_MARKER: &str = "[untranslated] ";
+/// Prefix of translations that failed the guardrails even when requested again.
+const SUSPECT_MARKER: &str = "[suspect] ";
+/// Prefix of segments kept untranslated because their translation altered an ID number.
+const PII_MARKER: &str = "[pii-preserved] ";
+/// Name of the report in an `--output-archive`.
+const REPORT_ENTRY: &str = "report.json";
+/// Reasons files are skipped before their handler is called.
+const EMPTY_FILE: &str = "empty file";
+const SPECIAL_FILE: &str = "not a regular file";
+
+/// Read from the current directory when `--config` isn't given and it has one, otherwise
+/// from next to the executable.
+const CONFIG_FILE: &str = "config.toml";
+const DEFAULT_CACHE_DIR: &str = ".dir-translate-cache";
+/// Environment variables overriding a key of the config file, e.g. `DIR_TRANSLATE_POOL_SIZE`.
+const ENV_PREFIX: &str = "DIR_TRANSLATE_";
+/// Time `--healthcheck` waits for all checks at most.
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Deserialize)]
 struct Config {
+    /// relative to the config file's directory unless absolute
     tesserac_data: String,
-    libretranslate_url: String,
+    /// one URL or a list of them to spread the requests over
+    libretranslate_url: ServerUrls,
+    /// handlers to turn off, same as `--disable-handler`
+    #[serde(default)]
+    disabled_handlers: Vec<String>,
+    /// handlers to use exclusively, same as `--enable-only`
+    enabled_handlers: Option<Vec<String>>,
+    /// connections kept open to the translation server
+    pool_size: Option<usize>,
+    #[serde(default)]
+    guardrails: Guardrails,
+    /// several segments per request, packed and sized as in `batch`
+    batching: Option<batch::Batching>,
+    /// tesseract instances run in parallel, the number of physical cores by default
+    ocr_threads: Option<usize>,
+    /// OpenMP threads of each tesseract instance, `OMP_THREAD_LIMIT` or 1 by default
+    tesseract_threads: Option<usize>,
+    /// longest side in pixels images are scaled down to before OCR
+    max_image_side: Option<u32>,
+    /// OCR blocks with more characters are split into paragraphs at their line gaps
+    max_block_chars: Option<usize>,
+    /// script of a source language, e.g. `sr = "latin"`; segments without a letter in it
+    /// aren't translated
+    #[serde(default)]
+    scripts: BTreeMap<String, Script>,
+    /// `proxy_url`, `ca_cert_path` and `danger_accept_invalid_certs`
+    #[serde(flatten)]
+    network: Network,
+    /// pdfium library file or the directory holding it, next to the executable by default
+    pdfium_path: Option<PathBuf>,
+    /// settings of the files of a profile, e.g. `[profiles.scan-form]`, below those of
+    /// the override files
+    #[serde(default)]
+    profiles: BTreeMap<Profile, Overrides>,
+    /// Latin spelling of letters for `--names-mode`, added to the Russian table, e.g.
+    /// `"і" = "i"`
+    #[serde(default)]
+    transliteration: BTreeMap<String, String>,
+    /// words written as listed here in translated names, whatever `--filename-case`, e.g.
+    /// `["USB", "PhD"]`
+    #[serde(default)]
+    acronyms: Vec<String>,
+    /// command and arguments reading a handwritten note for `--extract-handwriting`, run
+    /// with the path of the cut-out png appended and its stdout taken for the text
+    handwriting_recognizer: Option<Vec<String>>,
+    /// command and arguments fixing up the translations, see `post_edit`
+    post_edit_cmd: Option<Vec<String>>,
+    /// whether `post_edit_cmd` gets every segment or every output file
+    #[serde(default)]
+    post_edit_granularity: Granularity,
+    /// seconds `post_edit_cmd` may run, 10 by default
+    post_edit_timeout_secs: Option<u64>,
+    /// share of the bytes of the source files, 0 to 1, left out as unsupported beyond
+    /// which the run exits as partial, 0.5 by default
+    max_unsupported_share: Option<f64>,
+    /// language presets added to the built-in ones for `--preset`, e.g.
+    /// `[presets.serbian]`
+    #[serde(default)]
+    presets: BTreeMap<String, Preset>,
+    /// the preset of this run, `--preset` or the default one
+    #[serde(skip)]
+    preset: Preset,
+    /// patterns of ID numbers translations must keep unchanged, added to the defaults of
+    /// `pii`, e.g. `ogrn = '\b\d{13}\b'`
+    #[serde(default)]
+    pii_patterns: BTreeMap<String, String>,
+    /// `pii_patterns` compiled
+    #[serde(skip)]
+    pii: Pii,
+    /// directory of the config file, relative paths in it are resolved against it
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl Config {
+    /// Path of the config file to read when `--config` isn't given.
+    fn default_path() -> PathBuf {
+        let local = PathBuf::from(CONFIG_FILE);
+        if local.exists() {
+            return local;
+        }
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(CONFIG_FILE)))
+            .filter(|path| path.exists())
+            .unwrap_or(local)
+    }
+
+    /// Reads the config file at `path` with the `DIR_TRANSLATE_*` environment variables
+    /// applied on top. Unless `required`, a missing file is fine if the environment sets
+    /// all keys without a default.
+    fn load(path: &Path, required: bool) -> Result<Self> {
+        let exists = path.exists();
+        let mut table: toml::Table = if exists || required {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("config {}: {}", path.display(), e))?;
+            toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("config {}: {}", path.display(), e))?
+        } else {
+            toml::Table::new()
+        };
+        for (name, value) in std::env::vars() {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                table.insert(key.to_lowercase(), env_value(value));
+            }
+        }
+        let mut config: Config = table.try_into().map_err(|e| {
+            let found = if exists { "" } else { " (not found)" };
+            anyhow::anyhow!("config {}{}: {}", path.display(), found, e)
+        })?;
+        config.dir = if exists {
+            std::fs::canonicalize(path)?
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default()
+        } else {
+            std::env::current_dir()?
+        };
+        config.tesserac_data = config
+            .resolve(Path::new(&config.tesserac_data))
+            .to_string_lossy()
+            .to_string();
+        config.pdfium_path = config.pdfium_path.as_deref().map(|p| config.resolve(p));
+        if let Some(key) = config
+            .transliteration
+            .keys()
+            .find(|k| k.chars().count() != 1)
+        {
+            return Err(anyhow::anyhow!(
+                "config {}: transliteration key \"{}\" is not one letter",
+                path.display(),
+                key
+            ));
+        }
+        config.pii = Pii::new(&config.pii_patterns)
+            .map_err(|e| anyhow::anyhow!("config {}: {}", path.display(), e))?;
+        config.network.ca_cert_path = config
+            .network
+            .ca_cert_path
+            .as_deref()
+            .map(|p| config.resolve(p));
+        for preset in presets::all(&config.presets).into_values() {
+            config
+                .scripts
+                .entry(preset.source_lang)
+                .or_insert(preset.script);
+        }
+        Ok(config)
+    }
+
+    /// `path` relative to the config file's directory, unchanged if absolute.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    fn post_edit(&self) -> Option<PostEdit> {
+        let timeout = self
+            .post_edit_timeout_secs
+            .unwrap_or(post_edit::DEFAULT_TIMEOUT_SECS);
+        self.post_edit_cmd.clone().map(|command| {
+            PostEdit::new(
+                command,
+                self.post_edit_granularity,
+                Duration::from_secs(timeout),
+            )
+        })
+    }
 }
 
 #[derive(Parser)]
@@ -23,229 +337,2417 @@ struct Config {
 #[command(propagate_version = true)]
 struct Args {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+    /// config file, ./config.toml or the one next to the executable by default
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// run the checks of `check` within 10 seconds and exit with 0 if all pass, 1 otherwise,
+    /// e.g. as a readiness probe
+    #[arg(long)]
+    healthcheck: bool,
+    /// also print JSON lines to stdout as the run goes: when a file is started, per page
+    /// recognized and segment translated, per handled file and one for the summary
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
     /// directory to translate
     #[arg(short, long)]
-    source_dir: String,
+    source_dir: PathBuf,
+    /// only descend this many levels below the source dir (1 = top-level files only)
+    #[arg(long, global = true, conflicts_with = "no_recursive")]
+    max_depth: Option<usize>,
+    /// do not descend into subdirectories, same as --max-depth 1
+    #[arg(long, global = true)]
+    no_recursive: bool,
+    /// handle the files listed one per line in this file, or stdin for `-`, instead of
+    /// walking the source dir; relative paths are below the source dir
+    #[arg(long, global = true)]
+    files_from: Option<PathBuf>,
+    /// the paths of --files-from are separated by NUL characters, as `find -print0` writes
+    #[arg(short = '0', long, global = true, requires = "files_from")]
+    null: bool,
+    /// list the files that would be processed without translating anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// write a JSON report listing every processed file and its outputs
+    #[arg(long, global = true)]
+    report: Option<PathBuf>,
+    /// directory for state kept between runs, `.dir-translate-cache` next to the config
+    /// file by default
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+    /// directory the temp dir of a run is created in, the system temp dir by default
+    #[arg(long, global = true)]
+    temp_dir: Option<PathBuf>,
+    /// keep the temp dir of the run, with the page renders handed to OCR, and print
+    /// where it is
+    #[arg(long, global = true)]
+    keep_temp: bool,
+    /// monthly character quota of the translation server; once used up, remaining
+    /// segments are written untranslated
+    #[arg(long, global = true)]
+    char_budget: Option<usize>,
+    /// password of password-protected docx files
+    #[arg(long, global = true)]
+    docx_password: Option<String>,
+    /// also translate segments without a letter of the source language's script, like
+    /// numbers and codes, which are copied as they are by default
+    #[arg(long, global = true)]
+    translate_everything: bool,
+    /// translate only the parts of bilingual segments like "Договор / Agreement" that are
+    /// in the source language's script, leaving the rest as it is
+    #[arg(long, global = true)]
+    split_mixed_segments: bool,
+    /// send segments as extracted, without NFC normalization, dropping control
+    /// characters, joining runs of spaces and fixing Latin lookalikes in Cyrillic words
+    /// and the other way round
+    #[arg(long, global = true)]
+    no_normalize: bool,
+    /// write numbers in translations the English way, "1 234,56" as "1,234.56"
+    #[arg(long, global = true)]
+    normalize_numbers: bool,
+    /// write dates in translations like "12.03.2021" as 2021-03-12 (iso) or 03/12/2021 (us)
+    #[arg(long, global = true, value_enum, default_value_t = DateFormat::None)]
+    normalize_dates: DateFormat,
+    /// language preset setting the OCR and server languages, the script and the
+    /// abbreviations: russian (default), ukrainian, belarusian, kazakh or one of the
+    /// config file, see `languages`
+    #[arg(long, global = true)]
+    preset: Option<String>,
+    /// turn off a file handler (pdf, image, docx), can be repeated
+    #[arg(long, global = true)]
+    disable_handler: Vec<String>,
+    /// only use these handlers, comma separated
+    #[arg(long, global = true, value_delimiter = ',')]
+    enable_only: Option<Vec<String>>,
+    /// print counts and latencies of translation requests and OCR at the end
+    #[arg(long, global = true)]
+    metrics: bool,
+    /// write the metrics in the Prometheus text format to this file at the end, e.g. for
+    /// the textfile collector of node_exporter
+    #[arg(long, global = true)]
+    metrics_file: Option<PathBuf>,
+    /// print the settings each file is handled with
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// print the file handlers with their extensions and dependencies, then exit
+    #[arg(long, global = true)]
+    list_handlers: bool,
+    /// ask before renaming files in apply-plan or overwriting outputs of an earlier
+    /// translate run; `each` asks file by file
+    #[arg(long, global = true, value_enum, num_args = 0..=1, default_missing_value = "preview")]
+    interactive: Option<Interactive>,
 }
 
+impl Args {
+    fn cache_dir(&self, config: &Config) -> PathBuf {
+        self.cache_dir
+            .clone()
+            .unwrap_or_else(|| config.resolve(Path::new(DEFAULT_CACHE_DIR)))
+    }
+
+    fn max_depth(&self) -> usize {
+        if self.no_recursive {
+            1
+        } else {
+            self.max_depth.unwrap_or(usize::MAX)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// full translations
+    Translate,
+    /// a short English summary of each document, made by an LLM
+    Summarize,
+}
+
+/// Why `--mode summarize` doesn't run: LibreTranslate, the one backend, only translates.
+const NO_LLM_BACKEND: &str = "--mode summarize needs an LLM backend, and the translation server is LibreTranslate, which only translates; run without --mode or with --mode translate";
+
+/// Arguments of `translate`, and of `extract`, which runs it up to the server.
+#[derive(clap::Args)]
+struct TranslateArgs {
+    target_dir: PathBuf,
+    /// what is written for each document
+    #[arg(long, value_enum, default_value_t = Mode::Translate)]
+    mode: Mode,
+    /// write the extracted source text next to each output as `<name>.src.txt`
+    #[arg(long)]
+    keep_source_text: bool,
+    /// how tables in docx files are laid out in the text output
+    #[arg(long, value_enum, default_value_t = TableFormat::Markdown)]
+    docx_tables: TableFormat,
+    /// how scans are rotated before OCR
+    #[arg(long, value_enum, default_value_t = Orientation::Auto)]
+    orientation: Orientation,
+    /// keep tesseract's line breaks instead of joining OCR lines into paragraphs
+    #[arg(long)]
+    no_reflow: bool,
+    /// encoding of the txt outputs
+    #[arg(long, value_enum, default_value_t = Encoding::Utf8)]
+    output_encoding: Encoding,
+    /// line endings of the txt outputs, kept as extracted if not given
+    #[arg(long, value_enum)]
+    newline: Option<Newline>,
+    /// blank out a region before OCR, as left,top,right,bottom in percent of the page
+    /// (e.g. 0,0,100,8 for the top 8%), can be repeated
+    #[arg(long)]
+    ocr_mask: Vec<Mask>,
+    /// handle every file with the settings of this profile instead of classifying it
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
+    /// drop OCR segments with fewer characters than this
+    #[arg(long, default_value_t = filter::DEFAULT_MIN_LEN)]
+    min_segment_len: usize,
+    /// drop OCR segments in which fewer of the characters are letters (0 to 1)
+    #[arg(long, default_value_t = filter::DEFAULT_MIN_LETTER_RATIO)]
+    min_letter_ratio: f32,
+    /// drop OCR segments in which fewer of the letters are in the source language's
+    /// script (0 to 1), e.g. Latin noise from the Russian model
+    #[arg(long)]
+    min_script_ratio: Option<f32>,
+    /// drop OCR blocks whose words are lower than this share of the page's text height,
+    /// unless read with high confidence (0 to 1)
+    #[arg(long, default_value_t = filter::DEFAULT_MIN_HEIGHT_RATIO)]
+    min_height_ratio: f32,
+    /// keep OCR blocks of any text height, and boxes far too large for their text
+    #[arg(long)]
+    no_geometry_filter: bool,
+    /// written instead of characters the output encoding can't represent
+    #[arg(long, default_value = "?")]
+    substitute: String,
+    /// written between segments of every output, with \n, \r, \t and \\ escapes;
+    /// a blank line by default, where earlier versions wrote a line break and docx
+    /// sentences ended in ".\n"
+    #[arg(long, default_value = "\\n\\n", value_parser = output::unescape)]
+    segment_separator: String,
+    /// start every segment with `[page/block] `, e.g. `[3/12] `; documents without
+    /// pages count as page 1
+    #[arg(long)]
+    prefix_segments: bool,
+    /// start every segment with where it came from, e.g. `[p12 b3 (142,988,1630,1201)
+    /// conf 87] ` for a block of a scan and `[para 17] ` for a docx paragraph
+    #[arg(long, conflicts_with = "prefix_segments")]
+    annotate_provenance: bool,
+    /// start every text output with the path and SHA-256 of its source, the time of the
+    /// run and the language pair
+    #[arg(long)]
+    stamp_outputs: bool,
+    /// look for tables in scans and write them as tab separated rows; a heuristic
+    /// that sometimes takes multi-column text for a table
+    #[arg(long)]
+    detect_tables: bool,
+    /// what becomes of pages of scans taken for a table of contents or an index
+    #[arg(long, value_enum, default_value_t = TocHandling::Translate)]
+    toc_handling: TocHandling,
+    /// column layout of scans; `auto` finds the columns of every page, a number forces
+    /// that many
+    #[arg(long, value_enum, default_value_t = Columns::Auto)]
+    columns: Columns,
+    /// recognize PDF pages tesseract isn't confident about again, rendered with this
+    /// longer side in pixels, and keep the more confident text of every block
+    #[arg(long, num_args = 0..=1, default_missing_value = "3000", value_name = "SIZE")]
+    ocr_multi_pass: Option<u32>,
+    /// read stamps, margin notes and other text turned by 90° in scans, written after
+    /// the page's text under "Rotated text"; recognizes unclear blocks twice more
+    #[arg(long)]
+    detect_rotated_text: bool,
+    /// set apart blocks of scans that look handwritten: they are cut out into
+    /// <stem>/handwriting/ and left as a placeholder, or read by the
+    /// handwriting_recognizer of the config file
+    #[arg(long)]
+    extract_handwriting: bool,
+    /// translate this share of the segments (0 to 1) back into the source language and
+    /// write them to qa-sample.jsonl in the target dir for review
+    #[arg(long)]
+    back_translate_sample: Option<f64>,
+    /// write this share of the translated segments of the run (0 to 1), drawn evenly
+    /// over all files, to qa/<run id>.csv in the target dir for a spot check
+    #[arg(long)]
+    qa_sample: Option<f64>,
+    /// seed of the samples and the pages of `--preview`; the same seed samples the
+    /// same segments and pages
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// only translate the first page and a few others drawn with `--seed` of every PDF
+    /// and the first paragraph of every docx file and email, into `_preview/` below
+    /// the target dir, without writing the manifest
+    #[arg(long, conflicts_with = "output_archive")]
+    preview: bool,
+    /// pages of every PDF a preview translates, the first one included
+    #[arg(long, default_value_t = 3, requires = "preview")]
+    preview_pages: usize,
+    /// write _TRANSLATED.ok into the target folder of every subfolder of the source dir
+    /// whose files were all translated, _TRANSLATED.partial.json if some failed
+    #[arg(long, conflicts_with_all = ["output_archive", "preview"])]
+    per_folder_markers: bool,
+    /// how deep the folders with markers are, 1 for the immediate subfolders of the
+    /// source dir
+    #[arg(long, default_value_t = 1, requires = "per_folder_markers")]
+    marker_depth: usize,
+    /// always run OCR and don't store its results in the cache dir
+    #[arg(long)]
+    no_ocr_cache: bool,
+    /// only translate pages whose OCR result is cached, failing the files with
+    /// uncached pages instead of running OCR
+    #[arg(long, conflicts_with = "no_ocr_cache")]
+    retranslate_only: bool,
+    /// size of the OCR cache in megabytes, least recently used pages are removed
+    /// after a run
+    #[arg(long, default_value_t = ocr::DEFAULT_MAX_SIZE_MB)]
+    ocr_cache_size: u64,
+    /// give every output the modification and access time of its source
+    #[arg(long)]
+    preserve_times: bool,
+    /// with --preserve-times, also copy the permission bits of the source (Unix only)
+    #[arg(long, requires = "preserve_times")]
+    preserve_permissions: bool,
+    /// extract the attachments of emails that have a handler and translate them too
+    #[arg(long)]
+    email_attachments: bool,
+    /// extract the files embedded in PDFs that have a handler and translate them too
+    #[arg(long)]
+    pdf_attachments: bool,
+    /// read the pictures of docx files with OCR and translate them after the text
+    #[arg(long)]
+    docx_ocr_images: bool,
+    /// recognize images that look like photos too, instead of skipping them
+    #[arg(long)]
+    force_ocr_images: bool,
+    /// how the HTML bodies of emails are sent: as text, or as markup with
+    /// `format=html` for the server to keep the tags where the words went
+    #[arg(long, value_enum, default_value_t = HtmlMode::Client)]
+    html_mode: HtmlMode,
+    /// name the page outputs of pdfs after the lowercased file name with every `.pdf`
+    /// in it replaced, as before; kept for one release
+    #[arg(long)]
+    legacy_names: bool,
+    /// layout of the pdf and image outputs
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+    /// width in pixels of the page thumbnails in markdown outputs, 0 for none
+    #[arg(long, default_value_t = output::DEFAULT_THUMBNAIL_WIDTH)]
+    thumbnail_width: u32,
+    /// font of the translations in overlay outputs, DejaVu Sans or another system
+    /// font if not given
+    #[arg(long)]
+    font_path: Option<PathBuf>,
+    /// run even if the source dir overlaps the target dir or holds the manifest of an
+    /// earlier run; files listed as outputs in such a manifest are still skipped
+    #[arg(long)]
+    allow_target_as_source: bool,
+    /// translate a file only once its size and modification time haven't changed for
+    /// this many milliseconds; files still being written are retried at the end
+    #[arg(long, default_value_t = 0)]
+    settle_ms: u64,
+    /// files to take first, in the order of the source dir or of --files-from if not
+    /// given
+    #[arg(long, value_enum)]
+    order: Option<Order>,
+    /// keep the order of the source dir or of --files-from instead of taking scans and
+    /// the other files turn about
+    #[arg(long)]
+    no_reorder: bool,
+    /// write the pages whose mean OCR confidence is below --rescan-below to this CSV,
+    /// the worst first
+    #[arg(long)]
+    low_confidence_report: Option<PathBuf>,
+    /// mean OCR confidence of a page, 0 to 100, below which it may need a rescan
+    #[arg(long, default_value_t = confidence::DEFAULT_RESCAN_BELOW)]
+    rescan_below: f64,
+    /// compress the outputs of a .gz, .zst or .bz2 source like it
+    #[arg(long)]
+    compress_output: bool,
+    /// write the outputs into this .zip or .tar.zst instead of the target dir, which
+    /// then only holds the outputs of the file in progress; must not exist yet
+    #[arg(long)]
+    output_archive: Option<PathBuf>,
+    /// tesseract instances run in parallel, overrides `ocr_threads` of config.toml
+    #[arg(long)]
+    ocr_workers: Option<usize>,
+    /// translation requests in flight at most, overrides `pool_size` of config.toml
+    #[arg(long)]
+    translate_workers: Option<usize>,
+    /// processes rendering the pages of scanned pdfs, see "OCR threads" in the README
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    render_workers: u16,
+}
+
+// parsed once, the size of `Translate` doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// translate filenames only
-    Filenames,
+    Filenames {
+        /// write the proposed renames with any problems found to this file (.csv or .json)
+        /// instead of printing the translations
+        #[arg(long)]
+        plan: Option<PathBuf>,
+        /// how the original and translated names are written
+        #[arg(long, value_enum, default_value_t = MappingFormat::Tsv, conflicts_with = "plan")]
+        format: MappingFormat,
+        /// write the names to this file instead of stdout
+        #[arg(short, long, conflicts_with = "plan")]
+        output: Option<PathBuf>,
+        /// name files after the first sentence of their text, keeping the dates and
+        /// numbers of the old name
+        #[arg(long)]
+        names_from_content: bool,
+        /// translate names, spell them in Latin letters, or translate them with the proper
+        /// nouns spelled in Latin letters
+        #[arg(long, value_enum, default_value_t = NamesMode::Translate)]
+        names_mode: NamesMode,
+        /// casing of the translated names, acronyms keep theirs
+        #[arg(long, value_enum, default_value_t = FilenameCase::Keep)]
+        filename_case: FilenameCase,
+        /// put this between the words of the translated names instead of their spaces,
+        /// underscores and hyphens
+        #[arg(long, value_parser = ["_", "-", " "])]
+        filename_separator: Option<String>,
+    },
+    /// rename files exactly as listed in a plan written by `filenames --plan`
+    ApplyPlan {
+        #[arg(required_unless_present_any = ["resume_rename", "rollback_rename"])]
+        plan: Option<PathBuf>,
+        /// finish the renames of an interrupted apply-plan
+        #[arg(long, conflicts_with_all = ["plan", "rollback_rename"])]
+        resume_rename: bool,
+        /// undo the renames an interrupted apply-plan made
+        #[arg(long, conflicts_with = "plan")]
+        rollback_rename: bool,
+    },
     /// translate source folder into target folder
-    Translate { target_dir: String },
-}
-
-#[tokio::main]
-async fn main() {
-    let config: Config = toml::from_str(&std::fs::read_to_string("config.toml").unwrap()).unwrap();
-    let args = Args::parse();
-    let mut translator = Translator::new(config);
-    match args.command {
-        Commands::Filenames => {
-            for entry in WalkDir::new(args.source_dir) {
-                let entry = entry.unwrap();
-                if entry.metadata().unwrap().is_file() {
-                    println!(
-                        "{}",
-                        translator
-                            .translate(entry.path().to_str().unwrap())
-                            .await
-                            .unwrap()
-                    );
-                }
+    Translate(TranslateArgs),
+    /// extract the segments of source folder without translating them, into
+    /// `extraction.jsonl` in target folder, for `apply-translations`
+    Extract(TranslateArgs),
+    /// translate the segments of an `extraction.jsonl` and write the outputs it holds into
+    /// target folder, without the source files
+    ApplyTranslations {
+        bundle: PathBuf,
+        target_dir: PathBuf,
+    },
+    /// translate the segments that failed in a run again and patch them into its outputs
+    RetryFailures { report: PathBuf },
+    /// verify pdfium, tesseract, the translation server and the directories
+    Check { target_dir: Option<String> },
+    /// list the language presets and whether tesseract and the server know their languages
+    Languages,
+    /// count files, sizes, PDF pages and characters in the source dir without translating
+    Stats {
+        /// print the numbers as JSON
+        #[arg(long)]
+        json: bool,
+        /// number of largest files to list
+        #[arg(long, default_value_t = 10)]
+        largest: usize,
+    },
+    /// hash the outputs in a target dir again and compare them with its manifest.jsonl
+    Verify { target_dir: PathBuf },
+    /// take translation jobs over HTTP until stopped, into directories below
+    /// `target_dir`, see `serve` in the README
+    Serve {
+        target_dir: PathBuf,
+        /// address and port to listen on
+        #[arg(long, default_value = "127.0.0.1:8090")]
+        listen: String,
+    },
+    /// compare the outputs of two runs file by file
+    Diff {
+        old_target_dir: PathBuf,
+        new_target_dir: PathBuf,
+        /// print a unified diff of every changed text output
+        #[arg(long)]
+        show_content: bool,
+    },
+    /// render pdf pages for `--render-workers`, their indexes read from stdin
+    #[command(hide = true)]
+    RenderPages {
+        file: PathBuf,
+        /// where the PNGs are written
+        dir: PathBuf,
+        #[arg(long)]
+        legacy_names: bool,
+    },
+}
+
+impl Commands {
+    fn translate_args(&self) -> Option<&TranslateArgs> {
+        match self {
+            Commands::Translate(translate) | Commands::Extract(translate) => Some(translate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Summary {
+    processed: usize,
+    /// files that failed, e.g. because of a broken directory override or an unreadable pdf,
+    /// attachments included
+    failed: usize,
+    /// pdf pages that could not be rendered
+    failed_pages: usize,
+    /// files left when the run was cancelled, e.g. by SIGTERM
+    cancelled: usize,
+    unsupported: usize,
+    /// bytes of the source files, and of those left out as unsupported
+    source_bytes: u64,
+    unsupported_bytes: u64,
+    /// the unsupported files by extension
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    unsupported_extensions: BTreeMap<String, Unsupported>,
+    excluded_by_depth: usize,
+    characters: usize,
+    untranslated_segments: usize,
+    /// segments the server failed to translate, see `retry-failures`
+    failed_segments: usize,
+    /// translations that failed the guardrails, marked for review
+    suspect_segments: usize,
+    /// segments kept untranslated because their translation altered an ID number
+    pii_preserved_segments: usize,
+    /// segments whose translation came back identical to them
+    echoed_segments: usize,
+    /// the run stopped as the server seemed to echo its input
+    backend_echoing: bool,
+    /// segments translated back for `--back-translate-sample`
+    qa_samples: usize,
+    /// segments drawn for `--qa-sample`
+    qa_sample_segments: usize,
+    /// the file they were written to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qa_sample_file: Option<PathBuf>,
+    /// segments without a letter of the source script, copied without a request
+    passed_through_segments: usize,
+    /// segments cleaned up before translation, see `--no-normalize`
+    normalized_segments: usize,
+    /// numbers and dates rewritten the English way, see `--normalize-numbers` and
+    /// `--normalize-dates`
+    converted_numbers: usize,
+    converted_dates: usize,
+    /// characters the output encoding couldn't represent
+    substituted_chars: usize,
+    /// text blocks dropped by the OCR masks
+    masked_blocks: usize,
+    /// OCR segments dropped as noise
+    filtered_segments: usize,
+    /// OCR blocks dropped as too small or too sparse for their box
+    geometry_filtered_segments: usize,
+    /// files not overwritten because the answer at the `--interactive` prompt was no
+    declined: usize,
+    /// files in the source dir listed as outputs in the manifest of an earlier run
+    own_outputs: usize,
+    /// files still being written when the run ended
+    unstable: usize,
+    /// images skipped as photos, see `--force-ocr-images`
+    photos: usize,
+    /// pages of scans taken for a table of contents or an index, see `--toc-handling`
+    toc_pages: usize,
+    /// files whose outputs are named otherwise than with `--legacy-names`
+    renamed_outputs: usize,
+    /// files without content, skipped
+    empty: usize,
+    /// fifos, sockets and devices in the source dir, skipped
+    special: usize,
+    /// pages and images whose OCR result came from the cache
+    ocr_cache_hits: usize,
+    /// tesseract instances started again as they seemed broken, see `ocr_pool`
+    ocr_restarts: usize,
+    /// `--order` the files were taken in, the order of the listing if none
+    order: Option<Order>,
+    /// files moved for scans and the other files to alternate, see `--no-reorder`
+    reordered_files: usize,
+    /// pages with a mean OCR confidence below `--rescan-below`
+    low_confidence_pages: usize,
+    /// pdf pages and images recognized, not taken from the OCR cache
+    pages: usize,
+    /// pages recognized per minute of the run
+    pages_per_minute: Option<f64>,
+    /// share of the run the tesseract instances were busy
+    ocr_utilization: Option<f64>,
+    /// share of the run the translation workers were waiting for an answer
+    translation_utilization: Option<f64>,
+    requests: usize,
+    /// average round trip of a translation request
+    average_latency_ms: Option<u128>,
+    /// requests and errors of each server if there are several
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    instances: Vec<InstanceStats>,
+}
+
+impl Summary {
+    fn print(&self) {
+        eprintln!(
+            "{} files processed, {} unsupported, {} excluded by depth",
+            self.processed, self.unsupported, self.excluded_by_depth
+        );
+        unsupported::print(&self.unsupported_extensions);
+        if self.failed > 0 {
+            eprintln!(
+                "{} files failed, see the errors above or the report",
+                self.failed
+            );
+        }
+        if self.declined > 0 {
+            eprintln!("{} files left alone at the prompt", self.declined);
+        }
+        if self.cancelled > 0 {
+            eprintln!(
+                "{} files left untranslated, the run was cancelled",
+                self.cancelled
+            );
+        }
+        if self.unstable > 0 {
+            eprintln!(
+                "{} files still being written skipped, see --settle-ms",
+                self.unstable
+            );
+        }
+        if self.toc_pages > 0 {
+            eprintln!(
+                "{} pages taken for a table of contents or an index, see --toc-handling",
+                self.toc_pages
+            );
+        }
+        if self.photos > 0 {
+            eprintln!("{} photos skipped, see --force-ocr-images", self.photos);
+        }
+        if self.empty > 0 {
+            eprintln!("{} empty files skipped", self.empty);
+        }
+        if self.special > 0 {
+            eprintln!(
+                "{} fifos, sockets or devices in the source dir skipped",
+                self.special
+            );
+        }
+        if self.own_outputs > 0 {
+            eprintln!(
+                "{} outputs of an earlier run in the source dir skipped",
+                self.own_outputs
+            );
+        }
+        if self.failed_pages > 0 {
+            eprintln!("{} pdf pages could not be rendered", self.failed_pages);
+        }
+        eprintln!("{} characters sent for translation", self.characters);
+        if let Some(latency) = self.average_latency_ms {
+            eprintln!("{} requests, {} ms average latency", self.requests, latency);
+        }
+        for instance in &self.instances {
+            let rate = instance.errors as f64 / instance.requests.max(1) as f64;
+            eprintln!(
+                "  {}: {} requests, {:.0}% failed, left out {} times",
+                instance.url,
+                instance.requests,
+                rate * 100.0,
+                instance.ejections
+            );
+        }
+        if self.untranslated_segments > 0 {
+            eprintln!(
+                "{} segments left untranslated, the character budget was used up",
+                self.untranslated_segments
+            );
+        }
+        if self.failed_segments > 0 {
+            eprintln!(
+                "{} segments failed to translate, write a --report and run retry-failures on it",
+                self.failed_segments
+            );
+        }
+        if self.suspect_segments > 0 {
+            eprintln!(
+                "{} translations look wrong and are marked {}for review",
+                self.suspect_segments, SUSPECT_MARKER
+            );
+        }
+        if self.pii_preserved_segments > 0 {
+            eprintln!(
+                "{} segments kept untranslated as {}because the translation altered an ID number",
+                self.pii_preserved_segments, PII_MARKER
+            );
+        }
+        if self.passed_through_segments > 0 {
+            eprintln!(
+                "{} segments without a letter of the source script copied untranslated",
+                self.passed_through_segments
+            );
+        }
+        if self.normalized_segments > 0 {
+            eprintln!(
+                "{} segments cleaned up before translation, e.g. lookalike letters fixed",
+                self.normalized_segments
+            );
+        }
+        if self.converted_numbers + self.converted_dates > 0 {
+            eprintln!(
+                "{} numbers and {} dates written the English way",
+                self.converted_numbers, self.converted_dates
+            );
+        }
+        if self.qa_samples > 0 {
+            eprintln!(
+                "{} segments translated back for review, see {}",
+                self.qa_samples,
+                qa::QA_FILE
+            );
+        }
+        if let Some(path) = &self.qa_sample_file {
+            eprintln!(
+                "{} segments drawn for review into {}",
+                self.qa_sample_segments,
+                path.display()
+            );
+        }
+        if self.masked_blocks > 0 {
+            eprintln!("{} text blocks suppressed by OCR masks", self.masked_blocks);
+        }
+        if self.filtered_segments > 0 {
+            eprintln!(
+                "{} OCR segments dropped as noise, see --verbose",
+                self.filtered_segments
+            );
+        }
+        if self.geometry_filtered_segments > 0 {
+            eprintln!(
+                "{} OCR blocks dropped for their size, see --verbose",
+                self.geometry_filtered_segments
+            );
+        }
+        if let Some(rate) = self.pages_per_minute {
+            eprintln!("{} pages, {:.1} per minute", self.pages, rate);
+        }
+        if self.reordered_files > 0 {
+            eprintln!(
+                "{} files taken out of order for scans and the other files to alternate, see \
+                 --no-reorder",
+                self.reordered_files
+            );
+        }
+        if let Some(ocr) = self.ocr_utilization {
+            eprintln!("OCR busy {:.0}% of the run", ocr * 100.0);
+        }
+        if let Some(translation) = self.translation_utilization {
+            eprintln!("translation busy {:.0}% of the run", translation * 100.0);
+        }
+        if self.ocr_cache_hits > 0 {
+            eprintln!("{} pages taken from the OCR cache", self.ocr_cache_hits);
+        }
+        if self.ocr_restarts > 0 {
+            eprintln!(
+                "tesseract restarted {} times after reading nothing or failing",
+                self.ocr_restarts
+            );
+        }
+        if self.low_confidence_pages > 0 {
+            eprintln!(
+                "{} pages were read with a low OCR confidence and may need a rescan, see \
+                 --low-confidence-report",
+                self.low_confidence_pages
+            );
+        }
+        if self.renamed_outputs > 0 {
+            eprintln!(
+                "{} pdfs have outputs named after their stem as it is, earlier versions \
+                 lowercased it; --legacy-names keeps the old names for now",
+                self.renamed_outputs
+            );
+        }
+        if self.substituted_chars > 0 {
+            eprintln!(
+                "{} characters not representable in the output encoding were replaced",
+                self.substituted_chars
+            );
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct FileReport {
+    source: PathBuf,
+    handler: String,
+    outputs: Vec<PathBuf>,
+    /// settings after applying the directory overrides
+    settings: Option<Settings>,
+    /// the `<name>.translate.toml` applied on top of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sidecar: Option<PathBuf>,
+    /// the profile whose settings the file was handled with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<Profile>,
+    /// text blocks dropped by the OCR masks
+    masked_blocks: usize,
+    /// pages that could not be rendered, counted from 1
+    #[serde(default)]
+    failed_pages: Vec<usize>,
+    /// why the file was skipped or failed
+    error: Option<String>,
+    /// kind of `error`, e.g. `input_error` for a broken file
+    #[serde(default)]
+    error_kind: Option<ErrorKind>,
+    #[serde(default)]
+    failures: Vec<Failure>,
+    /// files extracted from this one, like email attachments or files embedded in a PDF
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<FileReport>,
+    /// pages recognized a second time with `--ocr-multi-pass`, with the blocks it won
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    second_pass: Vec<SecondPass>,
+    /// share of the segments translated that came back unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    echo_ratio: Option<f64>,
+    /// OCR confidence over the blocks of all pages, and of every page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    confidence: Option<Confidence>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    page_confidence: Vec<PageConfidence>,
+}
+
+impl FileReport {
+    fn failed(source: PathBuf, handler: &str, error: &anyhow::Error) -> Self {
+        metrics::file_failed(ErrorKind::of(error));
+        FileReport {
+            error_kind: Some(ErrorKind::of(error)),
+            ..FileReport::skipped(source, handler, error.to_string())
+        }
+    }
+
+    /// A file that was left alone for `reason` without failing the run.
+    fn skipped(source: PathBuf, handler: &str, reason: String) -> Self {
+        FileReport {
+            source,
+            handler: handler.to_string(),
+            outputs: Vec::new(),
+            settings: None,
+            sidecar: None,
+            profile: None,
+            masked_blocks: 0,
+            failed_pages: Vec::new(),
+            error: Some(reason),
+            error_kind: None,
+            failures: Vec::new(),
+            attachments: Vec::new(),
+            second_pass: Vec::new(),
+            echo_ratio: None,
+            confidence: None,
+            page_confidence: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Report {
+    summary: Summary,
+    /// encoding of the outputs, needed to patch them in `retry-failures`
+    #[serde(default)]
+    format: TextFormat,
+    files: Vec<FileReport>,
+    /// local dir a `--source-dir` URL is downloaded to and the URL, sources are
+    /// reported below the URL
+    #[serde(skip)]
+    remote: Option<(PathBuf, PathBuf)>,
+    /// folder markers of `--per-folder-markers`, updated by `retry-failures`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markers: Option<Markers>,
+}
+
+impl Report {
+    fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// `path` of a source as reported, below the URL of a remote source dir.
+    fn source(&self, path: &Path) -> PathBuf {
+        match &self.remote {
+            Some((local, url)) => match path.strip_prefix(local) {
+                Ok(relative) => url.join(relative),
+                Err(_) => path.to_path_buf(),
+            },
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Adds `file`, printing it as a JSON line for `--log-format json`.
+    fn push(&mut self, mut file: FileReport) {
+        file.source = self.source(&file.source);
+        events::emit(Event::FileFinished(Box::new(file.clone())));
+        self.files.push(file);
+    }
+}
+
+/// The files listed in `list` for `--files-from`. Those that don't exist, aren't files or
+/// are outside the source dir are reported as failed and left out; fifos, sockets and
+/// devices are counted and left out.
+fn read_file_list(args: &Args, list: &Path, report: &mut Report) -> Result<Vec<DirEntry>> {
+    let data = if list == Path::new("-") {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(list)?
+    };
+    let separator = if args.null { b'\0' } else { b'\n' };
+    let source_dir = std::fs::canonicalize(&args.source_dir)?;
+    let mut files = Vec::new();
+    for line in data.split(|&b| b == separator) {
+        let line = String::from_utf8_lossy(line);
+        let line = if args.null {
+            &line
+        } else {
+            line.trim_end_matches('\r')
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let listed = args.source_dir.join(line);
+        let problem = match std::fs::canonicalize(&listed) {
+            Err(e) => Err(e.to_string()),
+            Ok(path) => match path.strip_prefix(&source_dir) {
+                Err(_) => Err("outside the source dir".to_string()),
+                Ok(_) if !path.is_file() => Err("not a file".to_string()),
+                // mirrored below the source dir as given
+                Ok(relative) => Ok(args.source_dir.join(relative)),
+            },
+        };
+        match problem {
+            Err(_) if is_special(&listed) => {
+                eprintln!("{}: {}, skipped", listed.display(), SPECIAL_FILE);
+                report.summary.special += 1;
+            }
+            Ok(path) if overrides::is_override_file(path.file_name().unwrap()) => {}
+            Ok(path) => files.extend(WalkDir::new(path).into_iter().filter_map(|e| e.ok())),
+            Err(e) => {
+                eprintln!("{}: listed in {}, {}", listed.display(), list.display(), e);
+                report.summary.failed += 1;
+                let e = error::error(ErrorKind::InputError, anyhow::anyhow!("{}", e));
+                report.push(FileReport::failed(listed, "", &e));
             }
         }
-        Commands::Translate { target_dir } => {
-            for entry in WalkDir::new(args.source_dir) {
-                let entry = entry.unwrap();
-                if entry.metadata().unwrap().is_file() {
-                    let path = entry.into_path();
-                    if let Some(ext) = path.extension() {
-                        let ext = ext
-                            .to_str()
-                            .expect("could not create string from extension")
-                            .to_lowercase();
-                        match ext.as_str() {
-                            "pdf" => {
-                                let path_out = Path::new(&target_dir);
-                                translator.translate_pdf(&path, &path_out).await.unwrap()
-                            }
-                            "png" | "jpg" => {
-                                let path_out = Path::new(&target_dir);
-                                translator.translate_img(&path, &path_out).await.unwrap()
-                            }
-                            "docx" => {
-                                let path_out = Path::new(&target_dir);
-                                translator.translate_docx(&path, &path_out).await.unwrap()
-                            }
-                            _ => (),
-                        }
-                    }
-                }
+    }
+    Ok(files)
+}
+
+/// Walks the source dir down to the configured depth. Files below the cut-off are
+/// not returned but counted in the summary, as are fifos, sockets and devices. Dirs and
+/// files that can't be listed fail on their own.
+fn walk_source(args: &Args, report: &mut Report) -> Vec<DirEntry> {
+    let max_depth = args.max_depth();
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&args.source_dir).max_depth(max_depth) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(&args.source_dir).to_path_buf();
+                eprintln!("{}: {}", path.display(), e);
+                report.summary.failed += 1;
+                let e = error::error(ErrorKind::IoError, anyhow::anyhow!("{}", e));
+                report.push(FileReport::failed(path, "", &e));
+                continue;
+            }
+        };
+        let file_type = entry.file_type();
+        if file_type.is_file() {
+            if !overrides::is_override_file(entry.file_name()) {
+                files.push(entry);
+            }
+        } else if file_type.is_dir() {
+            if entry.depth() == max_depth {
+                report.summary.excluded_by_depth += WalkDir::new(entry.path())
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                    .count();
             }
+        } else if !file_type.is_symlink() {
+            report.summary.special += 1;
         }
     }
+    files
+}
+
+/// Whether `path` is a fifo, socket or device.
+fn is_special(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| !m.is_file() && !m.is_dir())
+}
+
+/// Why `path` isn't handed to its handler, `None` if it is: a fifo would block the
+/// handler and an empty pdf crash pdfium. Fails if the file can't be opened.
+fn skip_reason(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Ok(Some(SPECIAL_FILE));
+    }
+    if metadata.len() == 0 {
+        return Ok(Some(EMPTY_FILE));
+    }
+    std::fs::File::open(path)?;
+    Ok(None)
+}
+
+/// A config value from the environment: TOML like `8`, `true` or `["pdf", "docx"]`, a plain
+/// string otherwise.
+fn env_value(value: String) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(toml::Value::String(value))
+}
+
+/// Prints `error` and exits with the exit code of `kind`.
+fn fatal(kind: ErrorKind, error: impl std::fmt::Display) -> ! {
+    eprintln!("{}", error);
+    keys::restore();
+    events::close();
+    workspace::cleanup();
+    std::process::exit(kind.exit_code());
+}
+
+/// The kind a run ends with for `error` with a file or dir of its own, like the plan, the
+/// report or an output: its own kind if it has one, an I/O error if one caused it and a
+/// config error otherwise, as what the file holds is wrong.
+fn fatal_kind(error: &anyhow::Error) -> ErrorKind {
+    match ErrorKind::of(error) {
+        ErrorKind::InputError => ErrorKind::ConfigError,
+        kind => kind,
+    }
 }
 
-struct Translator {
-    lt: leptess::LepTess,
-    pdfium: Pdfium,
-    config: Config,
+/// Prints `error` with `path` and exits with its [`fatal_kind`].
+fn fatal_file(path: &Path, error: impl Into<anyhow::Error>) -> ! {
+    let error = error.into();
+    fatal(fatal_kind(&error), format!("{}: {}", path.display(), error))
 }
 
-impl Translator {
-    pub fn new(config: Config) -> Self {
-        Translator {
-            lt: leptess::LepTess::new(Some(&config.tesserac_data), "rus").unwrap(),
-            pdfium: Pdfium::new(
-                Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
-                    .or_else(|_| Pdfium::bind_to_system_library())
-                    .unwrap(),
-            ),
-            config,
+/// The usage of the char budget so far, stopping the run if it can't be read.
+fn load_usage(cache_dir: &Path, budget: Option<usize>) -> Usage {
+    Usage::load(cache_dir, budget)
+        .unwrap_or_else(|e| fatal_file(&cache_dir.join(usage::USAGE_FILE), e))
+}
+
+/// Saves the usage of the char budget, stopping the run if it can't be written.
+fn save_usage(translator: &Translator) {
+    let usage = &translator.backend.usage;
+    if let Err(e) = usage.save() {
+        fatal_file(usage.path(), e);
+    }
+}
+
+/// Ends the run on Ctrl-C, giving the terminal its settings back and removing the
+/// workspace unless it is kept.
+fn exit_on_ctrl_c() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            keys::restore();
+            workspace::cleanup();
+            eprintln!("interrupted");
+            std::process::exit(130);
         }
+    });
+}
+
+/// Cancels the run with `token` on SIGTERM, so the file in progress is finished and the
+/// manifest and report are written.
+fn cancel_on_sigterm(token: &CancellationToken) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let token = token.clone();
+        let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+            return;
+        };
+        tokio::spawn(async move {
+            terminate.recv().await;
+            eprintln!("terminated, stopping after the file in progress");
+            token.cancel();
+        });
     }
+    #[cfg(not(unix))]
+    let _ = token;
+}
 
-    pub async fn translate(&mut self, text: &str) -> Result<String> {
-        let data = translate_url(
-            SOURCE_LANG,
-            TARGET_LANG,
-            text,
-            &self.config.libretranslate_url,
-            None,
-        )
-        .await?;
-        Ok(data.output.to_owned())
+/// The character of `--filename-separator`.
+fn separator(arg: &Option<String>) -> Option<char> {
+    arg.as_deref().and_then(|s| s.chars().next())
+}
+
+/// Exits as tesseract is needed but could not be started, for `reason`.
+fn no_tesseract(reason: &str) -> ! {
+    fatal(ErrorKind::OcrInitError, reason);
+}
+
+/// The translator of a command with the options of `args` every command translating
+/// takes, stopping the run with a config error if it can't be set up.
+fn translator(config: &Config, args: &Args, usage: Usage) -> Translator {
+    let mut translator =
+        Translator::new(config, usage).unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+    translator.backend.translate_everything = args.translate_everything;
+    translator.backend.split_mixed = args.split_mixed_segments;
+    translator.backend.normalize = !args.no_normalize;
+    translator.backend.conversions.numbers = args.normalize_numbers;
+    translator.backend.conversions.dates = args.normalize_dates;
+    translator.backend.verbose = args.verbose;
+    translator.docx_password = args.docx_password.clone();
+    translator
+}
+
+/// Fails unless `path` is an existing directory.
+fn existing_dir(path: &Path) -> std::io::Result<()> {
+    if !std::fs::metadata(path)?.is_dir() {
+        return Err(std::io::Error::other("not a directory"));
     }
+    Ok(())
+}
 
-    pub async fn translate_docx(&mut self, file: &Path, out: &Path) -> Result<()> {
-        let docx_file = DocxFile::from_file(
-            file.to_str()
-                .ok_or_else(|| anyhow!("could not get file string"))?,
+/// Directory in the target tree mirroring the parent directory of `path` in the source tree.
+fn mirrored_dir(source_dir: &Path, target_dir: &Path, path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or(source_dir);
+    match parent.strip_prefix(source_dir) {
+        Ok(relative) => target_dir.join(relative),
+        Err(_) => target_dir.to_path_buf(),
+    }
+}
+
+fn main() {
+    let mut args = Args::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() {
+            ErrorKind::ConfigError.exit_code()
+        } else {
+            0
+        });
+    });
+    if let Some(Commands::Translate(translate) | Commands::Extract(translate)) = &mut args.command {
+        if translate.preview {
+            translate.target_dir.push(preview::PREVIEW_DIR);
+        }
+    }
+    if args.metrics || args.metrics_file.is_some() {
+        metrics::enable();
+    }
+    let config_path = args.config.clone().unwrap_or_else(Config::default_path);
+    let mut config = Config::load(&config_path, args.config.is_some())
+        .unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+    let preset = args.preset.as_deref().unwrap_or(presets::DEFAULT);
+    config.preset = presets::all(&config.presets)
+        .remove(preset)
+        .unwrap_or_else(|| {
+            let e = format!("no preset {}, see `languages`", preset);
+            fatal(ErrorKind::ConfigError, e)
+        });
+    // set_var isn't safe once other threads run, the runtime's workers among them
+    ocr_pool::limit_threads(config.tesseract_threads);
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| fatal(ErrorKind::IoError, e));
+    runtime.block_on(run(args, config));
+}
+
+/// `main` once config.toml is loaded and the runtime started.
+async fn run(args: Args, mut config: Config) {
+    if args.log_format == LogFormat::Json {
+        events::consume(|event| println!("{}", serde_json::to_string(&event).unwrap()));
+    }
+    let cache_dir = args.cache_dir(&config);
+    if args.healthcheck {
+        let ok = tokio::time::timeout(
+            HEALTHCHECK_TIMEOUT,
+            check::run(&config, &args.source_dir, None, &cache_dir),
         )
-        .map_err(|f| anyhow!("{:?}", f))?;
-        let docx = docx_file.parse().map_err(|f| anyhow!("{:?}", f))?;
-
-        let mut new_txt_file = file.file_name().unwrap().to_string_lossy().to_string();
-        new_txt_file.push_str(".txt");
-        let file_path = Path::new(&new_txt_file);
-        let mut out_path = out.to_path_buf();
-        out_path.push(file_path);
-        let mut output = File::create(out_path).unwrap();
-        let text = docx.document.body.text();
-        let parts = text.split(".");
-        for p in parts {
-            if let Ok(data) = translate_url(
-                SOURCE_LANG,
-                TARGET_LANG,
-                p,
-                &self.config.libretranslate_url,
-                None,
-            )
-            .await
-            {
-                write!(output, "{}.\n", data.output).unwrap();
+        .await
+        .unwrap_or_else(|_| {
+            println!("[FAIL] healthcheck: timed out");
+            false
+        });
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    let Some(command) = &args.command else {
+        let e = Args::command().error(
+            clap::error::ErrorKind::MissingSubcommand,
+            "a subcommand is required unless --healthcheck is given",
+        );
+        fatal(ErrorKind::ConfigError, e.render());
+    };
+    if let Commands::Serve { target_dir, listen } = command {
+        let runner = serve::Runner {
+            config: args.config.clone(),
+            cache_dir,
+            source_dir: args.source_dir.clone(),
+            target_dir: target_dir.clone(),
+        };
+        let e = serve::run(runner, listen).await.unwrap_err();
+        fatal(ErrorKind::IoError, e);
+    }
+    if let Commands::RenderPages {
+        file,
+        dir,
+        legacy_names,
+    } = command
+    {
+        let pdfium = bind_pdfium(config.pdfium_path.as_deref())
+            .map(Pdfium::new)
+            .unwrap_or_else(|e| {
+                let e = pdfium_error(config.pdfium_path.as_deref(), e);
+                fatal(ErrorKind::OcrInitError, e)
+            });
+        handlers::render_pages(&pdfium, file, dir, *legacy_names)
+            .unwrap_or_else(|e| fatal(ErrorKind::IoError, e));
+        return;
+    }
+    if let Some(TranslateArgs {
+        mode,
+        ocr_workers,
+        translate_workers,
+        ..
+    }) = command.translate_args()
+    {
+        if *mode == Mode::Summarize {
+            fatal(ErrorKind::ConfigError, NO_LLM_BACKEND);
+        }
+        config.ocr_threads = ocr_workers.or(config.ocr_threads);
+        config.pool_size = translate_workers.or(config.pool_size);
+    }
+    if let Commands::Languages = command {
+        if let Err(e) = check::languages(&config, &cache_dir).await {
+            fatal(fatal_kind(&e), e);
+        }
+        return;
+    }
+    if let Commands::Check { target_dir } = command {
+        let ok = check::run(
+            &config,
+            Path::new(&args.source_dir),
+            target_dir.as_deref().map(Path::new),
+            &cache_dir,
+        )
+        .await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    let interactive = args.interactive.filter(|_| !args.dry_run);
+    if interactive.is_some() {
+        if let Err(e) = prompt::ensure_terminal() {
+            fatal(ErrorKind::ConfigError, e);
+        }
+    }
+    if let Commands::Diff {
+        old_target_dir,
+        new_target_dir,
+        show_content,
+    } = command
+    {
+        diff::compare(old_target_dir, new_target_dir)
+            .unwrap_or_else(|e| fatal(fatal_kind(&e), e))
+            .print(*show_content);
+        return;
+    }
+    if let Commands::Verify { target_dir } = command {
+        let verification =
+            manifest::verify(target_dir).unwrap_or_else(|e| fatal_file(target_dir, e));
+        for path in &verification.missing {
+            println!("missing\t{}", path.display());
+        }
+        for path in &verification.changed {
+            println!("changed\t{}", path.display());
+        }
+        let problems = verification.missing.len() + verification.changed.len();
+        eprintln!(
+            "{} outputs checked, {} missing, {} changed",
+            verification.checked,
+            verification.missing.len(),
+            verification.changed.len()
+        );
+        std::process::exit(if problems == 0 { 0 } else { 1 });
+    }
+    // renaming again or planning over a half renamed tree would make a mess of it
+    let journal = args.source_dir.join(plan::JOURNAL_FILE);
+    if let Commands::Filenames { .. }
+    | Commands::ApplyPlan {
+        resume_rename: false,
+        rollback_rename: false,
+        ..
+    } = command
+    {
+        if let Some(entries) =
+            plan::read_journal(&journal).unwrap_or_else(|e| fatal_file(&journal, e))
+        {
+            eprintln!(
+                "{}: an earlier apply-plan stopped after {} of {} renames, finish it with \
+                 `apply-plan --resume-rename` or undo it with `apply-plan --rollback-rename`",
+                journal.display(),
+                entries.iter().filter(|e| e.done).count(),
+                entries.len()
+            );
+            std::process::exit(1);
+        }
+    }
+    if let Commands::ApplyPlan {
+        resume_rename,
+        rollback_rename,
+        ..
+    } = command
+    {
+        let recovery = if *resume_rename {
+            Some(plan::resume(&journal))
+        } else if *rollback_rename {
+            Some(plan::rollback(&journal))
+        } else {
+            None
+        };
+        match recovery {
+            None => {}
+            Some(Ok(recovery)) => {
+                for path in &recovery.modified {
+                    eprintln!("{}: externally modified, left alone", path.display());
+                }
+                eprintln!("{} files renamed", recovery.renamed);
+                std::process::exit(if recovery.modified.is_empty() { 0 } else { 1 });
+            }
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
-        Ok(())
     }
-
-    pub async fn translate_img(&mut self, file: &Path, out: &Path) -> Result<()> {
-        println!("{:?}", self.lt.set_image(&file));
-        let boxes = self
-            .lt
-            .get_component_boxes(leptess::capi::TessPageIteratorLevel_RIL_BLOCK, true);
-        for b in &boxes {
-            for x in b.into_iter() {
-                self.lt.set_rectangle_from_box(&x);
-                let input = self.lt.get_utf8_text().unwrap();
-
-                if let Ok(data) = translate_url(
-                    SOURCE_LANG,
-                    TARGET_LANG,
-                    &input,
-                    &self.config.libretranslate_url,
-                    None,
-                )
-                .await
-                {
-                    let mut new_txt_file = file.file_name().unwrap().to_string_lossy().to_string();
-                    new_txt_file.push_str(".txt");
-                    let file_path = Path::new(&new_txt_file);
-                    let mut out_path = out.to_path_buf();
-                    out_path.push(file_path);
-                    let mut output = File::create(out_path).unwrap();
-                    write!(output, "{}", data.output).unwrap();
+    if let Commands::ApplyPlan {
+        plan: Some(plan), ..
+    } = command
+    {
+        let mut entries = plan::read(plan).unwrap_or_else(|e| fatal_file(plan, e));
+        if let Some(mode) = interactive {
+            if let Err(e) = plan::check(&entries) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            entries.retain(|e| e.source != e.target);
+            let items: Vec<String> = entries
+                .iter()
+                .map(|e| format!("{} -> {}", e.source.display(), e.target.display()))
+                .collect();
+            let decisions = prompt::confirm(mode, "rename", &items)
+                .unwrap_or_else(|e| fatal(ErrorKind::IoError, e));
+            let mut decisions = decisions.into_iter();
+            entries.retain(|_| decisions.next().unwrap());
+        }
+        match plan::apply(&entries, args.dry_run, &journal) {
+            Ok(renamed) if args.dry_run => eprintln!("{} files would be renamed", renamed),
+            Ok(renamed) => eprintln!("{} files renamed", renamed),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // the source files stay on the machine they were extracted on
+    if let Commands::ApplyTranslations {
+        bundle: path,
+        target_dir,
+    } = command
+    {
+        let usage = load_usage(&cache_dir, args.char_budget);
+        let mut translator = translator(&config, &args, usage);
+        if let Err(e) = translator.backend.ping().await {
+            fatal(ErrorKind::of(&e), e);
+        }
+        let mut report = Report::default();
+        if let Err(e) = bundle::apply(
+            &mut translator,
+            path,
+            target_dir,
+            config.post_edit(),
+            &mut report,
+        )
+        .await
+        {
+            fatal(ErrorKind::of(&e), e);
+        }
+        report.summary.characters = translator.backend.usage.sent;
+        report.summary.untranslated_segments = translator.backend.untranslated;
+        report.summary.suspect_segments = translator.backend.suspect;
+        report.summary.requests = translator.backend.requests;
+        report.summary.converted_numbers = translator.backend.conversions.converted_numbers;
+        report.summary.converted_dates = translator.backend.conversions.converted_dates;
+        report.summary.print();
+        if let Some(path) = &args.report {
+            report.write(path).unwrap_or_else(|e| fatal_file(path, e));
+        }
+        return;
+    }
+    let remote =
+        source::parse(&args.source_dir).unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+    if remote.is_some() {
+        let translate = command.translate_args().is_some();
+        if !translate || args.dry_run || interactive.is_some() {
+            fatal(
+                ErrorKind::ConfigError,
+                "a source dir URL is only read by `translate`, without --dry-run or --interactive",
+            );
+        }
+    } else if let Err(e) = existing_dir(&args.source_dir) {
+        let e = format!("source dir {}: {}", args.source_dir.display(), e);
+        fatal(ErrorKind::IoError, e);
+    }
+    let mut registry = HandlerRegistry::default();
+    let mut disabled = config.disabled_handlers.clone();
+    disabled.extend(args.disable_handler.iter().cloned());
+    let enable_only = args
+        .enable_only
+        .as_ref()
+        .or(config.enabled_handlers.as_ref());
+    if let Err(e) = registry.configure(&disabled, enable_only.map(|v| v.as_slice())) {
+        fatal(ErrorKind::ConfigError, e);
+    }
+    let usage = load_usage(&cache_dir, args.char_budget);
+    if args.list_handlers {
+        registry.print(&translator(&config, &args, usage));
+        return;
+    }
+    if let Some(TranslateArgs { target_dir, .. }) = command.translate_args() {
+        if let Err(e) = std::fs::create_dir_all(target_dir).and_then(|_| existing_dir(target_dir)) {
+            let e = format!("target dir {}: {}", target_dir.display(), e);
+            fatal(ErrorKind::IoError, e);
+        }
+    }
+    if let Commands::RetryFailures { report } = command {
+        let mut translator = translator(&config, &args, usage);
+        if let Err(e) = translator.backend.ping().await {
+            fatal(ErrorKind::of(&e), e);
+        }
+        if let Err(e) = retry::run(&mut translator, report, config.post_edit()).await {
+            fatal(fatal_kind(&e), e);
+        }
+        save_usage(&translator);
+        return;
+    }
+    let mut report = Report::default();
+    // a URL is listed as the run goes
+    let mut files = match (&remote, &args.files_from) {
+        (Some(_), Some(_)) => fatal(
+            ErrorKind::ConfigError,
+            "--files-from needs a local source dir",
+        ),
+        (Some(_), None) => Vec::new(),
+        (None, Some(list)) => read_file_list(&args, list, &mut report)
+            .unwrap_or_else(|e| fatal(ErrorKind::IoError, format!("{}: {}", list.display(), e))),
+        (None, None) => walk_source(&args, &mut report),
+    };
+    if let Some(TranslateArgs {
+        target_dir,
+        allow_target_as_source,
+        ..
+    }) = command.translate_args()
+    {
+        let manifests: Vec<PathBuf> = files
+            .iter()
+            .filter(|e| e.file_name() == manifest::MANIFEST_FILE)
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        if !allow_target_as_source {
+            if let Some(problem) = feedback::problem(&args.source_dir, target_dir, &manifests) {
+                fatal(ErrorKind::ConfigError, format!(
+                    "{}, refusing to translate the outputs of a run again (--allow-target-as-source runs anyway)",
+                    problem
+                ));
+            }
+        }
+        let own =
+            feedback::own_outputs(&manifests).unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+        files.retain(|e| {
+            let own = std::fs::canonicalize(e.path()).is_ok_and(|path| own.contains(&path))
+                || manifests.iter().any(|m| m == e.path());
+            report.summary.own_outputs += own as usize;
+            !own
+        });
+    }
+    // files of a URL are downloaded as the run goes and not counted
+    report.summary.source_bytes = files
+        .iter()
+        .map(|e| e.metadata().map_or(0, |m| m.len()))
+        .sum();
+    if let Commands::Stats { json, largest } = command {
+        let pdfium = bind_pdfium(config.pdfium_path.as_deref())
+            .ok()
+            .map(Pdfium::new);
+        let stats = stats::collect(&files, &registry, pdfium.as_ref(), *largest);
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        } else {
+            stats.print();
+        }
+        return;
+    }
+    if args.dry_run {
+        let pdfium = bind_pdfium(config.pdfium_path.as_deref())
+            .ok()
+            .map(Pdfium::new);
+        let mut estimate = 0;
+        for entry in &files {
+            let action = match command {
+                Commands::Filenames { .. } => Some("filename"),
+                Commands::Translate(_) | Commands::Extract(_) => {
+                    registry.for_path(entry.path()).map(|h| h.name())
+                }
+                Commands::Check { .. }
+                | Commands::Languages
+                | Commands::Stats { .. }
+                | Commands::Verify { .. }
+                | Commands::Diff { .. }
+                | Commands::Serve { .. }
+                | Commands::RenderPages { .. }
+                | Commands::ApplyPlan { .. }
+                | Commands::RetryFailures { .. }
+                | Commands::ApplyTranslations { .. } => {
+                    unreachable!()
+                }
+            };
+            match action {
+                Some(action) => {
+                    report.summary.processed += 1;
+                    estimate += probe::estimate_chars(entry.path(), action, pdfium.as_ref());
+                    println!("{}\t{}", action, entry.path().display());
+                }
+                None => {
+                    report.summary.unsupported += 1;
+                    report.summary.unsupported_bytes += unsupported::add(
+                        &mut report.summary.unsupported_extensions,
+                        entry.path(),
+                        &registry,
+                    );
                 }
             }
         }
-        Ok(())
+        report.summary.print();
+        eprintln!("about {} characters to translate", estimate);
+        if let Some(remaining) = usage.remaining() {
+            eprintln!(
+                "{} characters left in the budget for {}",
+                remaining,
+                usage.month()
+            );
+            if estimate > remaining {
+                eprintln!("warning: the run will likely not fit into the remaining budget");
+            }
+        }
+        return;
     }
-
-    pub async fn translate_pdf(&mut self, file: &Path, out: &Path) -> Result<()> {
-        if let Ok(document) = self.pdfium.load_pdf_from_file(file, None) {
-            let render_config = PdfRenderConfig::new()
-                .set_target_width(2000)
-                .set_maximum_height(2000)
-                .rotate_if_landscape(PdfPageRenderRotation::Degrees90, true);
-            for (index, page) in document.pages().iter().enumerate() {
-                let rendered = page.render_with_config(&render_config).unwrap();
-                let image = rendered.as_image();
-                let mut bytes: Vec<u8> = Vec::new();
-                image
-                    .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
-                    .unwrap();
-                println!("{:?}", self.lt.set_image_from_mem(&bytes));
-                let new_txt_file = file
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string()
-                    .to_lowercase()
-                    .replace(".pdf", &format!("-page-{}.txt", index));
-                let file_path = Path::new(&new_txt_file);
-                let mut out_path = out.to_path_buf();
-                out_path.push(file_path);
-                let mut output = File::create(out_path).unwrap();
-                let boxes = self
-                    .lt
-                    .get_component_boxes(leptess::capi::TessPageIteratorLevel_RIL_BLOCK, true);
-                for b in &boxes {
-                    for x in b.into_iter() {
-                        self.lt.set_rectangle_from_box(&x);
-                        let input = self.lt.get_utf8_text().unwrap();
-
-                        if let Ok(data) = translate_url(
-                            SOURCE_LANG,
-                            TARGET_LANG,
-                            &input,
-                            &self.config.libretranslate_url,
-                            None,
-                        )
+    let mut translator = translator(&config, &args, usage);
+    // transliterated names and extract don't need the server
+    let offline = matches!(
+        command,
+        Commands::Filenames {
+            names_mode: NamesMode::Transliterate,
+            names_from_content: false,
+            ..
+        } | Commands::Extract(_)
+    );
+    if !offline {
+        if let Err(e) = translator.backend.ping().await {
+            fatal(ErrorKind::of(&e), e);
+        }
+    }
+    if command.translate_args().is_some() {
+        // a missing tesseract would fail every scan, better to stop before the first;
+        // empty files are skipped anyway
+        let needs_ocr = files.iter().any(|e| {
+            registry
+                .for_path(e.path())
+                .is_some_and(|h| h.dependencies().contains(&Dependency::Tesseract))
+                && e.metadata().is_ok_and(|m| m.len() > 0)
+        });
+        if let (true, Err(e)) = (needs_ocr, &translator.ocr) {
+            no_tesseract(e);
+        }
+    }
+    let mut archive = None;
+    match command {
+        Commands::Filenames {
+            plan: Some(plan),
+            names_from_content,
+            names_mode,
+            filename_case,
+            filename_separator,
+            ..
+        } => {
+            translator.names_mode = *names_mode;
+            translator.name_style.case = *filename_case;
+            translator.name_style.separator = separator(filename_separator);
+            let paths: Vec<_> = files.into_iter().map(|e| e.into_path()).collect();
+            let from_content = names_from_content.then_some(&registry);
+            let entries = plan::build(
+                &mut translator,
+                Path::new(&args.source_dir),
+                &paths,
+                from_content,
+            )
+            .await
+            .unwrap_or_else(|e| fatal(fatal_kind(&e), e));
+            save_usage(&translator);
+            report.summary.processed = entries.len();
+            let flagged = entries.iter().filter(|e| !e.issues.is_empty()).count();
+            plan::write(plan, &entries).unwrap_or_else(|e| fatal_file(plan, e));
+            eprintln!(
+                "{} of {} renames flagged, see {}",
+                flagged,
+                entries.len(),
+                plan.display()
+            );
+        }
+        Commands::Filenames {
+            plan: None,
+            format,
+            output,
+            names_from_content,
+            names_mode,
+            filename_case,
+            filename_separator,
+        } => {
+            translator.names_mode = *names_mode;
+            translator.name_style.case = *filename_case;
+            translator.name_style.separator = separator(filename_separator);
+            let out: Box<dyn std::io::Write> = match output {
+                Some(path) => Box::new(File::create(path).unwrap_or_else(|e| fatal_file(path, e))),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut writer = MappingWriter::new(*format, out);
+            let total = files.len();
+            for (index, entry) in files.into_iter().enumerate() {
+                let original = entry.path().to_string_lossy().to_string();
+                eprintln!("[{}/{}] {}", index + 1, total, original);
+                let named = if *names_from_content {
+                    titles::name(&mut translator, &registry, entry.path()).await
+                } else {
+                    None
+                };
+                let result = match named {
+                    // only the directories are translated, as one path
+                    Some(name) => {
+                        let parent = entry.path().parent().unwrap().to_string_lossy();
+                        let parent = names::translate(&mut translator, &parent).await;
+                        parent.map(|p| Path::new(&p).join(name).to_string_lossy().to_string())
+                    }
+                    None => names::translate(&mut translator, &original).await,
+                };
+                let style = &translator.name_style;
+                let result = result.map(|name| match name.strip_prefix(SUSPECT_MARKER) {
+                    Some(name) => format!("{}{}", SUSPECT_MARKER, style.apply_path(name)),
+                    None if name.starts_with(UNTRANSLATED_MARKER) => name,
+                    None => style.apply_path(&name),
+                });
+                let mapping = Mapping::new(original, result);
+                if let Some(error) = &mapping.error {
+                    eprintln!("{}: {}", mapping.original, error);
+                    report.summary.failed += 1;
+                }
+                if let Err(e) = writer.write(mapping) {
+                    fatal(fatal_kind(&e), e);
+                }
+                report.summary.processed += 1;
+                save_usage(&translator);
+            }
+            if let Err(e) = writer.finish() {
+                fatal(fatal_kind(&e), e);
+            }
+        }
+        Commands::Translate(translate) | Commands::Extract(translate) => {
+            let TranslateArgs {
+                target_dir,
+                keep_source_text,
+                docx_tables,
+                orientation,
+                no_reflow,
+                output_encoding,
+                newline,
+                substitute,
+                segment_separator,
+                prefix_segments,
+                annotate_provenance,
+                stamp_outputs,
+                ocr_mask,
+                profile,
+                min_segment_len,
+                min_letter_ratio,
+                min_script_ratio,
+                min_height_ratio,
+                no_geometry_filter,
+                detect_tables,
+                toc_handling,
+                columns,
+                ocr_multi_pass,
+                detect_rotated_text,
+                extract_handwriting,
+                back_translate_sample,
+                qa_sample,
+                seed,
+                preview,
+                preview_pages,
+                per_folder_markers,
+                marker_depth,
+                no_ocr_cache,
+                retranslate_only,
+                ocr_cache_size,
+                preserve_times,
+                preserve_permissions,
+                email_attachments,
+                pdf_attachments,
+                docx_ocr_images,
+                force_ocr_images,
+                html_mode,
+                legacy_names,
+                output_format,
+                thumbnail_width,
+                font_path,
+                output_archive,
+                settle_ms,
+                order,
+                no_reorder,
+                low_confidence_report,
+                rescan_below,
+                compress_output,
+                render_workers,
+                ..
+            } = translate;
+            let extract = matches!(command, Commands::Extract(_));
+            if extract && (*output_format == OutputFormat::Overlay || output_archive.is_some()) {
+                fatal(
+                    ErrorKind::ConfigError,
+                    "extract writes no overlays and no archive, with translations they are made by apply-translations",
+                );
+            }
+            if extract {
+                translator.backend.deferral = Some(Deferral::default());
+            }
+            translator.detect_tables = *detect_tables;
+            translator.toc_handling = *toc_handling;
+            translator.columns = *columns;
+            translator.preview = preview.then(|| Preview::new(*preview_pages, *seed));
+            if let Some(size) = ocr_multi_pass {
+                if *size <= handlers::RENDER_SIZE as u32 {
+                    fatal(
+                        ErrorKind::ConfigError,
+                        format!(
+                            "--ocr-multi-pass {} is not larger than the first render ({} pixels)",
+                            size,
+                            handlers::RENDER_SIZE
+                        ),
+                    );
+                }
+            }
+            translator.multi_pass = *ocr_multi_pass;
+            translator.rotated_text = *detect_rotated_text;
+            translator.handwriting = *extract_handwriting;
+            translator.output_format = *output_format;
+            translator.thumbnail_width = *thumbnail_width;
+            if *output_format == OutputFormat::Overlay {
+                let overlay = Overlay::load(font_path.as_deref())
+                    .unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+                translator.overlay = Some(overlay);
+            }
+            translator.email_attachments = *email_attachments;
+            translator.pdf_attachments = *pdf_attachments;
+            translator.docx_ocr_images = *docx_ocr_images;
+            translator.force_ocr_images = *force_ocr_images;
+            translator.html_mode = *html_mode;
+            translator.legacy_names = *legacy_names;
+            translator.render_workers = (*render_workers > 1).then(|| RenderSettings {
+                workers: *render_workers as usize,
+                config: args.config.clone(),
+            });
+            translator.attachment_extensions = registry.extensions();
+            if let Some(rate) = back_translate_sample {
+                translator.backend.qa = Some(
+                    QaSampler::new(Path::new(target_dir), *rate, *seed)
+                        .unwrap_or_else(|e| fatal(fatal_kind(&e), e)),
+                );
+            }
+            if let Some(rate) = qa_sample {
+                let sample = QaSample::new(Path::new(target_dir), *rate, *seed)
+                    .unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+                translator.backend.qa_sample = Some(sample);
+            }
+            if !*no_ocr_cache {
+                translator.ocr_cache = Some(OcrCache::new(
+                    &cache_dir,
+                    *retranslate_only,
+                    *ocr_cache_size,
+                ));
+            }
+            translator.text_format = TextFormat::new(*output_encoding, *newline, substitute)
+                .unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+            translator.text_format.separator = segment_separator.clone();
+            // the segments are edited once they are translated, by apply-translations
+            translator.text_format.post_edit = config.post_edit().filter(|_| !extract);
+            translator.text_format.prefix_segments = *prefix_segments;
+            translator.text_format.annotate_provenance = *annotate_provenance;
+            translator.filter = SegmentFilter {
+                min_len: *min_segment_len,
+                min_letter_ratio: *min_letter_ratio,
+                min_script_ratio: *min_script_ratio,
+                min_height_ratio: (!no_geometry_filter).then_some(*min_height_ratio),
+                verbose: args.verbose,
+            };
+            let base = Settings {
+                source_lang: config.preset.source_lang.clone(),
+                ocr_lang: config.preset.ocr_lang.clone(),
+                orientation: *orientation,
+                docx_tables: *docx_tables,
+                reflow: !*no_reflow,
+                keep_source_text: *keep_source_text,
+                ocr_mask: ocr_mask.clone(),
+            };
+            let workspace = TempWorkspace::create(args.temp_dir.as_deref(), args.keep_temp)
+                .unwrap_or_else(|e| fatal(ErrorKind::IoError, e));
+            exit_on_ctrl_c();
+            let spill =
+                remote.map(|source| Spill::new(source, workspace.spill_dir(), args.keep_temp));
+            let source_dir = spill
+                .as_ref()
+                .map_or(args.source_dir.as_path(), Spill::root);
+            report.remote = spill
+                .as_ref()
+                .map(|spill| (spill.root().to_path_buf(), args.source_dir.clone()));
+            let mut overrides = OverrideTree::new(source_dir);
+            let mut declined = HashSet::new();
+            if let Some(mode) = interactive {
+                let mut existing = Vec::new();
+                for entry in &files {
+                    let path = entry.path();
+                    let Some(handler) = registry.for_path(path) else {
+                        continue;
+                    };
+                    let path_out = mirrored_dir(source_dir, Path::new(target_dir), path);
+                    let outputs = handler.existing_outputs(path, &path_out);
+                    if !outputs.is_empty() {
+                        existing.push((path.to_path_buf(), outputs.len()));
+                    }
+                }
+                let items: Vec<String> = existing
+                    .iter()
+                    .map(|(path, n)| format!("{} ({} outputs)", path.display(), n))
+                    .collect();
+                let decisions = prompt::confirm(mode, "overwrite the outputs of", &items)
+                    .unwrap_or_else(|e| fatal(ErrorKind::IoError, e));
+                declined = existing
+                    .into_iter()
+                    .zip(decisions)
+                    .filter(|(_, yes)| !yes)
+                    .map(|((path, _), _)| path)
+                    .collect();
+            }
+            if let Some(path) = output_archive {
+                let created = Archive::create(path, target_dir)
+                    .unwrap_or_else(|e| fatal(ErrorKind::ConfigError, e));
+                archive = Some(created);
+            }
+            translator.workspace = Some(workspace);
+            // a preview doesn't count as a run of the files
+            let mut manifest = (!preview && !extract).then(|| {
+                Manifest::open(source_dir, Path::new(target_dir))
+                    .unwrap_or_else(|e| fatal_file(&target_dir.join(manifest::MANIFEST_FILE), e))
+            });
+            let mut bundle = extract.then(|| {
+                let path = target_dir.join(bundle::BUNDLE_FILE);
+                Bundle::create(&path, &translator.text_format).unwrap_or_else(|e| {
+                    fatal(ErrorKind::IoError, format!("{}: {}", path.display(), e))
+                })
+            });
+            // the outputs of extract go into the bundle
+            let out_dir = match extract {
+                true => translator.workspace.as_ref().unwrap().extract_dir(),
+                false => target_dir.clone(),
+            };
+            let run_started = std::time::Instant::now();
+            if let Some(order) = order {
+                // only text-layer-first looks into the pdfs
+                let pdfium = (*order == Order::TextLayerFirst)
+                    .then(|| translator.pdfium.get().ok())
+                    .flatten();
+                order::sort(&mut files, *order, &registry, pdfium);
+                report.summary.order = Some(*order);
+            } else if !*no_reorder {
+                let pdfium = translator.pdfium.get().ok();
+                report.summary.reordered_files =
+                    schedule::interleave(&mut files, &registry, pdfium);
+            }
+            let mut settle = Settle::new(Duration::from_millis(*settle_ms), &files);
+            // images are recognized ahead of their turn, objects of a URL only once downloaded
+            let ahead = match spill {
+                Some(_) => None,
+                None => Ahead::new(&translator),
+            };
+            let walked = files
+                .into_iter()
+                .map(DirEntry::into_path)
+                .map(|path| {
+                    // a compressed image is only there once it is decompressed
+                    let image = registry
+                        .for_path(&path)
+                        .is_some_and(|h| h.name() == "image");
+                    let ahead = image && Codec::of(&path).is_none();
+                    Walked { path, ahead }
+                })
+                .collect();
+            let ready = translator::ocr_pool(&translator.ocr).map_or(1, |pool| pool.size());
+            let mut pipeline = Pipeline::start(walked, ready, move |file: &Walked| {
+                ahead.as_ref()?.start(&file.path)
+            });
+            // listed objects and the files rechecked after the files of the pipeline
+            let mut queue: VecDeque<PathBuf> = VecDeque::new();
+            // files still being written go to the end of the run, rechecked a few times
+            let mut deferred = Vec::new();
+            let mut rechecks = 0;
+            let mut listing = spill.as_ref().map(Spill::list);
+            let max_depth = args.max_depth();
+            let cancellation = CancellationToken::default();
+            cancel_on_sigterm(&cancellation);
+            let status = Arc::new(Mutex::new(keys::Status::default()));
+            let files_on_stdin = args.files_from.as_deref() == Some(Path::new("-"));
+            let mut terminal = None;
+            if interactive.is_none() && !files_on_stdin {
+                let pause = translator.backend.pause.clone();
+                terminal = keys::start(pause, cancellation.clone(), status.clone());
+            }
+            loop {
+                translator.backend.pause.wait().await;
+                if translator.backend.echo.tripped() {
+                    eprintln!("{}, stopping the run", echo::ECHO_ERROR);
+                    report.summary.backend_echoing = true;
+                    for path in pipeline.stop().await.into_iter().rev() {
+                        queue.push_front(path);
+                    }
+                    report.summary.cancelled = queue.len() + deferred.len();
+                    break;
+                }
+                if cancellation.is_cancelled() {
+                    // a listing still going isn't counted
+                    for path in pipeline.stop().await.into_iter().rev() {
+                        queue.push_front(path);
+                    }
+                    report.summary.cancelled = queue.len() + deferred.len();
+                    break;
+                }
+                let taken = match pipeline.next().await {
+                    Some(prepared) => Some((prepared.path, prepared.ocr)),
+                    None => queue.pop_front().map(|path| (path, None)),
+                };
+                let Some((path, prefetched)) = taken else {
+                    if let Some(listed) = &mut listing {
+                        match listed.next().await {
+                            Some(Ok(path)) => {
+                                let depth =
+                                    path.strip_prefix(source_dir).unwrap().components().count();
+                                if depth > max_depth {
+                                    report.summary.excluded_by_depth += 1;
+                                } else if !overrides::is_override_file(path.file_name().unwrap()) {
+                                    queue.push_back(path);
+                                }
+                            }
+                            Some(Err(e)) => fatal(
+                                ErrorKind::IoError,
+                                format!("listing {}: {}", args.source_dir.display(), e),
+                            ),
+                            None => listing = None,
+                        }
+                        continue;
+                    }
+                    if deferred.is_empty() {
+                        break;
+                    }
+                    rechecks += 1;
+                    tokio::time::sleep(settle.recheck_wait()).await;
+                    queue.extend(deferred.drain(..));
+                    continue;
+                };
+                let Some(handler) = registry.for_path(&path) else {
+                    report.summary.unsupported += 1;
+                    report.summary.unsupported_bytes += unsupported::add(
+                        &mut report.summary.unsupported_extensions,
+                        &path,
+                        &registry,
+                    );
+                    continue;
+                };
+                if declined.contains(&path) {
+                    report.summary.declined += 1;
+                    continue;
+                }
+                // objects of a URL are written in one go
+                let unstable = match spill {
+                    Some(_) => None,
+                    None => settle.unstable(&path),
+                };
+                if let Some(reason) = unstable {
+                    if rechecks < settle::MAX_RECHECKS {
+                        deferred.push(path);
+                        continue;
+                    }
+                    // still empty, skipped as empty below
+                    if reason != settle::EMPTY {
+                        eprintln!("{}: unstable, skipped: {}", path.display(), reason);
+                        report.summary.unstable += 1;
+                        let reason = format!("unstable, skipped: {}", reason);
+                        let file = FileReport::skipped(path, handler.name(), reason);
+                        report.push(file);
+                        continue;
+                    }
+                }
+                // removed once the file is done
+                let _spilled = match &spill {
+                    Some(spill) => match spill.fetch(&path).await {
+                        Ok(spilled) => Some(spilled),
+                        Err(e) => {
+                            eprintln!("{}: could not download: {}", path.display(), e);
+                            report.summary.failed += 1;
+                            let e = error::error(ErrorKind::IoError, e);
+                            report.push(FileReport::failed(path, handler.name(), &e));
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                match skip_reason(&path) {
+                    Ok(None) => {}
+                    Ok(Some(SPECIAL_FILE)) => {
+                        eprintln!("{}: {}, skipped", path.display(), SPECIAL_FILE);
+                        report.summary.special += 1;
+                        continue;
+                    }
+                    Ok(Some(reason)) => {
+                        eprintln!("{}: {}, skipped", path.display(), reason);
+                        report.summary.empty += 1;
+                        let file = FileReport::skipped(path, handler.name(), reason.to_string());
+                        report.push(file);
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", path.display(), e);
+                        report.summary.failed += 1;
+                        let e = error::error(ErrorKind::IoError, e);
+                        report.push(FileReport::failed(path, handler.name(), &e));
+                        continue;
+                    }
+                }
+                if let Err(e) = &translator.ocr {
+                    if handler.dependencies().contains(&Dependency::Tesseract) {
+                        no_tesseract(e);
+                    }
+                }
+                // a compressed source is handled as the file inside, until it is done
+                let decompressed = match Codec::of(&path) {
+                    Some(_) => {
+                        let workspace = translator.workspace.as_ref().unwrap();
+                        let decompressed = workspace
+                            .file_dir(&path)
+                            .and_then(|dir| compression::decompress(&path, &dir));
+                        match decompressed {
+                            Ok(decompressed) => Some(decompressed),
+                            Err(e) => {
+                                eprintln!("{}: could not decompress: {}", path.display(), e);
+                                report.summary.failed += 1;
+                                let e = error::error(ErrorKind::InputError, e);
+                                report.push(FileReport::failed(path, handler.name(), &e));
+                                continue;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                let input = decompressed
+                    .as_ref()
+                    .map_or(path.clone(), |d| d.path.clone());
+                let profile = profile.or_else(|| {
+                    profiles::detect(&input, handler.name(), translator.pdfium.get().ok())
+                });
+                let mut profiled = base.clone();
+                if let Some(overrides) = profile.and_then(|p| config.profiles.get(&p)) {
+                    profiled.apply(overrides);
+                }
+                let settings = match overrides.settings_for(&profiled, &path) {
+                    Ok((settings, sidecar)) => translator
+                        .apply(&settings)
                         .await
-                        {
-                            write!(output, "{}", data.output).unwrap();
+                        .map(|_| (settings, sidecar)),
+                    Err(e) => Err(error::error(ErrorKind::ConfigError, e)),
+                };
+                let (settings, sidecar) = match settings {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        eprintln!("{}: skipped, {}", path.display(), e);
+                        report.summary.failed += 1;
+                        let file = FileReport::failed(path, handler.name(), &e);
+                        report.push(file);
+                        continue;
+                    }
+                };
+                if args.verbose {
+                    eprintln!("{}: {:?}", path.display(), settings);
+                }
+                if let Some(post_edit) = &mut translator.text_format.post_edit {
+                    post_edit.source = report.source(&path);
+                    post_edit.source_lang = settings.source_lang.clone();
+                }
+                if *stamp_outputs {
+                    // relative as in the manifest
+                    let relative = path.strip_prefix(source_dir).unwrap_or(&path);
+                    let sha256 = match manifest::sha256_file(&path) {
+                        Ok(sha256) => sha256,
+                        Err(e) => {
+                            eprintln!("{}: {}", path.display(), e);
+                            report.summary.failed += 1;
+                            let e = error::error(ErrorKind::IoError, e);
+                            report.push(FileReport::failed(path, handler.name(), &e));
+                            continue;
+                        }
+                    };
+                    translator.text_format.stamp = Some(stamp::header(
+                        &relative.to_string_lossy(),
+                        &sha256,
+                        &settings.source_lang,
+                        TARGET_LANG.as_code(),
+                    ));
+                }
+                let path_out = mirrored_dir(source_dir, &out_dir, &path);
+                if let Err(e) = std::fs::create_dir_all(&path_out) {
+                    fatal_file(&path_out, e);
+                }
+                if let Some(qa) = &mut translator.backend.qa {
+                    qa.file = path.clone();
+                }
+                if let Some(sample) = &mut translator.backend.qa_sample {
+                    sample.file = report.source(&path);
+                }
+                *status.lock().unwrap() = keys::Status {
+                    file: Some(report.source(&path)),
+                    processed: report.summary.processed,
+                    failed: report.summary.failed,
+                    left: pipeline.waiting() + queue.len() + deferred.len(),
+                    characters: translator.backend.usage.sent,
+                };
+                translator.backend.echo.take_file();
+                // the image handler takes it if the settings of the file are those of the run
+                translator.prefetched.clear();
+                if let Some(prefetched) = prefetched {
+                    translator.prefetched.insert(input.clone(), prefetched);
+                }
+                // segments of a file that failed or was skipped don't go with this one
+                if let Some(deferral) = &mut translator.backend.deferral {
+                    deferral.segments.clear();
+                }
+                let started = manifest::now();
+                events::emit(Event::FileStarted {
+                    source: path.clone(),
+                    handler: handler.name().to_string(),
+                });
+                let mut output = match handler.handle(&mut translator, &input, &path_out).await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        eprintln!("{}: {}", path.display(), e);
+                        report.summary.failed += 1;
+                        let handler = handler.name();
+                        report.push(FileReport::failed(path, handler, &e));
+                        continue;
+                    }
+                };
+                if let Some(reason) = output.skipped.take() {
+                    eprintln!("{}: skipped, {}", path.display(), reason);
+                    report.summary.photos += 1;
+                    report.push(FileReport {
+                        settings: Some(settings),
+                        sidecar,
+                        profile,
+                        ..FileReport::skipped(path, handler.name(), reason)
+                    });
+                    continue;
+                }
+                // attachments of email attachments are appended and handled in turn, those
+                // of PDFs only one level deep
+                let mut attachments = Vec::new();
+                translator.pdf_attachments = false;
+                while let Some(attachment) = output.attachments.pop() {
+                    let Some(handler) = registry.for_path(&attachment) else {
+                        continue;
+                    };
+                    let dir = attachment.parent().unwrap().to_path_buf();
+                    match handler.handle(&mut translator, &attachment, &dir).await {
+                        Ok(attached) => {
+                            // the failures stay with the parent, which retry-failures patches
+                            attachments.push(FileReport {
+                                outputs: attached.outputs.clone(),
+                                failed_pages: attached.failed_pages.clone(),
+                                error: None,
+                                confidence: confidence::file(&attached.page_confidence),
+                                page_confidence: attached.page_confidence.clone(),
+                                ..FileReport::skipped(attachment, handler.name(), String::new())
+                            });
+                            output.merge(attached);
+                        }
+                        Err(e) => {
+                            eprintln!("{}: {}", attachment.display(), e);
+                            // a failed attachment fails the run as a failed file does
+                            report.summary.failed += 1;
+                            let file = FileReport::failed(attachment, handler.name(), &e);
+                            attachments.push(file);
+                        }
+                    }
+                }
+                translator.pdf_attachments = *pdf_attachments;
+                drop(decompressed);
+                if let Some(bundle) = &mut bundle {
+                    let deferral = translator.backend.deferral.as_mut().unwrap();
+                    let bundled = bundle.append(
+                        path.strip_prefix(source_dir).unwrap_or(&path),
+                        handler.name(),
+                        &settings.source_lang,
+                        &out_dir,
+                        &output.outputs,
+                        std::mem::take(&mut deferral.segments),
+                    );
+                    match bundled {
+                        Ok(bundled) => {
+                            output.outputs = bundled;
+                            for output in attachments.iter_mut().flat_map(|a| &mut a.outputs) {
+                                if let Ok(relative) = output.strip_prefix(&out_dir) {
+                                    *output = relative.to_path_buf();
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("{}: could not add it to the bundle: {}", path.display(), e);
+                            report.summary.failed += 1;
+                            let e = error::error(ErrorKind::IoError, e);
+                            report.push(FileReport::failed(path, handler.name(), &e));
+                            continue;
                         }
                     }
                 }
-                let rgba8 = image.as_rgba8().unwrap();
-                let new_file = file
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string()
-                    .to_lowercase()
-                    .replace(".pdf", &format!("-page-{}.jpg", index));
-                let file_path = Path::new(&new_file);
-                let mut out_path = out.to_path_buf();
-                out_path.push(file_path);
-                rgba8
-                    .save_with_format(out_path.to_str().unwrap(), ImageFormat::Jpeg)
-                    .unwrap();
+                if let (true, false, Some(codec)) = (*compress_output, extract, Codec::of(&path)) {
+                    let compressed: Result<Vec<_>> =
+                        output.outputs.iter().map(|o| codec.compress(o)).collect();
+                    match compressed {
+                        Ok(compressed) => output.outputs = compressed,
+                        Err(e) => {
+                            eprintln!("{}: could not compress the outputs: {}", path.display(), e);
+                            report.summary.failed += 1;
+                            let e = error::error(ErrorKind::IoError, e);
+                            report.push(FileReport::failed(path, handler.name(), &e));
+                            continue;
+                        }
+                    }
+                }
+                if *preserve_times && !extract {
+                    times::preserve(&path, &output.outputs, *preserve_permissions);
+                }
+                if let Some(manifest) = &mut manifest {
+                    if let Err(e) = manifest.append(&path, handler.name(), &output.outputs, started)
+                    {
+                        fatal_file(&target_dir.join(manifest::MANIFEST_FILE), e);
+                    }
+                }
+                if let (Some(archive), Some(path)) = (&mut archive, output_archive) {
+                    if let Err(e) = archive.add(&output.outputs) {
+                        fatal_file(path, e);
+                    }
+                }
+                report.summary.failed_pages += output.failed_pages.len();
+                report.summary.processed += 1;
+                report.summary.masked_blocks += output.masked_blocks;
+                report.summary.failed_segments += output.failures.len();
+                report.summary.filtered_segments += output.filtered_segments;
+                report.summary.geometry_filtered_segments += output.geometry_filtered;
+                report.summary.ocr_cache_hits += output.ocr_cache_hits;
+                report.summary.renamed_outputs += usize::from(output.legacy_names_differ);
+                report.summary.pages += output.pages;
+                report.summary.toc_pages += output.toc_pages;
+                report.push(FileReport {
+                    source: path,
+                    handler: handler.name().to_string(),
+                    outputs: output.outputs,
+                    settings: Some(settings),
+                    sidecar,
+                    profile,
+                    masked_blocks: output.masked_blocks,
+                    failed_pages: output.failed_pages,
+                    error: None,
+                    error_kind: None,
+                    failures: output.failures,
+                    attachments,
+                    second_pass: output.second_pass,
+                    echo_ratio: translator.backend.echo.take_file(),
+                    confidence: confidence::file(&output.page_confidence),
+                    page_confidence: output.page_confidence,
+                });
+                save_usage(&translator);
+            }
+            drop(terminal);
+            if *per_folder_markers {
+                let markers = Markers {
+                    source_dir: args.source_dir.clone(),
+                    target_dir: target_dir.clone(),
+                    depth: *marker_depth,
+                };
+                let pending: Vec<PathBuf> = queue
+                    .iter()
+                    .chain(&deferred)
+                    .map(|path| report.source(path))
+                    .collect();
+                if let Err(e) = markers.write(&report.files, &pending) {
+                    fatal(fatal_kind(&e), format!("folder markers: {}", e));
+                }
+                report.markers = Some(markers);
+            }
+            let low: Vec<_> = report
+                .files
+                .iter()
+                .flat_map(|file| std::iter::once(file).chain(&file.attachments))
+                .flat_map(|file| {
+                    file.page_confidence
+                        .iter()
+                        .filter(|page| page.confidence.mean < *rescan_below)
+                        .map(|page| confidence::LowPage {
+                            source: file.source.clone(),
+                            page: page.page,
+                            mean: page.confidence.mean,
+                            p10: page.confidence.p10,
+                        })
+                })
+                .collect();
+            report.summary.low_confidence_pages = low.len();
+            if let Some(path) = low_confidence_report {
+                if let Err(e) = confidence::write(path, low) {
+                    eprintln!("could not write {}: {}", path.display(), e);
+                }
+            }
+            if report.summary.pages > 0 {
+                let minutes = run_started.elapsed().as_secs_f64() / 60.0;
+                report.summary.pages_per_minute = Some(report.summary.pages as f64 / minutes);
+            }
+            let elapsed = run_started.elapsed();
+            if let Ok(pool) = &translator.ocr {
+                report.summary.ocr_utilization =
+                    schedule::utilization(ocr_pool::busy(), elapsed, pool.size());
+            }
+            report.summary.translation_utilization = schedule::utilization(
+                translator.backend.request_time,
+                elapsed,
+                translator.backend.workers(),
+            );
+            if let Some(cache) = &translator.ocr_cache {
+                match cache.gc() {
+                    Ok(0) => {}
+                    Ok(removed) => eprintln!("{} old pages removed from the OCR cache", removed),
+                    // the outputs are written, a full cache stays until the next run
+                    Err(e) => eprintln!("could not clean the OCR cache: {}", e),
+                }
             }
         }
-        Ok(())
+        Commands::Check { .. }
+        | Commands::Languages
+        | Commands::Stats { .. }
+        | Commands::Verify { .. }
+        | Commands::Diff { .. }
+        | Commands::Serve { .. }
+        | Commands::RenderPages { .. }
+        | Commands::ApplyPlan { .. }
+        | Commands::RetryFailures { .. }
+        | Commands::ApplyTranslations { .. } => unreachable!(),
+    }
+    report.summary.characters = translator.backend.usage.sent;
+    report.summary.untranslated_segments = translator.backend.untranslated;
+    report.summary.suspect_segments = translator.backend.suspect;
+    report.summary.pii_preserved_segments = translator.backend.pii_preserved;
+    report.summary.echoed_segments = translator.backend.echo.echoed;
+    report.summary.passed_through_segments = translator.backend.passed_through;
+    report.summary.normalized_segments = translator.backend.normalized;
+    report.summary.converted_numbers = translator.backend.conversions.converted_numbers;
+    report.summary.converted_dates = translator.backend.conversions.converted_dates;
+    report.summary.qa_samples = translator.backend.qa.as_ref().map_or(0, |qa| qa.sampled);
+    if let Some(sample) = translator.backend.qa_sample.take() {
+        match sample.write() {
+            Ok((samples, path)) => {
+                report.summary.qa_sample_segments = samples;
+                report.summary.qa_sample_file = Some(path);
+            }
+            Err(e) => eprintln!("could not write the QA sample: {}", e),
+        }
+    }
+    report.summary.substituted_chars = translator.text_format.substituted();
+    report.format = translator.text_format.clone();
+    report.summary.requests = translator.backend.requests;
+    report.summary.ocr_restarts = ocr_pool::restarts();
+    report.summary.average_latency_ms = translator.backend.average_latency().map(|d| d.as_millis());
+    if translator.backend.instances.len() > 1 {
+        report.summary.instances = translator.backend.instances.stats();
+    }
+    report.summary.print();
+    events::emit(Event::RunFinished(Box::new(report.summary.clone())));
+    if let Some(path) = &args.report {
+        report.write(path).unwrap_or_else(|e| fatal_file(path, e));
+    }
+    if let (
+        Some(mut archive),
+        Some(TranslateArgs {
+            target_dir,
+            output_archive: Some(path),
+            ..
+        }),
+    ) = (archive, command.translate_args())
+    {
+        // the manifest of this run moves into the archive too, the report is copied
+        let added = archive
+            .add(&[target_dir.join(manifest::MANIFEST_FILE)])
+            .and_then(|_| match &args.report {
+                Some(report) => archive.append(report, Path::new(REPORT_ENTRY)),
+                None => Ok(()),
+            })
+            .and_then(|_| archive.finish());
+        if let Err(e) = added {
+            fatal_file(path, e);
+        }
+    }
+    if let Some(metrics) = metrics::get() {
+        if args.metrics {
+            metrics.print();
+        }
+        if let Some(path) = &args.metrics_file {
+            metrics.write(path).unwrap_or_else(|e| fatal_file(path, e));
+        }
+    }
+    events::close();
+    if report.summary.backend_echoing {
+        workspace::cleanup();
+        std::process::exit(ErrorKind::BackendUnavailable.exit_code());
+    }
+    let max_unsupported = config
+        .max_unsupported_share
+        .unwrap_or(unsupported::DEFAULT_MAX_SHARE);
+    let unsupported_share =
+        report.summary.unsupported_bytes as f64 / report.summary.source_bytes.max(1) as f64;
+    if unsupported_share > max_unsupported {
+        eprintln!(
+            "{:.0}% of the bytes of the source files are unsupported, more than \
+             max_unsupported_share",
+            unsupported_share * 100.0
+        );
+    }
+    // outputs with failed segment markers or pages left out are incomplete too
+    if report.summary.failed > 0
+        || report.summary.failed_segments > 0
+        || report.summary.failed_pages > 0
+        || report.summary.cancelled > 0
+        || unsupported_share > max_unsupported
+    {
+        // exiting skips the workspace's drop
+        workspace::cleanup();
+        std::process::exit(ErrorKind::PartialFailure.exit_code());
     }
 }
@@ -0,0 +1,165 @@
+//! `manifest.jsonl` in the target dir: one line per handled source file with the blake3
+//! hashes of the file and of everything written for it, for chain of custody. `verify`
+//! hashes the outputs again and compares them against it.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileHash {
+    /// relative to the source dir for sources, to the target dir for outputs
+    pub path: PathBuf,
+    pub size: u64,
+    pub blake3: String,
+    /// of sources, for the stamp of `--stamp-outputs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Size and blake3 hash of the file at `path`.
+pub fn hash_file(path: &Path) -> Result<(u64, String)> {
+    let mut hasher = blake3::Hasher::new();
+    let size = std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok((size, hasher.finalize().to_hex().to_string()))
+}
+
+/// SHA-256 hash of the file at `path`, in hex.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl FileHash {
+    fn of(path: &Path, relative_to: &Path) -> Result<Self> {
+        let (size, blake3) = hash_file(path)?;
+        Ok(FileHash {
+            path: path.strip_prefix(relative_to).unwrap_or(path).to_path_buf(),
+            size,
+            blake3,
+            sha256: None,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub source: FileHash,
+    pub handler: String,
+    pub outputs: Vec<FileHash>,
+    /// RFC 3339 times the handler started and finished
+    pub started: String,
+    pub finished: String,
+}
+
+/// The manifest of a target dir, opened for appending.
+pub struct Manifest {
+    file: File,
+    source_dir: PathBuf,
+    target_dir: PathBuf,
+}
+
+pub fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+impl Manifest {
+    pub fn open(source_dir: &Path, target_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(target_dir)?;
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(target_dir.join(MANIFEST_FILE))?;
+        Ok(Manifest {
+            file,
+            source_dir: source_dir.to_path_buf(),
+            target_dir: target_dir.to_path_buf(),
+        })
+    }
+
+    /// Hashes `source` and its outputs and appends them as one line, synced to disk so a
+    /// crash never leaves a partial line behind.
+    pub fn append(
+        &mut self,
+        source: &Path,
+        handler: &str,
+        outputs: &[PathBuf],
+        started: String,
+    ) -> Result<()> {
+        let entry = ManifestEntry {
+            source: FileHash {
+                sha256: Some(sha256_file(source)?),
+                ..FileHash::of(source, &self.source_dir)?
+            },
+            handler: handler.to_string(),
+            outputs: outputs
+                .iter()
+                .map(|o| FileHash::of(o, &self.target_dir))
+                .collect::<Result<_>>()?,
+            started,
+            finished: now(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Outcome of [`verify`].
+#[derive(Default)]
+pub struct Verification {
+    pub checked: usize,
+    pub missing: Vec<PathBuf>,
+    /// outputs whose size or hash differs from the manifest
+    pub changed: Vec<PathBuf>,
+}
+
+/// Every line of the manifest at `path`.
+pub fn read(path: &Path) -> Result<Vec<ManifestEntry>> {
+    let file = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: ManifestEntry = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Hashes every output listed in the manifest of `target_dir` again. For sources handled
+/// more than once the latest line counts.
+pub fn verify(target_dir: &Path) -> Result<Verification> {
+    let mut latest = BTreeMap::new();
+    for entry in read(&target_dir.join(MANIFEST_FILE))? {
+        latest.insert(entry.source.path.clone(), entry);
+    }
+    let mut verification = Verification::default();
+    for entry in latest.values() {
+        for output in &entry.outputs {
+            let full = target_dir.join(&output.path);
+            verification.checked += 1;
+            if !full.exists() {
+                verification.missing.push(output.path.clone());
+                continue;
+            }
+            let actual = FileHash::of(&full, target_dir)?;
+            if actual.size != output.size || actual.blake3 != output.blake3 {
+                verification.changed.push(output.path.clone());
+            }
+        }
+    }
+    Ok(verification)
+}
@@ -0,0 +1,100 @@
+//! Output of `filenames` without `--plan`: every source path next to its translation.
+
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MappingFormat {
+    /// original, translation and error separated by tabs, no header
+    Tsv,
+    /// the same columns as CSV with a header row
+    Csv,
+    /// an array of objects, written once all names are translated
+    Json,
+    /// only the translation, one per line
+    Plain,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Mapping {
+    pub original: String,
+    /// the original again if it could not be translated
+    pub translated: String,
+    pub error: Option<String>,
+}
+
+impl Mapping {
+    /// Builds the mapping from what the backend returned for `original`.
+    pub fn new(original: String, result: Result<String>) -> Self {
+        let (translated, error) = match result {
+            Ok(t) if t.starts_with(UNTRANSLATED_MARKER) => (
+                original.clone(),
+                Some("character budget used up".to_string()),
+            ),
+            Ok(t) => match t.strip_prefix(SUSPECT_MARKER) {
+                Some(t) => (t.to_string(), Some("suspect translation".to_string())),
+                None => (t, None),
+            },
+            Err(e) => (original.clone(), Some(e.to_string())),
+        };
+        Mapping {
+            original,
+            translated,
+            error,
+        }
+    }
+}
+
+enum Sink {
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    Json(Box<dyn Write>, Vec<Mapping>),
+    Plain(Box<dyn Write>),
+}
+
+pub struct MappingWriter {
+    sink: Sink,
+}
+
+impl MappingWriter {
+    pub fn new(format: MappingFormat, out: Box<dyn Write>) -> Self {
+        let sink = match format {
+            MappingFormat::Tsv => Sink::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .delimiter(b'\t')
+                    .has_headers(false)
+                    .from_writer(out),
+            )),
+            MappingFormat::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(out))),
+            MappingFormat::Json => Sink::Json(out, Vec::new()),
+            MappingFormat::Plain => Sink::Plain(out),
+        };
+        MappingWriter { sink }
+    }
+
+    pub fn write(&mut self, mapping: Mapping) -> Result<()> {
+        match &mut self.sink {
+            Sink::Csv(writer) => {
+                writer.serialize(&mapping)?;
+                writer.flush()?;
+            }
+            Sink::Json(_, mappings) => mappings.push(mapping),
+            Sink::Plain(out) => writeln!(out, "{}", mapping.translated)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.sink {
+            Sink::Csv(mut writer) => writer.flush()?,
+            Sink::Json(mut out, mappings) => {
+                serde_json::to_writer_pretty(&mut out, &mappings)?;
+                writeln!(out)?;
+            }
+            Sink::Plain(mut out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! `--per-folder-markers`: one marker per folder of the source dir at `--marker-depth`,
+//! written into its counterpart in the target dir once the run has handled all files in
+//! it. `_TRANSLATED.ok` when every file was translated without failures,
+//! `_TRANSLATED.partial.json` listing the files that weren't otherwise. Both hold the
+//! summary of the folder. A folder with files the run didn't get to, because it was
+//! cancelled, gets none and loses the one of an earlier run; `retry-failures` updates the
+//! markers already written.
+
+use crate::FileReport;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const OK_MARKER: &str = "_TRANSLATED.ok";
+pub const PARTIAL_MARKER: &str = "_TRANSLATED.partial.json";
+
+/// Where the markers of a run go, kept in the report for `retry-failures`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Markers {
+    /// what the sources of the report are below
+    pub source_dir: PathBuf,
+    pub target_dir: PathBuf,
+    /// 1 for the immediate subfolders of the source dir, 0 for the source dir itself
+    pub depth: usize,
+}
+
+#[derive(Default, Serialize)]
+struct FolderSummary {
+    files: usize,
+    outputs: usize,
+    failed_segments: usize,
+    failed_pages: usize,
+    /// files not translated in full
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    incomplete: Vec<Incomplete>,
+}
+
+#[derive(Serialize)]
+struct Incomplete {
+    source: PathBuf,
+    error: Option<String>,
+    failed_segments: usize,
+    failed_pages: Vec<usize>,
+}
+
+/// Whether `file` and its attachments were translated without failures.
+fn complete(file: &FileReport) -> bool {
+    file.error.is_none()
+        && file.failures.is_empty()
+        && file.failed_pages.is_empty()
+        && file.attachments.iter().all(complete)
+}
+
+impl Markers {
+    /// The folder at the marker depth `source` is in, relative to the source dir; `None`
+    /// for files above it.
+    fn folder(&self, source: &Path) -> Option<PathBuf> {
+        let parent = source.strip_prefix(&self.source_dir).ok()?.parent()?;
+        let components: Vec<_> = parent.components().collect();
+        if components.len() < self.depth {
+            return None;
+        }
+        Some(components[..self.depth].iter().collect())
+    }
+
+    /// Writes the marker of every folder with a file in `files` and none in `pending`,
+    /// and removes those of the folders with a pending file.
+    pub fn write(&self, files: &[FileReport], pending: &[PathBuf]) -> Result<()> {
+        let unfinished: Vec<PathBuf> = pending.iter().filter_map(|p| self.folder(p)).collect();
+        for folder in &unfinished {
+            self.remove(folder)?;
+        }
+        for (folder, files) in self.group(files) {
+            if !unfinished.contains(&folder) {
+                self.mark(&folder, &files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the marker of every folder of `files` that already has one, after they were
+    /// retried.
+    pub fn refresh(&self, files: &[FileReport]) -> Result<()> {
+        for (folder, files) in self.group(files) {
+            let dir = self.target_dir.join(&folder);
+            if dir.join(OK_MARKER).exists() || dir.join(PARTIAL_MARKER).exists() {
+                self.mark(&folder, &files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn group<'a>(&self, files: &'a [FileReport]) -> BTreeMap<PathBuf, Vec<&'a FileReport>> {
+        let mut folders: BTreeMap<PathBuf, Vec<&FileReport>> = BTreeMap::new();
+        for file in files {
+            if let Some(folder) = self.folder(&file.source) {
+                folders.entry(folder).or_default().push(file);
+            }
+        }
+        folders
+    }
+
+    fn mark(&self, folder: &Path, files: &[&FileReport]) -> Result<()> {
+        let mut summary = FolderSummary::default();
+        for file in files {
+            summary.files += 1;
+            summary.outputs += file.outputs.len();
+            summary.failed_segments += file.failures.len();
+            summary.failed_pages += file.failed_pages.len();
+            if !complete(file) {
+                summary.incomplete.push(Incomplete {
+                    source: file.source.clone(),
+                    error: file.error.clone(),
+                    failed_segments: file.failures.len(),
+                    failed_pages: file.failed_pages.clone(),
+                });
+            }
+        }
+        self.remove(folder)?;
+        let dir = self.target_dir.join(folder);
+        std::fs::create_dir_all(&dir)?;
+        let name = if summary.incomplete.is_empty() {
+            OK_MARKER
+        } else {
+            PARTIAL_MARKER
+        };
+        let mut json = serde_json::to_string_pretty(&summary)?;
+        json.push('\n');
+        std::fs::write(dir.join(name), json)?;
+        Ok(())
+    }
+
+    fn remove(&self, folder: &Path) -> Result<()> {
+        for name in [OK_MARKER, PARTIAL_MARKER] {
+            match std::fs::remove_file(self.target_dir.join(folder).join(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+//! Regions of a page that are blanked out before OCR, e.g. punched margins or stamps.
+
+use crate::orientation::png_bytes;
+use anyhow::Result;
+use image::{DynamicImage, GenericImage, Rgba};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Rectangle in percent of the page size, `left,top,right,bottom`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Mask {
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+}
+
+impl FromStr for Mask {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f32> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid mask {:?}: {}", s, e))?;
+        let [left, top, right, bottom] = values[..] else {
+            return Err(format!(
+                "invalid mask {:?}: expected left,top,right,bottom",
+                s
+            ));
+        };
+        if !(0.0..=100.0).contains(&left)
+            || !(0.0..=100.0).contains(&right)
+            || !(0.0..=100.0).contains(&top)
+            || !(0.0..=100.0).contains(&bottom)
+            || left >= right
+            || top >= bottom
+        {
+            return Err(format!(
+                "invalid mask {:?}: edges must be percentages with left < right and top < bottom",
+                s
+            ));
+        }
+        Ok(Mask {
+            left,
+            top,
+            right,
+            bottom,
+        })
+    }
+}
+
+impl TryFrom<String> for Mask {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Mask> for String {
+    fn from(mask: Mask) -> Self {
+        format!("{},{},{},{}", mask.left, mask.top, mask.right, mask.bottom)
+    }
+}
+
+impl Mask {
+    /// The mask in pixels of a `width`×`height` image, as `(left, top, right, bottom)`.
+    fn pixels(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = |p: f32| (width as f32 * p / 100.0).round() as u32;
+        let y = |p: f32| (height as f32 * p / 100.0).round() as u32;
+        (x(self.left), y(self.top), x(self.right), y(self.bottom))
+    }
+
+    /// Whether most of the box lies inside the mask.
+    fn covers(&self, geometry: &leptess::leptonica::BoxGeometry, width: u32, height: u32) -> bool {
+        let (left, top, right, bottom) = self.pixels(width, height);
+        let (x1, y1) = (geometry.x.max(0) as u32, geometry.y.max(0) as u32);
+        let (x2, y2) = (x1 + geometry.w.max(0) as u32, y1 + geometry.h.max(0) as u32);
+        let overlap_w = x2.min(right).saturating_sub(x1.max(left)) as u64;
+        let overlap_h = y2.min(bottom).saturating_sub(y1.max(top)) as u64;
+        let area = (x2 - x1) as u64 * (y2 - y1) as u64;
+        area > 0 && overlap_w * overlap_h * 2 >= area
+    }
+}
+
+/// Returns a copy of `image` with the masked regions painted white, together with the
+/// number of text blocks tesseract finds mostly inside them on the unmasked image.
+pub fn apply(
+    lt: &mut leptess::LepTess,
+    image: &DynamicImage,
+    masks: &[Mask],
+) -> Result<(DynamicImage, usize)> {
+    let (width, height) = (image.width(), image.height());
+    lt.set_image_from_mem(&png_bytes(image)?)?;
+    let suppressed = lt
+        .get_component_boxes(leptess::capi::TessPageIteratorLevel_RIL_BLOCK, true)
+        .map(|boxes| {
+            (&boxes)
+                .into_iter()
+                .filter(|b| {
+                    let geometry = b.get_geometry();
+                    masks.iter().any(|m| m.covers(&geometry, width, height))
+                })
+                .count()
+        })
+        .unwrap_or(0);
+    let mut masked = image.clone();
+    for mask in masks {
+        let (left, top, right, bottom) = mask.pixels(width, height);
+        paint_white(&mut masked, left, top, right, bottom);
+    }
+    Ok((masked, suppressed))
+}
+
+/// Paints the pixels from `left`,`top` up to `right`,`bottom` white, clipped to the image.
+pub fn paint_white(image: &mut DynamicImage, left: u32, top: u32, right: u32, bottom: u32) {
+    for y in top..bottom.min(image.height()) {
+        for x in left..right.min(image.width()) {
+            image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+        }
+    }
+}
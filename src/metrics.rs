@@ -0,0 +1,355 @@
+//! Counters and histograms of a run, to tell whether OCR or the translation server holds
+//! it up. Only collected with `--metrics` or `--metrics-file`; until [`enable`] is called
+//! recording is a single check of an unset cell.
+
+use crate::error::ErrorKind;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Upper bounds in seconds of the latency buckets of a translation request.
+const BACKEND_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+/// Upper bounds in seconds of the buckets of an OCR job, one per page or image.
+const OCR_BUCKETS: &[f64] = &[0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 60.0, 120.0];
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// one per bound and one for everything above the last
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&b| seconds <= b)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    fn sum(&self) -> f64 {
+        self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `None` above the last bound.
+    fn quantile(&self, q: f64) -> Option<f64> {
+        let rank = (self.count() as f64 * q).ceil() as u64;
+        let mut seen = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            seen += count.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+
+    fn describe(&self) -> String {
+        let count = self.count();
+        if count == 0 {
+            return "none".to_string();
+        }
+        let p95 = match self.quantile(0.95) {
+            Some(bound) => format!("<= {}s", bound),
+            None => format!("> {}s", self.bounds.last().unwrap()),
+        };
+        format!(
+            "{}, {:.3}s on average, 95% {}",
+            count,
+            self.sum() / count as f64,
+            p95
+        )
+    }
+
+    /// Adds the buckets of an exported histogram, `cumulative` as in [`Histogram::export`].
+    fn add(&self, cumulative: &[u64], sum: f64) {
+        let mut below = 0;
+        for (count, &up_to) in self.counts.iter().zip(cumulative) {
+            count.fetch_add(up_to.saturating_sub(below), Ordering::Relaxed);
+            below = up_to;
+        }
+        self.sum_micros
+            .fetch_add((sum * 1e6) as u64, Ordering::Relaxed);
+    }
+
+    fn export(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count()).unwrap();
+        writeln!(out, "{}_sum {}", name, self.sum()).unwrap();
+        writeln!(out, "{}_count {}", name, self.count()).unwrap();
+    }
+}
+
+pub struct Metrics {
+    pub segments: AtomicU64,
+    pub characters: AtomicU64,
+    pub failed_segments: AtomicU64,
+    /// tesseract instances started again as they seemed broken
+    pub ocr_restarts: AtomicU64,
+    pub backend_latency: Histogram,
+    pub ocr_seconds: Histogram,
+    /// files that failed, by the kind of their error
+    failures: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Starts collecting metrics.
+pub fn enable() {
+    METRICS.get_or_init(Metrics::new);
+}
+
+/// The metrics if they are collected.
+pub fn get() -> Option<&'static Metrics> {
+    METRICS.get()
+}
+
+/// Counts a translated segment of `chars` characters.
+pub fn segment_translated(chars: usize) {
+    if let Some(metrics) = get() {
+        metrics.segments.fetch_add(1, Ordering::Relaxed);
+        metrics
+            .characters
+            .fetch_add(chars as u64, Ordering::Relaxed);
+    }
+}
+
+pub fn segment_failed() {
+    if let Some(metrics) = get() {
+        metrics.failed_segments.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn backend_request(elapsed: Duration) {
+    if let Some(metrics) = get() {
+        metrics.backend_latency.observe(elapsed);
+    }
+}
+
+pub fn ocr_job(elapsed: Duration) {
+    if let Some(metrics) = get() {
+        metrics.ocr_seconds.observe(elapsed);
+    }
+}
+
+pub fn ocr_restart() {
+    if let Some(metrics) = get() {
+        metrics.ocr_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn file_failed(kind: ErrorKind) {
+    if let Some(metrics) = get() {
+        *metrics
+            .failures
+            .lock()
+            .unwrap()
+            .entry(kind.name().to_string())
+            .or_default() += 1;
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            segments: AtomicU64::new(0),
+            characters: AtomicU64::new(0),
+            failed_segments: AtomicU64::new(0),
+            ocr_restarts: AtomicU64::new(0),
+            backend_latency: Histogram::new(BACKEND_BUCKETS),
+            ocr_seconds: Histogram::new(OCR_BUCKETS),
+            failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The breakdown printed at the end of a run.
+    pub fn print(&self) {
+        eprintln!("metrics:");
+        eprintln!(
+            "  segments translated: {} ({} characters), {} failed",
+            self.segments.load(Ordering::Relaxed),
+            self.characters.load(Ordering::Relaxed),
+            self.failed_segments.load(Ordering::Relaxed)
+        );
+        eprintln!("  backend requests: {}", self.backend_latency.describe());
+        eprintln!("  OCR pages: {}", self.ocr_seconds.describe());
+        let restarts = self.ocr_restarts.load(Ordering::Relaxed);
+        if restarts > 0 {
+            eprintln!("  tesseract restarts: {}", restarts);
+        }
+        for (kind, count) in self.failures.lock().unwrap().iter() {
+            eprintln!("  failed files, {}: {}", kind, count);
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn export(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("segments", "segments translated", &self.segments),
+            (
+                "characters",
+                "characters sent for translation",
+                &self.characters,
+            ),
+            (
+                "failed_segments",
+                "segments the server failed to translate",
+                &self.failed_segments,
+            ),
+            (
+                "ocr_restarts",
+                "tesseract instances started again as they seemed broken",
+                &self.ocr_restarts,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP dir_translate_{}_total {}", name, help).unwrap();
+            writeln!(out, "# TYPE dir_translate_{}_total counter", name).unwrap();
+            let value = value.load(Ordering::Relaxed);
+            writeln!(out, "dir_translate_{}_total {}", name, value).unwrap();
+        }
+        self.backend_latency.export(
+            &mut out,
+            "dir_translate_backend_request_seconds",
+            "round trip of a translation request",
+        );
+        self.ocr_seconds.export(
+            &mut out,
+            "dir_translate_ocr_page_seconds",
+            "tesseract time per page or image",
+        );
+        writeln!(
+            out,
+            "# HELP dir_translate_failures_total files that failed, by kind"
+        )
+        .unwrap();
+        writeln!(out, "# TYPE dir_translate_failures_total counter").unwrap();
+        for (kind, count) in self.failures.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "dir_translate_failures_total{{kind=\"{}\"}} {}",
+                kind, count
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Adds the metrics of another run, as exported by [`Metrics::export`]: `serve` collects
+    /// those of its jobs, each run by a process of its own.
+    pub fn add(&self, export: &str) {
+        let counters = [
+            ("segments", &self.segments),
+            ("characters", &self.characters),
+            ("failed_segments", &self.failed_segments),
+            ("ocr_restarts", &self.ocr_restarts),
+        ];
+        let mut buckets: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+        let mut sums: BTreeMap<&str, f64> = BTreeMap::new();
+        for line in export.lines().filter(|line| !line.starts_with('#')) {
+            let Some((name, Ok(value))) = line.rsplit_once(' ').map(|(n, v)| (n, v.parse::<f64>()))
+            else {
+                continue;
+            };
+            let failures = name
+                .strip_prefix("dir_translate_failures_total{kind=\"")
+                .and_then(|kind| kind.strip_suffix("\"}"));
+            if let Some(kind) = failures {
+                *self
+                    .failures
+                    .lock()
+                    .unwrap()
+                    .entry(kind.to_string())
+                    .or_default() += value as u64;
+            } else if let Some((histogram, _)) = name.split_once("_bucket{") {
+                buckets.entry(histogram).or_default().push(value as u64);
+            } else if let Some(histogram) = name.strip_suffix("_sum") {
+                sums.insert(histogram, value);
+            } else if let Some((_, counter)) = counters
+                .iter()
+                .find(|(counter, _)| name == format!("dir_translate_{}_total", counter))
+            {
+                counter.fetch_add(value as u64, Ordering::Relaxed);
+            }
+        }
+        for (name, histogram) in [
+            (
+                "dir_translate_backend_request_seconds",
+                &self.backend_latency,
+            ),
+            ("dir_translate_ocr_page_seconds", &self.ocr_seconds),
+        ] {
+            let cumulative = buckets.get(name).map_or(&[][..], Vec::as_slice);
+            histogram.add(cumulative, sums.get(name).copied().unwrap_or(0.0));
+        }
+    }
+
+    /// Writes [`Metrics::export`] to `path` through a temporary file, so a collector
+    /// reading it never sees half of it.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("prom.partial");
+        std::fs::write(&partial, self.export())?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_export_added_to_empty_metrics_exports_the_same() {
+        let run = Metrics::new();
+        run.segments.store(3, Ordering::Relaxed);
+        run.characters.store(120, Ordering::Relaxed);
+        run.failed_segments.store(1, Ordering::Relaxed);
+        run.backend_latency.observe(Duration::from_millis(80));
+        run.backend_latency.observe(Duration::from_secs(40));
+        run.ocr_seconds.observe(Duration::from_millis(1500));
+        run.failures
+            .lock()
+            .unwrap()
+            .insert("backend_error".to_string(), 2);
+        let sum = Metrics::new();
+        sum.add(&run.export());
+        assert_eq!(sum.export(), run.export());
+        sum.add(&run.export());
+        assert_eq!(sum.segments.load(Ordering::Relaxed), 6);
+        assert_eq!(sum.backend_latency.count(), 4);
+        assert_eq!(sum.backend_latency.quantile(0.25), Some(0.1));
+        assert_eq!(sum.backend_latency.quantile(1.0), None);
+        assert!((sum.ocr_seconds.sum() - 3.0).abs() < 1e-6);
+        assert!(sum
+            .export()
+            .contains("dir_translate_failures_total{kind=\"backend_error\"} 4"));
+    }
+}
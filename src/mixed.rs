@@ -0,0 +1,192 @@
+//! `--split-mixed-segments`: bilingual lines like "Договор / Agreement" have half of their
+//! text in the target language already. Such segments are split into runs of words by
+//! script, only the runs in the source script are translated, and the results are put
+//! back between the untouched runs and separators.
+
+use crate::filter::Script;
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use anyhow::Result;
+
+/// Runs with fewer letters join their neighbour, so a name or an abbreviation isn't
+/// translated, or left out, on its own.
+const MIN_RUN_LETTERS: usize = 4;
+
+/// Part of a segment, the pieces of a segment joined are the segment again.
+pub struct Piece<'a> {
+    pub text: &'a str,
+    pub translate: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Class {
+    Source,
+    Target,
+}
+
+/// Words of one script with the words without letters between them, as byte offsets.
+struct Run {
+    class: Class,
+    start: usize,
+    end: usize,
+    letters: usize,
+}
+
+/// Splits `text` into the runs in the `source` script to translate and the rest. A single
+/// piece to translate if the text isn't mixed, or the scripts can't be told apart.
+pub fn split(text: &str, source: Script, target: Script) -> Vec<Piece<'_>> {
+    let whole = vec![Piece {
+        text,
+        translate: true,
+    }];
+    if source == target || source == Script::Any || target == Script::Any {
+        return whole;
+    }
+    let mut runs: Vec<Run> = Vec::new();
+    for (start, end) in words(text) {
+        let word = &text[start..end];
+        let in_source = word
+            .chars()
+            .filter(|&c| c.is_alphabetic() && source.contains(c))
+            .count();
+        let in_target = word
+            .chars()
+            .filter(|&c| c.is_alphabetic() && target.contains(c))
+            .count();
+        // the majority decides, for OCR reading a Cyrillic letter as its Latin twin
+        let class = match in_source.cmp(&in_target) {
+            std::cmp::Ordering::Greater => Class::Source,
+            std::cmp::Ordering::Less => Class::Target,
+            // digits, punctuation and the like go with the run around them
+            std::cmp::Ordering::Equal => continue,
+        };
+        let letters = in_source.max(in_target);
+        match runs.last_mut() {
+            Some(run) if run.class == class => {
+                run.end = end;
+                run.letters += letters;
+            }
+            _ => runs.push(Run {
+                class,
+                start,
+                end,
+                letters,
+            }),
+        }
+    }
+    merge_short(&mut runs);
+    if !runs.iter().any(|r| r.class == Class::Source)
+        || !runs.iter().any(|r| r.class == Class::Target)
+    {
+        return whole;
+    }
+    let mut pieces = Vec::new();
+    let mut at = 0;
+    for run in &runs {
+        if run.start > at {
+            pieces.push(Piece {
+                text: &text[at..run.start],
+                translate: false,
+            });
+        }
+        pieces.push(Piece {
+            text: &text[run.start..run.end],
+            translate: run.class == Class::Source,
+        });
+        at = run.end;
+    }
+    if at < text.len() {
+        pieces.push(Piece {
+            text: &text[at..],
+            translate: false,
+        });
+    }
+    pieces
+}
+
+/// Byte ranges of the whitespace separated words of `text`.
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+/// Joins every run shorter than [`MIN_RUN_LETTERS`] into the one before it, or the one
+/// after it for the first run, and joins neighbours of the same script.
+fn merge_short(runs: &mut Vec<Run>) {
+    while runs.len() > 1 {
+        let Some(short) = runs.iter().position(|r| r.letters < MIN_RUN_LETTERS) else {
+            break;
+        };
+        let dropped = runs.remove(short);
+        let run = &mut runs[short.saturating_sub(1)];
+        run.start = run.start.min(dropped.start);
+        run.end = run.end.max(dropped.end);
+        run.letters += dropped.letters;
+        let mut i = 1;
+        while i < runs.len() {
+            if runs[i].class == runs[i - 1].class {
+                let next = runs.remove(i);
+                runs[i - 1].end = next.end;
+                runs[i - 1].letters += next.letters;
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The translated segment from its `pieces` and the results of the pieces to translate,
+/// taken from `results` in order. Markers of the pieces move to the front; the first
+/// failed piece fails the segment.
+pub fn splice(
+    pieces: &[Piece],
+    results: &mut impl Iterator<Item = Result<String>>,
+) -> Result<String> {
+    if let [piece] = pieces {
+        if piece.translate {
+            return results.next().unwrap();
+        }
+    }
+    let mut out = String::new();
+    let mut markers = Vec::new();
+    let mut error = None;
+    for piece in pieces {
+        if !piece.translate {
+            out.push_str(piece.text);
+            continue;
+        }
+        match results.next().unwrap() {
+            Ok(mut translated) => {
+                for marker in [UNTRANSLATED_MARKER, SUSPECT_MARKER] {
+                    if let Some(rest) = translated.strip_prefix(marker) {
+                        translated = rest.to_string();
+                        if !markers.contains(&marker) {
+                            markers.push(marker);
+                        }
+                    }
+                }
+                out.push_str(translated.trim());
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(format!("{}{}", markers.concat(), out)),
+    }
+}
@@ -0,0 +1,179 @@
+//! `--ocr-multi-pass`: some degraded scans read better at a higher render resolution,
+//! others worse. Pages whose first pass is not confident enough are rendered and
+//! recognized again at the larger size, and every block keeps the text of the pass that
+//! was more confident about it.
+
+use crate::ocr::OcrBlock;
+use serde::{Deserialize, Serialize};
+
+/// Pages recognized at least this confidently by the first pass don't get a second one.
+pub const SKIP_CONFIDENCE: i32 = 85;
+
+/// The second pass of a page, in the report.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SecondPass {
+    /// 0-based page index
+    pub page: usize,
+    /// blocks whose text came from the second pass
+    pub blocks: usize,
+}
+
+/// Mean confidence of `blocks`, weighted by their length; 0 without text.
+pub fn confidence<'a>(blocks: impl IntoIterator<Item = &'a OcrBlock>) -> i32 {
+    let (mut sum, mut chars) = (0i64, 0i64);
+    for block in blocks {
+        let len = block.text.trim().chars().count() as i64;
+        sum += block.confidence as i64 * len;
+        chars += len;
+    }
+    if chars == 0 {
+        0
+    } else {
+        (sum / chars) as i32
+    }
+}
+
+/// Whether the first pass of a page needs a second one.
+pub fn needs_second_pass(first: &[OcrBlock]) -> bool {
+    confidence(first) < SKIP_CONFIDENCE
+}
+
+/// Merges the blocks of two passes over the same page, `second` recognized from an image
+/// `scale` times as large. Each block of `first` is compared with the `second` blocks
+/// centered in it and keeps whichever text is more confident; blocks only `second` found
+/// are added where they are on the page. Returns the blocks in `first`'s coordinates
+/// with the number taken from `second`.
+pub fn merge(first: &[OcrBlock], second: &[OcrBlock], scale: f32) -> (Vec<OcrBlock>, usize) {
+    let second: Vec<OcrBlock> = second
+        .iter()
+        .map(|block| OcrBlock {
+            bbox: block.bbox.map(|v| (v as f32 / scale).round() as i32),
+            text_height: (block.text_height as f32 / scale).round() as i32,
+            ..block.clone()
+        })
+        .collect();
+    let mut matched = vec![false; second.len()];
+    let mut merged = Vec::with_capacity(first.len());
+    let mut won = 0;
+    for block in first {
+        let group: Vec<usize> = (0..second.len())
+            .filter(|&i| !matched[i] && center_in(second[i].bbox, block.bbox))
+            .collect();
+        for &i in &group {
+            matched[i] = true;
+        }
+        let candidate = group.iter().map(|&i| &second[i]);
+        let candidate_confidence = confidence(candidate.clone());
+        if group.is_empty() || candidate_confidence <= block.confidence {
+            merged.push(block.clone());
+            continue;
+        }
+        let text_height = candidate.clone().map(|b| b.text_height).max().unwrap_or(0);
+        let text: Vec<&str> = candidate.map(|b| b.text.trim()).collect();
+        merged.push(OcrBlock {
+            text: text.join("\n\n"),
+            bbox: block.bbox,
+            confidence: candidate_confidence,
+            text_height,
+        });
+        won += 1;
+    }
+    for (block, matched) in second.into_iter().zip(matched) {
+        if matched || first.iter().any(|f| overlap(f.bbox, block.bbox)) {
+            continue;
+        }
+        // before the first block that starts below it
+        let at = merged
+            .iter()
+            .position(|b| b.bbox[1] > block.bbox[1])
+            .unwrap_or(merged.len());
+        merged.insert(at, block);
+        won += 1;
+    }
+    (merged, won)
+}
+
+fn center_in([x, y, w, h]: [i32; 4], [rx, ry, rw, rh]: [i32; 4]) -> bool {
+    let (cx, cy) = (x + w / 2, y + h / 2);
+    cx >= rx && cx < rx + rw && cy >= ry && cy < ry + rh
+}
+
+fn overlap([ax, ay, aw, ah]: [i32; 4], [bx, by, bw, bh]: [i32; 4]) -> bool {
+    ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(text: &str, bbox: [i32; 4], confidence: i32) -> OcrBlock {
+        OcrBlock {
+            text: text.to_string(),
+            bbox,
+            confidence,
+            text_height: bbox[3] / 2,
+        }
+    }
+
+    #[test]
+    fn confidence_is_weighted_by_length() {
+        let blocks = [block("ab", [0; 4], 90), block(" abcdefgh ", [0; 4], 40)];
+        assert_eq!(confidence(&blocks), 50);
+        assert_eq!(confidence(&[block("  ", [0; 4], 90)]), 0);
+        assert!(needs_second_pass(&blocks));
+        assert!(!needs_second_pass(&blocks[..1]));
+    }
+
+    #[test]
+    fn each_block_keeps_the_more_confident_text() {
+        let first = [
+            block("Пepвый", [0, 0, 100, 20], 60),
+            block("Второй", [0, 40, 100, 20], 90),
+        ];
+        let second = [
+            block("Первый", [0, 0, 200, 40], 85),
+            block("Bтopoй", [0, 80, 200, 40], 70),
+        ];
+        let (merged, won) = merge(&first, &second, 2.0);
+        assert_eq!(won, 1);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].text, "Первый");
+        assert_eq!(merged[0].bbox, [0, 0, 100, 20]);
+        assert_eq!(merged[0].confidence, 85);
+        assert_eq!(merged[0].text_height, 10);
+        assert_eq!(merged[1].text, "Второй");
+    }
+
+    #[test]
+    fn blocks_split_by_the_second_pass_are_taken_together() {
+        let first = [block("Абзац paз два", [0, 0, 200, 40], 50)];
+        let second = [
+            block("Абзац раз", [0, 0, 400, 40], 90),
+            block("два", [0, 40, 400, 40], 80),
+        ];
+        let (merged, won) = merge(&first, &second, 2.0);
+        assert_eq!(won, 1);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].text, "Абзац раз\n\nдва");
+        // weighted by the 8 and 3 letters
+        assert_eq!(merged[0].confidence, 87);
+    }
+
+    #[test]
+    fn blocks_only_the_second_pass_found_are_added_in_place() {
+        let first = [
+            block("Верх", [0, 0, 100, 20], 80),
+            block("Низ", [0, 40, 100, 20], 80),
+        ];
+        let second = [block("Середина", [0, 50, 200, 20], 70)];
+        let (merged, won) = merge(&first, &second, 2.0);
+        assert_eq!(won, 1);
+        let texts: Vec<&str> = merged.iter().map(|b| b.text.as_str()).collect();
+        assert_eq!(texts, ["Верх", "Середина", "Низ"]);
+        assert_eq!(merged[1].bbox, [0, 25, 100, 10]);
+        // one overlapping a block of the first pass without being centered in it is left out
+        let overlapping = [block("Bepx", [150, 30, 100, 20], 99)];
+        let (merged, won) = merge(&first, &overlapping, 2.0);
+        assert_eq!((merged.len(), won), (2, 0));
+    }
+}
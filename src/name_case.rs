@@ -0,0 +1,137 @@
+//! `--filename-case` and `--filename-separator`: translated names in one casing and with
+//! one separator between their words, like `Title_Case_With_Underscores`, whatever the
+//! server gave back. Acronyms keep their capitals: the words of `acronyms` in config.toml
+//! are written as listed there, and a word in capitals stays so unless the whole name is
+//! in capitals. Digits are left as they are.
+
+use crate::names;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum FilenameCase {
+    /// as translated
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+    /// every word capitalized
+    Title,
+    /// only the first word capitalized
+    Sentence,
+}
+
+/// Characters between words taken for a separator, the others are kept.
+const SEPARATORS: [char; 3] = [' ', '_', '-'];
+
+#[derive(Clone, Debug, Default)]
+pub struct NameStyle {
+    pub case: FilenameCase,
+    pub separator: Option<char>,
+    /// spelling of acronyms, `acronyms` of config.toml
+    pub acronyms: Vec<String>,
+}
+
+/// `word` with its first letter in capitals and the others in small letters.
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.as_str().to_lowercase().chars())
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// Whether `word` has letters, two at least, and all of them are capitals.
+fn in_capitals(word: &str) -> bool {
+    let letters = word.chars().filter(|c| c.is_alphabetic());
+    letters.clone().count() >= 2 && letters.clone().all(char::is_uppercase)
+}
+
+impl NameStyle {
+    /// A file or directory name without its extension, in the style.
+    pub fn apply(&self, name: &str) -> String {
+        if self.case == FilenameCase::Keep && self.separator.is_none() {
+            return name.to_string();
+        }
+        let tokens = names::tokens(name);
+        let words: Vec<&str> = tokens.iter().filter(|t| t.word).map(|t| t.text).collect();
+        let shouting = words
+            .iter()
+            .all(|w| in_capitals(w) || !w.chars().any(char::is_alphabetic));
+        let mut styled = String::with_capacity(name.len());
+        let mut first = true;
+        for token in &tokens {
+            if !token.word {
+                styled.push_str(&self.separate(token.text));
+                continue;
+            }
+            let listed = self
+                .acronyms
+                .iter()
+                .find(|a| a.to_lowercase() == token.text.to_lowercase());
+            let word = match listed {
+                Some(acronym) => acronym.clone(),
+                None if in_capitals(token.text) && !shouting => token.text.to_string(),
+                None => match self.case {
+                    FilenameCase::Keep => token.text.to_string(),
+                    FilenameCase::Lower => token.text.to_lowercase(),
+                    FilenameCase::Upper => token.text.to_uppercase(),
+                    FilenameCase::Title => capitalize(token.text),
+                    FilenameCase::Sentence if first => capitalize(token.text),
+                    FilenameCase::Sentence => token.text.to_lowercase(),
+                },
+            };
+            styled.push_str(&word);
+            first = false;
+        }
+        match self.separator {
+            Some(separator) => styled.trim_matches(separator).to_string(),
+            None => styled,
+        }
+    }
+
+    /// `text` between two words with its runs of separators replaced by the one of the
+    /// style.
+    fn separate(&self, text: &str) -> String {
+        let Some(separator) = self.separator else {
+            return text.to_string();
+        };
+        let mut separated = String::with_capacity(text.len());
+        for c in text.chars() {
+            if !SEPARATORS.contains(&c) {
+                separated.push(c);
+            } else if !separated.ends_with(separator) {
+                separated.push(separator);
+            }
+        }
+        separated
+    }
+
+    /// A file name, its extension left alone.
+    pub fn apply_file_name(&self, name: &str) -> String {
+        match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                format!("{}.{}", self.apply(stem), extension)
+            }
+            _ => self.apply(name),
+        }
+    }
+
+    /// A relative or absolute path, every directory and the file name in the style.
+    pub fn apply_path(&self, path: &str) -> String {
+        let components: Vec<&str> = path.split('/').collect();
+        let last = components.len() - 1;
+        components
+            .iter()
+            .enumerate()
+            .map(|(i, component)| match *component {
+                "" | "." | ".." => component.to_string(),
+                _ if i == last => self.apply_file_name(component),
+                _ => self.apply(component),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
@@ -0,0 +1,295 @@
+//! `--names-mode`: file names full of proper nouns, like "Щёлково_Иванов_акт.pdf", come
+//! out of a translation server as nonsense. They can be transliterated instead, with a
+//! BGN/PCGN-style Russian table that the `[transliteration]` table of the config file
+//! extends, or translated with the proper nouns transliterated.
+
+use crate::translator::Translator;
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap};
+
+/// Latin spelling of the Russian letters, lowercase.
+const RUSSIAN: [(char, &str); 33] = [
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('ё', "yo"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('й', "y"),
+    ('к', "k"),
+    ('л', "l"),
+    ('м', "m"),
+    ('н', "n"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('у', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ы', "y"),
+    ('ь', ""),
+    ('э', "e"),
+    ('ю', "yu"),
+    ('я', "ya"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NamesMode {
+    /// send the whole name to the server
+    Translate,
+    /// spell the name in Latin letters, without the server
+    Transliterate,
+    /// transliterate the proper nouns and whatever the server can't translate, translate
+    /// the rest
+    TranslateThenTransliterate,
+}
+
+/// Latin spellings by lowercase letter.
+#[derive(Clone, Debug)]
+pub struct Transliteration(HashMap<char, String>);
+
+impl Transliteration {
+    /// The Russian table with the letters of `extra` added or replaced; `extra` has
+    /// lowercase letters as keys, checked to be one character each by the config.
+    pub fn new(extra: &BTreeMap<String, String>) -> Self {
+        let mut table: HashMap<char, String> = RUSSIAN
+            .iter()
+            .map(|(letter, latin)| (*letter, latin.to_string()))
+            .collect();
+        for (letter, latin) in extra {
+            if let Some(letter) = letter.chars().next() {
+                table.insert(letter, latin.clone());
+            }
+        }
+        Transliteration(table)
+    }
+
+    /// `text` with the letters of the table spelled in Latin. Capitals become a capital
+    /// followed by small letters, "Щ" is "Shch", unless the word is in capitals around
+    /// them: "ЩИ" is "SHCHI".
+    pub fn apply(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut latin = String::with_capacity(text.len());
+        for (i, &c) in chars.iter().enumerate() {
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            let Some(spelling) = self.0.get(&lower) else {
+                latin.push(c);
+                continue;
+            };
+            if !c.is_uppercase() {
+                latin.push_str(spelling);
+                continue;
+            }
+            let next = chars.get(i + 1).filter(|c| c.is_alphabetic());
+            let previous = i
+                .checked_sub(1)
+                .map(|i| chars[i])
+                .filter(|c| c.is_alphabetic());
+            let capitals = match next {
+                Some(next) => next.is_uppercase(),
+                None => previous.is_some_and(char::is_uppercase),
+            };
+            if capitals {
+                latin.push_str(&spelling.to_uppercase());
+            } else {
+                let mut letters = spelling.chars();
+                if let Some(first) = letters.next() {
+                    latin.extend(first.to_uppercase());
+                    latin.push_str(letters.as_str());
+                }
+            }
+        }
+        latin
+    }
+
+    /// Whether `text` has a letter the table spells.
+    fn spells(&self, text: &str) -> bool {
+        text.chars()
+            .any(|c| self.0.contains_key(&c.to_lowercase().next().unwrap_or(c)))
+    }
+}
+
+/// A word of a name or what separates two words.
+#[derive(Debug, PartialEq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub word: bool,
+}
+
+/// Splits `name` into words, runs of letters and digits, and the separators between
+/// them. Joining the texts gives `name` back; "Петров-Водкин" is two words around a
+/// hyphen.
+pub fn tokens(name: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut word = None;
+    for (i, c) in name.char_indices() {
+        let is_word = c.is_alphanumeric();
+        if word.is_some_and(|word| word != is_word) {
+            tokens.push(Token {
+                text: &name[start..i],
+                word: !is_word,
+            });
+            start = i;
+        }
+        word = Some(is_word);
+    }
+    if let Some(word) = word {
+        tokens.push(Token {
+            text: &name[start..],
+            word,
+        });
+    }
+    tokens
+}
+
+/// Whether the word at `index` of `tokens` is taken for a proper noun: it starts with a
+/// capital, unless it is the first word of a name made of words with spaces between
+/// them, where it starts a sentence.
+fn proper_noun(tokens: &[Token], index: usize) -> bool {
+    let token = &tokens[index];
+    let capital = token.text.chars().next().is_some_and(char::is_uppercase);
+    let sentence = tokens.iter().any(|t| !t.word && t.text.contains(' '));
+    let first = tokens.iter().position(|t| t.word) == Some(index);
+    token.word && capital && !(sentence && first)
+}
+
+/// `name` in the target language as `translator.names_mode` has it.
+pub async fn translate(translator: &mut Translator, name: &str) -> Result<String> {
+    let table = translator.transliteration.clone();
+    match translator.names_mode {
+        NamesMode::Translate => translator.translate(name).await,
+        NamesMode::Transliterate => Ok(table.apply(name)),
+        NamesMode::TranslateThenTransliterate => {
+            let tokens = tokens(name);
+            let mut translated = String::with_capacity(name.len());
+            let mut index = 0;
+            while index < tokens.len() {
+                if !tokens[index].word || proper_noun(&tokens, index) {
+                    let text = tokens[index].text;
+                    translated.push_str(&table.apply(text));
+                    index += 1;
+                    continue;
+                }
+                // common words are sent together with the separators between them
+                let mut end = index + 1;
+                while end + 1 < tokens.len() && !proper_noun(&tokens, end + 1) {
+                    end += 2;
+                }
+                let words: String = tokens[index..end].iter().map(|t| t.text).collect();
+                translated.push_str(&translate_or_spell(translator, &table, &words).await);
+                index = end;
+            }
+            Ok(translated)
+        }
+    }
+}
+
+/// `words` translated, or transliterated if the server failed on them or gave them back
+/// unchanged.
+async fn translate_or_spell(
+    translator: &mut Translator,
+    table: &Transliteration,
+    words: &str,
+) -> String {
+    if !table.spells(words) {
+        return words.to_string();
+    }
+    match translator.translate(words).await {
+        Ok(translated)
+            if translated.trim() != words.trim()
+                && !translated.starts_with(UNTRANSLATED_MARKER)
+                && !translated.starts_with(SUSPECT_MARKER) =>
+        {
+            translated
+        }
+        _ => table.apply(words),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transliterate(text: &str) -> String {
+        Transliteration::new(&BTreeMap::new()).apply(text)
+    }
+
+    #[test]
+    fn russian_is_spelled_in_latin_letters() {
+        assert_eq!(
+            transliterate("Щёлково_Иванов_акт.pdf"),
+            "Shchyolkovo_Ivanov_akt.pdf"
+        );
+        assert_eq!(transliterate("Ёлкин"), "Yolkin");
+        assert_eq!(transliterate("подъезд и съезд"), "podezd i sezd");
+        assert_eq!(transliterate("Хрущёв"), "Khrushchyov");
+        assert_eq!(transliterate("ООО «Ромашка».pdf"), "OOO «Romashka».pdf");
+    }
+
+    #[test]
+    fn capitals_stay_capitals_in_words_of_capitals() {
+        assert_eq!(transliterate("ЩИ"), "SHCHI");
+        assert_eq!(transliterate("АКТ ЖКХ"), "AKT ZHKKH");
+        assert_eq!(transliterate("Щ"), "Shch");
+        assert_eq!(transliterate("Юля"), "Yulya");
+    }
+
+    #[test]
+    fn compound_names_keep_their_hyphen() {
+        assert_eq!(transliterate("Петров-Водкин"), "Petrov-Vodkin");
+        assert_eq!(transliterate("Ростов-на-Дону"), "Rostov-na-Donu");
+    }
+
+    #[test]
+    fn the_config_extends_and_replaces_letters() {
+        let extra = BTreeMap::from([
+            ("ї".to_string(), "yi".to_string()),
+            ("х".to_string(), "h".to_string()),
+        ]);
+        let table = Transliteration::new(&extra);
+        assert_eq!(table.apply("Київ"), "Kiyiv");
+        assert_eq!(table.apply("Хабаровск"), "Habarovsk");
+    }
+
+    #[test]
+    fn names_are_split_into_words_and_separators() {
+        let name = "Петров-Водкин_акт 12 (копия).pdf";
+        let split = tokens(name);
+        let words: Vec<&str> = split.iter().filter(|t| t.word).map(|t| t.text).collect();
+        assert_eq!(words, ["Петров", "Водкин", "акт", "12", "копия", "pdf"]);
+        assert_eq!(split.iter().map(|t| t.text).collect::<String>(), name);
+        assert_eq!(
+            split[1],
+            Token {
+                text: "-",
+                word: false
+            }
+        );
+        assert!(tokens("").is_empty());
+    }
+
+    #[test]
+    fn capitalized_words_are_proper_nouns_unless_they_start_a_sentence() {
+        let name = tokens("Щёлково_Иванов_акт");
+        assert!(proper_noun(&name, 0));
+        assert!(proper_noun(&name, 2));
+        assert!(!proper_noun(&name, 4));
+        let sentence = tokens("Акт приёмки Иванов");
+        assert!(!proper_noun(&sentence, 0));
+        assert!(proper_noun(&sentence, 4));
+    }
+}
@@ -0,0 +1,195 @@
+//! Cleanup of segments before they are sent, turned off by `--no-normalize`. Tesseract's
+//! Russian model now and then reads a letter of a Cyrillic word as its Latin lookalike,
+//! "Mосква" with a Latin M, and the server mistranslates such words or leaves them alone.
+//! Segments are NFC-normalized, control characters are dropped, runs of spaces become
+//! one, and lookalike letters are turned into the script the rest of their word is in.
+//! Words with a digit, like "СУ-27M", and words with letters only one script has on both
+//! sides are left as they are.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Latin letters and the Cyrillic letters that look the same.
+pub const CONFUSABLES: [(char, char); 19] = [
+    ('A', 'А'),
+    ('B', 'В'),
+    ('C', 'С'),
+    ('E', 'Е'),
+    ('H', 'Н'),
+    ('K', 'К'),
+    ('M', 'М'),
+    ('O', 'О'),
+    ('P', 'Р'),
+    ('T', 'Т'),
+    ('X', 'Х'),
+    ('Y', 'У'),
+    ('a', 'а'),
+    ('c', 'с'),
+    ('e', 'е'),
+    ('o', 'о'),
+    ('p', 'р'),
+    ('x', 'х'),
+    ('y', 'у'),
+];
+
+/// Invisible characters dropped along with the control characters: zero-width space,
+/// word joiner, byte order mark and soft hyphen.
+const INVISIBLE: [char; 4] = ['\u{200B}', '\u{2060}', '\u{FEFF}', '\u{AD}'];
+
+/// How many times more letters of one script than of the other a segment needs for a
+/// word made only of lookalikes of both to be turned into it.
+const DOMINANCE: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Cyrillic,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{C0}'..='\u{24F}' if c.is_alphabetic() => Some(Script::Latin),
+        '\u{400}'..='\u{52F}' if c.is_alphabetic() => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// `c` in the other script if it is one of the [`CONFUSABLES`].
+fn lookalike(c: char) -> Option<char> {
+    CONFUSABLES.iter().find_map(|&(latin, cyrillic)| {
+        if c == latin {
+            Some(cyrillic)
+        } else if c == cyrillic {
+            Some(latin)
+        } else {
+            None
+        }
+    })
+}
+
+/// Letters of `text` in each script that have no lookalike in the other, Latin first.
+fn distinct_letters(text: &str) -> (usize, usize) {
+    let mut counts = (0, 0);
+    for c in text.chars().filter(|&c| lookalike(c).is_none()) {
+        match script(c) {
+            Some(Script::Latin) => counts.0 += 1,
+            Some(Script::Cyrillic) => counts.1 += 1,
+            None => {}
+        }
+    }
+    counts
+}
+
+/// `text` cleaned up as described above.
+pub fn normalize(text: &str) -> String {
+    let text: String = text
+        .nfc()
+        .filter(|&c| !INVISIBLE.contains(&c) && (!c.is_control() || c == '\n' || c == '\t'))
+        .collect();
+    let segment = match distinct_letters(&text) {
+        (latin, cyrillic) if latin > DOMINANCE * cyrillic => Some(Script::Latin),
+        (latin, cyrillic) if cyrillic > DOMINANCE * latin => Some(Script::Cyrillic),
+        _ => None,
+    };
+    let mut normalized = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            normalized.push('\n');
+        }
+        let mut words = line.split([' ', '\t']).filter(|w| !w.is_empty()).peekable();
+        if line.starts_with([' ', '\t']) && words.peek().is_some() {
+            normalized.push(' ');
+        }
+        let mut first = true;
+        for word in words {
+            if !first {
+                normalized.push(' ');
+            }
+            first = false;
+            normalized.push_str(&word_in_script(word, segment));
+        }
+    }
+    normalized
+}
+
+/// `word` with its lookalike letters in the script of its other letters, or of the
+/// segment's if it has only lookalikes of both scripts.
+fn word_in_script(word: &str, segment: Option<Script>) -> String {
+    if word.chars().any(|c| c.is_numeric()) {
+        return word.to_string();
+    }
+    let target = match distinct_letters(word) {
+        (0, 0) => {
+            let scripts: Vec<Script> = word.chars().filter_map(script).collect();
+            let mixed = scripts.windows(2).any(|pair| pair[0] != pair[1]);
+            match segment {
+                Some(script) if mixed => script,
+                _ => return word.to_string(),
+            }
+        }
+        (0, _) => Script::Cyrillic,
+        (_, 0) => Script::Latin,
+        _ => return word.to_string(),
+    };
+    word.chars()
+        .map(|c| match lookalike(c) {
+            Some(other) if script(c) != Some(target) => other,
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_confusable_is_turned_into_the_script_of_its_word() {
+        for (latin, cyrillic) in CONFUSABLES {
+            assert_eq!(
+                normalize(&format!("д{}л", latin)),
+                format!("д{}л", cyrillic),
+                "{}",
+                latin
+            );
+            assert_eq!(
+                normalize(&format!("s{}t", cyrillic)),
+                format!("s{}t", latin),
+                "{}",
+                cyrillic
+            );
+        }
+    }
+
+    #[test]
+    fn lookalikes_in_words_are_fixed() {
+        assert_eq!(normalize("Mосква"), "Москва");
+        assert_eq!(normalize("Мoskva"), "Moskva");
+        assert_eq!(normalize("Пpивeт, мир"), "Привет, мир");
+        assert_eq!(normalize("iPhone-Х"), "iPhone-X");
+    }
+
+    #[test]
+    fn genuinely_mixed_words_are_left_alone() {
+        for text in ["СУ-27M", "CY-27M", "Яndex", "ТОП-10"] {
+            assert_eq!(normalize(text), text);
+        }
+    }
+
+    #[test]
+    fn words_of_lookalikes_only_follow_the_segment() {
+        // Cyrillic Т with Latin O and P
+        assert_eq!(normalize("Привет мир ТOP"), "Привет мир ТОР");
+        assert_eq!(normalize("hello world ТOP"), "hello world TOP");
+        // no script dominates, or the word isn't mixed
+        assert_eq!(normalize("ТOP"), "ТOP");
+        assert_eq!(normalize("Привет мир TOP"), "Привет мир TOP");
+    }
+
+    #[test]
+    fn text_is_composed_and_cleaned() {
+        assert_eq!(normalize("е\u{308}лка"), "ёлка");
+        assert_eq!(normalize("a\u{7}b\u{200B}c\u{AD}d\u{FEFF}"), "abcd");
+        assert_eq!(normalize("раз  два\t\tтри"), "раз два три");
+        assert_eq!(normalize("  раз\nдва\n\nтри "), " раз\nдва\n\nтри");
+    }
+}
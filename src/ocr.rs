@@ -0,0 +1,411 @@
+//! OCR of a page and the cache of its results. The cache lives in `<cache-dir>/ocr`, one
+//! JSON file per page and OCR settings, so runs that only change the target language or
+//! the backend skip rendering and OCR.
+
+use crate::columns::{self, Columns};
+use crate::handwriting;
+use crate::mask::{self, Mask};
+use crate::orientation::{self, png_bytes, Orientation};
+use crate::rotated;
+use crate::scan_tables::{self, ScanTable};
+use anyhow::Result;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default limit of the OCR cache in megabytes.
+pub const DEFAULT_MAX_SIZE_MB: u64 = 512;
+
+const OCR_DIR: &str = "ocr";
+
+/// Longest side images are scaled down to before OCR, about 400 DPI for A4; more detail
+/// doesn't help tesseract and costs memory.
+pub const DEFAULT_MAX_IMAGE_SIDE: u32 = 5000;
+
+/// Blocks with more characters are split into paragraphs at the gaps between their
+/// lines, one request of the server wouldn't take them and their paragraphs would be lost.
+pub const DEFAULT_MAX_BLOCK_CHARS: usize = 2000;
+
+/// Bumped whenever the way pages are recognized changes, to ignore older entries.
+const CACHE_VERSION: u32 = 4;
+
+/// Settings that change what tesseract reads from a page.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub orientation: Orientation,
+    pub masks: Vec<Mask>,
+    pub detect_tables: bool,
+    pub columns: Columns,
+    /// render size of a second pass over unclear PDF pages, `--ocr-multi-pass`
+    pub multi_pass: Option<u32>,
+    /// recognize unclear blocks turned by 90°, `--detect-rotated-text`
+    pub rotated_text: bool,
+    /// take blocks that look handwritten out of the segments, `--extract-handwriting`
+    pub handwriting: bool,
+    /// longer blocks are split at their line gaps, `max_block_chars`
+    pub max_block_chars: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OcrBlock {
+    pub text: String,
+    /// x, y, width, height in pixels of the oriented page
+    pub bbox: [i32; 4],
+    /// tesseract's mean word confidence, 0 to 100
+    pub confidence: i32,
+    /// median height in pixels of the words of the block, 0 if not known
+    #[serde(default)]
+    pub text_height: i32,
+}
+
+/// Raw OCR result of a page, before reflow, filtering and translation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OcrPage {
+    /// clockwise rotation applied before OCR
+    pub degrees: u16,
+    /// text blocks dropped by the OCR masks
+    pub masked_blocks: usize,
+    pub segments: Vec<OcrBlock>,
+    /// tables found with `--detect-tables`, left out of the segments
+    #[serde(default)]
+    pub tables: Vec<ScanTable>,
+    /// blocks taken from the second pass, `None` if there was none
+    #[serde(default)]
+    pub second_pass_blocks: Option<usize>,
+    /// blocks read turned by 90° with `--detect-rotated-text`, left out of the segments
+    #[serde(default)]
+    pub rotated: Vec<OcrBlock>,
+    /// blocks taken for handwriting with `--extract-handwriting`, left out of the segments
+    #[serde(default)]
+    pub handwriting: Vec<OcrBlock>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    /// checked on reads in case two keys hash the same
+    key: String,
+    #[serde(flatten)]
+    page: OcrPage,
+}
+
+pub fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Hash of the content of `path`, stable across runs and builds.
+pub fn hash_file(path: &Path) -> Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash = FNV_OFFSET;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+}
+
+/// Cache key of page `page` of a file, covering everything that changes what tesseract reads.
+pub fn key(file_hash: u64, page: usize, ocr_lang: &str, options: &Options) -> String {
+    let masks: Vec<String> = options.masks.iter().map(|&m| m.into()).collect();
+    let mut key = format!(
+        "v{} {:016x} {} {} {:?} [{}] {:?} {}",
+        CACHE_VERSION,
+        file_hash,
+        page,
+        ocr_lang,
+        options.orientation,
+        masks.join(" "),
+        options.columns,
+        options.max_block_chars
+    );
+    if options.detect_tables {
+        key.push_str(" tables");
+    }
+    if let Some(size) = options.multi_pass {
+        key.push_str(&format!(" multi {}", size));
+    }
+    if options.rotated_text {
+        key.push_str(" rotated");
+    }
+    if options.handwriting {
+        key.push_str(" handwriting");
+    }
+    key
+}
+
+/// Orients and masks `image`, then recognizes it block by block, column by column if it
+/// has several. With `detect_tables`, tables are looked for first and blanked out for the
+/// block pass, with `rotated_text` unclear blocks are read turned afterwards, and with
+/// `handwriting` those still unclear but inked are set apart. Returns the oriented,
+/// unmasked image with the result.
+pub fn recognize(
+    lt: &mut leptess::LepTess,
+    image: DynamicImage,
+    options: &Options,
+) -> Result<(DynamicImage, OcrPage)> {
+    let (image, degrees) = orientation::orient(lt, image, options.orientation)?;
+    let masks = &options.masks;
+    let mut masked_blocks = 0;
+    let mut masked = if masks.is_empty() {
+        None
+    } else {
+        let (masked, suppressed) = mask::apply(lt, &image, masks)?;
+        masked_blocks = suppressed;
+        Some(masked)
+    };
+    let mut tables = Vec::new();
+    if options.detect_tables {
+        let page = masked.as_ref().unwrap_or(&image);
+        lt.set_image_from_mem(&png_bytes(page)?)?;
+        tables = scan_tables::detect(&scan_tables::parse_tsv(&lt.get_tsv_text(0)?));
+        if !tables.is_empty() {
+            let mut blanked = page.clone();
+            for table in &tables {
+                let [x, y, w, h] = table.bbox.map(|v| v.max(0) as u32);
+                mask::paint_white(&mut blanked, x, y, x + w, y + h);
+            }
+            masked = Some(blanked);
+        }
+    }
+    let page = masked.as_ref().unwrap_or(&image);
+    let png = png_bytes(page)?;
+    lt.set_image_from_mem(&png)?;
+    drop(png);
+    let regions = match options.columns {
+        Columns::One => Vec::new(),
+        layout => {
+            let words = component_boxes(lt, leptess::capi::TessPageIteratorLevel_RIL_WORD);
+            let (width, height) = (page.width() as i32, page.height() as i32);
+            columns::regions(&words, width, height, layout)
+        }
+    };
+    let mut segments = Vec::new();
+    if regions.len() <= 1 {
+        segments = recognize_blocks(lt, 0, 0, options.max_block_chars)?;
+    } else {
+        for [x, y, w, h] in regions {
+            let region = page.crop_imm(x as u32, y as u32, w as u32, h as u32);
+            lt.set_image_from_mem(&png_bytes(&region)?)?;
+            segments.extend(recognize_blocks(lt, x, y, options.max_block_chars)?);
+        }
+    }
+    let rotated = if options.rotated_text {
+        rotated::recover(lt, page, &mut segments)?
+    } else {
+        Vec::new()
+    };
+    let handwriting = if options.handwriting {
+        handwriting::detect(page, &mut segments)
+    } else {
+        Vec::new()
+    };
+    // tesseract keeps its own copy, the masked one isn't needed anymore
+    drop(masked);
+    let page = OcrPage {
+        degrees,
+        masked_blocks,
+        segments,
+        tables,
+        second_pass_blocks: None,
+        rotated,
+        handwriting,
+    };
+    Ok((image, page))
+}
+
+/// Boxes, x, y, width and height, of the components of the image set in `lt` at `level`.
+fn component_boxes(
+    lt: &leptess::LepTess,
+    level: leptess::capi::TessPageIteratorLevel,
+) -> Vec<[i32; 4]> {
+    let Some(boxes) = lt.get_component_boxes(level, true) else {
+        return Vec::new();
+    };
+    (&boxes)
+        .into_iter()
+        .map(|b| {
+            let g = b.get_geometry();
+            [g.x, g.y, g.w, g.h]
+        })
+        .collect()
+}
+
+/// Recognizes the image set in `lt` block by block, with the boxes moved by `x` and `y`
+/// for a region cut out of the page. Blocks longer than `max_chars` are recognized again
+/// paragraph by paragraph.
+fn recognize_blocks(
+    lt: &mut leptess::LepTess,
+    x: i32,
+    y: i32,
+    max_chars: usize,
+) -> Result<Vec<OcrBlock>> {
+    let mut segments = Vec::new();
+    if let Some(boxes) =
+        lt.get_component_boxes(leptess::capi::TessPageIteratorLevel_RIL_BLOCK, true)
+    {
+        for b in &boxes {
+            lt.set_rectangle_from_box(&b);
+            let g = b.get_geometry();
+            let text = lt.get_utf8_text()?;
+            let confidence = lt.mean_text_conf();
+            let text_height = word_height(lt);
+            let paragraphs = if text.chars().count() > max_chars {
+                let level = leptess::capi::TessPageIteratorLevel_RIL_TEXTLINE;
+                paragraphs(component_boxes(lt, level))
+            } else {
+                Vec::new()
+            };
+            if paragraphs.len() <= 1 {
+                segments.push(OcrBlock {
+                    text,
+                    bbox: [g.x + x, g.y + y, g.w, g.h],
+                    confidence,
+                    text_height,
+                });
+                continue;
+            }
+            for [px, py, pw, ph] in paragraphs {
+                lt.set_rectangle(px, py, pw, ph);
+                segments.push(OcrBlock {
+                    text: lt.get_utf8_text()?,
+                    bbox: [px + x, py + y, pw, ph],
+                    confidence: lt.mean_text_conf(),
+                    text_height: word_height(lt),
+                });
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// Median height of the words in the rectangle set in `lt`, 0 without words.
+fn word_height(lt: &leptess::LepTess) -> i32 {
+    let mut heights: Vec<i32> = component_boxes(lt, leptess::capi::TessPageIteratorLevel_RIL_WORD)
+        .iter()
+        .map(|b| b[3])
+        .collect();
+    heights.sort();
+    heights.get(heights.len() / 2).copied().unwrap_or(0)
+}
+
+/// The boxes of the paragraphs the boxes of text `lines` make up, top to bottom. A new
+/// one starts after a gap half a line higher than the usual gap between lines.
+fn paragraphs(mut lines: Vec<[i32; 4]>) -> Vec<[i32; 4]> {
+    lines.sort_by_key(|l| (l[1], l[0]));
+    let median = |mut values: Vec<i32>| {
+        values.sort();
+        values.get(values.len() / 2).copied().unwrap_or(0)
+    };
+    let height = median(lines.iter().map(|l| l[3]).collect());
+    let gap = median(
+        lines
+            .windows(2)
+            .map(|w| w[1][1] - (w[0][1] + w[0][3]))
+            .collect(),
+    );
+    let mut paragraphs: Vec<[i32; 4]> = Vec::new();
+    let mut bottom = i32::MIN;
+    for [x, y, w, h] in lines {
+        match paragraphs.last_mut() {
+            Some(p) if y - bottom <= gap + height / 2 => {
+                let (right, below) = ((p[0] + p[2]).max(x + w), (p[1] + p[3]).max(y + h));
+                p[0] = p[0].min(x);
+                p[1] = p[1].min(y);
+                p[2] = right - p[0];
+                p[3] = below - p[1];
+            }
+            _ => paragraphs.push([x, y, w, h]),
+        }
+        bottom = bottom.max(y + h);
+    }
+    paragraphs
+}
+
+#[derive(Clone)]
+pub struct OcrCache {
+    dir: PathBuf,
+    /// fail on pages that aren't cached instead of running OCR
+    pub only: bool,
+    /// size in bytes above which the least recently used entries are removed
+    max_size: u64,
+}
+
+impl OcrCache {
+    pub fn new(cache_dir: &Path, only: bool, max_size_mb: u64) -> Self {
+        OcrCache {
+            dir: cache_dir.join(OCR_DIR),
+            only,
+            max_size: max_size_mb * 1024 * 1024,
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(FNV_OFFSET, key.as_bytes())))
+    }
+
+    /// The cached result for `key`. Unreadable entries count as missing.
+    pub fn get(&self, key: &str) -> Option<OcrPage> {
+        let path = self.path(key);
+        let data = std::fs::read(&path).ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&data).ok()?;
+        if entry.key != key {
+            return None;
+        }
+        // keep recently used entries out of the way of `gc`
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(entry.page)
+    }
+
+    pub fn put(&self, key: &str, page: &OcrPage) -> Result<()> {
+        let entry = CacheEntry {
+            key: key.to_string(),
+            page: page.clone(),
+        };
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(key), serde_json::to_vec(&entry)?)?;
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache fits its size limit.
+    /// Returns the number of entries removed.
+    pub fn gc(&self) -> Result<usize> {
+        let mut entries = Vec::new();
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in dir {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        let mut size: u64 = entries.iter().map(|e| e.1).sum();
+        entries.sort();
+        let mut removed = 0;
+        for (_, len, path) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            std::fs::remove_file(path)?;
+            size -= len;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,371 @@
+//! Pool of tesseract instances. Every recognition job gets an instance of its own and runs
+//! on a blocking thread, so no OCR state is shared between jobs or held across an await.
+//!
+//! After some malformed images an instance only returns empty pages until it is started
+//! again. A job that fails in leptess, or reads nothing on an instance that read text
+//! before, gets a new instance and runs once more; the restarts are counted for the
+//! summary. A job whose thread panics takes its instance down with it; a new one is
+//! started in its place.
+
+use crate::metrics;
+use crate::ocr::OcrPage;
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use leptess::LepTess;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// The image as recognized, turned upright, and its OCR result.
+pub type Recognized = (DynamicImage, OcrPage);
+
+/// Instances started again in the run, by every pool.
+static RESTARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn restarts() -> usize {
+    RESTARTS.load(Ordering::Relaxed)
+}
+
+/// Time instances spent on jobs in the run, by every pool, in microseconds.
+static BUSY_MICROS: AtomicU64 = AtomicU64::new(0);
+
+pub fn busy() -> Duration {
+    Duration::from_micros(BUSY_MICROS.load(Ordering::Relaxed))
+}
+
+/// What the pool runs jobs on: tesseract, or a stand-in in the unit tests.
+pub trait Engine: Sized + Send + 'static {
+    fn start(tessdata: &str, lang: &str) -> Result<Self>;
+}
+
+impl Engine for LepTess {
+    fn start(tessdata: &str, lang: &str) -> Result<Self> {
+        Ok(LepTess::new(Some(tessdata), lang)?)
+    }
+}
+
+struct Instance<E> {
+    engine: E,
+    /// it recognized text since it was started
+    read_text: bool,
+}
+
+impl<E: Engine> Instance<E> {
+    fn start(tessdata: &str, lang: &str) -> Result<Self> {
+        Ok(Instance {
+            engine: E::start(tessdata, lang)?,
+            read_text: false,
+        })
+    }
+}
+
+pub struct OcrPool<E: Engine = LepTess> {
+    size: usize,
+    tessdata: Arc<str>,
+    lang: Arc<str>,
+    /// one permit per idle instance
+    permits: Arc<Semaphore>,
+    idle: Arc<Mutex<Receiver<Instance<E>>>>,
+    returned: Sender<Instance<E>>,
+}
+
+// not derived, the instances needn't be `Clone`
+impl<E: Engine> Clone for OcrPool<E> {
+    fn clone(&self) -> Self {
+        OcrPool {
+            size: self.size,
+            tessdata: self.tessdata.clone(),
+            lang: self.lang.clone(),
+            permits: self.permits.clone(),
+            idle: self.idle.clone(),
+            returned: self.returned.clone(),
+        }
+    }
+}
+
+/// Instances started when config.toml doesn't set `ocr_threads`.
+pub fn default_size() -> usize {
+    num_cpus::get_physical().max(1)
+}
+
+/// Caps the OpenMP threads of every tesseract instance at `threads` through
+/// `OMP_THREAD_LIMIT`, which tesseract reads when the first instance starts. Without a
+/// limit in config.toml or the environment each instance gets one thread, as the pool
+/// already runs one instance per core and more threads per instance only contend for them.
+/// Called before any other thread starts, as changing the environment isn't safe then.
+pub fn limit_threads(threads: Option<usize>) {
+    let limit = match threads {
+        Some(threads) => threads.max(1),
+        None if std::env::var_os("OMP_THREAD_LIMIT").is_some() => return,
+        None => 1,
+    };
+    std::env::set_var("OMP_THREAD_LIMIT", limit.to_string());
+}
+
+impl<E: Engine> OcrPool<E> {
+    /// Starts `size` instances for `lang`. Fails if tesseract or the language data is missing.
+    pub fn new(tessdata: &str, lang: &str, size: usize) -> Result<Self> {
+        let (returned, idle) = channel();
+        for _ in 0..size.max(1) {
+            let instance = Instance::start(tessdata, lang)?;
+            // can't fail, the receiver goes into the pool
+            let _ = returned.send(instance);
+        }
+        Ok(OcrPool {
+            size: size.max(1),
+            tessdata: tessdata.into(),
+            lang: lang.into(),
+            permits: Arc::new(Semaphore::new(size.max(1))),
+            idle: Arc::new(Mutex::new(idle)),
+            returned,
+        })
+    }
+
+    /// Number of jobs that can run at the same time.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Starts `job` on the next free instance, waiting for one if all are busy. If the
+    /// instance seems broken, `job` runs again on a new one.
+    pub fn spawn<F>(&self, job: F) -> JoinHandle<Result<Recognized>>
+    where
+        F: Fn(&mut E) -> Result<Recognized> + Send + 'static,
+    {
+        let permits = self.permits.clone();
+        let idle = self.idle.clone();
+        let returned = self.returned.clone();
+        let (tessdata, lang) = (self.tessdata.clone(), self.lang.clone());
+        tokio::spawn(async move {
+            let permit = permits.acquire_owned().await?;
+            // holding a permit guarantees an idle instance
+            let mut instance = idle.lock().unwrap().try_recv()?;
+            let (job_tessdata, job_lang, job_returned) =
+                (tessdata.clone(), lang.clone(), returned.clone());
+            let result = tokio::task::spawn_blocking(move || {
+                let (tessdata, lang, returned) = (job_tessdata, job_lang, job_returned);
+                let started = Instant::now();
+                let mut result = job(&mut instance.engine);
+                if let Some(symptom) = broken(&instance, &result) {
+                    match Instance::start(&tessdata, &lang) {
+                        Ok(restarted) => {
+                            RESTARTS.fetch_add(1, Ordering::Relaxed);
+                            metrics::ocr_restart();
+                            eprintln!("tesseract {}, restarted it for the page", symptom);
+                            instance = restarted;
+                            result = job(&mut instance.engine);
+                        }
+                        Err(e) => eprintln!("tesseract {}, could not restart it: {}", symptom, e),
+                    }
+                }
+                if let Ok((_, page)) = &result {
+                    instance.read_text |= !page.segments.is_empty();
+                }
+                metrics::ocr_job(started.elapsed());
+                BUSY_MICROS.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+                let _ = returned.send(instance);
+                result
+            })
+            .await;
+            match result {
+                Ok(result) => result,
+                Err(e) => {
+                    // the instance went down with the job, without a new one the pool
+                    // is a job smaller
+                    let started =
+                        tokio::task::spawn_blocking(move || Instance::start(&tessdata, &lang));
+                    match started.await {
+                        Ok(Ok(instance)) => {
+                            let _ = returned.send(instance);
+                        }
+                        _ => permit.forget(),
+                    }
+                    Err(anyhow!("OCR job failed: {}", e))
+                }
+            }
+        })
+    }
+
+    /// Runs `job` on the next free instance and waits for its result.
+    pub async fn run<F>(&self, job: F) -> Result<Recognized>
+    where
+        F: Fn(&mut E) -> Result<Recognized> + Send + 'static,
+    {
+        self.spawn(job).await?
+    }
+}
+
+/// What makes `result` look like `instance` is broken, `None` if nothing does: an error
+/// from leptess, not one of reading the image, or no text at all from an instance that
+/// read some before.
+fn broken<E>(instance: &Instance<E>, result: &Result<Recognized>) -> Option<&'static str> {
+    match result {
+        Err(e) if e.downcast_ref::<leptess::leptonica::PixError>().is_some() => {
+            Some("could not take the image")
+        }
+        Err(e) if e.downcast_ref::<std::str::Utf8Error>().is_some() => Some("returned broken text"),
+        Ok((_, page)) if instance.read_text && page.segments.is_empty() => {
+            Some("read nothing after reading text before")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ocr::OcrBlock;
+
+    /// Instances started by the tests, for the ids of the fakes.
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// A tesseract that only knows which instance it is.
+    struct Fake {
+        id: usize,
+    }
+
+    impl Engine for Fake {
+        fn start(_: &str, _: &str) -> Result<Self> {
+            Ok(Fake {
+                id: STARTED.fetch_add(1, Ordering::SeqCst),
+            })
+        }
+    }
+
+    fn recognized(texts: &[&str]) -> Recognized {
+        let segments = texts
+            .iter()
+            .map(|text| OcrBlock {
+                text: text.to_string(),
+                bbox: [0, 0, 100, 20],
+                confidence: 90,
+                text_height: 10,
+            })
+            .collect();
+        let page = OcrPage {
+            degrees: 0,
+            masked_blocks: 0,
+            segments,
+            tables: Vec::new(),
+            second_pass_blocks: None,
+            rotated: Vec::new(),
+            handwriting: Vec::new(),
+        };
+        (DynamicImage::new_luma8(1, 1), page)
+    }
+
+    fn pool(size: usize) -> OcrPool<Fake> {
+        OcrPool::new("tessdata", "rus", size).unwrap()
+    }
+
+    /// A job recording the instances it runs on, with the result `results` gives for the
+    /// number of runs so far.
+    fn job(
+        ids: &Arc<Mutex<Vec<usize>>>,
+        results: fn(usize) -> Result<Recognized>,
+    ) -> impl Fn(&mut Fake) -> Result<Recognized> + Send + 'static {
+        let ids = ids.clone();
+        move |fake| {
+            let mut ids = ids.lock().unwrap();
+            ids.push(fake.id);
+            results(ids.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn no_more_jobs_run_at_once_than_there_are_instances() {
+        let pool = pool(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<_> = (0..6)
+            .map(|_| {
+                let (running, most) = (running.clone(), most.clone());
+                pool.spawn(move |_| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(recognized(&["Абзац"]))
+                })
+            })
+            .collect();
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+        assert!(most.load(Ordering::SeqCst) <= 2);
+        assert_eq!(pool.size(), 2);
+    }
+
+    #[tokio::test]
+    async fn an_instance_failing_in_leptess_is_restarted_for_the_job() {
+        let pool = pool(1);
+        let ids = Arc::default();
+        let (_, page) = pool
+            .run(job(&ids, |attempt| match attempt {
+                // what leptess fails with on text that isn't UTF-8
+                1 => Err(String::from_utf8(vec![0xff])
+                    .unwrap_err()
+                    .utf8_error()
+                    .into()),
+                _ => Ok(recognized(&["Абзац"])),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(page.segments.len(), 1);
+        let ids = ids.lock().unwrap().clone();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+        // an error reading the image is the image's, not the instance's
+        let other = Arc::default();
+        let failed = pool
+            .run(job(&other, |_| Err(anyhow!("not an image"))))
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(other.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn nothing_read_after_text_restarts_the_instance() {
+        let pool = pool(1);
+        let ids = Arc::default();
+        pool.run(job(&ids, |_| Ok(recognized(&["Абзац"]))))
+            .await
+            .unwrap();
+        let (_, page) = pool
+            .run(job(&ids, |attempt| match attempt {
+                2 => Ok(recognized(&[])),
+                _ => Ok(recognized(&["Абзац"])),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(page.segments.len(), 1);
+        let ids = ids.lock().unwrap().clone();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+        // a new instance reading nothing is taken at its word
+        let fresh = Arc::default();
+        let (_, page) = self::pool(1)
+            .run(job(&fresh, |_| Ok(recognized(&[]))))
+            .await
+            .unwrap();
+        assert!(page.segments.is_empty());
+        assert_eq!(fresh.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_job_that_panics_leaves_the_pool_its_size() {
+        let pool = pool(1);
+        let failed = pool.run(|_: &mut Fake| panic!("job went down")).await;
+        assert!(failed.unwrap_err().to_string().contains("OCR job failed"));
+        let next = pool.run(|_| Ok(recognized(&["Абзац"])));
+        let (_, page) = tokio::time::timeout(Duration::from_secs(5), next)
+            .await
+            .expect("no instance left")
+            .unwrap();
+        assert_eq!(page.segments.len(), 1);
+    }
+}
@@ -0,0 +1,337 @@
+//! Password-protected Office files are not zips but OLE compound files holding an
+//! `EncryptionInfo` and an `EncryptedPackage` stream. Files encrypted with the agile
+//! scheme of Office 2010 and later (AES with SHA-512) are decrypted back into the zip
+//! with `--docx-password`, as described in MS-OFFCRYPTO.
+
+use aes::cipher::consts::U16;
+use aes::cipher::generic_array::GenericArray;
+use aes::{Aes128, Aes192, Aes256, BlockCipher, NewBlockCipher};
+use anyhow::{anyhow, Result};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use sha2::{Digest, Sha512};
+
+/// First bytes of an OLE compound file.
+pub const OLE_MAGIC: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+/// End of a sector chain.
+const END_OF_CHAIN: u32 = 0xffff_fffe;
+/// Bytes of the package decrypted with one IV.
+const SEGMENT_SIZE: usize = 4096;
+/// Block keys of MS-OFFCRYPTO 2.3.4.13, mixed into the password hash for each use.
+const VERIFIER_INPUT_KEY: [u8; 8] = [0xfe, 0xa7, 0xd2, 0x76, 0x3b, 0x4b, 0x9e, 0x79];
+const VERIFIER_VALUE_KEY: [u8; 8] = [0xd7, 0xaa, 0x0f, 0x6d, 0x30, 0x61, 0x34, 0x4e];
+const KEY_VALUE_KEY: [u8; 8] = [0x14, 0x6e, 0x0b, 0xe7, 0xab, 0xac, 0xd0, 0xd6];
+
+/// The streams of an OLE compound file by name.
+pub struct CompoundFile<'a> {
+    data: &'a [u8],
+    sector_size: usize,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    mini_cutoff: u64,
+    /// name, first sector and size of each stream
+    streams: Vec<(String, u32, u64)>,
+}
+
+impl<'a> CompoundFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < 512 || data[..8] != OLE_MAGIC {
+            return Err(anyhow!("not an OLE compound file"));
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let sector_size = 1usize << u16::from_le_bytes([data[30], data[31]]).min(16);
+        let mut file = CompoundFile {
+            data,
+            sector_size,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            mini_cutoff: u32_at(56) as u64,
+            streams: Vec::new(),
+        };
+        // the sectors of the FAT, 109 in the header and the rest in a chain of DIFAT sectors
+        let mut fat_sectors: Vec<u32> = (0..109).map(|i| u32_at(76 + i * 4)).collect();
+        let mut difat = u32_at(68);
+        for _ in 0..u32_at(72) {
+            let sector = file.sector(difat)?;
+            let entries = sector.len() / 4 - 1;
+            fat_sectors.extend((0..entries).map(|i| le_u32(sector, i * 4)));
+            difat = le_u32(sector, entries * 4);
+        }
+        fat_sectors.truncate(u32_at(44) as usize);
+        for sector in fat_sectors {
+            let sector = file.sector(sector)?;
+            file.fat
+                .extend((0..sector.len() / 4).map(|i| le_u32(sector, i * 4)));
+        }
+        let directory = file.chain(u32_at(48))?;
+        let mini_fat = file.chain(u32_at(60))?;
+        file.mini_fat = (0..mini_fat.len() / 4)
+            .map(|i| le_u32(&mini_fat, i * 4))
+            .collect();
+        for entry in directory.chunks_exact(128) {
+            let name_len = (u16::from_le_bytes([entry[64], entry[65]]) as usize).min(64);
+            let name: Vec<u16> = entry[..name_len]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect();
+            let start = le_u32(entry, 116);
+            let size = u64::from_le_bytes(entry[120..128].try_into().unwrap());
+            match entry[66] {
+                // the root entry holds the mini stream
+                5 => {
+                    file.mini_stream = file.chain(start)?;
+                    file.mini_stream.truncate(size as usize);
+                }
+                2 => file
+                    .streams
+                    .push((String::from_utf16_lossy(&name), start, size)),
+                _ => {}
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn has_stream(&self, name: &str) -> bool {
+        self.streams.iter().any(|(n, _, _)| n == name)
+    }
+
+    /// Contents of the stream `name`.
+    pub fn stream(&self, name: &str) -> Result<Vec<u8>> {
+        let &(_, start, size) = self
+            .streams
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .ok_or_else(|| anyhow!("no {} stream", name))?;
+        let mut bytes = if size < self.mini_cutoff {
+            self.mini_chain(start)?
+        } else {
+            self.chain(start)?
+        };
+        if (bytes.len() as u64) < size {
+            return Err(anyhow!("{} stream is cut short", name));
+        }
+        bytes.truncate(size as usize);
+        Ok(bytes)
+    }
+
+    fn sector(&self, sector: u32) -> Result<&'a [u8]> {
+        let start = (sector as usize + 1) * self.sector_size;
+        self.data
+            .get(start..start + self.sector_size)
+            .ok_or_else(|| anyhow!("sector {} is past the end of the file", sector))
+    }
+
+    /// The sectors of the chain starting at `start`, one after the other.
+    fn chain(&self, start: u32) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut sector = start;
+        while sector != END_OF_CHAIN {
+            if bytes.len() > self.data.len() {
+                return Err(anyhow!("sector chain loops"));
+            }
+            bytes.extend_from_slice(self.sector(sector)?);
+            sector = *self
+                .fat
+                .get(sector as usize)
+                .ok_or_else(|| anyhow!("sector {} is not in the FAT", sector))?;
+        }
+        Ok(bytes)
+    }
+
+    fn mini_chain(&self, start: u32) -> Result<Vec<u8>> {
+        const MINI_SECTOR_SIZE: usize = 64;
+        let mut bytes = Vec::new();
+        let mut sector = start;
+        while sector != END_OF_CHAIN {
+            if bytes.len() > self.mini_stream.len() {
+                return Err(anyhow!("mini sector chain loops"));
+            }
+            let offset = sector as usize * MINI_SECTOR_SIZE;
+            let chunk = self
+                .mini_stream
+                .get(offset..offset + MINI_SECTOR_SIZE)
+                .ok_or_else(|| anyhow!("mini sector {} is past the mini stream", sector))?;
+            bytes.extend_from_slice(chunk);
+            sector = *self
+                .mini_fat
+                .get(sector as usize)
+                .ok_or_else(|| anyhow!("mini sector {} is not in the mini FAT", sector))?;
+        }
+        Ok(bytes)
+    }
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// `keyData` and `encryptedKey` of the agile `EncryptionInfo`, base64 decoded.
+#[derive(Default)]
+struct AgileInfo {
+    key_salt: Vec<u8>,
+    key_bits: usize,
+    hash_algorithm: String,
+    spin_count: u32,
+    password_salt: Vec<u8>,
+    password_key_bits: usize,
+    verifier_input: Vec<u8>,
+    verifier_value: Vec<u8>,
+    key_value: Vec<u8>,
+}
+
+/// The zip inside the encrypted Office file `file`, decrypted with `password`.
+pub fn decrypt(file: &CompoundFile, password: &str) -> Result<Vec<u8>> {
+    let info = file.stream("EncryptionInfo")?;
+    if info.len() < 8 {
+        return Err(anyhow!("EncryptionInfo is cut short"));
+    }
+    let version = (
+        u16::from_le_bytes([info[0], info[1]]),
+        u16::from_le_bytes([info[2], info[3]]),
+    );
+    if version != (4, 4) {
+        return Err(anyhow!(
+            "encrypted with the Office 2007 scheme (version {}.{}), only the agile scheme \
+             of Office 2010 and later is supported",
+            version.0,
+            version.1
+        ));
+    }
+    let info = agile_info(std::str::from_utf8(&info[8..])?)?;
+    if info.hash_algorithm != "SHA512" {
+        return Err(anyhow!(
+            "encrypted with {}, only SHA512 is supported",
+            info.hash_algorithm
+        ));
+    }
+    let password_key = |block_key: &[u8]| {
+        let mut hash = sha512(&[&info.password_salt, &utf16(password)]);
+        for i in 0..info.spin_count {
+            hash = sha512(&[&i.to_le_bytes(), &hash]);
+        }
+        let mut key = sha512(&[&hash, block_key]);
+        key.resize(info.password_key_bits / 8, 0x36);
+        key
+    };
+    let salt = &info.password_salt;
+    let input = aes_cbc(
+        &password_key(&VERIFIER_INPUT_KEY),
+        salt,
+        &info.verifier_input,
+    )?;
+    let value = aes_cbc(
+        &password_key(&VERIFIER_VALUE_KEY),
+        salt,
+        &info.verifier_value,
+    )?;
+    let input_hash = sha512(&[&input[..input.len().min(salt.len())]]);
+    if value.len() < input_hash.len() || value[..input_hash.len()] != input_hash[..] {
+        return Err(anyhow!("wrong password"));
+    }
+    let mut key = aes_cbc(&password_key(&KEY_VALUE_KEY), salt, &info.key_value)?;
+    key.truncate(info.key_bits / 8);
+    let package = file.stream("EncryptedPackage")?;
+    if package.len() < 8 {
+        return Err(anyhow!("EncryptedPackage is cut short"));
+    }
+    let size = u64::from_le_bytes(package[..8].try_into().unwrap()) as usize;
+    let mut zip = Vec::with_capacity(package.len());
+    for (index, segment) in package[8..].chunks(SEGMENT_SIZE).enumerate() {
+        let iv = sha512(&[&info.key_salt, &(index as u32).to_le_bytes()]);
+        zip.extend(aes_cbc(&key, &iv, segment)?);
+    }
+    if zip.len() < size {
+        return Err(anyhow!("EncryptedPackage is cut short"));
+    }
+    zip.truncate(size);
+    Ok(zip)
+}
+
+fn agile_info(xml: &str) -> Result<AgileInfo> {
+    let mut reader = Reader::from_str(xml);
+    let mut info = AgileInfo::default();
+    loop {
+        let e = match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => e,
+            Event::Eof => break,
+            _ => continue,
+        };
+        let local_name = e.local_name();
+        let element = local_name.as_ref();
+        if element != b"keyData" && element != b"encryptedKey" {
+            continue;
+        }
+        for attribute in e.attributes() {
+            let attribute = attribute?;
+            let value = String::from_utf8_lossy(&attribute.value).to_string();
+            let bytes = || base64::decode(&value).map_err(|e| anyhow!("EncryptionInfo: {}", e));
+            match (element, attribute.key.as_ref()) {
+                (b"keyData", b"saltValue") => info.key_salt = bytes()?,
+                (b"keyData", b"keyBits") => info.key_bits = value.parse()?,
+                (b"encryptedKey", b"hashAlgorithm") => info.hash_algorithm = value,
+                (b"encryptedKey", b"spinCount") => info.spin_count = value.parse()?,
+                (b"encryptedKey", b"saltValue") => info.password_salt = bytes()?,
+                (b"encryptedKey", b"keyBits") => info.password_key_bits = value.parse()?,
+                (b"encryptedKey", b"encryptedVerifierHashInput") => info.verifier_input = bytes()?,
+                (b"encryptedKey", b"encryptedVerifierHashValue") => info.verifier_value = bytes()?,
+                (b"encryptedKey", b"encryptedKeyValue") => info.key_value = bytes()?,
+                _ => {}
+            }
+        }
+    }
+    if info.key_salt.is_empty() || info.password_salt.is_empty() || info.key_value.is_empty() {
+        return Err(anyhow!("EncryptionInfo has no password key"));
+    }
+    Ok(info)
+}
+
+fn sha512(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// `data` decrypted with AES in CBC mode, the key size picking the variant; the first
+/// 16 bytes of `iv` are used.
+fn aes_cbc(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    match key.len() {
+        16 => cbc::<Aes128>(key, iv, data),
+        24 => cbc::<Aes192>(key, iv, data),
+        32 => cbc::<Aes256>(key, iv, data),
+        n => Err(anyhow!("unsupported AES key of {} bits", n * 8)),
+    }
+}
+
+fn cbc<C: NewBlockCipher + BlockCipher<BlockSize = U16>>(
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = C::new_varkey(key).map_err(|_| anyhow!("bad AES key"))?;
+    if iv.len() < 16 || !data.len().is_multiple_of(16) {
+        return Err(anyhow!("encrypted data is not in whole AES blocks"));
+    }
+    let mut previous: GenericArray<u8, U16> = GenericArray::clone_from_slice(&iv[..16]);
+    let mut plain = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(16) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        cipher.decrypt_block(&mut block);
+        for (byte, mask) in block.iter_mut().zip(&previous) {
+            *byte ^= mask;
+        }
+        plain.extend_from_slice(&block);
+        previous = GenericArray::clone_from_slice(chunk);
+    }
+    Ok(plain)
+}
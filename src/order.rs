@@ -0,0 +1,63 @@
+//! `--order`: which files a `translate` run takes first. With a deadline the cheap ones are
+//! worth more early, so an interrupted run has translated as many files as it could; the
+//! manifest marks every file done on its own, so a resumed run skips them in any order.
+
+use crate::handlers::{Dependency, HandlerRegistry};
+use crate::probe;
+use clap::ValueEnum;
+use pdfium_render::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::time::SystemTime;
+use walkdir::DirEntry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Order {
+    /// by path
+    Path,
+    /// the smallest files first
+    SmallestFirst,
+    /// files needing no OCR first, then PDFs with a text layer, then scans
+    TextLayerFirst,
+    /// the files modified last first
+    NewestFirst,
+}
+
+/// 0 for a file its handler reads without tesseract, 1 for a PDF with a text layer on its
+/// first page and 2 for a scan, or a PDF pdfium can't look at.
+pub fn ocr_cost(entry: &DirEntry, registry: &HandlerRegistry, pdfium: Option<&Pdfium>) -> u8 {
+    match registry.for_path(entry.path()) {
+        Some(handler) if handler.dependencies().contains(&Dependency::Tesseract) => {
+            let text_layer = handler.name() == "pdf"
+                && pdfium
+                    .and_then(|pdfium| probe::pdf(entry.path(), pdfium))
+                    .is_some_and(|probe| probe.text_layer);
+            if text_layer {
+                1
+            } else {
+                2
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Sorts `files` into `order`, files that tie keeping the order they were listed in.
+pub fn sort(
+    files: &mut [DirEntry],
+    order: Order,
+    registry: &HandlerRegistry,
+    pdfium: Option<&Pdfium>,
+) {
+    let size = |entry: &DirEntry| entry.metadata().map_or(0, |m| m.len());
+    match order {
+        Order::Path => files.sort_by(|a, b| a.path().cmp(b.path())),
+        Order::SmallestFirst => files.sort_by_cached_key(size),
+        Order::TextLayerFirst => files.sort_by_cached_key(|e| ocr_cost(e, registry, pdfium)),
+        Order::NewestFirst => files.sort_by_cached_key(|entry| {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            Reverse(modified.unwrap_or(SystemTime::UNIX_EPOCH))
+        }),
+    }
+}
@@ -0,0 +1,83 @@
+//! Page orientation handling before OCR.
+//!
+//! leptess doesn't expose tesseract's OSD call, so `auto` recognizes a downscaled copy of
+//! the page in all four orientations and keeps the one tesseract is most confident about.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// Longest side of the copy used to detect the orientation.
+const DETECTION_SIZE: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Orientation {
+    /// detect the orientation of every page/image
+    Auto,
+    /// use the image as is
+    None,
+    /// always rotate 90° clockwise
+    #[value(name = "force-90")]
+    #[serde(rename = "force-90")]
+    Force90,
+    /// always rotate 180°
+    #[value(name = "force-180")]
+    #[serde(rename = "force-180")]
+    Force180,
+    /// always rotate 270° clockwise
+    #[value(name = "force-270")]
+    #[serde(rename = "force-270")]
+    Force270,
+}
+
+/// Rotates `image` clockwise by a multiple of 90 degrees.
+pub fn rotate(image: &DynamicImage, degrees: u16) -> DynamicImage {
+    match degrees {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image.clone(),
+    }
+}
+
+pub fn png_bytes(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+/// Returns the clockwise rotation in degrees that makes the text in `image` upright.
+pub fn detect(lt: &mut leptess::LepTess, image: &DynamicImage) -> Result<u16> {
+    let small = image.thumbnail(DETECTION_SIZE, DETECTION_SIZE);
+    let mut best = (0, i32::MIN);
+    for degrees in [0, 90, 180, 270] {
+        lt.set_image_from_mem(&png_bytes(&rotate(&small, degrees))?)?;
+        let confidence = lt.mean_text_conf();
+        if confidence > best.1 {
+            best = (degrees, confidence);
+        }
+    }
+    Ok(best.0)
+}
+
+/// Rotates `image` according to `orientation` and returns it with the applied rotation.
+pub fn orient(
+    lt: &mut leptess::LepTess,
+    image: DynamicImage,
+    orientation: Orientation,
+) -> Result<(DynamicImage, u16)> {
+    let degrees = match orientation {
+        Orientation::Auto => detect(lt, &image)?,
+        Orientation::None => 0,
+        Orientation::Force90 => 90,
+        Orientation::Force180 => 180,
+        Orientation::Force270 => 270,
+    };
+    if degrees == 0 {
+        return Ok((image, 0));
+    }
+    Ok((rotate(&image, degrees), degrees))
+}
@@ -0,0 +1,96 @@
+//! The bookmarks of a PDF as a translated table of contents at the top of its outputs:
+//! a nested list of links to the page headings in the Markdown file, an indented list with
+//! page numbers in `<name>.pdf.contents.txt` next to the page files of the text output.
+//! Bookmarks of pages the run leaves out, like with `--preview`, are listed with a note.
+
+use crate::backend::Backend;
+use crate::output::{self, SegmentWriter};
+use anyhow::Result;
+use pdfium_render::prelude::*;
+
+pub const CONTENTS_HEADING: &str = "## Contents";
+
+/// Bookmarks beyond this many, or nested deeper, are left out; broken outlines can loop.
+const MAX_BOOKMARKS: usize = 10_000;
+const MAX_DEPTH: usize = 32;
+
+pub struct Bookmark {
+    pub title: String,
+    /// 0 for the top level
+    pub depth: usize,
+    /// page it points at, counting from 1
+    pub page: Option<usize>,
+}
+
+/// The bookmarks of `document` in reading order.
+pub fn read(document: &PdfDocument) -> Vec<Bookmark> {
+    let mut bookmarks = Vec::new();
+    if let Some(root) = document.bookmarks().root() {
+        walk(Some(root), 0, &mut bookmarks);
+    }
+    bookmarks
+}
+
+/// Adds `first` and its siblings, each followed by its children.
+fn walk(first: Option<PdfBookmark>, depth: usize, bookmarks: &mut Vec<Bookmark>) {
+    let mut next = first;
+    while let Some(bookmark) = next {
+        if bookmarks.len() >= MAX_BOOKMARKS {
+            return;
+        }
+        let page = match bookmark.destination() {
+            Some(destination) => destination.page_index().ok(),
+            None => bookmark.action().and_then(|action| {
+                let destination = action.as_local_destination_action()?.destination().ok()?;
+                destination.page_index().ok()
+            }),
+        };
+        bookmarks.push(Bookmark {
+            title: bookmark.title().unwrap_or_default().trim().to_string(),
+            depth,
+            page: page.map(|index| index as usize + 1),
+        });
+        if depth + 1 < MAX_DEPTH {
+            walk(bookmark.first_child(), depth + 1, bookmarks);
+        }
+        next = bookmark.next_sibling();
+    }
+}
+
+/// Translates the titles of `bookmarks` and writes them as a list, linking to the page
+/// headings if `markdown`. `translated(page)` says whether the run writes that page.
+pub async fn write(
+    backend: &mut Backend,
+    writer: &mut SegmentWriter,
+    bookmarks: &[Bookmark],
+    markdown: bool,
+    translated: impl Fn(usize) -> bool,
+) -> Result<()> {
+    let titles: Vec<&str> = bookmarks.iter().map(|b| b.title.as_str()).collect();
+    let translations = backend.translate_all(&titles).await;
+    let mut source = vec![CONTENTS_HEADING.to_string()];
+    let mut target = vec![CONTENTS_HEADING.to_string()];
+    for (bookmark, translation) in bookmarks.iter().zip(translations) {
+        let title = output::or_source(translation.ok(), &bookmark.title);
+        source.push(entry(bookmark, &bookmark.title, markdown, &translated));
+        target.push(entry(bookmark, &title, markdown, &translated));
+    }
+    writer.write_verbatim(&source.join("\n"), &target.join("\n"))
+}
+
+fn entry(
+    bookmark: &Bookmark,
+    title: &str,
+    markdown: bool,
+    translated: &impl Fn(usize) -> bool,
+) -> String {
+    let indent = "  ".repeat(bookmark.depth);
+    match bookmark.page {
+        Some(page) if !translated(page) => {
+            format!("{}- {} (page {}, not translated)", indent, title, page)
+        }
+        Some(page) if markdown => format!("{}- [{}](#page-{})", indent, title, page),
+        Some(page) => format!("{}- {} ... {}", indent, title, page),
+        None => format!("{}- {}", indent, title),
+    }
+}
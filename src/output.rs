@@ -0,0 +1,475 @@
+//! Writers for the text files produced by the handlers.
+
+use crate::post_edit::{Granularity, PostEdit};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, WINDOWS_1251};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark
+    Utf8Bom,
+    /// UTF-16 little endian with a byte order mark
+    Utf16le,
+    /// Windows Cyrillic
+    Cp1251,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Newline {
+    Lf,
+    Crlf,
+}
+
+/// Layout of the outputs of the OCR handlers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// a txt file and a full-size jpg per page
+    Text,
+    /// one Markdown file per document with a heading and a thumbnail per page
+    Markdown,
+    /// a txt file and a png per page with the translations painted over the text
+    Overlay,
+}
+
+/// Width page thumbnails are scaled down to in Markdown outputs.
+pub const DEFAULT_THUMBNAIL_WIDTH: u32 = 800;
+
+/// Text written between segments unless `--segment-separator` is given.
+pub const DEFAULT_SEPARATOR: &str = "\n\n";
+
+/// Encoding, line endings and segment layout of the txt outputs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TextFormat {
+    encoding: Encoding,
+    /// `None` keeps the line breaks as the handlers produce them
+    newline: Option<Newline>,
+    substitute: String,
+    /// written after every segment, `--segment-separator`
+    #[serde(skip, default = "default_separator")]
+    pub separator: String,
+    /// start every segment with `[page/block] `, `--prefix-segments`
+    #[serde(skip)]
+    pub prefix_segments: bool,
+    /// start every segment with its [`Origin`], `--annotate-provenance`
+    #[serde(skip)]
+    pub annotate_provenance: bool,
+    /// characters replaced by `substitute` so far, shared by all writers of a run
+    #[serde(skip)]
+    substituted: Rc<Cell<usize>>,
+    /// `post_edit_cmd` of config.toml, with the file being translated
+    #[serde(skip)]
+    pub post_edit: Option<PostEdit>,
+    /// first lines of the outputs of the file being translated, `--stamp-outputs`
+    #[serde(skip)]
+    pub stamp: Option<String>,
+}
+
+impl Default for TextFormat {
+    fn default() -> Self {
+        TextFormat {
+            encoding: Encoding::Utf8,
+            newline: None,
+            substitute: "?".to_string(),
+            separator: default_separator(),
+            prefix_segments: false,
+            annotate_provenance: false,
+            substituted: Rc::default(),
+            post_edit: None,
+            stamp: None,
+        }
+    }
+}
+
+fn default_separator() -> String {
+    DEFAULT_SEPARATOR.to_string()
+}
+
+/// Parses `--segment-separator`, turning `\n`, `\r`, `\t` and `\\` into the characters.
+pub fn unescape(text: &str) -> Result<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            other => {
+                return Err(anyhow!(
+                    "unknown escape \\{} in {:?}, use \\n, \\r, \\t or \\\\",
+                    other.map(String::from).unwrap_or_default(),
+                    text
+                ))
+            }
+        }
+    }
+    Ok(unescaped)
+}
+
+impl TextFormat {
+    /// Fails if `substitute` itself can't be written in `encoding`.
+    pub fn new(encoding: Encoding, newline: Option<Newline>, substitute: &str) -> Result<Self> {
+        let format = TextFormat {
+            encoding,
+            newline,
+            substitute: substitute.to_string(),
+            separator: default_separator(),
+            prefix_segments: false,
+            annotate_provenance: false,
+            substituted: Rc::default(),
+            post_edit: None,
+            stamp: None,
+        };
+        if encoding == Encoding::Cp1251 && encode_cp1251(substitute, "").1 > 0 {
+            return Err(anyhow!(
+                "substitute {:?} is not representable in cp1251",
+                substitute
+            ));
+        }
+        Ok(format)
+    }
+
+    /// Number of characters that couldn't be represented in the encoding.
+    pub fn substituted(&self) -> usize {
+        self.substituted.get()
+    }
+
+    fn bom(&self) -> &'static [u8] {
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Cp1251 => b"",
+            Encoding::Utf8Bom => b"\xEF\xBB\xBF",
+            Encoding::Utf16le => b"\xFF\xFE",
+        }
+    }
+
+    /// Reads a file written in this format back into a string.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        let encoding = match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => encoding_rs::UTF_8,
+            Encoding::Utf16le => encoding_rs::UTF_16LE,
+            Encoding::Cp1251 => WINDOWS_1251,
+        };
+        encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+
+    /// Encodes a whole file, byte order mark included.
+    pub fn encode_file(&self, text: &str) -> Vec<u8> {
+        let mut bytes = self.bom().to_vec();
+        bytes.extend(self.encode(text));
+        bytes
+    }
+
+    fn encode(&self, text: &str) -> Vec<u8> {
+        let text = match self.newline {
+            None => text.to_string(),
+            Some(Newline::Lf) => text.replace("\r\n", "\n"),
+            Some(Newline::Crlf) => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        };
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => text.into_bytes(),
+            Encoding::Utf16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Cp1251 => {
+                let (bytes, substituted) = encode_cp1251(&text, &self.substitute);
+                self.substituted.set(self.substituted.get() + substituted);
+                bytes
+            }
+        }
+    }
+}
+
+/// Encodes `text` as cp1251, writing `substitute` for unmappable characters. Returns the
+/// bytes and the number of characters substituted.
+fn encode_cp1251(text: &str, substitute: &str) -> (Vec<u8>, usize) {
+    let mut encoder = WINDOWS_1251.new_encoder();
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut buffer = [0; 1024];
+    let mut input = text;
+    let mut substituted = 0;
+    loop {
+        let (result, read, written) =
+            encoder.encode_from_utf8_without_replacement(input, &mut buffer, true);
+        bytes.extend_from_slice(&buffer[..written]);
+        input = &input[read..];
+        match result {
+            EncoderResult::InputEmpty => break,
+            EncoderResult::OutputFull => {}
+            EncoderResult::Unmappable(_) => {
+                substituted += 1;
+                // the substitute is checked to be representable when the format is built
+                let (replacement, _, _) = WINDOWS_1251.encode(substitute);
+                bytes.extend_from_slice(&replacement);
+            }
+        }
+    }
+    (bytes, substituted)
+}
+
+/// Where a segment is in its source file. Written before the segment with
+/// `--annotate-provenance` as `[p12 b3 (142,988,1630,1201) conf 87]` for OCR blocks
+/// and `[para 17]` for docx paragraphs; the README has the grammar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Origin {
+    /// block of a scanned page, both counted from 1, with its x, y, width and height in
+    /// pixels of the page as recognized and tesseract's confidence
+    Block {
+        page: usize,
+        block: usize,
+        bbox: [i32; 4],
+        confidence: i32,
+    },
+    /// docx paragraph the segment starts in, counted from 1
+    Paragraph { paragraph: usize },
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Origin::Block {
+                page,
+                block,
+                bbox: [x, y, w, h],
+                confidence,
+            } => write!(
+                f,
+                "[p{} b{} ({},{},{},{}) conf {}]",
+                page, block, x, y, w, h, confidence
+            ),
+            Origin::Paragraph { paragraph } => write!(f, "[para {}]", paragraph),
+        }
+    }
+}
+
+/// Source text of a segment with where it came from, `None` where the handler doesn't
+/// tell.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub text: String,
+    pub origin: Option<Origin>,
+}
+
+impl Segment {
+    pub fn new(text: impl Into<String>, origin: Option<Origin>) -> Self {
+        Segment {
+            text: text.into(),
+            origin,
+        }
+    }
+}
+
+/// A segment the server failed to translate. Its place in the output holds `marker`
+/// until `retry-failures` replaces it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub output: PathBuf,
+    pub marker: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+}
+
+/// What stands in `output` for `segment`, translated as `translated` or failed with
+/// `None`: the translation through a `post_edit_cmd` for segments, or the marker
+/// `[[dir-translate: failed segment <number>]]`, recorded in `failures` with the source
+/// text for `retry-failures`. The [`SegmentWriter`] of a run and `apply-translations`,
+/// filling in the outputs of `extract`, both place segments with it.
+pub fn place(
+    segment: &Segment,
+    translated: Option<&str>,
+    number: usize,
+    output: &Path,
+    post_edit: Option<&PostEdit>,
+    page: Option<usize>,
+    failures: &mut Vec<Failure>,
+) -> String {
+    let Some(translated) = translated else {
+        let marker = format!("[[dir-translate: failed segment {}]]", number);
+        failures.push(Failure {
+            output: output.to_path_buf(),
+            marker: marker.clone(),
+            source: segment.text.trim_end().to_string(),
+            origin: segment.origin,
+        });
+        return marker;
+    };
+    match post_edit.filter(|p| p.granularity == Granularity::Segment) {
+        Some(post_edit) => post_edit.edit(translated, output, page),
+        None => translated.to_string(),
+    }
+    .trim_end()
+    .to_string()
+}
+
+/// The text of a segment that keeps its source text if it fails, like a table cell or an
+/// email header field: its translation trimmed, or `source`.
+pub fn or_source(translated: Option<String>, source: &str) -> String {
+    translated.map_or_else(|| source.to_string(), |t| t.trim().to_string())
+}
+
+/// Writes translated segments to a txt or md file and, with `--keep-source-text`, the
+/// source segments to a `<name>.src.txt` or `.src.md` sibling using the same separator,
+/// so line N of one file corresponds to line N of the other.
+pub struct SegmentWriter {
+    output: File,
+    source: Option<File>,
+    format: TextFormat,
+    paths: Vec<PathBuf>,
+    segments: usize,
+    /// page and number of the block on it, for `--prefix-segments`
+    page: usize,
+    block: usize,
+    failures: Vec<Failure>,
+}
+
+impl SegmentWriter {
+    pub fn create(path: PathBuf, format: &TextFormat, keep_source_text: bool) -> Result<Self> {
+        let mut output = File::create(&path)?;
+        output.write_all(format.bom())?;
+        let stamp = format.stamp.as_deref().map(|stamp| format.encode(stamp));
+        if let Some(stamp) = &stamp {
+            output.write_all(stamp)?;
+        }
+        let mut paths = vec![path];
+        let source = if keep_source_text {
+            let ext = paths[0].extension().unwrap_or_default().to_string_lossy();
+            let source_path = paths[0].with_extension(format!("src.{}", ext));
+            let mut source = File::create(&source_path)?;
+            source.write_all(format.bom())?;
+            if let Some(stamp) = &stamp {
+                source.write_all(stamp)?;
+            }
+            paths.push(source_path);
+            Some(source)
+        } else {
+            None
+        };
+        Ok(SegmentWriter {
+            output,
+            source,
+            format: format.clone(),
+            paths,
+            segments: 0,
+            page: 1,
+            block: 0,
+            failures: Vec::new(),
+        })
+    }
+
+    /// Numbers the following blocks as being on page `number`.
+    pub fn set_page(&mut self, number: usize) {
+        self.page = number;
+        self.block = 0;
+    }
+
+    /// Writes a segment, prefixed with its page and block number for `--prefix-segments`.
+    pub fn write(&mut self, source: &str, translated: &str) -> Result<()> {
+        self.place(&Segment::new(source, None), Some(translated))
+    }
+
+    fn write_numbered(&mut self, source: &str, translated: &str) -> Result<()> {
+        self.block += 1;
+        if !self.format.prefix_segments {
+            return self.write_verbatim(source, translated);
+        }
+        let prefix = format!("[{}/{}] ", self.page, self.block);
+        self.write_verbatim(
+            &format!("{}{}", prefix, source),
+            &format!("{}{}", prefix, translated),
+        )
+    }
+
+    /// Writes a segment, prefixed with its origin for `--annotate-provenance`.
+    pub fn write_segment(&mut self, segment: &Segment, translated: &str) -> Result<()> {
+        self.place(segment, Some(translated))
+    }
+
+    fn place(&mut self, segment: &Segment, translated: Option<&str>) -> Result<()> {
+        let placed = place(
+            segment,
+            translated,
+            self.segments,
+            &self.paths[0],
+            self.format.post_edit.as_ref(),
+            Some(self.page),
+            &mut self.failures,
+        );
+        self.write_with_origin(segment, &placed)
+    }
+
+    fn write_with_origin(&mut self, segment: &Segment, translated: &str) -> Result<()> {
+        let Some(origin) = segment.origin.filter(|_| self.format.annotate_provenance) else {
+            return self.write_numbered(&segment.text, translated);
+        };
+        self.block += 1;
+        self.write_verbatim(
+            &format!("{} {}", origin, segment.text),
+            &format!("{} {}", origin, translated),
+        )
+    }
+
+    /// Writes a heading, table or note, which has no prefix.
+    pub fn write_verbatim(&mut self, source: &str, translated: &str) -> Result<()> {
+        let separator = self.format.separator.clone();
+        self.write_separated(source, translated, &separator)
+    }
+
+    pub fn write_separated(
+        &mut self,
+        source: &str,
+        translated: &str,
+        separator: &str,
+    ) -> Result<()> {
+        self.segments += 1;
+        let segment = format!("{}{}", translated.trim_end(), separator);
+        self.output.write_all(&self.format.encode(&segment))?;
+        if let Some(file) = &mut self.source {
+            let segment = format!("{}{}", source.trim_end(), separator);
+            file.write_all(&self.format.encode(&segment))?;
+        }
+        Ok(())
+    }
+
+    /// Writes a placeholder for a segment that failed to translate and records the source
+    /// text, so the segment can be retried without extracting it again.
+    pub fn write_failed(&mut self, segment: &Segment) -> Result<()> {
+        self.place(segment, None)
+    }
+
+    /// Page the following blocks are on, counting from 1.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Ends the outputs, running the `post_edit_cmd` for documents over the translation.
+    pub fn finish(self) -> (Vec<PathBuf>, Vec<Failure>) {
+        if let Some(post_edit) = &self.format.post_edit {
+            if post_edit.granularity == Granularity::Document {
+                let path = &self.paths[0];
+                let edited = std::fs::read(path).and_then(|bytes| {
+                    let text = self.format.decode(&bytes);
+                    let edited = post_edit.edit_document(&text, path);
+                    std::fs::write(path, self.format.encode_file(&edited))
+                });
+                if let Err(e) = edited {
+                    eprintln!("{}: {}", path.display(), e);
+                }
+            }
+        }
+        (self.paths, self.failures)
+    }
+}
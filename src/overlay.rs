@@ -0,0 +1,219 @@
+//! `--output-format overlay`: page images with every translated OCR block painted over
+//! the original text, for sharing without a txt next to them.
+
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use anyhow::{anyhow, Result};
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+/// Fonts tried when `--font-path` isn't given, all with Latin and Cyrillic glyphs.
+const SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/Library/Fonts/Arial Unicode.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+/// Text is shrunk down to this size to fit a block, and overflows below it at this size.
+const MIN_FONT_PX: f32 = 8.0;
+const MAX_FONT_PX: f32 = 72.0;
+/// Factor the font size shrinks by per attempt.
+const SHRINK: f32 = 0.9;
+/// Space kept free along the edges of a block, in pixels.
+const PADDING: f32 = 2.0;
+
+pub struct Overlay {
+    font: FontArc,
+}
+
+impl Overlay {
+    /// Loads the font at `path`, or the first of the usual system fonts that exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => SYSTEM_FONTS
+                .iter()
+                .map(PathBuf::from)
+                .find(|p| p.exists())
+                .ok_or_else(|| anyhow!("no font found for the overlay, pass --font-path"))?,
+        };
+        let bytes = std::fs::read(&path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let font = FontArc::try_from_vec(bytes)
+            .map_err(|e| anyhow!("{}: not a usable font: {}", path.display(), e))?;
+        Ok(Overlay { font })
+    }
+
+    /// Covers each block, x, y, width and height in pixels, with `text` fitted into it.
+    pub fn draw(&self, image: &mut RgbImage, blocks: &[([i32; 4], String)]) {
+        for (bbox, text) in blocks {
+            self.draw_block(image, *bbox, text);
+        }
+    }
+
+    fn draw_block(&self, image: &mut RgbImage, bbox: [i32; 4], text: &str) {
+        let [x, y, width, height] = bbox;
+        if width <= 0 || height <= 0 || text.trim().is_empty() {
+            return;
+        }
+        let background = border_color(image, bbox);
+        let (size, lines) = self.fit(text, width as f32, height as f32);
+        let scaled = self.font.as_scaled(PxScale::from(size));
+        let line_height = scaled.height() + scaled.line_gap();
+        // text that doesn't fit at the smallest size runs on below the block, on the same
+        // background so it stays readable
+        let needed = (lines.len() as f32 * line_height + 2.0 * PADDING).ceil() as i32;
+        fill(image, [x, y, width, height.max(needed)], background);
+        let color = if luminance(background) > 128.0 {
+            Rgb([0, 0, 0])
+        } else {
+            Rgb([255, 255, 255])
+        };
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = y as f32 + PADDING + scaled.ascent() + index as f32 * line_height;
+            self.draw_line(image, line, x as f32 + PADDING, baseline, size, color);
+        }
+    }
+
+    /// The largest size at which `text` wraps into the box, with its lines. Words are
+    /// never split; at the smallest size the lines may be taller or wider than the box.
+    fn fit(&self, text: &str, width: f32, height: f32) -> (f32, Vec<String>) {
+        let width = width - 2.0 * PADDING;
+        let height = height - 2.0 * PADDING;
+        let mut size = height.clamp(MIN_FONT_PX, MAX_FONT_PX);
+        loop {
+            let lines = self.wrap(text, size, width);
+            let scaled = self.font.as_scaled(PxScale::from(size));
+            let line_height = scaled.height() + scaled.line_gap();
+            let fits = lines.len() as f32 * line_height <= height
+                && lines.iter().all(|l| self.width(l, size) <= width);
+            if fits || size <= MIN_FONT_PX {
+                return (size, lines);
+            }
+            size = (size * SHRINK).max(MIN_FONT_PX);
+        }
+    }
+
+    /// Breaks `text` into lines no wider than `width` where possible, a word per line at
+    /// least.
+    fn wrap(&self, text: &str, size: f32, width: f32) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in text.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if line.is_empty() || self.width(&candidate, size) <= width {
+                line = candidate;
+            } else {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn width(&self, text: &str, size: f32) -> f32 {
+        let scaled = self.font.as_scaled(PxScale::from(size));
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                width += scaled.kern(previous, id);
+            }
+            width += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    }
+
+    fn draw_line(
+        &self,
+        image: &mut RgbImage,
+        line: &str,
+        x: f32,
+        baseline: f32,
+        size: f32,
+        color: Rgb<u8>,
+    ) {
+        let scaled = self.font.as_scaled(PxScale::from(size));
+        let mut caret = x;
+        let mut previous = None;
+        for c in line.chars() {
+            let mut glyph = scaled.scaled_glyph(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, glyph.id);
+            }
+            previous = Some(glyph.id);
+            glyph.position = point(caret, baseline);
+            caret += scaled.h_advance(glyph.id);
+            let Some(outlined) = self.font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i32 + gx as i32;
+                let py = bounds.min.y as i32 + gy as i32;
+                if px < 0 || py < 0 || px >= image.width() as i32 || py >= image.height() as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(px as u32, py as u32);
+                for channel in 0..3 {
+                    let blended =
+                        pixel[channel] as f32 * (1.0 - coverage) + color[channel] as f32 * coverage;
+                    pixel[channel] = blended.round() as u8;
+                }
+            });
+        }
+    }
+}
+
+/// Average color of the pixels along the edge of `bbox`, taken as the block's background.
+fn border_color(image: &RgbImage, bbox: [i32; 4]) -> Rgb<u8> {
+    let [x, y, width, height] = bbox;
+    let (right, bottom) = (x + width - 1, y + height - 1);
+    let mut sum = [0u64; 3];
+    let mut count = 0;
+    let mut add = |px: i32, py: i32| {
+        if px >= 0 && py >= 0 && (px as u32) < image.width() && (py as u32) < image.height() {
+            let pixel = image.get_pixel(px as u32, py as u32);
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as u64;
+            }
+            count += 1;
+        }
+    };
+    for px in x..=right {
+        add(px, y);
+        add(px, bottom);
+    }
+    for py in y..=bottom {
+        add(x, py);
+        add(right, py);
+    }
+    if count == 0 {
+        return Rgb([255, 255, 255]);
+    }
+    Rgb(sum.map(|s| (s / count) as u8))
+}
+
+fn fill(image: &mut RgbImage, bbox: [i32; 4], color: Rgb<u8>) {
+    let [x, y, width, height] = bbox;
+    let right = (x + width).min(image.width() as i32);
+    let bottom = (y + height).min(image.height() as i32);
+    for py in y.max(0)..bottom {
+        for px in x.max(0)..right {
+            image.put_pixel(px as u32, py as u32, color);
+        }
+    }
+}
+
+fn luminance(color: Rgb<u8>) -> f32 {
+    0.299 * color[0] as f32 + 0.587 * color[1] as f32 + 0.114 * color[2] as f32
+}
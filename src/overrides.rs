@@ -0,0 +1,148 @@
+//! Per-directory settings. A `.dir-translate.toml` in any directory below the source dir
+//! overrides languages and handler options for everything beneath it; the override file
+//! nearest to a file wins. A `<name>.translate.toml` sidecar next to a file, with the same
+//! keys, overrides them all for that file alone.
+
+use crate::docx_tables::TableFormat;
+use crate::mask::Mask;
+use crate::orientation::Orientation;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+pub const OVERRIDE_FILE: &str = ".dir-translate.toml";
+/// Appended to the name of a file, extension included, for its sidecar.
+pub const SIDECAR_SUFFIX: &str = ".translate.toml";
+
+/// Whether `name` is an override file or a sidecar rather than a file to translate.
+pub fn is_override_file(name: &OsStr) -> bool {
+    name == OVERRIDE_FILE || name.to_string_lossy().ends_with(SIDECAR_SUFFIX)
+}
+
+/// The sidecar of `file`, whether or not it exists.
+pub fn sidecar_path(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    file.with_file_name(name)
+}
+
+/// Settings a file is handled with, after applying all override files above it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// language code the server translates from
+    pub source_lang: String,
+    /// tesseract language
+    pub ocr_lang: String,
+    pub orientation: Orientation,
+    pub docx_tables: TableFormat,
+    pub reflow: bool,
+    pub keep_source_text: bool,
+    /// regions blanked out before OCR
+    pub ocr_mask: Vec<Mask>,
+}
+
+/// Keys of an override file, also those of a `[profiles.<name>]` table in the config.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    source_lang: Option<String>,
+    ocr_lang: Option<String>,
+    orientation: Option<Orientation>,
+    docx_tables: Option<TableFormat>,
+    reflow: Option<bool>,
+    keep_source_text: Option<bool>,
+    ocr_mask: Option<Vec<Mask>>,
+}
+
+impl Settings {
+    pub fn apply(&mut self, overrides: &Overrides) {
+        if let Some(source_lang) = &overrides.source_lang {
+            self.source_lang = source_lang.clone();
+        }
+        if let Some(ocr_lang) = &overrides.ocr_lang {
+            self.ocr_lang = ocr_lang.clone();
+        }
+        if let Some(orientation) = overrides.orientation {
+            self.orientation = orientation;
+        }
+        if let Some(docx_tables) = overrides.docx_tables {
+            self.docx_tables = docx_tables;
+        }
+        if let Some(reflow) = overrides.reflow {
+            self.reflow = reflow;
+        }
+        if let Some(keep_source_text) = overrides.keep_source_text {
+            self.keep_source_text = keep_source_text;
+        }
+        if let Some(ocr_mask) = &overrides.ocr_mask {
+            self.ocr_mask = ocr_mask.clone();
+        }
+    }
+}
+
+/// Override files found so far, read once per directory.
+pub struct OverrideTree {
+    root: PathBuf,
+    dirs: HashMap<PathBuf, Result<Option<Overrides>, String>>,
+}
+
+impl OverrideTree {
+    pub fn new(root: &Path) -> Self {
+        OverrideTree {
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, dir: &Path) -> &Result<Option<Overrides>, String> {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| read(&dir.join(OVERRIDE_FILE)))
+    }
+
+    /// Applies the override files from the source dir down to the directory of `file`,
+    /// then its sidecar, on top of `base`. Returns the sidecar if there is one. Fails if
+    /// any of them can't be read.
+    pub fn settings_for(
+        &mut self,
+        base: &Settings,
+        file: &Path,
+    ) -> Result<(Settings, Option<PathBuf>)> {
+        let mut dirs: Vec<PathBuf> = file
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+        dirs.reverse();
+        let mut settings = base.clone();
+        for dir in dirs {
+            match self.load(&dir) {
+                Ok(Some(overrides)) => settings.apply(overrides),
+                Ok(None) => {}
+                Err(e) => return Err(anyhow!("{}", e)),
+            }
+        }
+        let sidecar = sidecar_path(file);
+        match read(&sidecar).map_err(|e| anyhow!("{}", e))? {
+            Some(overrides) => {
+                settings.apply(&overrides);
+                Ok((settings, Some(sidecar)))
+            }
+            None => Ok((settings, None)),
+        }
+    }
+}
+
+/// The override file at `path`, `None` if there is none.
+fn read(path: &Path) -> Result<Option<Overrides>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(data) => toml::from_str(&data)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
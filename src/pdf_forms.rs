@@ -0,0 +1,70 @@
+//! Text of PDF form fields and annotations. Filled-in values and sticky notes are usually
+//! drawn small or not at all, so they're read through pdfium instead of relying on OCR.
+
+use pdfium_render::prelude::*;
+
+pub const FORM_FIELDS_HEADING: &str = "## Form fields";
+pub const ANNOTATIONS_HEADING: &str = "## Annotations";
+
+#[derive(Default)]
+pub struct PageText {
+    /// name and value of every filled-in text, combo box or list box field
+    pub fields: Vec<(String, String)>,
+    /// contents of comments, sticky notes, free text boxes and the like
+    pub annotations: Vec<String>,
+    /// fields whose value couldn't be read from an encrypted document
+    pub unreadable: usize,
+}
+
+impl PageText {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.annotations.is_empty() && self.unreadable == 0
+    }
+}
+
+pub fn is_encrypted(document: &PdfDocument) -> bool {
+    !matches!(
+        document.permissions().security_handler_revision(),
+        Ok(PdfSecurityHandlerRevision::Unprotected)
+    )
+}
+
+fn field_value(field: &PdfFormField) -> Option<Option<String>> {
+    let value = match field.field_type() {
+        PdfFormFieldType::Text => field.as_text_field()?.value(),
+        PdfFormFieldType::ComboBox => field.as_combo_box_field()?.value(),
+        PdfFormFieldType::ListBox => field.as_list_box_field()?.value(),
+        // buttons, checkboxes and signatures carry nothing to translate
+        _ => return None,
+    };
+    Some(value.filter(|v| !v.trim().is_empty()))
+}
+
+/// Collects the form field values and annotation contents of a page.
+pub fn read_page(page: &PdfPage, encrypted: bool) -> PageText {
+    let mut text = PageText::default();
+    for annotation in page.annotations().iter() {
+        if let Some(field) = annotation.as_form_field() {
+            match field_value(field) {
+                Some(Some(value)) => {
+                    let name = field.name().unwrap_or_default();
+                    text.fields.push((name, value));
+                }
+                Some(None) if encrypted => text.unreadable += 1,
+                _ => {}
+            }
+            continue;
+        }
+        // popups show the contents of their parent annotation, which is read already
+        if matches!(
+            annotation.annotation_type(),
+            PdfPageAnnotationType::Popup | PdfPageAnnotationType::Link
+        ) {
+            continue;
+        }
+        if let Some(contents) = annotation.contents().filter(|c| !c.trim().is_empty()) {
+            text.annotations.push(contents);
+        }
+    }
+    text
+}
@@ -0,0 +1,34 @@
+//! Photos among the scans, which tesseract spends long runs on for nothing but junk
+//! blocks. A page with text has a background, the paper: a narrow band of brightness most
+//! of its pixels fall in. A photo spreads over the whole range, so an image without such a
+//! band is taken for one and not recognized, unless `--force-ocr-images`.
+
+use image::DynamicImage;
+
+pub const PHOTO_REASON: &str = "photo, no text detected";
+
+/// Side the image is scaled down to before it is measured.
+const SAMPLE_SIDE: u32 = 256;
+/// Width of the brightness band of the background, of the 256 levels.
+const BAND: usize = 32;
+/// Share of the pixels the band has to hold for the image to be a page.
+const MIN_BACKGROUND_SHARE: f64 = 0.35;
+
+/// Share of the pixels of `image` in its most common band of brightness.
+fn background_share(image: &DynamicImage) -> f64 {
+    let sample = image.thumbnail(SAMPLE_SIDE, SAMPLE_SIDE).to_luma8();
+    let mut histogram = [0usize; 256];
+    for pixel in sample.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let densest = histogram
+        .windows(BAND)
+        .map(|band| band.iter().sum::<usize>())
+        .max()
+        .unwrap_or(0);
+    densest as f64 / sample.pixels().len().max(1) as f64
+}
+
+pub fn is_photo(image: &DynamicImage) -> bool {
+    background_share(image) < MIN_BACKGROUND_SHARE
+}
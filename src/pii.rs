@@ -0,0 +1,79 @@
+//! Numbers that identify people, like passport numbers, INN and SNILS, must come out of the
+//! translation exactly as they went in. The tokens the patterns find in a segment are
+//! looked for in its translation; if one is missing or altered, the segment is written
+//! untranslated with [`PII_MARKER`] instead. The patterns are the `[pii_patterns]` table
+//! of config.toml, added to the Russian defaults; a name of a default replaces it, an
+//! empty pattern turns it off.
+//!
+//! [`PII_MARKER`]: crate::PII_MARKER
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Name and pattern of the default checks.
+const DEFAULTS: [(&str, &str); 4] = [
+    // series and number, "45 08 123456"
+    ("passport", r"\b\d{2} ?\d{2} ?\d{6}\b"),
+    // 10 digits for organizations, 12 for people
+    ("inn", r"\b\d{10}(?:\d{2})?\b"),
+    ("snils", r"\b\d{3}-\d{3}-\d{3}[ -]\d{2}\b"),
+    // settlement account
+    (
+        "bank_account",
+        r"\b\d{5}[ .]?\d{3}[ .]?\d[ .]?\d{4}[ .]?\d{7}\b",
+    ),
+];
+
+#[derive(Clone, Debug)]
+pub struct Pii {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Default for Pii {
+    fn default() -> Self {
+        Pii::new(&BTreeMap::new()).unwrap()
+    }
+}
+
+impl Pii {
+    /// The defaults with `custom` patterns added or replacing them.
+    pub fn new(custom: &BTreeMap<String, String>) -> Result<Self> {
+        let mut sources: BTreeMap<String, String> = DEFAULTS
+            .iter()
+            .map(|&(name, pattern)| (name.to_string(), pattern.to_string()))
+            .collect();
+        sources.extend(custom.clone());
+        let mut patterns = Vec::new();
+        for (name, pattern) in sources {
+            if pattern.is_empty() {
+                continue;
+            }
+            let regex = Regex::new(&pattern).map_err(|e| anyhow!("pii pattern {}: {}", name, e))?;
+            patterns.push((name, regex));
+        }
+        Ok(Pii { patterns })
+    }
+
+    /// Where the tokens of the patterns are in `text`.
+    pub fn tokens(&self, text: &str) -> Vec<Range<usize>> {
+        self.patterns
+            .iter()
+            .flat_map(|(_, regex)| regex.find_iter(text).map(|token| token.range()))
+            .collect()
+    }
+
+    /// Names of the patterns whose tokens in `input` aren't all in `output` unchanged.
+    pub fn altered(&self, input: &str, output: &str) -> Vec<&str> {
+        self.patterns
+            .iter()
+            .filter(|(_, regex)| {
+                regex
+                    .find_iter(input)
+                    .any(|token| !output.contains(token.as_str()))
+            })
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
@@ -0,0 +1,193 @@
+//! The stages of `translate`, joined by bounded channels. The walker sends the files in the
+//! order of the run; the OCR stage starts recognizing those that can be recognized ahead
+//! of their turn, images, in the tesseract pool of `--ocr-workers` instances; the
+//! translation stage, the loop of the run, takes the files in that order and handles them
+//! one by one, the requests of a file `--translate-workers` at a time, writing its outputs
+//! before it takes the next. The OCR stage only starts on a file once there is room for it
+//! in the channel to the translation stage, which holds as many files as the pool has
+//! instances, so recognition runs that far ahead of translation and then waits. Scanned
+//! PDFs are recognized page by page in their turn, their pages ahead of translation the
+//! same way, see the PDF handler.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::task::JoinHandle;
+
+/// Files the walker sends ahead of the OCR stage.
+const WALKED: usize = 16;
+
+/// A file of the run and whether the OCR stage recognizes it ahead of its turn.
+pub struct Walked {
+    pub path: PathBuf,
+    pub ahead: bool,
+}
+
+/// A file for the translation stage, with its recognition started ahead if it was.
+pub struct Prepared<T> {
+    pub path: PathBuf,
+    pub ocr: Option<T>,
+}
+
+pub struct Pipeline<T> {
+    prepared: Receiver<Prepared<T>>,
+    /// each returns the files it took and didn't send on when the pipeline is stopped
+    walker: Option<JoinHandle<Vec<PathBuf>>>,
+    ocr: Option<JoinHandle<Vec<PathBuf>>>,
+    /// files not taken by the translation stage yet
+    waiting: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    /// Starts the walker over `files` and the OCR stage, which recognizes a file with
+    /// `start` and keeps at most `ahead` files ready for the translation stage.
+    pub fn start(
+        files: Vec<Walked>,
+        ahead: usize,
+        start: impl Fn(&Walked) -> Option<T> + Send + 'static,
+    ) -> Self {
+        let waiting = Arc::new(AtomicUsize::new(files.len()));
+        let (walked_tx, mut walked) = mpsc::channel::<Walked>(WALKED);
+        let (prepared_tx, prepared) = mpsc::channel(ahead.max(1));
+        let walker = tokio::spawn(async move {
+            let mut files = files.into_iter();
+            while let Some(file) = files.next() {
+                // the OCR stage is gone once the pipeline is stopped
+                let Ok(permit) = walked_tx.reserve().await else {
+                    return std::iter::once(file.path)
+                        .chain(files.map(|f| f.path))
+                        .collect();
+                };
+                permit.send(file);
+            }
+            Vec::new()
+        });
+        let ocr = tokio::spawn(async move {
+            while let Some(file) = walked.recv().await {
+                // room for it first, so recognition doesn't run further ahead
+                let Ok(permit) = prepared_tx.reserve().await else {
+                    walked.close();
+                    let mut rest = vec![file.path];
+                    while let Ok(file) = walked.try_recv() {
+                        rest.push(file.path);
+                    }
+                    return rest;
+                };
+                let ocr = file.ahead.then(|| start(&file)).flatten();
+                permit.send(Prepared {
+                    path: file.path,
+                    ocr,
+                });
+            }
+            Vec::new()
+        });
+        Pipeline {
+            prepared,
+            walker: Some(walker),
+            ocr: Some(ocr),
+            waiting,
+        }
+    }
+
+    /// The next file in the order of the run, `None` once all were taken.
+    pub async fn next(&mut self) -> Option<Prepared<T>> {
+        let prepared = self.prepared.recv().await?;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        Some(prepared)
+    }
+
+    /// Files not taken yet.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Stops the stages and returns the files not taken, in order. Recognition started
+    /// ahead for them is dropped.
+    pub async fn stop(&mut self) -> Vec<PathBuf> {
+        self.prepared.close();
+        let mut rest = Vec::new();
+        while let Some(prepared) = self.prepared.recv().await {
+            rest.push(prepared.path);
+        }
+        for stage in [self.ocr.take(), self.walker.take()].into_iter().flatten() {
+            rest.extend(stage.await.unwrap_or_default());
+        }
+        self.waiting.store(0, Ordering::Relaxed);
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn files(count: usize) -> Vec<Walked> {
+        (0..count)
+            .map(|i| Walked {
+                path: PathBuf::from(format!("{}.png", i)),
+                ahead: i % 2 == 0,
+            })
+            .collect()
+    }
+
+    /// A pipeline recording the files its OCR stage started on.
+    fn pipeline(count: usize, ahead: usize) -> (Pipeline<PathBuf>, Arc<Mutex<Vec<PathBuf>>>) {
+        let started: Arc<Mutex<Vec<PathBuf>>> = Arc::default();
+        let recorded = started.clone();
+        let pipeline = Pipeline::start(files(count), ahead, move |file: &Walked| {
+            recorded.lock().unwrap().push(file.path.clone());
+            Some(file.path.clone())
+        });
+        (pipeline, started)
+    }
+
+    #[tokio::test]
+    async fn files_come_out_in_order_with_the_recognition_of_those_ahead() {
+        let (mut pipeline, started) = pipeline(5, 2);
+        assert_eq!(pipeline.waiting(), 5);
+        let mut taken = Vec::new();
+        while let Some(prepared) = pipeline.next().await {
+            assert_eq!(prepared.ocr.is_some(), taken.len() % 2 == 0);
+            taken.push(prepared.path);
+        }
+        let names: Vec<String> = taken.iter().map(|p| p.display().to_string()).collect();
+        assert_eq!(names, ["0.png", "1.png", "2.png", "3.png", "4.png"]);
+        assert_eq!(started.lock().unwrap().len(), 3);
+        assert_eq!(pipeline.waiting(), 0);
+        assert!(pipeline.stop().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recognition_waits_while_translation_is_that_far_behind() {
+        let (mut pipeline, started) = pipeline(20, 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // files 0 and 1 in the channel, only images are started on
+        assert_eq!(started.lock().unwrap().len(), 1);
+        for _ in 0..2 {
+            pipeline.next().await.unwrap();
+        }
+        // 2 and 3 in their place, 4 waiting for room
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(started.lock().unwrap().len(), 2);
+        assert_eq!(pipeline.waiting(), 18);
+        pipeline.stop().await;
+    }
+
+    #[tokio::test]
+    async fn stopping_returns_the_files_not_taken_in_order() {
+        let (mut pipeline, _) = pipeline(40, 3);
+        for _ in 0..4 {
+            pipeline.next().await.unwrap();
+        }
+        let rest = pipeline.stop().await;
+        let expected: Vec<PathBuf> = (4..40)
+            .map(|i| PathBuf::from(format!("{}.png", i)))
+            .collect();
+        assert_eq!(rest, expected);
+        assert_eq!(pipeline.waiting(), 0);
+        assert!(pipeline.next().await.is_none());
+    }
+}
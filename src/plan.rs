@@ -0,0 +1,444 @@
+//! Rename plans for Filenames mode: `filenames --plan` writes the proposed renames with
+//! everything that looks wrong about them, `apply-plan` carries out a reviewed plan.
+//! The renames are recorded in a journal in the source dir before the first one, so that
+//! an interrupted `apply-plan` can be finished or undone.
+
+use crate::handlers::HandlerRegistry;
+use crate::names;
+use crate::titles;
+use crate::translator::Translator;
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Longest file name most file systems accept, in bytes.
+const MAX_NAME_BYTES: usize = 255;
+/// Windows MAX_PATH, in characters.
+const MAX_PATH_CHARS: usize = 260;
+/// Characters Windows doesn't allow in file names, replaced by `_`.
+const FORBIDDEN: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Journal of the renames of `apply-plan`, in the source dir while they are underway.
+pub const JOURNAL_FILE: &str = ".rename-journal.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanEntry {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// size of the source when the plan was made
+    pub size: u64,
+    /// mtime of the source when the plan was made, seconds since the epoch
+    pub modified: u64,
+    /// problems found while planning, separated by "; "
+    pub issues: String,
+}
+
+impl PlanEntry {
+    fn flag(&mut self, issue: String) {
+        if !self.issues.is_empty() {
+            self.issues.push_str("; ");
+        }
+        self.issues.push_str(&issue);
+    }
+}
+
+fn fingerprint(path: &Path) -> Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    Ok((metadata.len(), modified))
+}
+
+/// `name` with the characters Windows doesn't allow replaced by `_`.
+pub fn sanitize(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if FORBIDDEN.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Translates one path component, keeping the extension of file names. Returns `None` if
+/// the backend failed or gave nothing usable back.
+async fn translate_component(
+    translator: &mut Translator,
+    component: &str,
+    is_file: bool,
+) -> Option<String> {
+    let (stem, extension) = match component.rsplit_once('.') {
+        Some((stem, extension)) if is_file && !stem.is_empty() => (stem, Some(extension)),
+        _ => (component, None),
+    };
+    let translated = names::translate(translator, stem).await.ok()?;
+    if translated.starts_with(UNTRANSLATED_MARKER) || translated.starts_with(SUSPECT_MARKER) {
+        return None;
+    }
+    let translated = translator.name_style.apply(&sanitize(&translated));
+    if translated.is_empty() || translated == "." || translated == ".." {
+        return None;
+    }
+    Some(match extension {
+        Some(extension) => format!("{}.{}", translated, extension),
+        None => translated,
+    })
+}
+
+/// Proposes a translated path for every file, see [`disambiguate`] for those that would
+/// clash, and flags case-only renames, names that are too long and components that
+/// could not be translated. With
+/// `names_from_content` file names are made from the text of the files where they have
+/// any, see [`titles::name`].
+pub async fn build(
+    translator: &mut Translator,
+    source_dir: &Path,
+    files: &[PathBuf],
+    names_from_content: Option<&HandlerRegistry>,
+) -> Result<Vec<PlanEntry>> {
+    // directory names repeat for every file below them, translate each one once
+    let mut translated: HashMap<(String, bool), Option<String>> = HashMap::new();
+    let mut plan = Vec::with_capacity(files.len());
+    for file in files {
+        let relative = file.strip_prefix(source_dir).unwrap_or(file);
+        let components: Vec<_> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        let (size, modified) = fingerprint(file)?;
+        let mut entry = PlanEntry {
+            source: file.clone(),
+            target: source_dir.to_path_buf(),
+            size,
+            modified,
+            issues: String::new(),
+        };
+        for (index, component) in components.iter().enumerate() {
+            let key = (component.clone(), index + 1 == components.len());
+            // the same name can stand for different documents, these aren't shared
+            let from_content = match names_from_content {
+                Some(registry) if key.1 => {
+                    let name = titles::name(translator, registry, file).await;
+                    if name.is_none() {
+                        entry.flag("no text to name it by, the file name is translated".into());
+                    }
+                    name
+                }
+                _ => None,
+            };
+            if from_content.is_none() && !translated.contains_key(&key) {
+                let result = translate_component(translator, component, key.1).await;
+                translated.insert(key.clone(), result);
+            }
+            let name = from_content.or_else(|| translated[&key].clone());
+            match &name {
+                Some(name) => {
+                    if name.len() > MAX_NAME_BYTES {
+                        entry.flag(format!(
+                            "name longer than {} bytes: {}",
+                            MAX_NAME_BYTES, name
+                        ));
+                    }
+                    entry.target.push(name);
+                }
+                None => {
+                    entry.flag(format!("untranslated: {}", component));
+                    entry.target.push(component);
+                }
+            }
+        }
+        if entry.target.to_string_lossy().chars().count() > MAX_PATH_CHARS {
+            entry.flag(format!("path longer than {} characters", MAX_PATH_CHARS));
+        }
+        plan.push(entry);
+    }
+    disambiguate(&mut plan, source_dir);
+    validate(&mut plan);
+    Ok(plan)
+}
+
+fn lowercase(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// `name` with `-` and `suffix` before its extension.
+fn with_suffix(name: &str, suffix: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}-{}.{}", stem, suffix, extension)
+        }
+        _ => format!("{}-{}", name, suffix),
+    }
+}
+
+/// Gives the files whose targets clash on a case-insensitive file system, with each other
+/// or with a file that stays where it is, a suffix made from the hash of their source
+/// path, so the name a file gets doesn't depend on the order the files were planned in.
+/// A file keeping its name keeps it without a suffix.
+fn disambiguate(plan: &mut [PlanEntry], source_dir: &Path) {
+    let mut by_target: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, entry) in plan.iter().enumerate() {
+        by_target
+            .entry(lowercase(&entry.target))
+            .or_default()
+            .push(index);
+    }
+    let sources: HashSet<String> = plan.iter().map(|e| lowercase(&e.source)).collect();
+    for index in 0..plan.len() {
+        let entry = &plan[index];
+        let key = lowercase(&entry.target);
+        let others: Vec<_> = by_target[&key]
+            .iter()
+            .filter(|&&other| other != index)
+            .map(|&other| plan[other].source.display().to_string())
+            .collect();
+        let on_disk = entry.target.exists() && !sources.contains(&key);
+        if entry.source == entry.target || (others.is_empty() && !on_disk) {
+            continue;
+        }
+        let relative = entry
+            .source
+            .strip_prefix(source_dir)
+            .unwrap_or(&entry.source);
+        let hash = blake3::hash(relative.to_string_lossy().as_bytes()).to_hex();
+        let name = entry.target.file_name().unwrap().to_string_lossy();
+        let target = entry.target.with_file_name(with_suffix(&name, &hash[..6]));
+        let with = match on_disk {
+            true => entry.target.display().to_string(),
+            false => others.join(", "),
+        };
+        let entry = &mut plan[index];
+        entry.flag(format!(
+            "renamed to {} to avoid a collision with {}",
+            target.file_name().unwrap().to_string_lossy(),
+            with
+        ));
+        entry.target = target;
+    }
+}
+
+/// Flags targets that clash on a case-insensitive file system, either with each other or
+/// with a file that stays where it is, and renames that only change the case.
+fn validate(plan: &mut [PlanEntry]) {
+    let mut by_target: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, entry) in plan.iter().enumerate() {
+        by_target
+            .entry(lowercase(&entry.target))
+            .or_default()
+            .push(index);
+    }
+    let sources: HashSet<String> = plan.iter().map(|e| lowercase(&e.source)).collect();
+    for index in 0..plan.len() {
+        let key = lowercase(&plan[index].target);
+        let others: Vec<_> = by_target[&key]
+            .iter()
+            .filter(|&&other| other != index)
+            .map(|&other| plan[other].source.display().to_string())
+            .collect();
+        let entry = &mut plan[index];
+        if !others.is_empty() {
+            entry.flag(format!("collides with {}", others.join(", ")));
+        }
+        if entry.source != entry.target {
+            if lowercase(&entry.source) == key {
+                entry.flag("only the case changes".to_string());
+            } else if entry.target.exists() && !sources.contains(&key) {
+                entry.flag("target already exists".to_string());
+            }
+        }
+    }
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+}
+
+/// Writes the plan as CSV if `path` ends in `.csv`, as JSON otherwise.
+pub fn write(path: &Path, plan: &[PlanEntry]) -> Result<()> {
+    if is_csv(path) {
+        let mut writer = csv::Writer::from_path(path)?;
+        for entry in plan {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+    } else {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, plan)?;
+    }
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Vec<PlanEntry>> {
+    if is_csv(path) {
+        let mut reader = csv::Reader::from_path(path)?;
+        Ok(reader.deserialize().collect::<Result<_, _>>()?)
+    } else {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+}
+
+/// Fails, printing every problem, if a source is missing or changed since the plan was
+/// made, or a target already exists or is planned twice.
+pub fn check(plan: &[PlanEntry]) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut targets = HashSet::new();
+    for entry in plan.iter().filter(|e| e.source != e.target) {
+        match fingerprint(&entry.source) {
+            Ok(fingerprint) if fingerprint != (entry.size, entry.modified) => {
+                problems.push(format!(
+                    "{}: changed since the plan was made",
+                    entry.source.display()
+                ))
+            }
+            Err(e) => problems.push(format!("{}: {}", entry.source.display(), e)),
+            Ok(_) => {}
+        }
+        let same_file = lowercase(&entry.source) == lowercase(&entry.target);
+        if entry.target.exists() && !same_file {
+            problems.push(format!("{}: already exists", entry.target.display()));
+        }
+        if !targets.insert(lowercase(&entry.target)) {
+            problems.push(format!(
+                "{}: planned more than once",
+                entry.target.display()
+            ));
+        }
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        return Err(anyhow!("plan not applied, {} problems", problems.len()));
+    }
+    Ok(())
+}
+
+/// One rename of the journal, `done` once the file has its new name.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub done: bool,
+}
+
+/// Writes the journal atomically, a crash leaves either the old or the new one.
+fn write_journal(path: &Path, journal: &[JournalEntry]) -> Result<()> {
+    let partial = path.with_extension("json.partial");
+    serde_json::to_writer_pretty(std::fs::File::create(&partial)?, journal)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// The journal left at `path` by an interrupted `apply-plan`, `None` if there is none.
+pub fn read_journal(path: &Path) -> Result<Option<Vec<JournalEntry>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_reader(std::fs::File::open(path)?)?))
+}
+
+/// Renames every file of the plan exactly as listed, marking each one done in the
+/// journal at `journal`, which is removed once all are. Nothing is renamed if [`check`]
+/// fails. With `dry_run` only the checks run and the renames are printed.
+pub fn apply(plan: &[PlanEntry], dry_run: bool, journal: &Path) -> Result<usize> {
+    check(plan)?;
+    let mut entries: Vec<JournalEntry> = plan
+        .iter()
+        .filter(|e| e.source != e.target)
+        .map(|e| JournalEntry {
+            source: e.source.clone(),
+            target: e.target.clone(),
+            done: false,
+        })
+        .collect();
+    if dry_run {
+        for entry in &entries {
+            println!("{}\t{}", entry.source.display(), entry.target.display());
+        }
+        return Ok(entries.len());
+    }
+    write_journal(journal, &entries)?;
+    finish(&mut entries, journal)
+}
+
+/// What resuming or rolling back a journal did.
+pub struct Recovery {
+    pub renamed: usize,
+    /// entries whose file has neither name of the journal, left alone
+    pub modified: Vec<PathBuf>,
+}
+
+/// Carries out the entries of the journal not done yet, marking each one done and
+/// removing the journal at the end. Returns how many files were renamed.
+fn finish(entries: &mut [JournalEntry], journal: &Path) -> Result<usize> {
+    let mut renamed = 0;
+    for index in 0..entries.len() {
+        let entry = &entries[index];
+        if entry.done {
+            continue;
+        }
+        println!("{}\t{}", entry.source.display(), entry.target.display());
+        if let Some(parent) = entry.target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&entry.source, &entry.target)?;
+        entries[index].done = true;
+        write_journal(journal, entries)?;
+        renamed += 1;
+    }
+    std::fs::remove_file(journal)?;
+    Ok(renamed)
+}
+
+/// Finishes the renames of an interrupted `apply-plan`. A rename that happened just
+/// before the interruption, with the file under its new name only, is counted as done;
+/// files under neither name, or under both, were changed by someone else since and are
+/// left alone.
+pub fn resume(journal: &Path) -> Result<Recovery> {
+    let mut entries = read_journal(journal)?.ok_or_else(|| anyhow!("no rename to resume"))?;
+    let mut modified = Vec::new();
+    for entry in entries.iter_mut().filter(|e| !e.done) {
+        match (entry.source.exists(), entry.target.exists()) {
+            (true, false) => {}
+            (false, true) => entry.done = true,
+            _ => {
+                modified.push(entry.source.clone());
+                entry.done = true;
+            }
+        }
+    }
+    let renamed = finish(&mut entries, journal)?;
+    Ok(Recovery { renamed, modified })
+}
+
+/// Undoes the renames an interrupted `apply-plan` made, last first, and removes the
+/// journal. Files no longer under their new name alone are left alone.
+pub fn rollback(journal: &Path) -> Result<Recovery> {
+    let entries = read_journal(journal)?.ok_or_else(|| anyhow!("no rename to roll back"))?;
+    let mut recovery = Recovery {
+        renamed: 0,
+        modified: Vec::new(),
+    };
+    for entry in entries.iter().rev() {
+        match (entry.source.exists(), entry.target.exists()) {
+            (false, true) => {}
+            (true, false) if !entry.done => continue,
+            _ => {
+                recovery.modified.push(entry.target.clone());
+                continue;
+            }
+        }
+        println!("{}\t{}", entry.target.display(), entry.source.display());
+        std::fs::rename(&entry.target, &entry.source)?;
+        recovery.renamed += 1;
+    }
+    std::fs::remove_file(journal)?;
+    Ok(recovery)
+}
@@ -0,0 +1,126 @@
+//! `post_edit_cmd`: a command fixing up the translations, e.g. the terminology of a field.
+//! It gets a translated segment, or with `post_edit_granularity = "document"` a whole
+//! output file once written, on stdin and its stdout replaces it. A command that fails,
+//! prints nothing or runs longer than `post_edit_timeout_secs` leaves the translation as
+//! it was, with a warning. Only the outputs are edited: the OCR cache holds no
+//! translations, so nothing edited ends up in a cache.
+
+use crate::stamp;
+use crate::TARGET_LANG;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    /// every translated paragraph or block
+    #[default]
+    Segment,
+    /// every output file
+    Document,
+}
+
+#[derive(Clone, Debug)]
+pub struct PostEdit {
+    /// program and arguments
+    command: Vec<String>,
+    pub granularity: Granularity,
+    timeout: Duration,
+    /// file being translated and its language, passed to the command
+    pub source: PathBuf,
+    pub source_lang: String,
+}
+
+impl PostEdit {
+    pub fn new(command: Vec<String>, granularity: Granularity, timeout: Duration) -> Self {
+        PostEdit {
+            command,
+            granularity,
+            timeout,
+            source: PathBuf::new(),
+            source_lang: String::new(),
+        }
+    }
+
+    /// `text` of a whole `output` as the command prints it, the stamp of `--stamp-outputs`
+    /// at its top left as it is.
+    pub fn edit_document(&self, text: &str, output: &Path) -> String {
+        // the stamp isn't part of the translation
+        let body = stamp::strip(text);
+        let stamp = &text[..text.len() - body.len()];
+        format!("{}{}", stamp, self.edit(body, output, None))
+    }
+
+    /// `text` of `output`, on `page` for a segment, as the command prints it.
+    pub fn edit(&self, text: &str, output: &Path, page: Option<usize>) -> String {
+        let mut env = vec![
+            ("POST_EDIT_SOURCE", self.source.display().to_string()),
+            ("POST_EDIT_OUTPUT", output.display().to_string()),
+            ("POST_EDIT_SOURCE_LANG", self.source_lang.clone()),
+            ("POST_EDIT_TARGET_LANG", TARGET_LANG.as_code().to_string()),
+        ];
+        if let Some(page) = page {
+            env.push(("POST_EDIT_PAGE", page.to_string()));
+        }
+        match self.run(text, &env) {
+            Ok(edited) => edited,
+            Err(e) => {
+                eprintln!(
+                    "{}: post_edit_cmd {}, translation kept",
+                    output.display(),
+                    e
+                );
+                text.to_string()
+            }
+        }
+    }
+
+    fn run(&self, text: &str, env: &[(&str, String)]) -> Result<String> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("is empty"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("{}: {}", program, e))?;
+        // written and read on threads so a command answering early can't block either
+        let mut stdin = child.stdin.take().unwrap();
+        let input = text.to_string();
+        std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!("timed out after {} s", self.timeout.as_secs()));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(anyhow!("failed with {}", status));
+        }
+        let output = String::from_utf8_lossy(&reader.join().unwrap()?).into_owned();
+        if output.trim().is_empty() && !text.trim().is_empty() {
+            return Err(anyhow!("printed nothing"));
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,105 @@
+//! Language presets for `--preset`: the tesseract language, the language code of the
+//! server, the script the filters count letters of and the abbreviations that don't end a
+//! sentence, for one source language. More can be added as `[presets.<name>]` in the
+//! config file, a preset of the same name as a built-in one replaces it.
+
+use crate::filter::Script;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Used without `--preset`.
+pub const DEFAULT: &str = "russian";
+
+/// Name, language code, tesseract language and abbreviations of the built-in presets, all
+/// written in Cyrillic.
+const BUILTIN: [(&str, &str, &str, &[&str]); 4] = [
+    (
+        "russian",
+        "ru",
+        "rus",
+        &[
+            "г", "гг", "ул", "д", "им", "см", "стр", "т", "е", "п", "пп", "др", "пр", "тыс", "млн",
+            "млрд", "руб", "коп", "обл", "проф", "акад", "ст", "рис", "табл",
+        ],
+    ),
+    (
+        "ukrainian",
+        "uk",
+        "ukr",
+        &[
+            "р", "рр", "вул", "буд", "ім", "див", "с", "т", "ін", "п", "пп", "тис", "млн", "млрд",
+            "грн", "коп", "обл", "проф", "акад", "ст", "рис", "табл",
+        ],
+    ),
+    (
+        "belarusian",
+        "be",
+        "bel",
+        &[
+            "г", "гг", "вул", "д", "імя", "гл", "с", "ст", "і", "інш", "п", "тыс", "млн", "млрд",
+            "руб", "кап", "вобл", "праф", "акад", "рыс", "табл",
+        ],
+    ),
+    (
+        "kazakh",
+        "kk",
+        "kaz",
+        &[
+            "ж", "жж", "көш", "үй", "б", "т", "мыс", "тағы", "млн", "млрд", "обл", "проф", "акад",
+            "табл",
+        ],
+    ),
+];
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// language code the server translates from
+    pub source_lang: String,
+    /// tesseract language, several joined by `+`
+    pub ocr_lang: String,
+    /// script of the language, for the filters and guardrails
+    pub script: Script,
+    /// words that don't end a sentence when followed by a period, without the period
+    #[serde(default)]
+    pub abbreviations: Vec<String>,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        all(&BTreeMap::new()).remove(DEFAULT).unwrap()
+    }
+}
+
+/// The built-in presets with `custom` ones of the config file added or replacing them.
+pub fn all(custom: &BTreeMap<String, Preset>) -> BTreeMap<String, Preset> {
+    let mut presets: BTreeMap<String, Preset> = BUILTIN
+        .iter()
+        .map(|&(name, source_lang, ocr_lang, abbreviations)| {
+            let preset = Preset {
+                source_lang: source_lang.to_string(),
+                ocr_lang: ocr_lang.to_string(),
+                script: Script::Cyrillic,
+                abbreviations: abbreviations.iter().map(|a| a.to_string()).collect(),
+            };
+            (name.to_string(), preset)
+        })
+        .collect();
+    presets.extend(custom.clone());
+    presets
+}
+
+/// The tesseract languages of `preset` missing from `tessdata`.
+pub fn missing_traineddata(preset: &Preset, tessdata: &str) -> Vec<String> {
+    preset
+        .ocr_lang
+        .split('+')
+        .filter(|lang| {
+            !Path::new(tessdata)
+                .join(format!("{}.traineddata", lang))
+                .exists()
+        })
+        .map(String::from)
+        .collect()
+}
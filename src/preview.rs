@@ -0,0 +1,36 @@
+//! `--preview`: a quick look at the OCR and translation of a source dir before the full
+//! run. Only a few pages of every PDF, the first one and others drawn with `--seed`, and
+//! the first paragraph of every docx file and email are translated, into `_preview/`
+//! below the target dir. No manifest is written, a later full run handles every file.
+
+use crate::ocr::{fnv1a, FNV_OFFSET};
+use std::path::Path;
+
+/// Directory below the target dir the previews go into.
+pub const PREVIEW_DIR: &str = "_preview";
+
+#[derive(Clone, Copy, Debug)]
+pub struct Preview {
+    /// pages translated of every PDF, the first one included
+    pages: usize,
+    seed: u64,
+}
+
+impl Preview {
+    pub fn new(pages: usize, seed: u64) -> Self {
+        Preview { pages, seed }
+    }
+
+    /// Indices of the pages of `file`, which has `count`, to translate in order. The
+    /// same seed draws the same pages of a file of the same name.
+    pub fn pages(&self, file: &Path, count: usize) -> Vec<usize> {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let hash = fnv1a(fnv1a(FNV_OFFSET, &self.seed.to_le_bytes()), name.as_bytes());
+        let mut others: Vec<usize> = (1..count).collect();
+        others.sort_by_key(|index| fnv1a(hash, &index.to_le_bytes()));
+        others.truncate(self.pages.saturating_sub(1));
+        let mut pages: Vec<usize> = (0..count.min(1)).chain(others).collect();
+        pages.sort();
+        pages
+    }
+}
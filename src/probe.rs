@@ -0,0 +1,72 @@
+//! Cheap look at a source file without OCR or translation, shared by `--dry-run` and
+//! `stats`.
+
+use crate::docx_tables;
+use docx_rust::DocxFile;
+use pdfium_render::prelude::*;
+use std::path::Path;
+
+/// Rough number of characters on a scanned page, used to estimate runs without OCR.
+pub const ESTIMATED_CHARS_PER_PAGE: usize = 1800;
+/// Characters the first page needs in its text layer for a PDF to count as not scanned.
+const TEXT_LAYER_MIN_CHARS: usize = 20;
+
+pub struct PdfProbe {
+    pub pages: usize,
+    /// the first page has a text layer, so the PDF was likely not scanned
+    pub text_layer: bool,
+}
+
+/// Page count and text layer of a PDF, `None` if pdfium can't open it.
+pub fn pdf(path: &Path, pdfium: &Pdfium) -> Option<PdfProbe> {
+    let document = pdfium.load_pdf_from_file(path, None).ok()?;
+    let pages = document.pages();
+    let text_layer = pages
+        .get(0)
+        .ok()
+        .and_then(|page| page.text().ok().map(|text| text.all()))
+        .is_some_and(|text| text.trim().chars().count() >= TEXT_LAYER_MIN_CHARS);
+    Some(PdfProbe {
+        pages: pages.len() as usize,
+        text_layer,
+    })
+}
+
+/// Characters in the body and tables of a docx file, 0 if it can't be read.
+pub fn docx_chars(path: &Path) -> usize {
+    let body = path
+        .to_str()
+        .and_then(|p| DocxFile::from_file(p).ok())
+        .and_then(|f| {
+            f.parse()
+                .ok()
+                .map(|d| d.document.body.text().chars().count())
+        })
+        .unwrap_or(0);
+    let tables: usize = docx_tables::read_tables(path)
+        .map(|tables| {
+            tables
+                .iter()
+                .flat_map(|t| t.rows.iter().flatten())
+                .map(|c| c.text.chars().count())
+                .sum()
+        })
+        .unwrap_or(0);
+    body + tables
+}
+
+/// Guesses how many characters a file will send to the backend, without doing any OCR.
+pub fn estimate_chars(path: &Path, action: &str, pdfium: Option<&Pdfium>) -> usize {
+    match action {
+        "filename" => path.to_string_lossy().chars().count(),
+        "docx" => docx_chars(path),
+        "pdf" => {
+            let pages = pdfium
+                .and_then(|p| pdf(path, p))
+                .map(|p| p.pages)
+                .unwrap_or(1);
+            pages * ESTIMATED_CHARS_PER_PAGE
+        }
+        _ => ESTIMATED_CHARS_PER_PAGE,
+    }
+}
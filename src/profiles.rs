@@ -0,0 +1,187 @@
+//! Profiles: scans, forms, born-digital documents and photos want different settings.
+//! Each file is classified from a cheap look at it, before any OCR, and the
+//! `[profiles.<name>]` table of the config file for its profile is applied under the
+//! directory overrides. `--profile` puts every file in one profile.
+
+use crate::probe;
+use clap::ValueEnum;
+use image::{DynamicImage, GrayImage};
+use pdfium_render::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Longest side in pixels of the image the traits of a scan are measured on.
+const SAMPLE_SIZE: u32 = 256;
+/// Luma below which a pixel counts as ink.
+const INK_LUMA: u8 = 128;
+/// Luma range of the mid-tones, which scans of paper have few of and photos many.
+const MIDTONES: std::ops::Range<u8> = 64..192;
+/// Share of mid-tone pixels from which an image is a photo.
+const PHOTO_MIDTONES: f32 = 0.35;
+/// Share of mid-tone pixels from which a landscape image is a photo.
+const LANDSCAPE_PHOTO_MIDTONES: f32 = 0.2;
+/// Share of ink below which a scan is a sparsely filled form rather than a text page.
+const FORM_INK: f32 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// scanned pages full of text
+    ScanDense,
+    /// scans with little text in fields and boxes
+    ScanForm,
+    /// documents with a text layer, docx files and emails
+    DigitalText,
+    /// photos, of documents or not
+    Photo,
+}
+
+/// What a file is classified by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Traits {
+    pub width: u32,
+    pub height: u32,
+    /// text comes without OCR
+    pub text_layer: bool,
+    /// share of dark pixels
+    pub ink: f32,
+    /// share of mid-tone pixels
+    pub midtones: f32,
+}
+
+/// The profile of a file with `traits`.
+pub fn classify(traits: &Traits) -> Profile {
+    let landscape = traits.width > traits.height;
+    if traits.text_layer {
+        Profile::DigitalText
+    } else if traits.midtones >= PHOTO_MIDTONES
+        || (landscape && traits.midtones >= LANDSCAPE_PHOTO_MIDTONES)
+    {
+        Profile::Photo
+    } else if traits.ink < FORM_INK {
+        Profile::ScanForm
+    } else {
+        Profile::ScanDense
+    }
+}
+
+/// The profile of the file at `path` going to `handler`, from the first page of a PDF;
+/// `None` if it can't be read, the handler reports why.
+pub fn detect(path: &Path, handler: &str, pdfium: Option<&Pdfium>) -> Option<Profile> {
+    let image = match handler {
+        "image" => image::open(path).ok()?,
+        "pdf" => {
+            let pdfium = pdfium?;
+            if probe::pdf(path, pdfium)?.text_layer {
+                return Some(Profile::DigitalText);
+            }
+            let document = pdfium.load_pdf_from_file(path, None).ok()?;
+            let page = document.pages().get(0).ok()?;
+            let config = PdfRenderConfig::new()
+                .set_target_width(SAMPLE_SIZE as i32)
+                .set_maximum_height(SAMPLE_SIZE as i32);
+            let image = page.render_with_config(&config).ok()?.as_image();
+            image
+        }
+        _ => return Some(Profile::DigitalText),
+    };
+    Some(classify(&traits(&image)))
+}
+
+/// Traits of a scan or photo without a text layer.
+fn traits(image: &DynamicImage) -> Traits {
+    let sample = image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE).to_luma8();
+    let pixels = sample.pixels().count().max(1) as f32;
+    let midtones = sample
+        .pixels()
+        .filter(|p| MIDTONES.contains(&p.0[0]))
+        .count() as f32;
+    Traits {
+        width: image.width(),
+        height: image.height(),
+        text_layer: false,
+        ink: ink(&sample),
+        midtones: midtones / pixels,
+    }
+}
+
+/// Share of dark pixels of `image`.
+pub fn ink(image: &GrayImage) -> f32 {
+    let pixels = image.pixels().count().max(1) as f32;
+    image.pixels().filter(|p| p.0[0] < INK_LUMA).count() as f32 / pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    const PAGE: Traits = Traits {
+        width: 2480,
+        height: 3508,
+        text_layer: false,
+        ink: 0.12,
+        midtones: 0.05,
+    };
+
+    /// A white A4-shaped image with `inked` of every 100 rows black.
+    fn page(inked: u32) -> DynamicImage {
+        let image = GrayImage::from_fn(210, 297, |_, y| {
+            Luma([if y % 100 < inked { 0 } else { 255 }])
+        });
+        DynamicImage::ImageLuma8(image)
+    }
+
+    #[test]
+    fn files_are_classified_by_their_traits() {
+        assert_eq!(classify(&PAGE), Profile::ScanDense);
+        let form = Traits { ink: 0.02, ..PAGE };
+        assert_eq!(classify(&form), Profile::ScanForm);
+        let photo = Traits {
+            midtones: 0.5,
+            ..PAGE
+        };
+        assert_eq!(classify(&photo), Profile::Photo);
+        let digital = Traits {
+            text_layer: true,
+            midtones: 0.5,
+            ..PAGE
+        };
+        assert_eq!(classify(&digital), Profile::DigitalText);
+    }
+
+    #[test]
+    fn landscape_images_are_photos_with_fewer_midtones() {
+        let portrait = Traits {
+            midtones: 0.25,
+            ..PAGE
+        };
+        assert_eq!(classify(&portrait), Profile::ScanDense);
+        let landscape = Traits {
+            width: PAGE.height,
+            height: PAGE.width,
+            ..portrait
+        };
+        assert_eq!(classify(&landscape), Profile::Photo);
+    }
+
+    #[test]
+    fn traits_are_measured_on_the_image() {
+        let dense = traits(&page(20));
+        assert!((dense.ink - 0.2).abs() < 0.03, "{:?}", dense);
+        assert_eq!(dense.midtones, 0.0);
+        assert_eq!((dense.width, dense.height), (210, 297));
+        assert_eq!(classify(&dense), Profile::ScanDense);
+        assert_eq!(classify(&traits(&page(2))), Profile::ScanForm);
+        let gradient = GrayImage::from_fn(300, 200, |x, _| Luma([(x * 255 / 299) as u8]));
+        let photo = traits(&DynamicImage::ImageLuma8(gradient));
+        assert_eq!(classify(&photo), Profile::Photo);
+    }
+
+    #[test]
+    fn files_without_pages_are_digital_text() {
+        let path = Path::new("memo.docx");
+        assert_eq!(detect(path, "docx", None), Some(Profile::DigitalText));
+        assert_eq!(detect(Path::new("scan.pdf"), "pdf", None), None);
+    }
+}
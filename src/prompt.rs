@@ -0,0 +1,92 @@
+//! Confirmation prompts of `--interactive`. Only `main` asks; the handlers and the plan
+//! code never read from stdin.
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Items listed before the question in `preview` mode.
+const PREVIEW_ITEMS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Interactive {
+    /// list the first items and ask once for all of them
+    Preview,
+    /// ask for every item: y(es), n(o), a(ll remaining), q(uit)
+    Each,
+}
+
+/// Fails unless stdin and stderr are a terminal, so `--interactive` never waits on a pipe.
+pub fn ensure_terminal() -> Result<()> {
+    if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "--interactive needs a terminal, run without it to proceed unattended"
+        ))
+    }
+}
+
+/// Asks `question` and returns the trimmed, lowercase answer, `None` at the end of input.
+fn ask(question: &str) -> Result<Option<String>> {
+    eprint!("{} ", question);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    if std::io::stdin().lock().read_line(&mut line)? == 0 {
+        eprintln!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_lowercase()))
+}
+
+/// Asks whether to `action` each of `items`, returning one decision per item.
+pub fn confirm(mode: Interactive, action: &str, items: &[String]) -> Result<Vec<bool>> {
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    match mode {
+        Interactive::Preview => {
+            for item in items.iter().take(PREVIEW_ITEMS) {
+                eprintln!("  {}", item);
+            }
+            if items.len() > PREVIEW_ITEMS {
+                eprintln!("  ... and {} more", items.len() - PREVIEW_ITEMS);
+            }
+            let question = format!("{} {} files? [y/N]", action, items.len());
+            let yes = matches!(ask(&question)?.as_deref(), Some("y" | "yes"));
+            Ok(vec![yes; items.len()])
+        }
+        Interactive::Each => {
+            let mut decisions = Vec::with_capacity(items.len());
+            for (index, item) in items.iter().enumerate() {
+                let decision = loop {
+                    let question = format!(
+                        "({}/{}) {} {}? [y,n,a,q]",
+                        index + 1,
+                        items.len(),
+                        action,
+                        item
+                    );
+                    match ask(&question)?.as_deref() {
+                        Some("y") => break Some(true),
+                        Some("n") => break Some(false),
+                        Some("a") => break None,
+                        Some("q") | None => {
+                            decisions.resize(items.len(), false);
+                            return Ok(decisions);
+                        }
+                        Some(_) => eprintln!("y: yes, n: no, a: this and all remaining, q: quit"),
+                    }
+                };
+                match decision {
+                    Some(yes) => decisions.push(yes),
+                    None => {
+                        decisions.resize(items.len(), true);
+                        return Ok(decisions);
+                    }
+                }
+            }
+            Ok(decisions)
+        }
+    }
+}
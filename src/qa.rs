@@ -0,0 +1,75 @@
+//! Back-translation spot checks for `--back-translate-sample`. Sampled segments are
+//! translated back into the source language and written next to the original and the
+//! translation to `qa-sample.jsonl` in the target dir, for a reviewer to skim.
+
+use crate::ocr::{fnv1a, FNV_OFFSET};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const QA_FILE: &str = "qa-sample.jsonl";
+
+#[derive(Serialize)]
+struct Sample<'a> {
+    file: &'a Path,
+    original: &'a str,
+    translation: &'a str,
+    back_translation: &'a str,
+}
+
+pub struct QaSampler {
+    /// share of segments sampled, 0 to 1
+    rate: f64,
+    seed: u64,
+    out: File,
+    /// source file the next segments belong to
+    pub file: PathBuf,
+    pub sampled: usize,
+}
+
+impl QaSampler {
+    pub fn new(target_dir: &Path, rate: f64, seed: u64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(anyhow!(
+                "--back-translate-sample must be between 0 and 1, got {}",
+                rate
+            ));
+        }
+        std::fs::create_dir_all(target_dir)?;
+        Ok(QaSampler {
+            rate,
+            seed,
+            out: File::create(target_dir.join(QA_FILE))?,
+            file: PathBuf::new(),
+            sampled: 0,
+        })
+    }
+
+    /// Whether `text` is sampled. Depends only on the seed and the text, so a run with the
+    /// same seed samples the same segments.
+    pub fn picks(&self, text: &str) -> bool {
+        let hash = fnv1a(fnv1a(FNV_OFFSET, &self.seed.to_le_bytes()), text.as_bytes());
+        (hash as f64 / u64::MAX as f64) < self.rate
+    }
+
+    pub fn record(
+        &mut self,
+        original: &str,
+        translation: &str,
+        back_translation: &str,
+    ) -> Result<()> {
+        let sample = Sample {
+            file: &self.file,
+            original: original.trim(),
+            translation: translation.trim(),
+            back_translation: back_translation.trim(),
+        };
+        let mut line = serde_json::to_string(&sample)?;
+        line.push('\n');
+        self.out.write_all(line.as_bytes())?;
+        self.sampled += 1;
+        Ok(())
+    }
+}
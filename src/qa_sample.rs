@@ -0,0 +1,144 @@
+//! `--qa-sample`: a share of the translated segments of the whole run, drawn uniformly
+//! over all files with `--seed`, written to `qa/<run id>.csv` in the target dir for a
+//! human spot check. Every segment gets a random key and the run keeps those with the
+//! lowest keys; as the share of a stream of unknown length only settles at its end, twice
+//! as many candidates as samples so far are kept, which bounds the memory without
+//! favouring the early files.
+
+use crate::output::{Origin, Segment};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BinaryHeap;
+use std::path::{Path, PathBuf};
+
+pub const QA_DIR: &str = "qa";
+
+/// Candidates kept beyond twice the samples, so a short run has some to choose from.
+const SPARE_CANDIDATES: usize = 16;
+
+#[derive(Serialize)]
+struct Row {
+    source: PathBuf,
+    /// `p3 b7` for a block of a scan, `para 17` for a docx paragraph
+    position: String,
+    source_text: String,
+    translation: String,
+    confidence: Option<i32>,
+    /// server that translated it, empty for segments copied without a request
+    backend: String,
+}
+
+/// A row with its key, ordered by the key and then by its place in the run.
+struct Candidate {
+    key: u64,
+    number: u64,
+    row: Row,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        (self.key, self.number) == (other.key, other.number)
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.key, self.number).cmp(&(other.key, other.number))
+    }
+}
+
+pub struct QaSample {
+    /// share of segments sampled, 0 to 1
+    rate: f64,
+    seed: u64,
+    /// segments offered so far
+    seen: u64,
+    /// the lowest keys so far, the highest on top
+    candidates: BinaryHeap<Candidate>,
+    path: PathBuf,
+    /// source file the next segments belong to
+    pub file: PathBuf,
+}
+
+/// SplitMix64 of `seed` and `n`, an evenly spread key per segment.
+fn key(seed: u64, n: u64) -> u64 {
+    let mut z = seed ^ n.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl QaSample {
+    pub fn new(target_dir: &Path, rate: f64, seed: u64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(anyhow!("--qa-sample must be between 0 and 1, got {}", rate));
+        }
+        let run = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        Ok(QaSample {
+            rate,
+            seed,
+            seen: 0,
+            candidates: BinaryHeap::new(),
+            path: target_dir.join(QA_DIR).join(format!("{}.csv", run)),
+            file: PathBuf::new(),
+        })
+    }
+
+    /// Samples of a run of `seen` segments.
+    fn samples(&self, seen: u64) -> usize {
+        (self.rate * seen as f64).ceil() as usize
+    }
+
+    pub fn offer(&mut self, segment: &Segment, translation: &str, backend: &str) {
+        self.seen += 1;
+        let (position, confidence) = match segment.origin {
+            Some(Origin::Block {
+                page,
+                block,
+                confidence,
+                ..
+            }) => (format!("p{} b{}", page, block), Some(confidence)),
+            Some(Origin::Paragraph { paragraph }) => (format!("para {}", paragraph), None),
+            None => (String::new(), None),
+        };
+        self.candidates.push(Candidate {
+            key: key(self.seed, self.seen),
+            number: self.seen,
+            row: Row {
+                source: self.file.clone(),
+                position,
+                source_text: segment.text.trim().to_string(),
+                translation: translation.trim().to_string(),
+                confidence,
+                backend: backend.to_string(),
+            },
+        });
+        let keep = 2 * self.samples(self.seen) + SPARE_CANDIDATES;
+        while self.candidates.len() > keep {
+            self.candidates.pop();
+        }
+    }
+
+    /// Writes the samples in the order of the run, returning how many and where.
+    pub fn write(self) -> Result<(usize, PathBuf)> {
+        let samples = self.samples(self.seen);
+        let mut rows = self.candidates.into_sorted_vec();
+        rows.truncate(samples);
+        rows.sort_by_key(|candidate| candidate.number);
+        std::fs::create_dir_all(self.path.parent().unwrap())?;
+        let mut out = csv::Writer::from_path(&self.path)?;
+        for candidate in &rows {
+            out.serialize(&candidate.row)?;
+        }
+        out.flush()?;
+        Ok((rows.len(), self.path))
+    }
+}
@@ -0,0 +1,99 @@
+//! Joins the hard line breaks tesseract puts at the physical line ends of a scan back into
+//! paragraphs, so the backend gets whole sentences instead of line fragments.
+
+const HYPHENS: [char; 3] = ['-', '\u{00AD}', '\u{2010}'];
+
+/// Reflows an OCR block: lines are joined with a space, blank lines stay paragraph breaks
+/// and words hyphenated at a line end are merged back together.
+pub fn reflow(text: &str) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if current.is_empty() {
+            current.push_str(line);
+        } else if let Some(head) = hyphenated_head(&current, line) {
+            current.truncate(head);
+            current.push_str(line);
+        } else if ends_with_attached_hyphen(&current) {
+            current.push_str(line);
+        } else {
+            current.push(' ');
+            current.push_str(line);
+        }
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+    paragraphs.join("\n\n")
+}
+
+/// A hyphen glued to the word before it joins a compound or range, so the next line
+/// continues without a space ("1941-1945", "Северо-Запад").
+fn ends_with_attached_hyphen(current: &str) -> bool {
+    let mut chars = current.chars().rev();
+    matches!(chars.next(), Some(c) if HYPHENS.contains(&c))
+        && matches!(chars.next(), Some(c) if !c.is_whitespace())
+}
+
+/// If `current` ends in a word broken by a hyphen that `next` continues, returns the
+/// length of `current` without the hyphen.
+///
+/// Only merges when the fragment before the hyphen is all letters and the next line starts
+/// with a lowercase letter, so ranges like "1941-\n1945" and compounds continuing with a
+/// capital ("Северо-\nЗапад") keep their hyphen.
+fn hyphenated_head(current: &str, next: &str) -> Option<usize> {
+    let hyphen = current.chars().last().filter(|c| HYPHENS.contains(c))?;
+    let head = &current[..current.len() - hyphen.len_utf8()];
+    let fragment = head.rsplit(char::is_whitespace).next().unwrap_or_default();
+    if fragment.is_empty() || !fragment.chars().all(char::is_alphabetic) {
+        return None;
+    }
+    if !next.chars().next()?.is_lowercase() {
+        return None;
+    }
+    Some(head.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_joined_into_paragraphs() {
+        assert_eq!(
+            reflow("Первая строка\n  вторая строка \n\n\nНовый абзац"),
+            "Первая строка вторая строка\n\nНовый абзац"
+        );
+    }
+
+    #[test]
+    fn words_broken_at_a_line_end_are_merged() {
+        assert_eq!(reflow("это пере-\nвод текста"), "это перевод текста");
+        assert_eq!(reflow("пере\u{00AD}\nвод"), "перевод");
+        assert_eq!(reflow("пере\u{2010}\nвод"), "перевод");
+    }
+
+    #[test]
+    fn ranges_and_compounds_keep_their_hyphen() {
+        assert_eq!(reflow("в 1941-\n1945 годах"), "в 1941-1945 годах");
+        assert_eq!(reflow("Северо-\nЗапад"), "Северо-Запад");
+        assert_eq!(reflow("СУ27-\nмодель"), "СУ27-модель");
+    }
+
+    #[test]
+    fn a_dash_between_words_stays_one() {
+        assert_eq!(reflow("слово -\nдругое"), "слово - другое");
+        assert_eq!(reflow("-\nначало"), "- начало");
+    }
+
+    #[test]
+    fn a_hyphen_before_a_blank_line_is_kept() {
+        assert_eq!(reflow("пере-\n\nвод"), "пере-\n\nвод");
+    }
+}
@@ -0,0 +1,212 @@
+//! `--render-workers`: the pages of a scan rendered by more than one process. pdfium-render
+//! serializes every call to the library behind one lock per process, so threads can't
+//! render in parallel; each worker is this binary again, run with the hidden `render-pages`
+//! command. A worker reads page indexes from its stdin, writes each page into the file's
+//! dir of the workspace as a PNG and answers with a line `ok <index>` or
+//! `failed <index> <reason>`. Pages are handed out in turn as the OCR queue takes them, so
+//! rendering runs as far ahead of OCR as it does without workers.
+
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// How the workers of a run are started.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub workers: usize,
+    /// `--config` of the run, which the workers load for `pdfium_path`
+    pub config: Option<PathBuf>,
+}
+
+/// Why a worker has no render of a page. The page is reported as failed to render, as
+/// when pdfium fails on it in this process.
+#[derive(Debug)]
+pub struct RenderFailed(pub String);
+
+impl fmt::Display for RenderFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RenderFailed {}
+
+/// Renders answered by the workers, by page index: the PNG or why there is none.
+#[derive(Default)]
+struct Answers {
+    pages: Mutex<HashMap<u16, Result<PathBuf, String>>>,
+    changed: Condvar,
+}
+
+impl Answers {
+    fn answer(&self, index: u16, answer: Result<PathBuf, String>) {
+        self.pages.lock().unwrap().insert(index, answer);
+        self.changed.notify_all();
+    }
+}
+
+/// The render of a page handed to a worker.
+pub struct Render {
+    index: u16,
+    answers: Arc<Answers>,
+}
+
+impl Render {
+    /// Waits for the worker and reads the page. Blocks, called from the OCR pool.
+    pub fn wait(&self) -> Result<DynamicImage> {
+        let mut pages = self.answers.pages.lock().unwrap();
+        let answer = loop {
+            match pages.get(&self.index) {
+                Some(answer) => break answer.clone(),
+                None => pages = self.answers.changed.wait(pages).unwrap(),
+            }
+        };
+        drop(pages);
+        let path = answer.map_err(RenderFailed)?;
+        image::open(&path).map_err(|e| RenderFailed(format!("{}: {}", path.display(), e)).into())
+    }
+}
+
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    /// pages handed to it, failed if it exits before answering
+    pages: Arc<Mutex<Vec<(u16, PathBuf)>>>,
+}
+
+/// The workers rendering the pages of one pdf, started with the first page asked for.
+/// Dropping them ends the workers and removes the renders unless they are kept.
+pub struct RenderWorkers {
+    settings: RenderSettings,
+    file: PathBuf,
+    legacy_names: bool,
+    dir: PathBuf,
+    keep: bool,
+    workers: Vec<Worker>,
+    next: usize,
+    answers: Arc<Answers>,
+}
+
+impl RenderWorkers {
+    /// Workers for `file` writing into `dir`, kept after the file with `keep`.
+    pub fn new(
+        settings: &RenderSettings,
+        file: &Path,
+        legacy_names: bool,
+        dir: PathBuf,
+        keep: bool,
+    ) -> Self {
+        RenderWorkers {
+            settings: settings.clone(),
+            file: file.to_path_buf(),
+            legacy_names,
+            dir,
+            keep,
+            workers: Vec::new(),
+            next: 0,
+            answers: Arc::default(),
+        }
+    }
+
+    /// Hands page `index` to the next worker, to be written to `name` in the dir.
+    pub fn request(&mut self, index: u16, name: &str) -> Result<Render> {
+        if self.workers.is_empty() {
+            for _ in 0..self.settings.workers {
+                let worker = self.start()?;
+                self.workers.push(worker);
+            }
+        }
+        let turn = self.next % self.workers.len();
+        let worker = &mut self.workers[turn];
+        self.next += 1;
+        let path = self.dir.join(name);
+        worker.pages.lock().unwrap().push((index, path));
+        if let Err(e) = writeln!(worker.stdin, "{}", index) {
+            self.answers
+                .answer(index, Err(format!("render worker gone: {}", e)));
+        }
+        Ok(Render {
+            index,
+            answers: self.answers.clone(),
+        })
+    }
+
+    fn start(&self) -> Result<Worker> {
+        let mut command = Command::new(std::env::current_exe()?);
+        if let Some(config) = &self.settings.config {
+            command.arg("--config").arg(config);
+        }
+        // the source dir is required, not used
+        let parent = self.file.parent().unwrap_or(Path::new("."));
+        command.arg("--source-dir").arg(parent);
+        command.arg("render-pages").arg(&self.file).arg(&self.dir);
+        if self.legacy_names {
+            command.arg("--legacy-names");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("could not start a render worker: {}", e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let pages: Arc<Mutex<Vec<(u16, PathBuf)>>> = Arc::default();
+        let (answers, handed) = (self.answers.clone(), pages.clone());
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let mut words = line.splitn(3, ' ');
+                let (Some(kind), Some(Ok(index))) = (words.next(), words.next().map(str::parse))
+                else {
+                    continue;
+                };
+                let path = handed
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(i, _)| *i == index)
+                    .cloned();
+                match (kind, path) {
+                    ("ok", Some((_, path))) => answers.answer(index, Ok(path)),
+                    _ => answers.answer(index, Err(words.next().unwrap_or_default().to_string())),
+                }
+            }
+            // the worker is done or went down, the pages it didn't answer get no render
+            let pending: Vec<u16> = {
+                let pages = answers.pages.lock().unwrap();
+                let handed = handed.lock().unwrap();
+                handed
+                    .iter()
+                    .map(|(index, _)| *index)
+                    .filter(|index| !pages.contains_key(index))
+                    .collect()
+            };
+            for index in pending {
+                answers.answer(index, Err("the render worker exited".to_string()));
+            }
+        });
+        Ok(Worker {
+            child,
+            stdin,
+            pages,
+        })
+    }
+}
+
+impl Drop for RenderWorkers {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            let mut child = worker.child;
+            // pages rendered ahead of an error aren't wanted anymore
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! `retry-failures` subcommand: translates the segments recorded as failed in a run report
+//! again and replaces their markers in the outputs, without extracting anything again.
+//! A `post_edit_cmd` for segments edits them as a run would.
+
+use crate::post_edit::{Granularity, PostEdit};
+use crate::translator::Translator;
+use crate::Report;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub async fn run(
+    translator: &mut Translator,
+    report_path: &Path,
+    mut post_edit: Option<PostEdit>,
+) -> Result<()> {
+    post_edit = post_edit.filter(|p| p.granularity == Granularity::Segment);
+    let mut report = Report::read(report_path)?;
+    let format = report.format.clone();
+    let mut retried = 0;
+    let mut remaining = 0;
+    let source_lang = translator.backend.source_lang.clone();
+    for file in &mut report.files {
+        if file.failures.is_empty() {
+            continue;
+        }
+        translator.backend.source_lang = file
+            .settings
+            .as_ref()
+            .map(|s| s.source_lang.clone())
+            .unwrap_or_else(|| source_lang.clone());
+        if let Some(post_edit) = &mut post_edit {
+            post_edit.source = file.source.clone();
+            post_edit.source_lang = translator.backend.source_lang.clone();
+        }
+        // translate first, then patch each output once
+        let mut patches: BTreeMap<PathBuf, Vec<(String, String)>> = BTreeMap::new();
+        let mut failures = Vec::new();
+        for failure in std::mem::take(&mut file.failures) {
+            match translator.backend.translate(&failure.source).await {
+                Ok(translated) => patches
+                    .entry(failure.output.clone())
+                    .or_default()
+                    .push((failure.marker, translated)),
+                Err(e) => {
+                    eprintln!("{}: {}: {}", failure.output.display(), failure.marker, e);
+                    failures.push(failure);
+                }
+            }
+        }
+        for (output, replacements) in patches {
+            let mut text = format.decode(&std::fs::read(&output)?);
+            for (marker, translated) in &replacements {
+                let translated = match &post_edit {
+                    Some(post_edit) => post_edit.edit(translated, &output, None),
+                    None => translated.clone(),
+                };
+                text = text.replacen(marker, translated.trim_end(), 1);
+            }
+            std::fs::write(&output, format.encode_file(&text))?;
+            retried += replacements.len();
+        }
+        remaining += failures.len();
+        file.failures = failures;
+    }
+    report.summary.failed_segments = remaining;
+    report.summary.characters += translator.backend.usage.sent;
+    report.summary.suspect_segments += translator.backend.suspect;
+    report.write(report_path)?;
+    if let Some(markers) = &report.markers {
+        markers.refresh(&report.files)?;
+    }
+    eprintln!(
+        "{} segments translated, {} still failing",
+        retried, remaining
+    );
+    Ok(())
+}
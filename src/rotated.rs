@@ -0,0 +1,97 @@
+//! `--detect-rotated-text`: stamps, margin notes and table headers turned by 90° are
+//! read as noise or not at all by the page pass. Blocks tesseract isn't confident about,
+//! and the non-text blocks it found, are cut out and recognized again turned both ways;
+//! a turned reading that is clearly better takes the block out of the reading order of
+//! the page, it is written after the page's text under its own heading.
+
+use crate::ocr::OcrBlock;
+use crate::orientation::{png_bytes, rotate};
+use anyhow::Result;
+use image::DynamicImage;
+
+/// Blocks recognized at least this confidently are left as they are.
+const MAX_CONFIDENCE: i32 = 60;
+/// Least confidence of a turned reading to be taken.
+const MIN_CONFIDENCE: i32 = 70;
+/// Regions with a side shorter than this many pixels hold no readable line.
+const MIN_SIDE: i32 = 20;
+/// Fewest letters a turned reading needs, so a turned line or frame doesn't count.
+const MIN_LETTERS: usize = 3;
+
+/// Heading of the rotated text of a page in the outputs.
+pub const HEADING: &str = "## Rotated text";
+
+/// Recognizes the unclear blocks of `segments` and the regions of `page` without text
+/// turned by 90° both ways. Blocks read better turned are removed from `segments`; the
+/// turned readings are returned top to bottom, with their boxes on `page`.
+pub fn recover(
+    lt: &mut leptess::LepTess,
+    page: &DynamicImage,
+    segments: &mut Vec<OcrBlock>,
+) -> Result<Vec<OcrBlock>> {
+    lt.set_image_from_mem(&png_bytes(page)?)?;
+    let mut candidates: Vec<[i32; 4]> = Vec::new();
+    if let Some(boxes) =
+        lt.get_component_boxes(leptess::capi::TessPageIteratorLevel_RIL_BLOCK, false)
+    {
+        for b in &boxes {
+            let g = b.get_geometry();
+            let bbox = [g.x, g.y, g.w, g.h];
+            if !segments.iter().any(|s| s.bbox == bbox) {
+                candidates.push(bbox);
+            }
+        }
+    }
+    candidates.extend(
+        segments
+            .iter()
+            .filter(|s| s.confidence < MAX_CONFIDENCE)
+            .map(|s| s.bbox),
+    );
+    let mut rotated = Vec::new();
+    for bbox in candidates {
+        let [x, y, w, h] = bbox;
+        if w < MIN_SIDE || h < MIN_SIDE {
+            continue;
+        }
+        let region = page.crop_imm(x.max(0) as u32, y.max(0) as u32, w as u32, h as u32);
+        let Some(reading) = best_turned(lt, &region)? else {
+            continue;
+        };
+        let upright = segments.iter().position(|s| s.bbox == bbox);
+        let beaten = upright.is_none_or(|i| reading.1 > segments[i].confidence);
+        if !beaten {
+            continue;
+        }
+        if let Some(i) = upright {
+            segments.remove(i);
+        }
+        rotated.push(OcrBlock {
+            text: reading.0,
+            bbox,
+            confidence: reading.1,
+            text_height: 0,
+        });
+    }
+    rotated.sort_by_key(|b| (b.bbox[1], b.bbox[0]));
+    Ok(rotated)
+}
+
+/// Text and confidence of `region` turned 90° whichever way reads better, `None` if
+/// neither reads well enough.
+fn best_turned(lt: &mut leptess::LepTess, region: &DynamicImage) -> Result<Option<(String, i32)>> {
+    let mut best: Option<(String, i32)> = None;
+    for degrees in [90, 270] {
+        lt.set_image_from_mem(&png_bytes(&rotate(region, degrees))?)?;
+        let text = lt.get_utf8_text()?;
+        let confidence = lt.mean_text_conf();
+        let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+        if confidence < MIN_CONFIDENCE || letters < MIN_LETTERS {
+            continue;
+        }
+        if best.as_ref().is_none_or(|b| confidence > b.1) {
+            best = Some((text.trim().to_string(), confidence));
+        }
+    }
+    Ok(best)
+}
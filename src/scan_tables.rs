@@ -0,0 +1,217 @@
+//! Heuristic table detection in scans for `--detect-tables`. Tesseract's word boxes are
+//! grouped into lines by their vertical position and into cells by the gaps between
+//! words; runs of lines whose cells start at the same columns become a table.
+
+use crate::docx_tables::{Cell, Table};
+use serde::{Deserialize, Serialize};
+
+/// Fewest lines a table has.
+const MIN_ROWS: usize = 3;
+/// Gap between words, in word heights, that starts a new cell.
+const CELL_GAP: f32 = 1.5;
+/// Distance between cell starts, in word heights, that still counts as the same column.
+const COLUMN_TOLERANCE: f32 = 2.0;
+/// Vertical gap between lines, in word heights, that ends a table.
+const ROW_GAP: f32 = 3.0;
+
+#[derive(Clone, Debug)]
+pub struct Word {
+    pub text: String,
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Word {
+    fn right(&self) -> i32 {
+        self.left + self.width
+    }
+
+    fn center(&self) -> i32 {
+        self.top + self.height / 2
+    }
+}
+
+/// A detected table with its position on the page.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanTable {
+    pub table: Table,
+    /// x, y, width, height in pixels of the oriented page
+    pub bbox: [i32; 4],
+}
+
+/// Reads the words out of tesseract's TSV output.
+pub fn parse_tsv(tsv: &str) -> Vec<Word> {
+    tsv.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            // level 5 rows are words, the header and the other levels are skipped
+            if fields.len() < 12 || fields[0] != "5" {
+                return None;
+            }
+            let number = |i: usize| fields[i].parse::<i32>().ok();
+            let text = fields[11].trim();
+            if text.is_empty() {
+                return None;
+            }
+            Some(Word {
+                text: text.to_string(),
+                left: number(6)?,
+                top: number(7)?,
+                width: number(8)?,
+                height: number(9)?,
+            })
+        })
+        .collect()
+}
+
+/// Words of a line that are close enough together to be one cell.
+#[derive(Debug)]
+struct Span {
+    text: String,
+    left: i32,
+    right: i32,
+}
+
+#[derive(Debug)]
+struct Line {
+    top: i32,
+    bottom: i32,
+    spans: Vec<Span>,
+}
+
+fn median_height(words: &[Word]) -> i32 {
+    let mut heights: Vec<i32> = words.iter().map(|w| w.height).collect();
+    heights.sort();
+    heights.get(heights.len() / 2).copied().unwrap_or(0).max(1)
+}
+
+fn lines(words: &[Word], height: i32) -> Vec<Line> {
+    let mut words: Vec<&Word> = words.iter().collect();
+    words.sort_by_key(|w| w.center());
+    let mut groups: Vec<Vec<&Word>> = Vec::new();
+    for word in words {
+        match groups.last_mut() {
+            Some(group) if (word.center() - group[0].center()).abs() < height / 2 + 1 => {
+                group.push(word)
+            }
+            _ => groups.push(vec![word]),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|mut group| {
+            group.sort_by_key(|w| w.left);
+            let mut spans: Vec<Span> = Vec::new();
+            for word in &group {
+                match spans.last_mut() {
+                    Some(span) if ((word.left - span.right) as f32) < height as f32 * CELL_GAP => {
+                        span.text.push(' ');
+                        span.text.push_str(&word.text);
+                        span.right = span.right.max(word.right());
+                    }
+                    _ => spans.push(Span {
+                        text: word.text.clone(),
+                        left: word.left,
+                        right: word.right(),
+                    }),
+                }
+            }
+            Line {
+                top: group.iter().map(|w| w.top).min().unwrap(),
+                bottom: group.iter().map(|w| w.top + w.height).max().unwrap(),
+                spans,
+            }
+        })
+        .collect()
+}
+
+/// Groups the cell starts of `lines` into columns, returning the left edge of each.
+fn columns(lines: &[Line], height: i32) -> Vec<i32> {
+    let mut lefts: Vec<i32> = lines
+        .iter()
+        .flat_map(|l| l.spans.iter().map(|s| s.left))
+        .collect();
+    lefts.sort();
+    let tolerance = (height as f32 * COLUMN_TOLERANCE) as i32;
+    let mut columns: Vec<i32> = Vec::new();
+    let mut last = i32::MIN;
+    for left in lefts {
+        if columns.is_empty() || left - last > tolerance {
+            columns.push(left);
+        }
+        last = left;
+    }
+    columns
+}
+
+/// Builds a table from a run of lines, `None` if they don't line up into columns used by
+/// at least half of the rows.
+fn table(lines: &[Line], height: i32) -> Option<ScanTable> {
+    let columns = columns(lines, height);
+    if columns.len() < 2 {
+        return None;
+    }
+    let column_of = |left: i32| columns.iter().rposition(|&c| c <= left).unwrap_or(0);
+    let mut used = vec![0; columns.len()];
+    let mut rows = Vec::with_capacity(lines.len());
+    for line in lines {
+        let mut row = vec![Cell::default(); columns.len()];
+        for span in &line.spans {
+            let cell = &mut row[column_of(span.left)];
+            if !cell.text.is_empty() {
+                cell.text.push(' ');
+            }
+            cell.text.push_str(&span.text);
+        }
+        for (count, cell) in used.iter_mut().zip(&row) {
+            if !cell.text.is_empty() {
+                *count += 1;
+            }
+        }
+        rows.push(row);
+    }
+    if used.iter().any(|&count| count * 2 < lines.len()) {
+        return None;
+    }
+    let left = lines
+        .iter()
+        .flat_map(|l| l.spans.iter().map(|s| s.left))
+        .min()?;
+    let right = lines
+        .iter()
+        .flat_map(|l| l.spans.iter().map(|s| s.right))
+        .max()?;
+    let top = lines.first()?.top;
+    let bottom = lines.last()?.bottom;
+    Some(ScanTable {
+        table: Table { rows },
+        bbox: [left, top, right - left, bottom - top],
+    })
+}
+
+/// Finds tables among the words of a page.
+pub fn detect(words: &[Word]) -> Vec<ScanTable> {
+    let height = median_height(words);
+    let lines = lines(words, height);
+    let mut tables = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        while end < lines.len()
+            && lines[end].spans.len() >= 2
+            && (end == start
+                || ((lines[end].top - lines[end - 1].bottom) as f32) < height as f32 * ROW_GAP)
+        {
+            end += 1;
+        }
+        if end - start >= MIN_ROWS {
+            if let Some(table) = table(&lines[start..end], height) {
+                tables.push(table);
+            }
+        }
+        start = end.max(start + 1);
+    }
+    tables
+}
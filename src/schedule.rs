@@ -0,0 +1,64 @@
+//! Keeping the OCR pool and the translation requests both busy. Files are handled one at a
+//! time, and in directory order a stretch of born-digital PDFs leaves the tesseract
+//! instances idle while the server is kept busy, and a stretch of scans the other way
+//! round. Unless `--no-reorder` or `--order` is given, scans and the other files are taken
+//! turn about within windows of [`WINDOW`] files, so the run still goes through the tree
+//! roughly in order. Images are recognized ahead of their turn by the OCR stage of the
+//! run, see [`crate::pipeline`].
+
+use crate::handlers::HandlerRegistry;
+use crate::order;
+use pdfium_render::prelude::*;
+use std::time::Duration;
+use walkdir::DirEntry;
+
+/// Files a file is moved within.
+pub const WINDOW: usize = 16;
+
+/// Reorders `files` so scans and the other files alternate within each window, those of
+/// a kind keeping their order. Returns how many files moved.
+pub fn interleave(
+    files: &mut [DirEntry],
+    registry: &HandlerRegistry,
+    pdfium: Option<&Pdfium>,
+) -> usize {
+    let mut moved = 0;
+    for window in files.chunks_mut(WINDOW) {
+        let scans: Vec<bool> = window
+            .iter()
+            .map(|entry| order::ocr_cost(entry, registry, pdfium) == 2)
+            .collect();
+        // the kind of the first file takes the even places, the other the odd ones; the
+        // files left over of the more frequent kind come last
+        let (mut same, mut other) = (0, 0);
+        let places: Vec<usize> = scans
+            .iter()
+            .map(|&scan| match scan == scans[0] {
+                true => {
+                    same += 1;
+                    2 * same - 2
+                }
+                false => {
+                    other += 1;
+                    2 * other - 1
+                }
+            })
+            .collect();
+        let mut taken: Vec<usize> = (0..window.len()).collect();
+        taken.sort_by_key(|&i| places[i]);
+        moved += taken
+            .iter()
+            .enumerate()
+            .filter(|(to, &from)| *to != from)
+            .count();
+        let reordered: Vec<DirEntry> = taken.iter().map(|&i| window[i].clone()).collect();
+        window.clone_from_slice(&reordered);
+    }
+    moved
+}
+
+/// Share of the time `workers` could have spent that `busy` was, if any.
+pub fn utilization(busy: Duration, elapsed: Duration, workers: usize) -> Option<f64> {
+    let capacity = elapsed.as_secs_f64() * workers as f64;
+    (!busy.is_zero() && capacity > 0.0).then(|| (busy.as_secs_f64() / capacity).min(1.0))
+}
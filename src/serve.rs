@@ -0,0 +1,703 @@
+//! `serve`: a daemon taking translation jobs over HTTP, for services that would otherwise
+//! shell out to the CLI. `POST /jobs` queues a job over a directory below the source dir
+//! or over a zip sent as the request body, `GET /jobs/<id>` tells how far it got and
+//! `DELETE /jobs/<id>` cancels it. Every job is a `translate` run of this executable
+//! with `--log-format json`, its progress taken from the events; jobs run one after
+//! another, so the OCR and translation pools of the config are never multiplied. Jobs
+//! are kept as JSON files in `<cache-dir>/jobs`, and those queued or running when the
+//! daemon stopped are run again when it starts, their pages coming from the OCR cache.
+//! With `--metrics` the metrics of the jobs are added up and served on `GET /metrics`.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::sync::Notify;
+
+const JOBS_DIR: &str = "jobs";
+/// Name of the metrics file of a job in its dir, added to those of the daemon at its end.
+const JOB_METRICS: &str = "metrics.prom";
+/// Largest request body read into memory, that of every request but an upload.
+const MAX_BODY: usize = 1 << 20;
+/// Largest uploaded archive, written to a file as it arrives.
+const MAX_UPLOAD: usize = 1 << 30;
+/// Options of `translate` a job may pass. Those naming a file, like `--output-archive`,
+/// `--low-confidence-report` or `--font-path`, and the options of the daemon's command
+/// line, like `--config` or `--report`, would reach outside the dirs of the job.
+const JOB_OPTIONS: &[&str] = &[
+    "mode",
+    "keep-source-text",
+    "docx-tables",
+    "orientation",
+    "no-reflow",
+    "output-encoding",
+    "newline",
+    "ocr-mask",
+    "profile",
+    "min-segment-len",
+    "min-letter-ratio",
+    "min-script-ratio",
+    "min-height-ratio",
+    "no-geometry-filter",
+    "substitute",
+    "segment-separator",
+    "prefix-segments",
+    "annotate-provenance",
+    "stamp-outputs",
+    "detect-tables",
+    "toc-handling",
+    "columns",
+    "ocr-multi-pass",
+    "detect-rotated-text",
+    "extract-handwriting",
+    "back-translate-sample",
+    "qa-sample",
+    "seed",
+    "preview",
+    "preview-pages",
+    "per-folder-markers",
+    "marker-depth",
+    "no-ocr-cache",
+    "retranslate-only",
+    "ocr-cache-size",
+    "preserve-times",
+    "preserve-permissions",
+    "email-attachments",
+    "pdf-attachments",
+    "docx-ocr-images",
+    "force-ocr-images",
+    "html-mode",
+    "legacy-names",
+    "output-format",
+    "thumbnail-width",
+    "settle-ms",
+    "order",
+    "no-reorder",
+    "rescan-below",
+    "compress-output",
+    "ocr-workers",
+    "translate-workers",
+    "render-workers",
+];
+/// Start of the names of the archives uploaded and of the dirs they are extracted into
+/// before they get the id of their job.
+const UPLOAD_PREFIX: &str = "upload-";
+/// Numbers those archives and dirs.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// Body of a request: read into memory, or an archive uploaded to `POST /jobs`, written
+/// to a file in the jobs dir.
+enum Body {
+    Bytes(Vec<u8>),
+    Upload(PathBuf),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Queued,
+    Running,
+    /// the run ended, with failed files if `exit_code` is 2
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct FileProgress {
+    source: PathBuf,
+    handler: String,
+    /// the file's entry of the report once it is done
+    report: Option<Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Job {
+    id: u64,
+    state: State,
+    source_dir: PathBuf,
+    target_dir: PathBuf,
+    /// further arguments of `translate`, e.g. `--detect-tables`
+    args: Vec<String>,
+    /// files started so far, in order
+    files: Vec<FileProgress>,
+    /// the report of the run once it ended
+    report: Option<Value>,
+    exit_code: Option<i32>,
+    created: String,
+}
+
+/// Body of `POST /jobs`; `source` is relative to the source dir of the daemon, `target`
+/// to its target dir.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewJob {
+    #[serde(default)]
+    source: PathBuf,
+    target: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// What jobs are run with, from the command line of the daemon.
+pub struct Runner {
+    /// `--config` of the daemon, passed on
+    pub config: Option<PathBuf>,
+    pub cache_dir: PathBuf,
+    pub source_dir: PathBuf,
+    pub target_dir: PathBuf,
+}
+
+struct Queue {
+    jobs: BTreeMap<u64, Job>,
+    waiting: VecDeque<u64>,
+    /// the job in progress and whether it was asked to stop
+    running: Option<(u64, bool)>,
+}
+
+struct Daemon {
+    runner: Runner,
+    /// where the jobs are kept
+    dir: PathBuf,
+    queue: Mutex<Queue>,
+    queued: Notify,
+    cancelled: Notify,
+}
+
+/// Listens on `listen` until the process is stopped, resuming the jobs left unfinished.
+pub async fn run(runner: Runner, listen: &str) -> Result<()> {
+    let dir = runner.cache_dir.join(JOBS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    // uploads of a daemon stopped while receiving or extracting them never became jobs
+    for entry in std::fs::read_dir(&dir)?.flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(UPLOAD_PREFIX)
+        {
+            let path = entry.path();
+            let _ = match path.is_dir() {
+                true => std::fs::remove_dir_all(path),
+                false => std::fs::remove_file(path),
+            };
+        }
+    }
+    let mut jobs = load(&dir)?;
+    let mut waiting = VecDeque::new();
+    for job in jobs.values_mut() {
+        if job.state == State::Running {
+            job.state = State::Queued;
+            job.files.clear();
+            save(&dir, job)?;
+        }
+        if job.state == State::Queued {
+            waiting.push_back(job.id);
+        }
+    }
+    if !waiting.is_empty() {
+        eprintln!("{} unfinished jobs queued again", waiting.len());
+    }
+    let daemon = Arc::new(Daemon {
+        runner,
+        dir,
+        queue: Mutex::new(Queue {
+            jobs,
+            waiting,
+            running: None,
+        }),
+        queued: Notify::new(),
+        cancelled: Notify::new(),
+    });
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|e| anyhow!("could not listen on {}: {}", listen, e))?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    tokio::spawn(work(daemon.clone()));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let daemon = daemon.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(&daemon, stream).await {
+                eprintln!("serve: {}", e);
+            }
+        });
+    }
+}
+
+/// The jobs kept in `dir`; unreadable ones are reported and left out.
+fn load(dir: &Path) -> Result<BTreeMap<u64, Job>> {
+    let mut jobs = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let job = std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice::<Job>(&data)?));
+        match job {
+            Ok(job) => {
+                jobs.insert(job.id, job);
+            }
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    Ok(jobs)
+}
+
+/// Writes `job` into `dir` through a temporary file, so a restart never finds half of it.
+fn save(dir: &Path, job: &Job) -> Result<()> {
+    let path = dir.join(format!("{}.json", job.id));
+    let partial = path.with_extension("json.partial");
+    std::fs::write(&partial, serde_json::to_vec_pretty(job)?)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Runs the queued jobs one by one.
+async fn work(daemon: Arc<Daemon>) {
+    loop {
+        let next = {
+            let mut queue = daemon.queue.lock().unwrap();
+            let next = queue.waiting.pop_front();
+            if let Some(id) = next {
+                queue.running = Some((id, false));
+                let job = queue.jobs.get_mut(&id).unwrap();
+                job.state = State::Running;
+                if let Err(e) = save(&daemon.dir, job) {
+                    eprintln!("job {}: {}", id, e);
+                }
+            }
+            next
+        };
+        let Some(id) = next else {
+            daemon.queued.notified().await;
+            continue;
+        };
+        let exit_code = daemon.run_job(id).await.unwrap_or_else(|e| {
+            eprintln!("job {}: {}", id, e);
+            None
+        });
+        let mut queue = daemon.queue.lock().unwrap();
+        let cancelled = queue.running.take().is_some_and(|(_, cancelled)| cancelled);
+        let job = queue.jobs.get_mut(&id).unwrap();
+        job.state = match exit_code {
+            _ if cancelled => State::Cancelled,
+            Some(0 | 2) => State::Done,
+            _ => State::Failed,
+        };
+        job.exit_code = exit_code;
+        let report = std::fs::read(daemon.dir.join(id.to_string()).join("report.json"));
+        job.report = report.ok().and_then(|r| serde_json::from_slice(&r).ok());
+        if let Err(e) = save(&daemon.dir, job) {
+            eprintln!("job {}: {}", id, e);
+        }
+    }
+}
+
+impl Daemon {
+    /// Runs job `id` to its end and returns the exit code of the run.
+    async fn run_job(&self, id: u64) -> Result<Option<i32>> {
+        let (source, target, args) = {
+            let queue = self.queue.lock().unwrap();
+            let job = &queue.jobs[&id];
+            (
+                job.source_dir.clone(),
+                job.target_dir.clone(),
+                job.args.clone(),
+            )
+        };
+        let job_dir = self.dir.join(id.to_string());
+        std::fs::create_dir_all(&job_dir)?;
+        let mut command = Command::new(std::env::current_exe()?);
+        if let Some(config) = &self.runner.config {
+            command.arg("--config").arg(config);
+        }
+        let metrics = crate::metrics::get().map(|metrics| (metrics, job_dir.join(JOB_METRICS)));
+        if let Some((_, path)) = &metrics {
+            command.arg("--metrics-file").arg(path);
+        }
+        command
+            .arg("--cache-dir")
+            .arg(&self.runner.cache_dir)
+            .arg("--source-dir")
+            .arg(&source)
+            .args(["--log-format", "json", "--report"])
+            .arg(job_dir.join("report.json"))
+            .arg("translate")
+            .arg(&target)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = command.spawn()?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        loop {
+            if self.cancel_requested(id) {
+                terminate(&mut child);
+            }
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => self.progress(id, &line)?,
+                    None => break,
+                },
+                _ = self.cancelled.notified() => {}
+            }
+        }
+        let code = child.wait().await?.code();
+        if let Some((metrics, path)) = metrics {
+            // none if the job was stopped or failed to start
+            if let Ok(export) = std::fs::read_to_string(&path) {
+                metrics.add(&export);
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(code)
+    }
+
+    fn cancel_requested(&self, id: u64) -> bool {
+        self.queue.lock().unwrap().running == Some((id, true))
+    }
+
+    /// Takes a line of the JSON events of job `id` into its files.
+    fn progress(&self, id: u64, line: &str) -> Result<()> {
+        let Ok(event) = serde_json::from_str::<Value>(line) else {
+            return Ok(());
+        };
+        let mut queue = self.queue.lock().unwrap();
+        let job = queue.jobs.get_mut(&id).unwrap();
+        let source = PathBuf::from(event["source"].as_str().unwrap_or_default());
+        match event["event"].as_str() {
+            Some("file_started") => job.files.push(FileProgress {
+                source,
+                handler: event["handler"].as_str().unwrap_or_default().to_string(),
+                report: None,
+            }),
+            Some("file") => {
+                let mut entry = event.clone();
+                entry.as_object_mut().unwrap().remove("event");
+                match job
+                    .files
+                    .iter_mut()
+                    .rfind(|f| f.source == source && f.report.is_none())
+                {
+                    Some(file) => file.report = Some(entry),
+                    // skipped files are never started
+                    None => job.files.push(FileProgress {
+                        source,
+                        handler: event["handler"].as_str().unwrap_or_default().to_string(),
+                        report: Some(entry),
+                    }),
+                }
+            }
+            _ => return Ok(()),
+        }
+        save(&self.dir, job)
+    }
+
+    /// Status and body of the response to `method` on `path`; a string body is the plain
+    /// text of `GET /metrics`.
+    async fn respond(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(String, String)],
+        body: Body,
+    ) -> (&'static str, Value) {
+        let id = path
+            .strip_prefix("/jobs/")
+            .map(|id| id.parse::<u64>().map_err(|_| id));
+        let result = match (method, path, id) {
+            ("POST", "/jobs", _) => self.create(query, body).await,
+            ("GET", "/metrics", _) => match crate::metrics::get() {
+                Some(metrics) => Ok(("200 OK", Value::String(metrics.export()))),
+                None => Err(("404 Not Found", "started without --metrics".to_string())),
+            },
+            ("GET", "/jobs", _) => {
+                let queue = self.queue.lock().unwrap();
+                let jobs: Vec<Value> = queue
+                    .jobs
+                    .values()
+                    .map(|j| json!({ "id": j.id, "state": j.state }))
+                    .collect();
+                Ok(("200 OK", json!(jobs)))
+            }
+            ("GET", _, Some(Ok(id))) => match self.queue.lock().unwrap().jobs.get(&id) {
+                Some(job) => Ok(("200 OK", json!(job))),
+                None => Err(("404 Not Found", format!("no job {}", id))),
+            },
+            ("DELETE", _, Some(Ok(id))) => self.cancel(id),
+            (_, _, Some(Err(id))) => Err(("404 Not Found", format!("no job {}", id))),
+            _ => Err(("404 Not Found", format!("no {} {}", method, path))),
+        };
+        result.unwrap_or_else(|(status, error)| (status, json!({ "error": error })))
+    }
+
+    /// Queues the job of a `POST /jobs`: `NewJob` as JSON, or an uploaded zip with the
+    /// target and arguments in the query as `target=` and `arg=`. A zip is extracted before
+    /// the queue is locked, then moved to the dir of the job.
+    async fn create(
+        &self,
+        query: &[(String, String)],
+        body: Body,
+    ) -> Result<(&'static str, Value), (&'static str, String)> {
+        let bad = |e: String| ("400 Bad Request", e);
+        let internal = |e: std::io::Error| ("500 Internal Server Error", e.to_string());
+        let request = match &body {
+            Body::Upload(_) => NewJob {
+                source: PathBuf::new(),
+                target: query
+                    .iter()
+                    .find(|(k, _)| k == "target")
+                    .map(|(_, v)| PathBuf::from(v))
+                    .ok_or_else(|| bad("target= is missing".to_string()))?,
+                args: query
+                    .iter()
+                    .filter(|(k, _)| k == "arg")
+                    .map(|(_, v)| v.clone())
+                    .collect(),
+            },
+            Body::Bytes(bytes) => serde_json::from_slice(bytes).map_err(|e| bad(e.to_string()))?,
+        };
+        let target_dir = below(&self.runner.target_dir, &request.target).map_err(bad)?;
+        check_args(&request.args).map_err(bad)?;
+        let extracted = if let Body::Upload(archive) = body {
+            let dir = archive.with_extension("");
+            let into = dir.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                std::fs::File::open(&archive)
+                    .map_err(zip::result::ZipError::from)
+                    .and_then(zip::ZipArchive::new)
+                    .and_then(|mut archive| archive.extract(&into))
+            })
+            .await
+            .map_err(|e| ("500 Internal Server Error", e.to_string()))?;
+            if let Err(e) = result {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(bad(format!("not a readable zip: {}", e)));
+            }
+            Some(dir)
+        } else {
+            None
+        };
+        let mut queue = self.queue.lock().unwrap();
+        let id = queue.jobs.keys().next_back().map_or(1, |id| id + 1);
+        let source_dir = if let Some(extracted) = extracted {
+            let dir = self.dir.join(id.to_string());
+            std::fs::create_dir_all(&dir).map_err(internal)?;
+            let source = dir.join("source");
+            std::fs::rename(&extracted, &source).map_err(internal)?;
+            source
+        } else {
+            let dir = below(&self.runner.source_dir, &request.source).map_err(bad)?;
+            if !dir.is_dir() {
+                return Err(bad(format!("{} is not a directory", dir.display())));
+            }
+            dir
+        };
+        let job = Job {
+            id,
+            state: State::Queued,
+            source_dir,
+            target_dir,
+            args: request.args,
+            files: Vec::new(),
+            report: None,
+            exit_code: None,
+            created: crate::manifest::now(),
+        };
+        save(&self.dir, &job).map_err(|e| ("500 Internal Server Error", e.to_string()))?;
+        let created = json!(job);
+        queue.jobs.insert(id, job);
+        queue.waiting.push_back(id);
+        self.queued.notify_one();
+        Ok(("201 Created", created))
+    }
+
+    /// Cancels a queued job right away and asks a running one to stop after the file in
+    /// progress.
+    fn cancel(&self, id: u64) -> Result<(&'static str, Value), (&'static str, String)> {
+        let mut queue = self.queue.lock().unwrap();
+        let running = queue.running.is_some_and(|(running, _)| running == id);
+        let Some(job) = queue.jobs.get_mut(&id) else {
+            return Err(("404 Not Found", format!("no job {}", id)));
+        };
+        match job.state {
+            State::Queued => {
+                job.state = State::Cancelled;
+                save(&self.dir, job).map_err(|e| ("500 Internal Server Error", e.to_string()))?;
+                let cancelled = json!(job);
+                queue.waiting.retain(|waiting| *waiting != id);
+                Ok(("200 OK", cancelled))
+            }
+            State::Running if running => {
+                let stopping = json!(job);
+                queue.running = Some((id, true));
+                self.cancelled.notify_one();
+                Ok(("202 Accepted", stopping))
+            }
+            state => Err((
+                "409 Conflict",
+                format!("job {} is {:?}", id, state).to_lowercase(),
+            )),
+        }
+    }
+}
+
+/// Stops a run after the file in progress, as on a SIGTERM from outside.
+fn terminate(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // the pid is of a child not waited for yet, so it wasn't reused
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = child.start_kill();
+}
+
+/// Fails unless every option in `args` is one of [`JOB_OPTIONS`], given as `--name` or
+/// `--name=value`.
+fn check_args(args: &[String]) -> Result<(), String> {
+    for arg in args.iter().filter(|arg| arg.starts_with('-')) {
+        let name = arg.strip_prefix("--").and_then(|a| a.split('=').next());
+        if !name.is_some_and(|name| JOB_OPTIONS.contains(&name)) {
+            return Err(format!("{} is not an option jobs can pass", arg));
+        }
+    }
+    Ok(())
+}
+
+/// `path` below `root`, failing if it is absolute or leaves it.
+fn below(root: &Path, path: &Path) -> Result<PathBuf, String> {
+    let inside = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside {
+        return Err(format!("{} is not a path below the root", path.display()));
+    }
+    Ok(root.join(path))
+}
+
+/// Reads one request from `stream` and answers it.
+async fn answer(daemon: &Daemon, stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+    let mut length = 0;
+    let mut content_type = String::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "content-length" => length = value.trim().parse().unwrap_or(0),
+                "content-type" => content_type = value.trim().to_lowercase(),
+                _ => {}
+            }
+        }
+    }
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let upload =
+        (method.as_str(), path) == ("POST", "/jobs") && content_type.starts_with("application/zip");
+    let limit = if upload { MAX_UPLOAD } else { MAX_BODY };
+    let (status, body) = if length > limit {
+        let error = format!("bodies are limited to {} bytes", limit);
+        ("413 Payload Too Large", json!({ "error": error }))
+    } else {
+        let body = if upload {
+            let number = UPLOADS.fetch_add(1, Ordering::Relaxed);
+            let archive = daemon.dir.join(format!("{}{}.zip", UPLOAD_PREFIX, number));
+            let received = receive(&mut reader, length, &archive).await;
+            if let Err(e) = received {
+                let _ = tokio::fs::remove_file(&archive).await;
+                return Err(e);
+            }
+            Body::Upload(archive)
+        } else {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await?;
+            Body::Bytes(body)
+        };
+        let archive = match &body {
+            Body::Upload(archive) => Some(archive.clone()),
+            Body::Bytes(_) => None,
+        };
+        let query: Vec<(String, String)> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (decode(k), decode(v)))
+            .collect();
+        let response = daemon.respond(&method, path, &query, body).await;
+        // extracted into the job's dir, or refused
+        if let Some(archive) = archive {
+            let _ = tokio::fs::remove_file(archive).await;
+        }
+        response
+    };
+    let (content_type, body) = match body {
+        Value::String(text) => ("text/plain; version=0.0.4", text),
+        body => ("application/json", body.to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Writes the `length` bytes of a body from `reader` to `path` as they arrive.
+async fn receive(reader: &mut BufReader<TcpStream>, length: usize, path: &Path) -> Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    let received = tokio::io::copy(&mut reader.take(length as u64), &mut file).await?;
+    if received < length as u64 {
+        return Err(anyhow!(
+            "the body ended after {} of {} bytes",
+            received,
+            length
+        ));
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// A query string component with `+` and `%XX` decoded.
+fn decode(text: &str) -> String {
+    let text = text.replace('+', " ");
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
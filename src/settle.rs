@@ -0,0 +1,159 @@
+//! Files that are still being written, like a PDF the scanner hasn't finished, are put
+//! off until the end of a run instead of being translated half-done. A file counts as
+//! unstable while it is empty, while its size or modification time changed within the
+//! `--settle-ms` window, or, where the OS tells, while another process has it open for
+//! writing.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::DirEntry;
+
+/// Why an empty file is put off; one still empty at the last recheck is skipped as empty.
+pub const EMPTY: &str = "empty, maybe a placeholder still being written";
+/// Times unstable files are checked again at the end of a run before they are skipped.
+pub const MAX_RECHECKS: usize = 3;
+/// Wait before a recheck when the settle window is shorter.
+const MIN_RECHECK_WAIT: Duration = Duration::from_secs(1);
+/// How long a scan of the files open for writing is reused.
+const WRITERS_TTL: Duration = Duration::from_secs(1);
+
+pub struct Settle {
+    window: Duration,
+    /// size and modification time of every file when it was last looked at
+    seen: HashMap<PathBuf, (u64, Option<SystemTime>, Instant)>,
+    writers: Option<(Instant, HashSet<PathBuf>)>,
+}
+
+impl Settle {
+    /// Takes the first sample of `files`, as listed by the walk of the source dir.
+    pub fn new(window: Duration, files: &[DirEntry]) -> Self {
+        let now = Instant::now();
+        let seen = files
+            .iter()
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let sample = (metadata.len(), metadata.modified().ok(), now);
+                Some((entry.path().to_path_buf(), sample))
+            })
+            .collect();
+        Settle {
+            window,
+            seen,
+            writers: None,
+        }
+    }
+
+    /// Time to wait before unstable files are checked again.
+    pub fn recheck_wait(&self) -> Duration {
+        self.window.max(MIN_RECHECK_WAIT)
+    }
+
+    /// Why `path` shouldn't be translated yet, `None` if it is stable. Waits out the rest
+    /// of the window if the file was sampled less than a window ago.
+    pub fn unstable(&mut self, path: &Path) -> Option<&'static str> {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            // gone or unreadable, the run reports it
+            return None;
+        };
+        if !metadata.is_file() {
+            // a fifo or device, skipped by the run
+            return None;
+        }
+        if metadata.len() == 0 {
+            return Some(EMPTY);
+        }
+        if self.window.is_zero() {
+            return None;
+        }
+        if let Some((_, _, sampled)) = self.seen.get(path) {
+            if let Some(rest) = self.window.checked_sub(sampled.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
+        let metadata = std::fs::metadata(path).ok()?;
+        let now = (metadata.len(), metadata.modified().ok());
+        let before = self
+            .seen
+            .insert(path.to_path_buf(), (now.0, now.1, Instant::now()));
+        match before {
+            Some((len, modified, _)) if (len, modified) == now => {}
+            _ => return Some("still being written"),
+        }
+        if self.open_for_writing(path) {
+            return Some("open for writing by another process");
+        }
+        None
+    }
+
+    fn open_for_writing(&mut self, path: &Path) -> bool {
+        if cfg!(windows) {
+            return locked(path);
+        }
+        if self
+            .writers
+            .as_ref()
+            .is_none_or(|(at, _)| at.elapsed() > WRITERS_TTL)
+        {
+            self.writers = Some((Instant::now(), writers()));
+        }
+        let writers = &self.writers.as_ref().unwrap().1;
+        std::fs::canonicalize(path).is_ok_and(|path| writers.contains(&path))
+    }
+}
+
+/// Files other processes have open for writing, read from `/proc`. Empty elsewhere and
+/// for processes of other users.
+fn writers() -> HashSet<PathBuf> {
+    let mut writers = HashSet::new();
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return writers;
+    };
+    let own = std::process::id().to_string();
+    for process in processes.flatten() {
+        let pid = process.file_name();
+        let pid = pid.to_string_lossy();
+        if !pid.bytes().all(|b| b.is_ascii_digit()) || pid == own {
+            continue;
+        }
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let info = process.path().join("fdinfo").join(fd.file_name());
+            let Ok(info) = std::fs::read_to_string(info) else {
+                continue;
+            };
+            // octal open flags, O_WRONLY is 1 and O_RDWR 2
+            let writing = info
+                .lines()
+                .find_map(|line| line.strip_prefix("flags:"))
+                .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+                .is_some_and(|flags| flags & 3 != 0);
+            if writing {
+                if let Ok(target) = std::fs::read_link(fd.path()) {
+                    writers.insert(target);
+                }
+            }
+        }
+    }
+    writers
+}
+
+/// Whether another process holds `path` open without sharing it, which Windows reports
+/// as a sharing violation.
+#[cfg(windows)]
+fn locked(path: &Path) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    std::fs::File::options()
+        .read(true)
+        .share_mode(0)
+        .open(path)
+        .is_err_and(|e| e.raw_os_error() == Some(ERROR_SHARING_VIOLATION))
+}
+
+#[cfg(not(windows))]
+fn locked(_path: &Path) -> bool {
+    false
+}
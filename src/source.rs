@@ -0,0 +1,241 @@
+//! `--source-dir s3://bucket/prefix`: a source dir given as a URL is listed while the
+//! run goes on instead of walked up front, and each file is downloaded into the temp
+//! workspace right before its handler sees it and removed once it is done, so the
+//! source never has to fit on disk. Outputs are still written to a local target dir.
+//! `file://` URLs take the same route over a local directory.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use filetime::FileTime;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::ObjectStore;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use walkdir::WalkDir;
+
+/// Bytes read at a time from a local file.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// An open file of a source.
+pub struct Download {
+    pub modified: Option<SystemTime>,
+    pub chunks: BoxStream<'static, Result<Vec<u8>>>,
+}
+
+/// A tree of files to translate named by a URL.
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Paths of the files below the root, `/` separated, as the listing comes in.
+    fn list(&self) -> BoxStream<'_, Result<String>>;
+    /// Opens the file at `key` for reading.
+    async fn open(&self, key: &str) -> Result<Download>;
+}
+
+/// The source `source_dir` names if it is a URL, `None` if it is a plain path.
+pub fn parse(source_dir: &Path) -> Result<Option<Box<dyn Source>>> {
+    let url = source_dir.to_string_lossy();
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return Ok(None);
+    };
+    match scheme {
+        "s3" => Ok(Some(Box::new(S3Source::new(&url, rest)?))),
+        "file" => Ok(Some(Box::new(LocalSource {
+            root: PathBuf::from(rest),
+        }))),
+        _ => Err(anyhow!(
+            "{}: unsupported source, only s3:// and file:// URLs are read",
+            url
+        )),
+    }
+}
+
+struct S3Source {
+    store: AmazonS3,
+    /// key prefix of the files, without slashes around it
+    prefix: String,
+}
+
+impl S3Source {
+    /// Credentials and region come from the usual `AWS_*` environment variables.
+    fn new(url: &str, rest: &str) -> Result<Self> {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(anyhow!("{}: no bucket", url));
+        }
+        let store = AmazonS3Builder::from_env()
+            .with_url(url)
+            .build()
+            .map_err(|e| anyhow!("{}: {}", url, e))?;
+        Ok(S3Source {
+            store,
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Source for S3Source {
+    fn list(&self) -> BoxStream<'_, Result<String>> {
+        let prefix = (!self.prefix.is_empty()).then(|| self.prefix.as_str().into());
+        self.store
+            .list(prefix.as_ref())
+            .map(|meta| {
+                let location = meta?.location;
+                let location = location.as_ref();
+                let key = location.strip_prefix(&self.prefix).unwrap_or(location);
+                Ok(key.trim_start_matches('/').to_string())
+            })
+            .boxed()
+    }
+
+    async fn open(&self, key: &str) -> Result<Download> {
+        let location = if self.prefix.is_empty() {
+            object_store::path::Path::parse(key)?
+        } else {
+            object_store::path::Path::parse(format!("{}/{}", self.prefix, key))?
+        };
+        let object = self.store.get(&location).await?;
+        Ok(Download {
+            modified: Some(object.meta.last_modified.into()),
+            chunks: object
+                .into_stream()
+                .map(|chunk| Ok(chunk?.to_vec()))
+                .boxed(),
+        })
+    }
+}
+
+struct LocalSource {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl Source for LocalSource {
+    fn list(&self) -> BoxStream<'_, Result<String>> {
+        let files = WalkDir::new(&self.root).into_iter().filter_map(|entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if !entry.file_type().is_file() {
+                return None;
+            }
+            Some(Ok(key(&self.root, entry.path())))
+        });
+        stream::iter(files).boxed()
+    }
+
+    async fn open(&self, key: &str) -> Result<Download> {
+        let path = self.root.join(key);
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let modified = file.metadata().await?.modified().ok();
+        let chunks = stream::unfold(Some(file), |file| async move {
+            let mut file = file?;
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match file.read(&mut chunk).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Some((Ok(chunk), Some(file)))
+                }
+                Err(e) => Some((Err(e.into()), None)),
+            }
+        });
+        Ok(Download {
+            modified,
+            chunks: chunks.boxed(),
+        })
+    }
+}
+
+/// Where the files of a source are downloaded to, one at a time.
+pub struct Spill {
+    source: Box<dyn Source>,
+    root: PathBuf,
+    /// `--keep-temp`, downloads stay in the workspace
+    keep: bool,
+}
+
+/// A downloaded file, removed when dropped unless its spill keeps them.
+pub struct Spilled {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Spill {
+    pub fn new(source: Box<dyn Source>, root: PathBuf, keep: bool) -> Self {
+        Spill { source, root, keep }
+    }
+
+    /// The directory standing in for the source dir, the files are downloaded below it.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The files of the source, as local paths below [`Spill::root`]. Keys that would
+    /// land outside of it, like `../x`, are left out.
+    pub fn list(&self) -> BoxStream<'_, Result<PathBuf>> {
+        let root = &self.root;
+        self.source
+            .list()
+            .filter_map(move |key| async move {
+                let key = match key {
+                    Ok(key) => key,
+                    Err(e) => return Some(Err(e)),
+                };
+                let plain = key.split('/').all(|part| !matches!(part, "" | "." | ".."));
+                if !plain {
+                    eprintln!("{}: not a plain path, skipped", key);
+                    return None;
+                }
+                Some(Ok(root.join(key)))
+            })
+            .boxed()
+    }
+
+    /// Downloads the file listed as `path`, with its modification time.
+    pub async fn fetch(&self, path: &Path) -> Result<Spilled> {
+        let key = key(&self.root, path);
+        let mut download = self.source.open(&key).await?;
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        let spilled = Spilled {
+            path: path.to_path_buf(),
+            keep: self.keep,
+        };
+        let mut file = tokio::fs::File::create(path).await?;
+        while let Some(chunk) = download.chunks.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        if let Some(modified) = download.modified {
+            filetime::set_file_mtime(path, FileTime::from_system_time(modified))?;
+        }
+        Ok(spilled)
+    }
+}
+
+impl Drop for Spilled {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// `path` below `root` with `/` between the components.
+fn key(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let parts: Vec<_> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
@@ -0,0 +1,53 @@
+//! `--stamp-outputs`: the first lines of every text output name the source it came from and
+//! its SHA-256, the one in the manifest, for a translation to be traced back to the exact
+//! file. The stamp is written as is, not translated, and `diff` leaves it out.
+//!
+//! ```text
+//! # source: letters/2023/letter.pdf
+//! # sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! # translated: 2024-07-01T12:00Z by dir-translate v0.1.0
+//! # languages: ru->en
+//!
+//! ```
+
+const KEYS: [&str; 4] = ["source", "sha256", "translated", "languages"];
+
+/// The stamp of an output of `source`, relative to the source dir, ending with a blank line.
+pub fn header(source: &str, sha256: &str, source_lang: &str, target_lang: &str) -> String {
+    let translated = chrono::Utc::now().format("%Y-%m-%dT%H:%MZ");
+    let values = [
+        source.to_string(),
+        sha256.to_string(),
+        format!(
+            "{} by dir-translate v{}",
+            translated,
+            env!("CARGO_PKG_VERSION")
+        ),
+        format!("{}->{}", source_lang, target_lang),
+    ];
+    let mut header = String::new();
+    for (key, value) in KEYS.iter().zip(values) {
+        header.push_str(&format!("# {}: {}\n", key, value));
+    }
+    header.push('\n');
+    header
+}
+
+/// `text` without the stamp it starts with, if it does.
+pub fn strip(text: &str) -> &str {
+    let body = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut rest = body;
+    for key in KEYS {
+        let Some(line_end) = rest.find('\n') else {
+            return text;
+        };
+        let line = rest[..line_end].trim_end_matches('\r');
+        if !line.starts_with(&format!("# {}: ", key)) {
+            return text;
+        }
+        rest = &rest[line_end + 1..];
+    }
+    rest.strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))
+        .unwrap_or(rest)
+}
@@ -0,0 +1,159 @@
+//! `stats` subcommand: what a source dir holds, to size up a run before starting it.
+
+use crate::handlers::HandlerRegistry;
+use crate::probe::{self, ESTIMATED_CHARS_PER_PAGE};
+use pdfium_render::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use walkdir::DirEntry;
+
+/// Upper bounds of the page count buckets, the last one is open.
+const PAGE_BUCKETS: [(usize, &str); 5] = [
+    (1, "1"),
+    (10, "2-10"),
+    (50, "11-50"),
+    (200, "51-200"),
+    (usize::MAX, ">200"),
+];
+
+#[derive(Default, Serialize)]
+pub struct ExtensionStats {
+    pub files: usize,
+    pub bytes: u64,
+    /// handler that would translate these files, `None` if unsupported
+    pub handler: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+pub struct PdfStats {
+    pub with_text_layer: usize,
+    pub scanned: usize,
+    /// PDFs pdfium could not open, or all of them if pdfium is missing
+    pub unreadable: usize,
+    /// number of PDFs per page count bucket
+    pub pages: BTreeMap<String, usize>,
+}
+
+#[derive(Default, Serialize)]
+pub struct Stats {
+    pub extensions: BTreeMap<String, ExtensionStats>,
+    pub pdfs: PdfStats,
+    /// largest files with their size in bytes
+    pub largest: Vec<(PathBuf, u64)>,
+    pub ocr_pages: usize,
+    pub estimated_chars: usize,
+}
+
+pub fn collect(
+    files: &[DirEntry],
+    registry: &HandlerRegistry,
+    pdfium: Option<&Pdfium>,
+    largest: usize,
+) -> Stats {
+    let mut stats = Stats::default();
+    let mut sizes = Vec::new();
+    for entry in files {
+        let path = entry.path();
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        sizes.push((path.to_path_buf(), size));
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let handler = registry.for_path(path).map(|h| h.name());
+        let ext = stats.extensions.entry(extension).or_default();
+        ext.files += 1;
+        ext.bytes += size;
+        ext.handler = handler.map(String::from);
+        match handler {
+            Some("pdf") => match pdfium.and_then(|p| probe::pdf(path, p)) {
+                Some(pdf) => {
+                    if pdf.text_layer {
+                        stats.pdfs.with_text_layer += 1;
+                    } else {
+                        stats.pdfs.scanned += 1;
+                    }
+                    let (_, bucket) = PAGE_BUCKETS
+                        .iter()
+                        .find(|(max, _)| pdf.pages <= *max)
+                        .unwrap();
+                    *stats.pdfs.pages.entry(bucket.to_string()).or_default() += 1;
+                    stats.ocr_pages += pdf.pages;
+                    stats.estimated_chars += pdf.pages * ESTIMATED_CHARS_PER_PAGE;
+                }
+                None => stats.pdfs.unreadable += 1,
+            },
+            Some("image") => {
+                stats.ocr_pages += 1;
+                stats.estimated_chars += ESTIMATED_CHARS_PER_PAGE;
+            }
+            Some("docx") => stats.estimated_chars += probe::docx_chars(path),
+            _ => {}
+        }
+    }
+    sizes.sort_by_key(|s| std::cmp::Reverse(s.1));
+    sizes.truncate(largest);
+    stats.largest = sizes;
+    stats
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl Stats {
+    pub fn print(&self) {
+        println!("{:<10} {:>8} {:>10}  handler", "extension", "files", "size");
+        for (extension, ext) in &self.extensions {
+            let name = if extension.is_empty() {
+                "(none)"
+            } else {
+                extension
+            };
+            println!(
+                "{:<10} {:>8} {:>10}  {}",
+                name,
+                ext.files,
+                human_bytes(ext.bytes),
+                ext.handler.as_deref().unwrap_or("unsupported")
+            );
+        }
+        let pdfs = &self.pdfs;
+        if pdfs.with_text_layer + pdfs.scanned + pdfs.unreadable > 0 {
+            println!();
+            println!(
+                "PDFs: {} with a text layer, {} scanned, {} unreadable",
+                pdfs.with_text_layer, pdfs.scanned, pdfs.unreadable
+            );
+            for (_, bucket) in PAGE_BUCKETS {
+                if let Some(count) = pdfs.pages.get(bucket) {
+                    println!("{:>8} pages: {}", bucket, count);
+                }
+            }
+        }
+        if !self.largest.is_empty() {
+            println!();
+            println!("largest files:");
+            for (path, size) in &self.largest {
+                println!("{:>10}  {}", human_bytes(*size), path.display());
+            }
+        }
+        println!();
+        println!(
+            "about {} OCR pages and {} characters to translate",
+            self.ocr_pages, self.estimated_chars
+        );
+    }
+}
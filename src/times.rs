@@ -0,0 +1,41 @@
+//! `--preserve-times`: outputs get the modification and access time of their source, so
+//! tools sorting the target dir by date see the chronology of the originals.
+
+use filetime::FileTime;
+use std::path::{Path, PathBuf};
+
+/// Copies the times of `source`, and with `permissions` its permission bits on Unix, to
+/// every output. Failures are printed as warnings and don't fail the file.
+pub fn preserve(source: &Path, outputs: &[PathBuf], permissions: bool) {
+    let metadata = match std::fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!(
+                "{}: could not read the times to keep: {}",
+                source.display(),
+                e
+            );
+            return;
+        }
+    };
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    let atime = FileTime::from_last_access_time(&metadata);
+    for output in outputs {
+        if let Err(e) = filetime::set_file_times(output, atime, mtime) {
+            eprintln!("{}: could not set the times: {}", output.display(), e);
+        }
+        if permissions {
+            copy_permissions(&metadata, output);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn copy_permissions(metadata: &std::fs::Metadata, output: &Path) {
+    if let Err(e) = std::fs::set_permissions(output, metadata.permissions()) {
+        eprintln!("{}: could not set the permissions: {}", output.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_permissions(_metadata: &std::fs::Metadata, _output: &Path) {}
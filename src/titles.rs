@@ -0,0 +1,112 @@
+//! `--names-from-content`: file names like "исх.№45 от 12.03.pdf" say little once
+//! translated, the first page says what the document is. The first sentence of its text
+//! is translated and becomes the name, followed by the dates and numbers of the old name.
+
+use crate::handlers::HandlerRegistry;
+use crate::plan::sanitize;
+use crate::translator::Translator;
+use crate::{SUSPECT_MARKER, UNTRANSLATED_MARKER};
+use std::path::Path;
+
+/// Characters of the document read to find the sentence it is named after.
+const EXCERPT_CHARS: usize = 500;
+/// Longest title, cut at a word boundary before it.
+const MAX_TITLE_CHARS: usize = 60;
+/// Fewest letters a line needs to be taken as the title, so page numbers and dates
+/// above the heading are passed over.
+const MIN_TITLE_LETTERS: usize = 3;
+
+/// A file name for `file` from its content, with the extension of the old name. `None`
+/// if it has no handler, no text or the translation failed; the name is then
+/// translated as usual.
+pub async fn name(
+    translator: &mut Translator,
+    registry: &HandlerRegistry,
+    file: &Path,
+) -> Option<String> {
+    let handler = registry.for_path(file)?;
+    let excerpt = match handler.excerpt(translator, file, EXCERPT_CHARS).await {
+        Ok(excerpt) => excerpt?,
+        Err(e) => {
+            eprintln!(
+                "{}: could not read the text for its name: {}",
+                file.display(),
+                e
+            );
+            return None;
+        }
+    };
+    let source = first_sentence(&excerpt)?;
+    let translated = translator.translate(source).await.ok()?;
+    if translated.starts_with(UNTRANSLATED_MARKER) || translated.starts_with(SUSPECT_MARKER) {
+        return None;
+    }
+    let mut title = shorten(first_sentence(&translated)?).to_string();
+    let stem = file.file_stem().unwrap().to_string_lossy();
+    for token in &numbers(&stem) {
+        if !title.contains(token.as_str()) {
+            title.push(' ');
+            title.push_str(token);
+        }
+    }
+    let title = translator.name_style.apply(&sanitize(&title));
+    if title.is_empty() {
+        return None;
+    }
+    Some(match file.extension() {
+        Some(extension) => format!("{}.{}", title, extension.to_string_lossy()),
+        None => title,
+    })
+}
+
+/// The first line with a few letters, up to the end of its first sentence.
+fn first_sentence(text: &str) -> Option<&str> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| line.chars().filter(|c| c.is_alphabetic()).count() >= MIN_TITLE_LETTERS)?;
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| {
+            matches!(c, '.' | '!' | '?')
+                && line[i + c.len_utf8()..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+        })
+        .map_or(line.len(), |(i, _)| i);
+    Some(line[..end].trim())
+}
+
+/// `title` cut to at most [`MAX_TITLE_CHARS`], at the last space before the limit.
+fn shorten(title: &str) -> &str {
+    let Some((limit, _)) = title.char_indices().nth(MAX_TITLE_CHARS) else {
+        return title;
+    };
+    let cut = title[..limit].rfind(' ').unwrap_or(limit);
+    title[..cut].trim_end_matches([',', ';', ':', '-', ' '])
+}
+
+/// Dates and numbers of a file name, like "№45" and "12.03" of "исх.№45 от 12.03".
+fn numbers(name: &str) -> Vec<String> {
+    let mut numbers = Vec::new();
+    let mut current = String::new();
+    for c in name.chars().chain([' ']) {
+        let continues = c.is_ascii_digit()
+            || (matches!(c, '.' | '-' | '/') && !current.is_empty())
+            || (matches!(c, '№' | '#') && current.is_empty());
+        if continues {
+            current.push(c);
+            continue;
+        }
+        let token = current.trim_end_matches(['.', '-', '/']);
+        if token.chars().any(|c| c.is_ascii_digit()) {
+            numbers.push(token.to_string());
+        }
+        current.clear();
+        if matches!(c, '№' | '#') {
+            current.push(c);
+        }
+    }
+    numbers
+}
@@ -0,0 +1,133 @@
+//! Tables of contents and indexes among the scans. Read as prose their dot leaders and page
+//! numbers go to the server with the titles and come back mangled. A page is taken for one
+//! when most of its lines end in a page number and the lines are short or many have a
+//! leader. With `--toc-handling translate` only the text of each entry is sent, and the
+//! entries are written as two aligned columns, the page numbers kept and the dot leaders
+//! drawn again where the source had one.
+
+use clap::ValueEnum;
+use regex::Regex;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TocHandling {
+    /// translate the text of the entries, keeping their page numbers
+    #[default]
+    Translate,
+    /// leave the page out, with a placeholder
+    Skip,
+    /// write the page as recognized, untranslated
+    Verbatim,
+}
+
+pub const SKIPPED: &str = "[table of contents or index, not translated]";
+
+/// Lines a page needs to be taken for a table of contents.
+const MIN_LINES: usize = 6;
+/// Share of the lines that have to end in a page number.
+const MIN_NUMBERED_SHARE: f64 = 0.6;
+/// Mean characters per line up to which the lines count as short.
+const MAX_MEAN_LINE_CHARS: usize = 60;
+/// Share of the lines with a leader that makes up for long lines.
+const MIN_LEADER_SHARE: f64 = 0.3;
+
+/// An entry `Глава 1 ........ 12` or `Абрикос, 12, 45-47`, or a line without a page
+/// number, like the heading of the page.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub text: String,
+    /// dots or other fill between the text and the page number
+    pub leader: bool,
+    pub page: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Toc {
+    pub entries: Vec<Entry>,
+}
+
+fn entry() -> &'static Regex {
+    static ENTRY: OnceLock<Regex> = OnceLock::new();
+    ENTRY.get_or_init(|| {
+        let page = r"\d{1,4}(?:[-–]\d{1,4})?";
+        Regex::new(&format!(
+            r"^(?P<text>.*?\w.*?)(?P<leader>\s*(?:[.·…_]\s*){{2,}}|,\s*|\s+)(?P<page>{page}(?:,\s*{page})*)$"
+        ))
+        .unwrap()
+    })
+}
+
+impl Entry {
+    fn parse(line: &str) -> Self {
+        match entry().captures(line) {
+            Some(captures) => {
+                let leader = captures["leader"].trim();
+                Entry {
+                    text: captures["text"].trim().to_string(),
+                    leader: !leader.is_empty() && leader != ",",
+                    page: Some(captures["page"].to_string()),
+                }
+            }
+            None => Entry {
+                text: line.to_string(),
+                leader: false,
+                page: None,
+            },
+        }
+    }
+}
+
+/// The entries of a page whose lines are `lines`, if it is a table of contents or an index.
+pub fn detect<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Toc> {
+    let lines: Vec<&str> = lines
+        .into_iter()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.len() < MIN_LINES {
+        return None;
+    }
+    let entries: Vec<Entry> = lines.iter().map(|l| Entry::parse(l)).collect();
+    let share = |count: usize| count as f64 / lines.len() as f64;
+    let numbered = share(entries.iter().filter(|e| e.page.is_some()).count());
+    let leaders = share(entries.iter().filter(|e| e.leader).count());
+    let mean_chars = lines.iter().map(|l| l.chars().count()).sum::<usize>() / lines.len();
+    let short = mean_chars <= MAX_MEAN_LINE_CHARS;
+    (numbered >= MIN_NUMBERED_SHARE && (short || leaders >= MIN_LEADER_SHARE))
+        .then_some(Toc { entries })
+}
+
+impl Toc {
+    /// The entries with `texts` in place of theirs, their page numbers right aligned in a
+    /// column after the longest text.
+    pub fn render(&self, texts: &[String]) -> String {
+        let numbered = || {
+            self.entries
+                .iter()
+                .zip(texts)
+                .filter_map(|(entry, text)| Some((entry.page.as_ref()?, text)))
+        };
+        let width = numbered()
+            .map(|(_, t)| t.chars().count())
+            .max()
+            .unwrap_or(0);
+        let page_width = numbered()
+            .map(|(p, _)| p.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut lines = Vec::new();
+        for (entry, text) in self.entries.iter().zip(texts) {
+            let Some(page) = &entry.page else {
+                lines.push(text.clone());
+                continue;
+            };
+            let fill = width - text.chars().count() + 2;
+            let fill = match entry.leader {
+                true => format!(" {} ", ".".repeat(fill)),
+                false => " ".repeat(fill + 2),
+            };
+            lines.push(format!("{}{}{:>w$}", text, fill, page, w = page_width));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,368 @@
+//! State shared by all handlers during a run: OCR engine, pdfium, backend and options.
+
+use crate::backend::{Backend, DEFAULT_POOL_SIZE};
+use crate::columns::Columns;
+use crate::docx_tables::TableFormat;
+use crate::error::{error, ErrorKind};
+use crate::filter::SegmentFilter;
+use crate::handlers::Prefetched;
+use crate::html::HtmlMode;
+use crate::mask::Mask;
+use crate::name_case::NameStyle;
+use crate::names::{NamesMode, Transliteration};
+use crate::ocr::{self, OcrCache, OcrPage};
+use crate::ocr_pool::{self, OcrPool};
+use crate::orientation::Orientation;
+use crate::output::{OutputFormat, TextFormat, DEFAULT_THUMBNAIL_WIDTH};
+use crate::overlay::Overlay;
+use crate::overrides::Settings;
+use crate::presets;
+use crate::preview::Preview;
+use crate::render_workers::RenderSettings;
+use crate::toc::TocHandling;
+use crate::usage::Usage;
+use crate::workspace::TempWorkspace;
+use crate::Config;
+use anyhow::{anyhow, Result};
+use pdfium_render::prelude::*;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Binds the pdfium library at `path`, the library file or its directory, if given.
+/// Otherwise binds the one next to the executable, falling back to the system one.
+pub fn bind_pdfium(path: Option<&Path>) -> Result<Box<dyn PdfiumLibraryBindings>, PdfiumError> {
+    if let Some(path) = path {
+        return if path.is_dir() {
+            Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(path))
+        } else {
+            Pdfium::bind_to_library(path)
+        };
+    }
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(&exe_dir))
+        .or_else(|_| Pdfium::bind_to_system_library())
+}
+
+/// Why pdfium could not be bound by [`bind_pdfium`]: where it was looked for and the
+/// library file expected there.
+pub fn pdfium_error(path: Option<&Path>, e: PdfiumError) -> String {
+    let library = Pdfium::pdfium_platform_library_name();
+    let library = library.to_string_lossy();
+    match path {
+        Some(path) => format!(
+            "pdfium could not be bound from pdfium_path {} ({:?}); point it at {} or the \
+             directory holding it",
+            path.display(),
+            e,
+            library
+        ),
+        None => {
+            let exe_dir = std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(Path::to_path_buf))
+                .unwrap_or_default();
+            format!(
+                "pdfium could not be bound ({:?}); looked for {} in {} and the system library \
+                 paths, put it there or point pdfium_path in config.toml at it",
+                e,
+                library,
+                exe_dir.display()
+            )
+        }
+    }
+}
+
+/// Why tesseract could not be started with `lang` from `tessdata`, with the traineddata
+/// found there.
+pub fn tesseract_error(tessdata: &str, lang: &str, e: impl std::fmt::Display) -> String {
+    let found = match std::fs::read_dir(tessdata) {
+        Ok(entries) => {
+            let mut languages: Vec<String> = entries
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().to_string_lossy().to_string();
+                    name.strip_suffix(".traineddata").map(String::from)
+                })
+                .collect();
+            languages.sort();
+            if languages.is_empty() {
+                "which holds no traineddata".to_string()
+            } else {
+                format!("which holds {}", languages.join(", "))
+            }
+        }
+        Err(e) => format!("which can't be read: {}", e),
+    };
+    format!(
+        "tesseract could not be started with {} ({}); tesserac_data is {}, {}, install \
+         {}.traineddata (e.g. tesseract-ocr-{}) there",
+        lang, e, tessdata, found, lang, lang
+    )
+}
+
+/// The pdfium library, bound the first time a PDF needs it so that runs without PDFs
+/// don't need it at all.
+pub struct LazyPdfium {
+    path: Option<PathBuf>,
+    bound: OnceCell<Result<Pdfium, String>>,
+}
+
+impl LazyPdfium {
+    pub fn new(path: Option<&Path>) -> Self {
+        LazyPdfium {
+            path: path.map(Path::to_path_buf),
+            bound: OnceCell::new(),
+        }
+    }
+
+    /// The library, or why it could not be bound.
+    pub fn get(&self) -> Result<&Pdfium> {
+        self.bound
+            .get_or_init(|| {
+                bind_pdfium(self.path.as_deref())
+                    .map(Pdfium::new)
+                    .map_err(|e| pdfium_error(self.path.as_deref(), e))
+            })
+            .as_ref()
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+pub struct Translator {
+    /// why not if tesseract or its traineddata is missing
+    pub ocr: Result<OcrPool, String>,
+    pub pdfium: LazyPdfium,
+    pub backend: Backend,
+    pub keep_source_text: bool,
+    pub text_format: TextFormat,
+    pub table_format: TableFormat,
+    pub orientation: Orientation,
+    /// join OCR line breaks into paragraphs before translating
+    pub reflow: bool,
+    /// what becomes of tables of contents and indexes in scans, `--toc-handling`
+    pub toc_handling: TocHandling,
+    pub ocr_mask: Vec<Mask>,
+    pub filter: SegmentFilter,
+    /// look for tables in scans and write them as TSV
+    pub detect_tables: bool,
+    /// column layout of scans, `--columns`
+    pub columns: Columns,
+    /// render size of the second OCR pass over PDF pages, `--ocr-multi-pass`
+    pub multi_pass: Option<u32>,
+    /// read unclear blocks of scans turned by 90°, `--detect-rotated-text`
+    pub rotated_text: bool,
+    /// set apart and cut out handwritten notes, `--extract-handwriting`
+    pub handwriting: bool,
+    /// command reading the text of a cut-out note, `handwriting_recognizer`
+    pub handwriting_recognizer: Option<Vec<String>>,
+    /// `Some` with `--preview`, which pages and paragraphs are translated
+    pub preview: Option<Preview>,
+    /// images with a longer side are scaled down to it before OCR
+    pub max_image_side: u32,
+    /// OCR blocks with more characters are split into paragraphs
+    pub max_block_chars: usize,
+    /// `None` with `--no-ocr-cache`
+    pub ocr_cache: Option<OcrCache>,
+    /// images being recognized ahead of their turn, see [`crate::pipeline`]
+    pub prefetched: HashMap<PathBuf, Prefetched>,
+    /// extensions of the attachments to extract and translate, those with a handler
+    pub attachment_extensions: Vec<&'static str>,
+    /// `--email-attachments`
+    pub email_attachments: bool,
+    /// `--pdf-attachments`, off while an attachment is handled so attachments of
+    /// attachments aren't extracted from PDFs
+    pub pdf_attachments: bool,
+    /// `--docx-ocr-images`
+    pub docx_ocr_images: bool,
+    /// `--force-ocr-images`, images taken for photos are recognized too
+    pub force_ocr_images: bool,
+    /// `--html-mode`, how the HTML bodies of emails are sent
+    pub html_mode: HtmlMode,
+    /// `--legacy-names`, page outputs named after the lowercased file name
+    pub legacy_names: bool,
+    pub output_format: OutputFormat,
+    /// width of the page thumbnails in Markdown outputs, 0 for none
+    pub thumbnail_width: u32,
+    /// font of `--output-format overlay`, `None` for the other formats
+    pub overlay: Option<Overlay>,
+    /// temp files of the run, only set for `translate`
+    pub workspace: Option<TempWorkspace>,
+    /// `--render-workers` above 1, processes that render the pages of scans
+    pub render_workers: Option<RenderSettings>,
+    /// `--docx-password`, for password-protected docx files
+    pub docx_password: Option<String>,
+    /// how file names are translated, `--names-mode`
+    pub names_mode: NamesMode,
+    pub transliteration: Transliteration,
+    /// `--filename-case` and `--filename-separator`
+    pub name_style: NameStyle,
+    tessdata: String,
+    /// language `ocr` was started with
+    ocr_lang: String,
+    /// tesseract instances in the pool
+    ocr_threads: usize,
+}
+
+impl Translator {
+    /// Fails if the servers of config.toml can't be set up, like with an invalid proxy.
+    pub fn new(config: &Config, usage: Usage) -> Result<Self> {
+        let ocr_threads = config.ocr_threads.unwrap_or_else(ocr_pool::default_size);
+        let mut backend = Backend::new(
+            &config.libretranslate_url.list(),
+            usage,
+            config.pool_size.unwrap_or(DEFAULT_POOL_SIZE),
+            config.guardrails.clone(),
+            config.batching.clone(),
+            &config.network,
+        )?;
+        backend.scripts = config.scripts.clone();
+        backend.pii = config.pii.clone();
+        backend.source_lang = config.preset.source_lang.clone();
+        for preset in presets::all(&config.presets).into_values() {
+            backend
+                .abbreviations
+                .entry(preset.source_lang)
+                .or_insert(preset.abbreviations);
+        }
+        let preset = &config.preset;
+        backend
+            .abbreviations
+            .insert(preset.source_lang.clone(), preset.abbreviations.clone());
+        let lang = &config.preset.ocr_lang;
+        Ok(Translator {
+            ocr: OcrPool::new(&config.tesserac_data, lang, ocr_threads)
+                .map_err(|e| tesseract_error(&config.tesserac_data, lang, e)),
+            pdfium: LazyPdfium::new(config.pdfium_path.as_deref()),
+            backend,
+            keep_source_text: false,
+            text_format: TextFormat::default(),
+            table_format: TableFormat::Markdown,
+            orientation: Orientation::Auto,
+            reflow: true,
+            toc_handling: TocHandling::default(),
+            ocr_mask: Vec::new(),
+            filter: SegmentFilter::default(),
+            detect_tables: false,
+            columns: Columns::Auto,
+            multi_pass: None,
+            rotated_text: false,
+            handwriting: false,
+            handwriting_recognizer: config.handwriting_recognizer.clone(),
+            preview: None,
+            max_image_side: config.max_image_side.unwrap_or(ocr::DEFAULT_MAX_IMAGE_SIDE),
+            max_block_chars: config
+                .max_block_chars
+                .unwrap_or(ocr::DEFAULT_MAX_BLOCK_CHARS),
+            ocr_cache: None,
+            prefetched: HashMap::new(),
+            attachment_extensions: Vec::new(),
+            email_attachments: false,
+            pdf_attachments: false,
+            docx_ocr_images: false,
+            force_ocr_images: false,
+            html_mode: HtmlMode::Client,
+            legacy_names: false,
+            output_format: OutputFormat::Text,
+            thumbnail_width: DEFAULT_THUMBNAIL_WIDTH,
+            overlay: None,
+            workspace: None,
+            render_workers: None,
+            docx_password: None,
+            names_mode: NamesMode::Translate,
+            transliteration: Transliteration::new(&config.transliteration),
+            name_style: NameStyle {
+                acronyms: config.acronyms.clone(),
+                ..NameStyle::default()
+            },
+            tessdata: config.tesserac_data.clone(),
+            ocr_lang: config.preset.ocr_lang.clone(),
+            ocr_threads,
+        })
+    }
+
+    /// Switches to the settings of the next file, reinitializing tesseract if the OCR
+    /// language changes. Fails if the language pair or OCR language isn't available.
+    pub async fn apply(&mut self, settings: &Settings) -> Result<()> {
+        self.backend.check_pair(&settings.source_lang).await?;
+        if settings.ocr_lang != self.ocr_lang {
+            let lang = &settings.ocr_lang;
+            let pool = OcrPool::new(&self.tessdata, lang, self.ocr_threads).map_err(|e| {
+                let e = anyhow!("{}", tesseract_error(&self.tessdata, lang, e));
+                error(ErrorKind::OcrInitError, e)
+            })?;
+            self.ocr = Ok(pool);
+            self.ocr_lang = settings.ocr_lang.clone();
+        }
+        self.backend.source_lang = settings.source_lang.clone();
+        self.keep_source_text = settings.keep_source_text;
+        self.table_format = settings.docx_tables;
+        self.orientation = settings.orientation;
+        self.reflow = settings.reflow;
+        self.ocr_mask = settings.ocr_mask.clone();
+        Ok(())
+    }
+
+    /// Content hash of `file` for the OCR cache keys, `None` without OCR cache.
+    pub fn ocr_file_hash(&self, file: &Path) -> Result<Option<u64>> {
+        match self.ocr_cache {
+            Some(_) => Ok(Some(ocr::hash_file(file)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Language the OCR pool was started with.
+    pub fn ocr_lang(&self) -> &str {
+        &self.ocr_lang
+    }
+
+    /// What tesseract is run with for the current file.
+    pub fn ocr_options(&self) -> ocr::Options {
+        ocr::Options {
+            orientation: self.orientation,
+            masks: self.ocr_mask.clone(),
+            detect_tables: self.detect_tables,
+            columns: self.columns,
+            multi_pass: self.multi_pass,
+            rotated_text: self.rotated_text,
+            handwriting: self.handwriting,
+            max_block_chars: self.max_block_chars,
+        }
+    }
+
+    /// OCR cache key of page `page` of a file under the current settings.
+    pub fn ocr_key(&self, file_hash: Option<u64>, page: usize) -> Option<String> {
+        file_hash.map(|hash| ocr::key(hash, page, &self.ocr_lang, &self.ocr_options()))
+    }
+
+    /// The cached OCR result for `key`. A miss is an error with `--retranslate-only`.
+    pub fn cached_ocr(&self, key: Option<&str>) -> Result<Option<OcrPage>> {
+        let (Some(cache), Some(key)) = (&self.ocr_cache, key) else {
+            return Ok(None);
+        };
+        match cache.get(key) {
+            Some(page) => Ok(Some(page)),
+            None if cache.only => Err(anyhow!(
+                "no cached OCR result, run without --retranslate-only first"
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn cache_ocr(&self, key: Option<&str>, page: &OcrPage) -> Result<()> {
+        match (&self.ocr_cache, key) {
+            (Some(cache), Some(key)) => cache.put(key, page),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn translate(&mut self, text: &str) -> Result<String> {
+        self.backend.translate(text).await
+    }
+}
+
+pub fn ocr_pool(ocr: &Result<OcrPool, String>) -> Result<&OcrPool> {
+    ocr.as_ref().map_err(|e| anyhow!("{}", e))
+}
@@ -0,0 +1,71 @@
+//! Files no enabled handler takes, counted by extension with their bytes as the run
+//! comes across them. The summary shows the largest ten with the disabled handler that
+//! would take them, and a run that left out more than `max_unsupported_share` of the
+//! bytes it saw exits as partial.
+
+use crate::handlers::HandlerRegistry;
+use crate::stats::human_bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Share of the bytes left out as unsupported beyond which the run is partial, unless
+/// `max_unsupported_share` of config.toml says otherwise.
+pub const DEFAULT_MAX_SHARE: f64 = 0.5;
+/// Extensions shown in the summary.
+const SHOWN: usize = 10;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Unsupported {
+    pub files: usize,
+    pub bytes: u64,
+    /// handler that takes these files but is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled_handler: Option<String>,
+}
+
+/// Counts `path` under its extension, lowercased, empty for none.
+pub fn add(
+    extensions: &mut BTreeMap<String, Unsupported>,
+    path: &Path,
+    registry: &HandlerRegistry,
+) -> u64 {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let bytes = std::fs::metadata(path).map_or(0, |m| m.len());
+    let entry = extensions.entry(extension).or_default();
+    entry.files += 1;
+    entry.bytes += bytes;
+    entry.disabled_handler = registry.disabled_for(path).map(String::from);
+    bytes
+}
+
+pub fn print(extensions: &BTreeMap<String, Unsupported>) {
+    let mut largest: Vec<_> = extensions.iter().collect();
+    largest.sort_by_key(|(_, e)| std::cmp::Reverse(e.bytes));
+    for (extension, unsupported) in largest.iter().take(SHOWN) {
+        let name = match extension.is_empty() {
+            true => "(none)".to_string(),
+            false => format!(".{}", extension),
+        };
+        let hint = match &unsupported.disabled_handler {
+            Some(handler) => format!(", the {} handler takes them but is disabled", handler),
+            None => String::new(),
+        };
+        eprintln!(
+            "  {}: {} files, {}{}",
+            name,
+            unsupported.files,
+            human_bytes(unsupported.bytes),
+            hint
+        );
+    }
+    if largest.len() > SHOWN {
+        eprintln!(
+            "  and {} more extensions, see the report",
+            largest.len() - SHOWN
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Monthly count of characters sent to the translation backend, persisted in the cache
+//! dir so runs against a quota-limited server can stop before exceeding it.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const USAGE_FILE: &str = "usage.json";
+
+pub struct Usage {
+    path: PathBuf,
+    month: String,
+    months: BTreeMap<String, usize>,
+    budget: Option<usize>,
+    /// characters sent during this run
+    pub sent: usize,
+}
+
+impl Usage {
+    pub fn load(cache_dir: &Path, budget: Option<usize>) -> Result<Self> {
+        let path = cache_dir.join(USAGE_FILE);
+        let months = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Usage {
+            path,
+            month: chrono::Local::now().format("%Y-%m").to_string(),
+            months,
+            budget,
+            sent: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn month(&self) -> &str {
+        &self.month
+    }
+
+    /// Characters used this month, including this run.
+    pub fn used(&self) -> usize {
+        self.months.get(&self.month).copied().unwrap_or(0)
+    }
+
+    /// Characters left in the budget this month, `None` if there is no budget.
+    pub fn remaining(&self) -> Option<usize> {
+        self.budget.map(|b| b.saturating_sub(self.used()))
+    }
+
+    /// Books `chars` against the budget. Returns false, without booking anything, if the
+    /// request would exceed it.
+    pub fn spend(&mut self, chars: usize) -> bool {
+        if matches!(self.remaining(), Some(remaining) if chars > remaining) {
+            return false;
+        }
+        *self.months.entry(self.month.clone()).or_default() += chars;
+        self.sent += chars;
+        true
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&self.months)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+//! Temp files of a run live in one workspace directory under the system temp dir, or
+//! `--temp-dir`, removed when the run ends: on a normal exit or a panic by the guard's
+//! drop, on a fatal error or Ctrl-C through [`cleanup`]. Workspaces of runs that were
+//! killed are swept at the next start once they are a day old. `--keep-temp` keeps the
+//! workspace, with the page renders handed to OCR, for a look at a misbehaving file.
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const PREFIX: &str = "dir-translate-run-";
+/// Age at which a workspace left behind by another run is removed.
+const ORPHAN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The workspace to remove if the run ends without dropping its guard.
+static ACTIVE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub struct TempWorkspace {
+    dir: PathBuf,
+    keep: bool,
+    /// numbers the file directories, sources of the same name don't share one
+    next: AtomicUsize,
+}
+
+impl TempWorkspace {
+    /// Creates the workspace of this run in `base`, the system temp dir if `None`, after
+    /// removing orphaned ones there.
+    pub fn create(base: Option<&Path>, keep: bool) -> Result<Self> {
+        let base = base.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        sweep(&base);
+        let name = format!(
+            "{}{}-{}",
+            PREFIX,
+            chrono::Local::now().format("%Y%m%d-%H%M%S"),
+            std::process::id()
+        );
+        let dir = base.join(name);
+        std::fs::create_dir_all(&dir).map_err(|e| anyhow!("{}: {}", dir.display(), e))?;
+        if keep {
+            eprintln!("temp files are kept in {}", dir.display());
+        } else {
+            *ACTIVE.lock().unwrap() = Some(dir.clone());
+        }
+        Ok(TempWorkspace {
+            dir,
+            keep,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Whether the workspace outlives the run, `--keep-temp`.
+    pub fn keep(&self) -> bool {
+        self.keep
+    }
+
+    /// The directory `extract` writes the outputs to before they go into the bundle.
+    pub fn extract_dir(&self) -> PathBuf {
+        self.dir.join("extract")
+    }
+
+    /// The directory the files of a `--source-dir` URL are downloaded to.
+    pub fn spill_dir(&self) -> PathBuf {
+        self.dir.join("source")
+    }
+
+    /// A new directory in the workspace for the temp files of `source`.
+    pub fn file_dir(&self, source: &Path) -> Result<PathBuf> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let name = source.file_name().unwrap_or_default().to_string_lossy();
+        let dir = self.dir.join(format!("{:04}-{}", index, name));
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+}
+
+impl Drop for TempWorkspace {
+    fn drop(&mut self) {
+        if !self.keep {
+            cleanup();
+        }
+    }
+}
+
+/// Removes the workspace of this run unless it is kept. Called before exiting without
+/// unwinding.
+pub fn cleanup() {
+    if let Some(dir) = ACTIVE.lock().unwrap().take() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            eprintln!("could not remove {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Removes the workspaces in `base` older than [`ORPHAN_AGE`], left by runs that were
+/// killed. Kept ones are swept as well once they are that old.
+fn sweep(base: &Path) {
+    let Ok(entries) = std::fs::read_dir(base) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(PREFIX) || running(&name) {
+            continue;
+        }
+        let old = entry
+            .metadata()
+            .ok()
+            .filter(|m| m.is_dir())
+            .and_then(|m| m.modified().ok())
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > ORPHAN_AGE);
+        if old {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+/// Whether the run that created the workspace `name` is still going, as far as `/proc`
+/// tells.
+fn running(name: &str) -> bool {
+    let pid = name.rsplit('-').next().unwrap_or_default();
+    cfg!(target_os = "linux") && Path::new("/proc").join(pid).exists()
+}
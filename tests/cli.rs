@@ -0,0 +1,4226 @@
+//! Runs the binary from a working directory that has neither the config nor the sources.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Fresh directory under the system temp dir with a `conf/config.toml` pointing at `url`,
+/// a `src/` and an empty `cwd/` to run from.
+fn scratch(name: &str, url: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dir-translate-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("conf")).unwrap();
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::create_dir_all(dir.join("cwd")).unwrap();
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = \"tessdata\"\nlibretranslate_url = \"{}\"\n",
+            url
+        ),
+    )
+    .unwrap();
+    dir
+}
+
+/// Nothing listens there, requests fail right away.
+const NO_SERVER: &str = "http://127.0.0.1:9";
+
+fn run(cwd: &Path, args: &[&str]) -> Output {
+    run_with_env(cwd, args, &[])
+}
+
+fn run_with_env(cwd: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dir-translate"))
+        .current_dir(cwd)
+        .args(args)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn config_is_found_through_the_flag() {
+    let dir = scratch("config", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--list-handlers",
+            "translate",
+            "out",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("docx"));
+}
+
+#[test]
+fn missing_config_is_reported() {
+    let dir = scratch("no-config", NO_SERVER);
+    let output = run(
+        &dir.join("cwd"),
+        &["-s", dir.join("src").to_str().unwrap(), "translate", "out"],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("config config.toml"));
+}
+
+#[test]
+fn an_empty_server_list_is_a_config_error() {
+    let dir = scratch("no-servers", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    std::fs::write(
+        &config,
+        "tesserac_data = \"tessdata\"\nlibretranslate_url = []\n",
+    )
+    .unwrap();
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            "out",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("libretranslate_url is an empty list"));
+}
+
+#[test]
+fn an_invalid_proxy_is_a_config_error() {
+    let dir = scratch("bad-proxy", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    std::fs::write(
+        &config,
+        format!(
+            "tesserac_data = \"tessdata\"\nlibretranslate_url = \"{}\"\nproxy_url = \"http://proxy corp:3128\"\n",
+            NO_SERVER
+        ),
+    )
+    .unwrap();
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            "out",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("invalid proxy http://proxy corp:3128")
+    );
+}
+
+#[test]
+fn missing_source_dir_is_reported() {
+    let dir = scratch("no-source", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            "missing",
+            "translate",
+            "out",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("source dir missing:"));
+    assert!(!dir.join("cwd/out").exists());
+}
+
+#[test]
+fn unwritable_outputs_are_io_errors() {
+    let (url, _) = serve_fake(Behavior::Reverse);
+    let dir = scratch("unwritable", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    std::fs::write(
+        dir.join("src/letters/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    // a file where a dir has to go
+    std::fs::create_dir_all(dir.join("out")).unwrap();
+    std::fs::write(dir.join("out/letters"), "").unwrap();
+    let config = dir.join("conf/config.toml");
+    let args = ["--config", config.to_str().unwrap(), "-s"];
+    let src = dir.join("src");
+    let out = dir.join("out");
+    let mut translate = args.to_vec();
+    translate.extend([src.to_str().unwrap(), "translate", out.to_str().unwrap()]);
+    let output = run(&dir.join("cwd"), &translate);
+    assert_eq!(output.status.code(), Some(6), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("out/letters:"));
+    let mapping = dir.join("out/letters/names.tsv");
+    let mut filenames = args.to_vec();
+    filenames.extend([
+        src.to_str().unwrap(),
+        "filenames",
+        "-o",
+        mapping.to_str().unwrap(),
+    ]);
+    let output = run(&dir.join("cwd"), &filenames);
+    assert_eq!(output.status.code(), Some(6), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("names.tsv:"));
+}
+
+#[test]
+fn tessdata_is_relative_to_the_config() {
+    let dir = scratch("tessdata", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "check",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let tessdata = std::fs::canonicalize(dir.join("conf"))
+        .unwrap()
+        .join("tessdata");
+    assert!(stdout.contains(tessdata.to_str().unwrap()), "{}", stdout);
+}
+
+/// Answers `Абзац N` with `Paragraph N` after a delay that shrinks with N, so later
+/// segments come back first, and fails every segment containing `сбой`.
+fn serve_out_of_order() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            std::thread::spawn(move || answer(stream, &out_of_order));
+        }
+    });
+    url
+}
+
+/// Status and body of the response to a request line and body.
+type Respond<'a> = dyn Fn(&str, &[u8]) -> (&'static str, String) + 'a;
+
+/// Answers the HTTP requests on `stream` with the status and body `respond` gives for
+/// the request line and body.
+fn answer(stream: TcpStream, respond: &Respond<'_>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let (status, response) = respond(&request_line, &body);
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        );
+    }
+}
+
+fn out_of_order(request_line: &str, body: &[u8]) -> (&'static str, String) {
+    if !request_line.starts_with("POST /translate") {
+        return ("404 Not Found", String::new());
+    }
+    let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+    let q = request["q"].as_str().unwrap();
+    let number: u64 = q.trim_start_matches("Абзац ").parse().unwrap_or(0);
+    std::thread::sleep(Duration::from_millis(20 * (10 - number.min(10))));
+    let response = if q.contains("сбой") {
+        serde_json::json!({ "error": "model crashed" })
+    } else {
+        serde_json::json!({ "translatedText": q.replace("Абзац", "Paragraph") })
+    };
+    ("200 OK", response.to_string())
+}
+
+/// What the LibreTranslate of [`serve_fake`] answers to `/translate`.
+#[derive(Clone, Copy)]
+enum Behavior {
+    /// every text as it was sent
+    Echo,
+    /// every text in Latin letters with its words in reverse order, `Абзац 1` as `1 Abzac`
+    Reverse,
+    /// a 500 for every Nth request, the others in Latin letters, `Abzac 1`
+    FailEveryNth(usize),
+    /// a 429 for every request after the first N, which are in Latin letters
+    RateLimit(usize),
+}
+
+/// A LibreTranslate that knows Russian and English, with `/translate`, `/languages` and
+/// `/detect`, translating as `behavior` says. Returns its URL and the number of
+/// `/translate` requests it got so far.
+fn serve_fake(behavior: Behavior) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let requests = counted.clone();
+            std::thread::spawn(move || {
+                answer(stream, &|request_line, body| {
+                    fake(behavior, &requests, request_line, body)
+                })
+            });
+        }
+    });
+    (url, requests)
+}
+
+fn fake(
+    behavior: Behavior,
+    requests: &AtomicUsize,
+    request_line: &str,
+    body: &[u8],
+) -> (&'static str, String) {
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let response = match path {
+        "/languages" => serde_json::json!([
+            { "code": "ru", "name": "Russian", "targets": ["en"] },
+            { "code": "en", "name": "English", "targets": ["ru"] },
+        ]),
+        "/detect" => serde_json::json!([{ "language": "ru", "confidence": 90.0 }]),
+        "/translate" => {
+            let n = requests.fetch_add(1, Ordering::SeqCst) + 1;
+            match behavior {
+                Behavior::FailEveryNth(every) if n.is_multiple_of(every) => {
+                    let error = serde_json::json!({ "error": "scripted failure" });
+                    return ("500 Internal Server Error", error.to_string());
+                }
+                Behavior::RateLimit(allowed) if n > allowed => {
+                    let error = serde_json::json!({ "error": "Slowdown: 1 per 1 second" });
+                    return ("429 Too Many Requests", error.to_string());
+                }
+                _ => {}
+            }
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            let translate = |q: &serde_json::Value| {
+                let q = q.as_str().unwrap();
+                let translated = match behavior {
+                    Behavior::Echo => q.to_string(),
+                    Behavior::Reverse => {
+                        let words: Vec<_> = q.split_whitespace().rev().collect();
+                        transliterate(&words.join(" "))
+                    }
+                    _ => transliterate(q),
+                };
+                serde_json::Value::from(translated)
+            };
+            let translated = match &request["q"] {
+                serde_json::Value::Array(q) => q.iter().map(translate).collect(),
+                q => translate(q),
+            };
+            serde_json::json!({ "translatedText": translated })
+        }
+        _ => return ("404 Not Found", String::new()),
+    };
+    ("200 OK", response.to_string())
+}
+
+/// `text` with its Cyrillic letters in Latin ones, which passes the script guardrail.
+fn transliterate(text: &str) -> String {
+    const LATIN: [&str; 33] = [
+        "a", "b", "v", "g", "d", "e", "zh", "z", "i", "y", "k", "l", "m", "n", "o", "p", "r", "s",
+        "t", "u", "f", "h", "c", "ch", "sh", "sch", "", "y", "", "e", "yu", "ya", "e",
+    ];
+    let mut latin = String::with_capacity(text.len());
+    for c in text.chars() {
+        let lower = c.to_lowercase().next().unwrap();
+        let index = match lower {
+            'а'..='я' => lower as usize - 'а' as usize,
+            'ё' => 32,
+            _ => {
+                latin.push(c);
+                continue;
+            }
+        };
+        let letters = LATIN[index];
+        if c.is_uppercase() {
+            let mut letters = letters.chars();
+            latin.extend(letters.next().into_iter().flat_map(char::to_uppercase));
+            latin.push_str(letters.as_str());
+        } else {
+            latin.push_str(letters);
+        }
+    }
+    latin
+}
+
+/// Writes a docx whose `word/document.xml` has `body` in its `<w:body>`.
+fn write_docx(path: &Path, body: &str) {
+    let mut docx = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    docx.start_file("[Content_Types].xml", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+          <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+          <Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\
+          </Types>",
+    )
+    .unwrap();
+    docx.start_file("_rels/.rels", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+          <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\
+          </Relationships>",
+    )
+    .unwrap();
+    docx.start_file("word/document.xml", options).unwrap();
+    docx.write_all(
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">\
+             <w:body>{}</w:body></w:document>",
+            body
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    docx.finish().unwrap();
+}
+
+/// Whether an OCR test has to stop as tesseract, its rus data or pdfium is `missing`. It
+/// fails instead with `--features ocr-tests`, for CI that has them.
+fn skip_ocr(missing: bool) -> bool {
+    if missing {
+        if cfg!(feature = "ocr-tests") {
+            panic!("tesseract, its rus data or pdfium is missing");
+        }
+        eprintln!("skipped, tesseract, its rus data or pdfium is missing");
+    }
+    missing
+}
+
+#[test]
+fn docx_paragraphs_and_tables_are_translated() {
+    let (url, requests) = serve_fake(Behavior::Reverse);
+    let dir = scratch("fake-docx", &url);
+    let cell = |text: &str| format!("<w:tc><w:p><w:r><w:t>{}</w:t></w:r></w:p></w:tc>", text);
+    write_docx(
+        &dir.join("src/memo.docx"),
+        &format!(
+            "<w:p><w:r><w:t>Абзац 1.</w:t></w:r></w:p>\
+             <w:tbl><w:tblGrid><w:gridCol w:w=\"2000\"/><w:gridCol w:w=\"2000\"/></w:tblGrid><w:tr>{}{}</w:tr><w:tr>{}{}</w:tr></w:tbl>",
+            cell("Номер"),
+            cell("Сумма"),
+            cell("Первый"),
+            cell("Десять рублей")
+        ),
+    );
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/memo.docx.txt")).unwrap();
+    assert!(text.contains("1. Abzac"), "{}", text);
+    assert!(text.contains("| Nomer | Summa |"), "{}", text);
+    assert!(text.contains("| Pervyy | rubley Desyat |"), "{}", text);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["processed"], 1, "{}", report);
+    assert_eq!(report["summary"]["failed_segments"], 0, "{}", report);
+    assert!(requests.load(Ordering::SeqCst) >= 5);
+}
+
+#[test]
+fn segments_failed_by_the_server_are_reported() {
+    let (url, requests) = serve_fake(Behavior::FailEveryNth(3));
+    let dir = scratch("fake-failures", &url);
+    let body: Vec<String> = (1..=6).map(|n| format!("Абзац {}", n)).collect();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            body.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let failed = report["summary"]["failed_segments"].as_u64().unwrap() as usize;
+    assert!(failed >= 1, "{:?} {}", output, report);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert_eq!(text.matches("Abzac").count(), 6 - failed, "{}", text);
+    assert_eq!(
+        report["files"][0]["failures"].as_array().unwrap().len(),
+        failed
+    );
+    assert!(requests.load(Ordering::SeqCst) >= 6);
+}
+
+#[test]
+fn segments_refused_by_a_rate_limit_fail() {
+    let (url, requests) = serve_fake(Behavior::RateLimit(2));
+    let dir = scratch("fake-rate-limit", &url);
+    let body: Vec<String> = (1..=6).map(|n| format!("Абзац {}", n)).collect();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            body.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    // refused segments leave markers, the run is incomplete
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let sent = requests.load(Ordering::SeqCst);
+    assert_eq!(report["summary"]["failed_segments"], sent - 2, "{}", report);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("run retry-failures"));
+}
+
+#[test]
+fn segments_keep_their_order_when_answered_out_of_order() {
+    let url = serve_out_of_order();
+    let dir = scratch("order", &url);
+    let paragraphs = [
+        "Абзац 1",
+        "Абзац 2",
+        "сбой",
+        "Абзац 4",
+        "Абзац 5",
+        "сбой",
+        "Абзац 7",
+        "Абзац 8",
+    ];
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            paragraphs.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--translate-workers",
+            "8",
+        ],
+    );
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt"))
+        .unwrap_or_else(|e| panic!("{}: {:?}", e, output));
+    let segments: Vec<&str> = text.split("\n\n").filter(|s| !s.is_empty()).collect();
+    assert_eq!(
+        segments,
+        [
+            "Paragraph 1",
+            "Paragraph 2",
+            "[[dir-translate: failed segment 2]]",
+            "Paragraph 4",
+            "Paragraph 5",
+            "[[dir-translate: failed segment 5]]",
+            "Paragraph 7",
+            "Paragraph 8",
+        ],
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn metrics_are_written_for_prometheus() {
+    let url = serve_out_of_order();
+    let dir = scratch("metrics", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\nсбой\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let metrics = dir.join("metrics.prom");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--metrics",
+            "--metrics-file",
+            metrics.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    let text = std::fs::read_to_string(&metrics).unwrap_or_else(|e| panic!("{}: {:?}", e, output));
+    assert!(
+        text.contains("dir_translate_segments_total 1\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("dir_translate_characters_total 7\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("dir_translate_failed_segments_total 1\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("dir_translate_backend_request_seconds_count 2\n"),
+        "{}",
+        text
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("segments translated: 1"));
+}
+
+#[test]
+fn outputs_can_go_into_a_zip() {
+    let url = serve_out_of_order();
+    let dir = scratch("archive", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    std::fs::write(
+        dir.join("src/letters/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let archive = dir.join("out.zip");
+    let (source, report, target) = (dir.join("src"), dir.join("report.json"), dir.join("out"));
+    let args = [
+        "--config",
+        config.to_str().unwrap(),
+        "-s",
+        source.to_str().unwrap(),
+        "--report",
+        report.to_str().unwrap(),
+        "translate",
+        target.to_str().unwrap(),
+        "--output-archive",
+        archive.to_str().unwrap(),
+    ];
+    let output = run(&dir.join("cwd"), &args);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(&archive).unwrap()).unwrap();
+    let names: Vec<String> = zip.file_names().map(String::from).collect();
+    assert_eq!(
+        names,
+        ["letters/letter.eml.txt", "manifest.jsonl", "report.json"],
+        "{:?}",
+        names
+    );
+    let mut text = String::new();
+    zip.by_name("letters/letter.eml.txt")
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text.trim(), "Paragraph 1");
+    assert!(!dir.join("out/letters").exists());
+    assert!(!dir.join("out/manifest.jsonl").exists());
+
+    let output = run(&dir.join("cwd"), &args);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not resumed"));
+}
+
+#[test]
+fn only_the_source_half_of_bilingual_lines_is_sent() {
+    let url = serve_out_of_order();
+    let dir = scratch("bilingual", &url);
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bilingual.eml");
+    std::fs::copy(fixture, dir.join("src/contract.eml")).unwrap();
+    let config = dir.join("conf/config.toml");
+    let metrics = dir.join("metrics.prom");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--split-mixed-segments",
+            "--metrics-file",
+            metrics.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/contract.eml.txt")).unwrap();
+    assert!(text.contains("Paragraph 1 / Paragraph 1"), "{}", text);
+    assert!(
+        text.contains("Срок действия договора – 12 месяцев (Term of the Agreement – 12 months)"),
+        "{}",
+        text
+    );
+    // the Cyrillic runs only, the English halves and the separators between are kept
+    let metrics = std::fs::read_to_string(&metrics).unwrap();
+    assert!(
+        metrics.contains("dir_translate_characters_total 122\n"),
+        "{}",
+        metrics
+    );
+}
+
+#[test]
+fn files_can_be_named_after_their_content() {
+    let url = serve_out_of_order();
+    let dir = scratch("names-from-content", &url);
+    std::fs::write(
+        dir.join("src/исх.№45 от 12.03.eml"),
+        "Subject: Абзац 3\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let plan = dir.join("plan.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "filenames",
+            "--plan",
+            plan.to_str().unwrap(),
+            "--names-from-content",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let plan: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&plan).unwrap()).unwrap();
+    let entry = &plan[0];
+    let target = Path::new(entry["target"].as_str().unwrap());
+    assert_eq!(
+        target.file_name().unwrap(),
+        "Paragraph 3 №45 12.03.eml",
+        "{}",
+        entry
+    );
+    assert!(entry["source"]
+        .as_str()
+        .unwrap()
+        .ends_with("исх.№45 от 12.03.eml"));
+    assert_eq!(entry["issues"], "");
+}
+
+#[test]
+fn colliding_translated_names_get_a_suffix_of_their_own() {
+    let url = serve_out_of_order();
+    let dir = scratch("name-collisions", &url);
+    // both become "Paragraph 3" on a case-insensitive file system, and the first one
+    // "paragraph 1.eml", which stays where it is
+    for name in [
+        "Абзац 3.eml",
+        "Абзац 3.EML",
+        "Абзац 1.eml",
+        "paragraph 1.eml",
+    ] {
+        std::fs::write(dir.join("src").join(name), "Абзац 1\r\n").unwrap();
+    }
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let plan = |name: &str| {
+        let plan = dir.join(name);
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                source.to_str().unwrap(),
+                "filenames",
+                "--plan",
+                plan.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(output.status.code(), Some(0), "{:?}", output);
+        let plan: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&plan).unwrap()).unwrap();
+        let mut targets: Vec<(String, String)> = plan
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let name = |key: &str| {
+                    let path = Path::new(entry[key].as_str().unwrap());
+                    path.file_name().unwrap().to_string_lossy().to_string()
+                };
+                (name("source"), name("target"))
+            })
+            .collect();
+        targets.sort();
+        (targets, plan)
+    };
+    let (targets, first) = plan("first.json");
+    let suffixed = |source: &str, stem: &str, extension: &str| {
+        let (_, target) = targets.iter().find(|(s, _)| s == source).unwrap();
+        let suffix = target
+            .strip_prefix(stem)
+            .and_then(|t| t.strip_suffix(extension))
+            .unwrap_or_else(|| panic!("{}", target));
+        assert!(suffix.len() == 7 && suffix.starts_with('-'), "{}", target);
+        suffix.to_string()
+    };
+    let three = suffixed("Абзац 3.eml", "Paragraph 3", ".eml");
+    assert_ne!(three, suffixed("Абзац 3.EML", "Paragraph 3", ".EML"));
+    suffixed("Абзац 1.eml", "Paragraph 1", ".eml");
+    assert!(targets.contains(&("paragraph 1.eml".into(), "paragraph 1.eml".into())));
+    for entry in first.as_array().unwrap() {
+        if entry["source"] != entry["target"] {
+            let issues = entry["issues"].as_str().unwrap();
+            assert!(issues.contains("to avoid a collision with"), "{}", entry);
+        }
+    }
+    // the same files get the same names in a second plan
+    assert_eq!(plan("second.json").0, targets);
+
+    let plan = dir.join("first.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "apply-plan",
+            plan.to_str().unwrap(),
+            "--dry-run",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn temp_dirs_are_removed_unless_kept() {
+    let url = serve_out_of_order();
+    let dir = scratch("temp", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let temp = dir.join("tmp");
+    // left by a run that was killed two days ago, and by one that just started
+    let orphan = temp.join("dir-translate-run-20000101-000000-999999999");
+    let recent = temp.join("dir-translate-run-20000101-000000-999999998");
+    std::fs::create_dir_all(&orphan).unwrap();
+    std::fs::create_dir_all(&recent).unwrap();
+    let two_days = Duration::from_secs(2 * 24 * 60 * 60);
+    std::fs::File::open(&orphan)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - two_days)
+        .unwrap();
+    let config = dir.join("conf/config.toml");
+    let (source, target) = (dir.join("src"), dir.join("out"));
+    let args = [
+        "--config",
+        config.to_str().unwrap(),
+        "-s",
+        source.to_str().unwrap(),
+        "--temp-dir",
+        temp.to_str().unwrap(),
+        "translate",
+        target.to_str().unwrap(),
+    ];
+    let output = run(&dir.join("cwd"), &args);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let left: Vec<_> = std::fs::read_dir(&temp)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(left, [recent]);
+
+    let output = run(&dir.join("cwd"), &[&args[..], &["--keep-temp"]].concat());
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let kept = stderr
+        .lines()
+        .find_map(|l| l.strip_prefix("temp files are kept in "))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert!(Path::new(kept).is_dir());
+    assert!(Path::new(kept).starts_with(&temp));
+}
+
+#[test]
+fn url_sources_are_downloaded_one_file_at_a_time() {
+    let url = serve_out_of_order();
+    let dir = scratch("url-source", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    std::fs::write(
+        dir.join("src/letters/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = format!("file://{}", dir.join("src").display());
+    let temp = dir.join("tmp");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            &source,
+            "--temp-dir",
+            temp.to_str().unwrap(),
+            "--report",
+            dir.join("report.json").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/letters/letter.eml.txt")).unwrap();
+    assert!(text.contains("Paragraph 1"), "{}", text);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("report.json")).unwrap()).unwrap();
+    assert_eq!(
+        report["files"][0]["source"],
+        format!("{}/letters/letter.eml", source)
+    );
+    assert_eq!(std::fs::read_dir(&temp).unwrap().count(), 0);
+
+    let output = run(
+        &dir.join("cwd"),
+        &["--config", config.to_str().unwrap(), "-s", &source, "stats"],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+}
+
+#[test]
+fn attachments_are_reported_under_their_parent() {
+    let url = serve_out_of_order();
+    let dir = scratch("attachments", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+         --b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\
+         --b\r\nContent-Type: message/rfc822\r\n\
+         Content-Disposition: attachment; filename=\"forwarded.eml\"\r\n\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 2\r\n\
+         --b--\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--email-attachments",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let attached = dir.join("out/letter/attachments/forwarded.eml.txt");
+    let text = std::fs::read_to_string(&attached).unwrap();
+    assert!(text.contains("Paragraph 2"), "{}", text);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let attachments = files[0]["attachments"].as_array().unwrap();
+    assert_eq!(attachments.len(), 1, "{:?}", attachments);
+    assert!(attachments[0]["source"]
+        .as_str()
+        .unwrap()
+        .ends_with("forwarded.eml"));
+    assert_eq!(attachments[0]["handler"], "email");
+    assert!(attachments[0]["error"].is_null());
+}
+
+#[test]
+fn a_corrupt_attachment_fails_the_run() {
+    let url = serve_out_of_order();
+    let dir = scratch("corrupt-attachment", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+         --b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\
+         --b\r\nContent-Type: application/vnd.openxmlformats-officedocument.wordprocessingml.document\r\n\
+         Content-Disposition: attachment; filename=\"memo.docx\"\r\n\r\n\
+         not a zip\r\n\
+         --b--\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--email-attachments",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+    assert!(text.contains("Paragraph 1"), "{}", text);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["processed"], 1);
+    assert_eq!(report["summary"]["failed"], 1);
+    let attachments = report["files"][0]["attachments"].as_array().unwrap();
+    assert_eq!(attachments.len(), 1, "{:?}", attachments);
+    assert_eq!(attachments[0]["handler"], "docx");
+    assert!(attachments[0]["error"].is_string(), "{:?}", attachments);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn files_still_being_written_are_skipped() {
+    let url = serve_out_of_order();
+    let dir = scratch("settle", &url);
+    let letter = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n";
+    std::fs::write(dir.join("src/done.eml"), letter).unwrap();
+    std::fs::write(dir.join("src/placeholder.eml"), "").unwrap();
+    let mut busy = std::fs::File::create(dir.join("src/busy.eml")).unwrap();
+    busy.write_all(letter.as_bytes()).unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--settle-ms",
+            "100",
+        ],
+    );
+    drop(busy);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert!(dir.join("out/done.eml.txt").exists());
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["unstable"], 1);
+    assert_eq!(report["summary"]["empty"], 1);
+    let error = |name: &str| {
+        let files = report["files"].as_array().unwrap();
+        let file = files
+            .iter()
+            .find(|f| f["source"].as_str().unwrap().ends_with(name))
+            .unwrap();
+        file["error"].as_str().unwrap().to_string()
+    };
+    assert!(error("placeholder.eml").contains("empty"));
+    assert!(error("busy.eml").contains("open for writing"));
+}
+
+#[test]
+fn config_can_come_from_the_environment_alone() {
+    let url = serve_out_of_order();
+    let dir = scratch("env", NO_SERVER);
+    std::fs::write(
+        dir.join("src/note.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let output = run_with_env(
+        &dir.join("cwd"),
+        &[
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--log-format",
+            "json",
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+        &[
+            ("DIR_TRANSLATE_TESSERAC_DATA", "/usr/share/tessdata"),
+            ("DIR_TRANSLATE_LIBRETRANSLATE_URL", &url),
+            ("DIR_TRANSLATE_POOL_SIZE", "2"),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["file_started", "segment", "file", "summary"]);
+    assert_eq!(events[1]["chars"], 7);
+    assert_eq!(events[2]["handler"], "email");
+    assert_eq!(events[3]["processed"], 1);
+}
+
+#[test]
+fn a_corrupt_file_is_a_partial_failure() {
+    let url = serve_out_of_order();
+    let dir = scratch("corrupt", &url);
+    std::fs::write(dir.join("src/broken.docx"), "not a zip").unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["files"][0]["error_kind"], "input_error");
+}
+
+#[test]
+fn an_unreachable_backend_stops_the_run() {
+    let dir = scratch("unreachable", NO_SERVER);
+    std::fs::write(dir.join("src/broken.docx"), "not a zip").unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(4), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("translation server"));
+}
+
+/// Where tesseract's language data is installed, for the tests that run OCR.
+fn system_tessdata() -> String {
+    std::env::var("TESSDATA_PREFIX")
+        .unwrap_or_else(|_| "/usr/share/tesseract-ocr/5/tessdata".to_string())
+}
+
+#[test]
+fn lines_of_a_column_stay_together() {
+    let url = serve_out_of_order();
+    let dir = scratch("columns", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two-columns.png"),
+        dir.join("src/two-columns.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+        ],
+    );
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    let text = std::fs::read_to_string(dir.join("out/two-columns.png.txt"))
+        .unwrap_or_else(|e| panic!("{}: {:?}", e, output));
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    for sentence in [
+        "Первая колонка начинается здесь и продолжается на следующей строке.",
+        "Третье предложение закрывает левую колонку.",
+        "Правая колонка говорит о совсем другом предмете этой старой статьи.",
+        "Последнее предложение завершает правую колонку научного журнала прошлого века без всяких исключений.",
+    ] {
+        assert!(text.contains(sentence), "{:?} not in {}", sentence, text);
+    }
+}
+
+#[test]
+fn second_pass_must_render_larger() {
+    let url = serve_out_of_order();
+    let dir = scratch("multi-pass", &url);
+    let config = dir.join("conf/config.toml");
+    let translate = |size: &str| {
+        run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                dir.join("src").to_str().unwrap(),
+                "translate",
+                dir.join("out").to_str().unwrap(),
+                "--ocr-multi-pass",
+                size,
+            ],
+        )
+    };
+    let output = translate("1500");
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not larger than the first render"));
+    let output = translate("4000");
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+}
+
+#[test]
+fn rotated_stamps_are_read_after_the_page() {
+    let url = serve_out_of_order();
+    let dir = scratch("rotated", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rotated-stamp.png"),
+        dir.join("src/rotated-stamp.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |out: &str, extra: &[&str]| {
+        let target = dir.join(out);
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            target.to_str().unwrap(),
+            "--orientation",
+            "none",
+        ];
+        run(&dir.join("cwd"), &[&args[..], extra].concat())
+    };
+    let output = translate("plain", &[]);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    let output = translate("out", &["--detect-rotated-text"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/rotated-stamp.png.txt")).unwrap();
+    let (body, rotated) = text
+        .split_once("## Rotated text")
+        .unwrap_or_else(|| panic!("no rotated text in {}", text));
+    assert!(rotated.contains("ОДОБРЕНО КОМИССИЕЙ"), "{}", rotated);
+    assert!(!body.contains("ОДОБРЕНО"), "{}", body);
+    let plain = std::fs::read_to_string(dir.join("plain/rotated-stamp.png.txt")).unwrap();
+    for line in ["Первая строка основного текста", "и заканчивается здесь."]
+    {
+        assert!(body.contains(line), "{:?} not in {}", line, body);
+        assert!(plain.contains(line), "{:?} not in {}", line, plain);
+    }
+}
+
+#[test]
+fn handwritten_notes_are_cut_out() {
+    let url = serve_out_of_order();
+    let dir = scratch("handwriting", &url);
+    let config = dir.join("conf/config.toml");
+    let write_config = |recognizer: &str| {
+        std::fs::write(
+            &config,
+            format!(
+                "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n{}",
+                system_tessdata(),
+                url,
+                recognizer
+            ),
+        )
+        .unwrap();
+    };
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/margin-note.png"),
+        dir.join("src/margin-note.png"),
+    )
+    .unwrap();
+    let source = dir.join("src");
+    let translate = |out: &str| {
+        let target = dir.join(out);
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            target.to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--extract-handwriting",
+        ];
+        run(&dir.join("cwd"), &args)
+    };
+    write_config("");
+    let output = translate("out");
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/margin-note.png.txt")).unwrap();
+    assert!(text.contains("Работы выполнены"), "{}", text);
+    assert!(
+        text.contains("[handwritten note, not recognized — see page image]"),
+        "{}",
+        text
+    );
+    assert!(dir
+        .join("out/margin-note/handwriting/page-1-1.png")
+        .exists());
+    // the command gets the crop as its last argument
+    write_config(r#"handwriting_recognizer = ["sh", "-c", "test -f \"$0\" && echo Абзац 9"]"#);
+    let output = translate("recognized");
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("recognized/margin-note.png.txt")).unwrap();
+    assert!(text.contains("Paragraph 9"), "{}", text);
+    assert!(!text.contains("handwritten note"), "{}", text);
+}
+
+#[test]
+fn overlay_needs_a_readable_font() {
+    let url = serve_out_of_order();
+    let dir = scratch("overlay-font", &url);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--output-format",
+            "overlay",
+            "--font-path",
+            dir.join("missing.ttf").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.ttf"));
+}
+
+#[test]
+fn healthcheck_fails_without_a_server() {
+    let dir = scratch("health", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--healthcheck",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("[FAIL] libretranslate"));
+}
+
+#[test]
+fn summaries_are_refused_without_an_llm_backend() {
+    let dir = scratch("summarize", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            "--mode",
+            "summarize",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs an LLM backend"));
+    assert!(!dir.join("out").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn target_reached_through_a_symlink_inside_the_source_is_refused() {
+    let dir = scratch("overlap", NO_SERVER);
+    std::fs::create_dir_all(dir.join("src/out")).unwrap();
+    std::os::unix::fs::symlink(dir.join("src/out"), dir.join("link")).unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("link").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("inside the source dir"));
+}
+
+#[cfg(unix)]
+#[test]
+fn files_can_be_taken_smallest_or_newest_first() {
+    let (url, _) = serve_fake(Behavior::Reverse);
+    let dir = scratch("order", &url);
+    let now = std::time::SystemTime::now();
+    // the largest file is the newest
+    for (name, paragraphs, age) in [("b.eml", 3, 1), ("a.eml", 1, 2), ("c.eml", 2, 3)] {
+        let body: Vec<String> = (1..=paragraphs).map(|n| format!("Абзац {}", n)).collect();
+        let path = dir.join("src").join(name);
+        let email = format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            body.join("\r\n\r\n")
+        );
+        std::fs::write(&path, email).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(age * 60))
+            .unwrap();
+    }
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let order = |order: &str| {
+        let (report, target) = (dir.join(format!("{}.json", order)), dir.join(order));
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                source.to_str().unwrap(),
+                "--report",
+                report.to_str().unwrap(),
+                "translate",
+                target.to_str().unwrap(),
+                "--order",
+                order,
+            ],
+        );
+        assert_eq!(output.status.code(), Some(0), "{:?}", output);
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(report["summary"]["order"], order);
+        report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| {
+                let source = Path::new(file["source"].as_str().unwrap());
+                source.file_name().unwrap().to_string_lossy().to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(order("smallest-first"), ["a.eml", "c.eml", "b.eml"]);
+    assert_eq!(order("newest-first"), ["b.eml", "a.eml", "c.eml"]);
+    assert_eq!(order("path"), ["a.eml", "b.eml", "c.eml"]);
+}
+
+/// Runs `extract` over the source dir of `dir` and `apply-translations` without it into
+/// `applied`, then `translate` into `oneshot`, `extra` given to both runs reading the
+/// sources. `None` if OCR is needed and missing.
+fn extract_apply_and_translate(dir: &Path, url: &str, extra: &[&str]) -> Option<()> {
+    let online = dir.join("conf/online.toml");
+    let toml = format!(
+        "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+        system_tessdata(),
+        url
+    );
+    std::fs::write(&online, toml).unwrap();
+    let offline = dir.join("conf/offline.toml");
+    let toml = format!(
+        "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+        system_tessdata(),
+        NO_SERVER
+    );
+    std::fs::write(&offline, toml).unwrap();
+    let source = dir.join("src");
+    let (bundle, applied) = (dir.join("bundle"), dir.join("applied"));
+    // nothing to reach the server with
+    let mut args = vec![
+        "--config",
+        offline.to_str().unwrap(),
+        "-s",
+        source.to_str().unwrap(),
+        "extract",
+        bundle.to_str().unwrap(),
+    ];
+    args.extend(extra);
+    let output = run(&dir.join("cwd"), &args);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return None;
+    }
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+
+    // the sources aren't needed any more
+    let elsewhere = dir.join("elsewhere");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            online.to_str().unwrap(),
+            "-s",
+            elsewhere.to_str().unwrap(),
+            "apply-translations",
+            bundle.join("extraction.jsonl").to_str().unwrap(),
+            applied.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let oneshot = dir.join("oneshot");
+    let mut args = vec![
+        "--config",
+        online.to_str().unwrap(),
+        "-s",
+        source.to_str().unwrap(),
+        "translate",
+        oneshot.to_str().unwrap(),
+    ];
+    args.extend(extra);
+    let output = run(&dir.join("cwd"), &args);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    Some(())
+}
+
+/// Asserts that every output in `oneshot` of `dir` is in `applied` as it is.
+fn assert_outputs_alike(dir: &Path) {
+    let (applied, oneshot) = (dir.join("applied"), dir.join("oneshot"));
+    let mut compared = 0;
+    for entry in walkdir::WalkDir::new(&oneshot) {
+        let entry = entry.unwrap();
+        let relative = entry.path().strip_prefix(&oneshot).unwrap();
+        if !entry.file_type().is_file() || relative == Path::new("manifest.jsonl") {
+            continue;
+        }
+        let expected = std::fs::read(entry.path()).unwrap();
+        let actual = std::fs::read(applied.join(relative))
+            .unwrap_or_else(|e| panic!("{}: {}", relative.display(), e));
+        assert!(expected == actual, "{} differs", relative.display());
+        compared += 1;
+    }
+    assert!(compared > 0);
+}
+
+#[test]
+fn extracted_segments_are_translated_elsewhere_as_a_run_would() {
+    let dir = scratch("two-pass", NO_SERVER);
+    let (url, _) = serve_fake(Behavior::Reverse);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    // a text that looks like a marker stays as it is
+    let email = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\n\
+                 Абзац [[dir-translate: segment 1]]\r\n\r\nАбзац 2\r\n";
+    std::fs::write(dir.join("src/letters/letter.eml"), email).unwrap();
+    write_docx(
+        &dir.join("src/memo.docx"),
+        "<w:p><w:r><w:t>Абзац 3. Абзац 4.</w:t></w:r></w:p>",
+    );
+    extract_apply_and_translate(&dir, &url, &["--keep-source-text"]).unwrap();
+    let bundle = dir.join("bundle");
+    let extraction = std::fs::read_to_string(bundle.join("extraction.jsonl")).unwrap();
+    let header: serde_json::Value =
+        serde_json::from_str(extraction.lines().next().unwrap()).unwrap();
+    assert_eq!(header["version"], 1);
+    assert!(extraction.contains("Абзац 4."), "{}", extraction);
+    assert!(!bundle.join("letters").exists());
+    for output in ["letters/letter.eml.txt", "memo.docx.txt"] {
+        let text = std::fs::read_to_string(dir.join("applied").join(output)).unwrap();
+        assert!(text.contains("Abzac"), "{}", text);
+    }
+    assert!(dir.join("applied/memo.docx.src.txt").exists());
+    let source = std::fs::read_to_string(dir.join("applied/letters/letter.eml.src.txt")).unwrap();
+    assert!(
+        source.contains("Абзац [[dir-translate: segment 1]]\n"),
+        "{}",
+        source
+    );
+    assert_outputs_alike(&dir);
+
+    // a bundle writes nothing outside the target dir
+    let header = extraction.lines().next().unwrap();
+    let file = r#"{"source": "x.eml", "handler": "email", "source_lang": "ru", "outputs": [{"path": "../escaped.txt", "text": "x"}], "segments": []}"#;
+    let tampered = dir.join("tampered.jsonl");
+    std::fs::write(&tampered, format!("{}\n{}\n", header, file)).unwrap();
+    let online = dir.join("conf/online.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            online.to_str().unwrap(),
+            "-s",
+            dir.join("elsewhere").to_str().unwrap(),
+            "apply-translations",
+            tampered.to_str().unwrap(),
+            dir.join("tampered").to_str().unwrap(),
+        ],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("not a path below the target dir"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("escaped.txt").exists());
+}
+
+#[test]
+fn extracted_scans_are_translated_elsewhere_as_a_run_would() {
+    let dir = scratch("two-pass-scans", NO_SERVER);
+    let (url, _) = serve_fake(Behavior::Reverse);
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hyphenated.pdf"),
+        dir.join("src/hyphenated.pdf"),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two-columns.png"),
+        dir.join("src/two-columns.png"),
+    )
+    .unwrap();
+    let extra = [
+        "--keep-source-text",
+        "--orientation",
+        "none",
+        "--output-format",
+        "markdown",
+    ];
+    if extract_apply_and_translate(&dir, &url, &extra).is_none() {
+        return;
+    }
+    assert!(dir.join("applied/hyphenated.pdf.md").exists());
+    assert_outputs_alike(&dir);
+}
+
+#[test]
+fn outputs_can_be_stamped_with_the_hash_of_their_source() {
+    let (url, _) = serve_fake(Behavior::Reverse);
+    let dir = scratch("stamp", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    let email = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n";
+    std::fs::write(dir.join("src/letters/letter.eml"), email).unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |target: &str| {
+        let target = dir.join(target);
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            target.to_str().unwrap(),
+            "--stamp-outputs",
+        ];
+        let output = run(&dir.join("cwd"), &args);
+        assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    };
+    translate("old");
+    let text = std::fs::read_to_string(dir.join("old/letters/letter.eml.txt")).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "# source: letters/letter.eml", "{}", text);
+    assert!(lines[2].starts_with("# translated: "), "{}", text);
+    assert_eq!(lines[3], "# languages: ru->en", "{}", text);
+    assert_eq!(lines[4], "");
+    assert!(text.contains("1 Abzac"), "{}", text);
+    let manifest = std::fs::read_to_string(dir.join("old/manifest.jsonl")).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(manifest.lines().next().unwrap()).unwrap();
+    let sha256 = entry["source"]["sha256"].as_str().unwrap();
+    assert_eq!(lines[1], format!("# sha256: {}", sha256));
+
+    // a later run stamps another time, which diff doesn't count as a change
+    translate("new");
+    let path = dir.join("new/letters/letter.eml.txt");
+    let text = std::fs::read_to_string(&path).unwrap();
+    let text = text.replacen("# translated: ", "# translated: 1999-01-01T00:00Z ", 1);
+    std::fs::write(&path, text).unwrap();
+    let (old, new) = (dir.join("old"), dir.join("new"));
+    let output = run(
+        &dir.join("cwd"),
+        &["diff", old.to_str().unwrap(), new.to_str().unwrap()],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("letter.eml.txt"), "{}", stdout);
+}
+
+#[test]
+fn compressed_sources_are_handled_as_the_file_inside() {
+    let (url, _) = serve_fake(Behavior::Reverse);
+    let dir = scratch("compressed", &url);
+    let email = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n";
+    let mut gz = flate2::write::GzEncoder::new(
+        std::fs::File::create(dir.join("src/letter.eml.gz")).unwrap(),
+        flate2::Compression::default(),
+    );
+    gz.write_all(email.as_bytes()).unwrap();
+    gz.finish().unwrap();
+    // no inner extension, the docx is told by its content
+    let docx = dir.join("memo.docx");
+    write_docx(&docx, "<w:p><w:r><w:t>Абзац 2</w:t></w:r></w:p>");
+    let compressed = zstd::encode_all(std::fs::File::open(&docx).unwrap(), 0).unwrap();
+    std::fs::write(dir.join("src/memo.zst"), compressed).unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |target: &str, extra: &[&str]| {
+        let target = dir.join(target);
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            target.to_str().unwrap(),
+        ];
+        let output = run(&dir.join("cwd"), &[&args[..], extra].concat());
+        assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    };
+    translate("out", &[]);
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+    assert!(text.contains("1 Abzac"), "{}", text);
+    let text = std::fs::read_to_string(dir.join("out/memo.docx.txt")).unwrap();
+    assert!(text.contains("2 Abzac"), "{}", text);
+
+    translate("compressed", &["--compress-output"]);
+    assert!(!dir.join("compressed/letter.eml.txt").exists());
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(
+        std::fs::File::open(dir.join("compressed/letter.eml.txt.gz")).unwrap(),
+    )
+    .read_to_string(&mut text)
+    .unwrap();
+    assert!(text.contains("1 Abzac"), "{}", text);
+    let memo = std::fs::File::open(dir.join("compressed/memo.docx.txt.zst")).unwrap();
+    let text = String::from_utf8(zstd::decode_all(memo).unwrap()).unwrap();
+    assert!(text.contains("2 Abzac"), "{}", text);
+}
+
+#[test]
+fn unsupported_files_are_counted_by_extension() {
+    let (url, _) = serve_fake(Behavior::Reverse);
+    let dir = scratch("unsupported", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("src/budget.xlsx"), vec![0u8; 4000]).unwrap();
+    std::fs::write(dir.join("src/photo.png"), vec![0u8; 1000]).unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |target: &str| {
+        let (report, target) = (dir.join(format!("{}.json", target)), dir.join(target));
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                source.to_str().unwrap(),
+                "--report",
+                report.to_str().unwrap(),
+                "--disable-handler",
+                "image",
+                "translate",
+                target.to_str().unwrap(),
+            ],
+        );
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        (output, report)
+    };
+    // nearly all the bytes are left out
+    let (output, report) = translate("out");
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("  .xlsx: 1 files, 3.9 KB\n"), "{}", stderr);
+    assert!(
+        stderr.contains("  .png: 1 files, 1000 B, the image handler takes them but is disabled"),
+        "{}",
+        stderr
+    );
+    let extensions = &report["summary"]["unsupported_extensions"];
+    assert_eq!(extensions["xlsx"]["bytes"], 4000, "{}", extensions);
+    assert_eq!(
+        extensions["png"]["disabled_handler"], "image",
+        "{}",
+        extensions
+    );
+    assert_eq!(report["summary"]["unsupported_bytes"], 5000);
+
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("max_unsupported_share = 1.0\n");
+    std::fs::write(&config, text).unwrap();
+    let (output, _) = translate("lenient");
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+}
+
+#[test]
+fn fifos_and_empty_files_are_skipped() {
+    let url = serve_out_of_order();
+    let dir = scratch("special", &url);
+    let fifo = dir.join("src/pipe.eml");
+    let status = Command::new("mkfifo").arg(&fifo).status().unwrap();
+    assert!(status.success());
+    std::fs::write(dir.join("src/scan.pdf"), "").unwrap();
+    std::fs::write(
+        dir.join("src/note.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let list = dir.join("list.txt");
+    std::fs::write(&list, "pipe.eml\nscan.pdf\nnote.eml\n").unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let source = dir.join("src");
+    for (out, extra) in [
+        ("walked", &[][..]),
+        ("listed", &["--files-from", list.to_str().unwrap()]),
+    ] {
+        let out = dir.join(out);
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+        ];
+        args.extend(extra);
+        args.extend(["translate", out.to_str().unwrap()]);
+        let output = run(&dir.join("cwd"), &args);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(out.join("note.eml.txt").exists());
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(report["summary"]["empty"], 1, "{}", report);
+        assert_eq!(report["summary"]["special"], 1, "{}", report);
+        let empty = report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|file| file["source"].as_str().unwrap().ends_with("scan.pdf"))
+            .unwrap();
+        assert_eq!(empty["error"], "empty file", "{}", report);
+    }
+}
+
+#[test]
+fn outputs_of_an_earlier_run_are_skipped() {
+    let url = serve_out_of_order();
+    let dir = scratch("earlier", &url);
+    std::fs::write(
+        dir.join("src/note.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let translate = |source: &str, target: &str, extra: &[&str]| {
+        let mut args = vec!["--config", config.to_str().unwrap(), "-s", source];
+        args.extend(["translate", target]);
+        args.extend(extra);
+        run(&dir.join("cwd"), &args)
+    };
+    let first = dir.join("first");
+    let second = dir.join("second");
+    let output = translate(
+        dir.join("src").to_str().unwrap(),
+        first.to_str().unwrap(),
+        &[],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    // an eml saved next to the outputs is new and gets translated
+    std::fs::copy(dir.join("src/note.eml"), first.join("copy.eml")).unwrap();
+
+    let output = translate(first.to_str().unwrap(), second.to_str().unwrap(), &[]);
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("manifest.jsonl"));
+
+    let output = translate(
+        first.to_str().unwrap(),
+        second.to_str().unwrap(),
+        &["--allow-target-as-source"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(second.join("copy.eml.txt").exists());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2 outputs of an earlier run in the source dir skipped"));
+}
+
+#[test]
+fn segments_can_be_one_per_line_with_their_position() {
+    let url = serve_out_of_order();
+    let dir = scratch("separator", &url);
+    std::fs::write(
+        dir.join("src/note.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\nАбзац 2\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--segment-separator",
+            "\\n",
+            "--prefix-segments",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/note.eml.txt")).unwrap();
+    assert_eq!(text, "[1/1] Paragraph 1\n[1/2] Paragraph 2\n");
+}
+
+#[test]
+fn docx_segments_can_carry_their_paragraph() {
+    let url = serve_out_of_order();
+    let dir = scratch("provenance", &url);
+    write_docx(
+        &dir.join("src/memo.docx"),
+        "<w:p><w:r><w:t>Абзац 1.</w:t></w:r></w:p>\
+         <w:p><w:r><w:t>Абзац 2. Абзац 3.</w:t></w:r></w:p>",
+    );
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--segment-separator",
+            "\\n",
+            "--annotate-provenance",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/memo.docx.txt")).unwrap();
+    assert_eq!(
+        text,
+        "[para 1] Paragraph 1.\n[para 2] Paragraph 2.\n[para 2] Paragraph 3.\n"
+    );
+}
+
+#[test]
+fn long_docx_files_are_translated_in_chunks() {
+    let (url, requests) = serve_fake(Behavior::Reverse);
+    let dir = scratch("long-docx", &url);
+    // every tenth paragraph has no period, its sentence goes on in the next one
+    let body: String = (1..=600)
+        .map(|n| {
+            let period = if n % 10 == 0 { "" } else { "." };
+            format!("<w:p><w:r><w:t>Абзац {}{}</w:t></w:r></w:p>", n, period)
+        })
+        .collect();
+    write_docx(&dir.join("src/long.docx"), &body);
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--annotate-provenance",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/long.docx.txt")).unwrap();
+    assert_eq!(text.matches("[para ").count(), 541, "{}", text);
+    assert!(text.contains("[para 10] "), "{}", text);
+    assert!(!text.contains("[para 11] "), "{}", text);
+    assert!(text.contains("[para 599] 599. Abzac"), "{}", text);
+    assert!(text.contains("[para 600] 600 Abzac"), "{}", text);
+    assert_eq!(requests.load(Ordering::SeqCst), 541);
+}
+
+#[test]
+fn profiles_choose_the_settings_of_a_file() {
+    let url = serve_out_of_order();
+    let dir = scratch("profiles", &url);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("[profiles.digital-text]\nkeep_source_text = true\n");
+    std::fs::write(&config, text).unwrap();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    let translate = |out: &str, extra: &[&str]| {
+        let report = dir.join(format!("{}.json", out));
+        let source = dir.join("src");
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+        ];
+        let target = dir.join(out);
+        args.push(target.to_str().unwrap());
+        args.extend(extra);
+        let output = run(&dir.join("cwd"), &args);
+        assert!(output.status.success(), "{:?}", output);
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+        report["files"][0]["profile"].as_str().unwrap().to_string()
+    };
+    assert_eq!(translate("out", &[]), "digital-text");
+    assert!(dir.join("out/letter.eml.src.txt").exists());
+    assert_eq!(
+        translate("forced", &["--profile", "scan-form"]),
+        "scan-form"
+    );
+    assert!(!dir.join("forced/letter.eml.src.txt").exists());
+}
+
+#[test]
+fn sidecars_change_the_settings_of_one_file() {
+    let url = serve_out_of_order();
+    let dir = scratch("sidecars", &url);
+    for name in ["kept.eml", "typo.eml", "plain.eml"] {
+        std::fs::write(
+            dir.join("src").join(name),
+            "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+        )
+        .unwrap();
+    }
+    std::fs::write(
+        dir.join("src/kept.eml.translate.toml"),
+        "keep_source_text = true\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("src/typo.eml.translate.toml"), "pages = \"1-5\"\n").unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(dir.join("out/kept.eml.src.txt").exists());
+    assert!(dir.join("out/plain.eml.txt").exists());
+    assert!(!dir.join("out/plain.eml.src.txt").exists());
+    assert!(!dir.join("out/kept.eml.translate.toml.txt").exists());
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    let files = report["files"].as_array().unwrap();
+    let file = |name: &str| {
+        files
+            .iter()
+            .find(|f| f["source"].as_str().unwrap().ends_with(name))
+            .unwrap()
+    };
+    assert!(file("kept.eml")["sidecar"]
+        .as_str()
+        .unwrap()
+        .ends_with("kept.eml.translate.toml"));
+    assert!(file("plain.eml").get("sidecar").is_none());
+    let error = file("typo.eml")["error"].as_str().unwrap();
+    assert!(error.contains("unknown field `pages`"), "{}", error);
+    assert_eq!(files.len(), 3);
+}
+
+#[test]
+fn only_the_listed_files_are_translated() {
+    let url = serve_out_of_order();
+    let dir = scratch("files-from", &url);
+    std::fs::create_dir_all(dir.join("src/sub")).unwrap();
+    for name in ["src/a.eml", "src/b.eml", "src/sub/c.eml", "outside.eml"] {
+        std::fs::write(
+            dir.join(name),
+            "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+        )
+        .unwrap();
+    }
+    let list = dir.join("list.txt");
+    std::fs::write(
+        &list,
+        format!(
+            "sub/c.eml\r\n{}\nmissing.eml\n../outside.eml\n",
+            dir.join("src/a.eml").display()
+        ),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--files-from",
+            list.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(dir.join("out/a.eml.txt").exists());
+    assert!(dir.join("out/sub/c.eml.txt").exists());
+    assert!(!dir.join("out/b.eml.txt").exists());
+    assert!(!dir.join("outside.eml.txt").exists());
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    let errors: Vec<&str> = report["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|f| f["error"].as_str())
+        .collect();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors.contains(&"outside the source dir"), "{:?}", errors);
+    assert_eq!(report["summary"]["processed"], 2);
+}
+
+#[test]
+fn translations_go_through_the_post_edit_command() {
+    let url = serve_out_of_order();
+    let dir = scratch("post-edit", &url);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str(
+        "post_edit_cmd = ['sh', '-c', 'case $POST_EDIT_SOURCE in *broken*) exit 3;; esac; tr a-z A-Z']\n",
+    );
+    std::fs::write(&config, text).unwrap();
+    for name in ["good.eml", "broken.eml"] {
+        std::fs::write(
+            dir.join("src").join(name),
+            "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+        )
+        .unwrap();
+    }
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let good = std::fs::read_to_string(dir.join("out/good.eml.txt")).unwrap();
+    assert!(good.contains("PARAGRAPH 1"), "{}", good);
+    let broken = std::fs::read_to_string(dir.join("out/broken.eml.txt")).unwrap();
+    assert!(broken.contains("Paragraph 1"), "{}", broken);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("post_edit_cmd failed"), "{}", stderr);
+}
+
+#[test]
+fn a_share_of_the_segments_is_drawn_for_review() {
+    let url = serve_out_of_order();
+    let dir = scratch("qa-sample", &url);
+    for n in 0..30 {
+        std::fs::write(
+            dir.join(format!("src/letter-{:02}.eml", n)),
+            format!(
+                "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац {}\r\n",
+                n
+            ),
+        )
+        .unwrap();
+    }
+    let config = dir.join("conf/config.toml");
+    let sample = |out: &str| {
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                dir.join("src").to_str().unwrap(),
+                "translate",
+                dir.join(out).to_str().unwrap(),
+                "--qa-sample",
+                "0.1",
+                "--seed",
+                "7",
+            ],
+        );
+        assert!(output.status.success(), "{:?}", output);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("3 segments drawn for review"), "{}", stderr);
+        let qa = std::fs::read_dir(dir.join(out).join("qa"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let csv = std::fs::read_to_string(qa).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("source,position,source_text,translation,confidence,backend")
+        );
+        let rows: Vec<String> = lines
+            .map(|l| l.split_once(',').unwrap().1.to_string())
+            .collect();
+        assert_eq!(rows.len(), 3, "{}", csv);
+        assert!(rows.iter().all(|r| r.contains(&url)), "{}", csv);
+        rows
+    };
+    assert_eq!(sample("first"), sample("second"));
+}
+
+#[test]
+fn a_server_echoing_its_input_stops_the_run() {
+    let (url, _) = serve_fake(Behavior::Echo);
+    let dir = scratch("echo", &url);
+    let body: Vec<String> = (1..=60).map(|n| format!("Текст {}", n)).collect();
+    std::fs::write(
+        dir.join("src/a.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            body.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("src/b.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+    )
+    .unwrap();
+    // handled in this order
+    let list = dir.join("list.txt");
+    std::fs::write(&list, "a.eml\nb.eml\n").unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--files-from",
+            list.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(4), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("appears to be echoing its input"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("out/b.eml.txt").exists());
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["files"][0]["echo_ratio"], 1.0, "{}", report);
+    assert_eq!(report["summary"]["cancelled"], 1, "{}", report);
+}
+
+#[test]
+fn html_bodies_can_be_sent_as_markup() {
+    // answers `Абзац` for what it is, markup kept, or refuses `format=html` if `refuse`
+    let serve = |refuse: bool| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                std::thread::spawn(move || {
+                    answer(stream.unwrap(), &|request_line, body| {
+                        if !request_line.starts_with("POST /translate") {
+                            return ("404 Not Found", String::new());
+                        }
+                        let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                        if refuse && request.get("format").is_some() {
+                            return (
+                                "400 Bad Request",
+                                serde_json::json!({ "error": "Invalid format" }).to_string(),
+                            );
+                        }
+                        let translate = |q: &serde_json::Value| {
+                            serde_json::Value::from(
+                                q.as_str().unwrap().replace("Абзац", "Paragraph"),
+                            )
+                        };
+                        let translated = match &request["q"] {
+                            serde_json::Value::Array(q) => q.iter().map(translate).collect(),
+                            q => translate(q),
+                        };
+                        (
+                            "200 OK",
+                            serde_json::json!({ "translatedText": translated }).to_string(),
+                        )
+                    })
+                });
+            }
+        });
+        url
+    };
+    for (mode, refuse) in [("client", false), ("server", false), ("server", true)] {
+        let dir = scratch(&format!("html-{}-{}", mode, refuse), &serve(refuse));
+        std::fs::write(
+            dir.join("src/letter.eml"),
+            "Content-Type: text/html; charset=utf-8\r\n\r\n\
+             <html><body><p>Абзац <b>1</b></p><p>Абзац 2</p></body></html>\r\n",
+        )
+        .unwrap();
+        let config = dir.join("conf/config.toml");
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                dir.join("src").to_str().unwrap(),
+                "translate",
+                "--html-mode",
+                mode,
+                dir.join("out").to_str().unwrap(),
+            ],
+        );
+        assert!(output.status.success(), "{:?}", output);
+        let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+        assert!(
+            text.contains("Paragraph 1"),
+            "{} {}: {}",
+            mode,
+            refuse,
+            text
+        );
+        assert!(
+            text.contains("Paragraph 2"),
+            "{} {}: {}",
+            mode,
+            refuse,
+            text
+        );
+        assert!(!text.contains('<'), "{} {}: {}", mode, refuse, text);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(
+            stderr.contains("doesn't take format=html"),
+            refuse,
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn proper_nouns_in_names_are_transliterated() {
+    let url = serve_out_of_order();
+    let dir = scratch("names-mode", &url);
+    let name = "Абзац 2 Щёлково_Иванов-Петров подъезд ЩИ.eml";
+    std::fs::write(dir.join("src").join(name), "Абзац 1\r\n").unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let plan = |mode: &str| {
+        let plan = dir.join(format!("{}.json", mode));
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                source.to_str().unwrap(),
+                "filenames",
+                "--plan",
+                plan.to_str().unwrap(),
+                "--names-mode",
+                mode,
+            ],
+        );
+        assert_eq!(output.status.code(), Some(0), "{:?}", output);
+        let plan: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&plan).unwrap()).unwrap();
+        let target = plan[0]["target"].as_str().unwrap().to_string();
+        Path::new(&target)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string()
+    };
+    assert_eq!(
+        plan("transliterate"),
+        "Abzats 2 Shchyolkovo_Ivanov-Petrov podezd SHCHI.eml"
+    );
+    // the server gives "подъезд" back as it is
+    assert_eq!(
+        plan("translate-then-transliterate"),
+        "Paragraph 2 Shchyolkovo_Ivanov-Petrov podezd SHCHI.eml"
+    );
+}
+
+#[test]
+fn translated_names_can_be_put_in_one_case() {
+    let dir = scratch("filename-case", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("acronyms = [\"PhD\"]\n");
+    std::fs::write(&config, text).unwrap();
+    for name in ["отчёт о проверке USB 2023 phd.eml", "АКТ ПРИЁМКИ_3.eml"] {
+        std::fs::write(dir.join("src").join(name), "Абзац 1\r\n").unwrap();
+    }
+    let plan = dir.join("plan.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "filenames",
+            "--plan",
+            plan.to_str().unwrap(),
+            "--names-mode",
+            "transliterate",
+            "--filename-case",
+            "title",
+            "--filename-separator",
+            "_",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let plan: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&plan).unwrap()).unwrap();
+    let mut names: Vec<String> = plan
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| {
+            let target = entry["target"].as_str().unwrap();
+            Path::new(target)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    names.sort();
+    // a word in capitals is an acronym unless the whole name is in capitals
+    assert_eq!(
+        names,
+        ["Akt_Priyomki_3.eml", "Otchyot_O_Proverke_USB_2023_PhD.eml"]
+    );
+}
+
+#[test]
+fn broken_and_protected_docx_files_fail_by_kind() {
+    let url = serve_out_of_order();
+    let dir = scratch("docx-kinds", &url);
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    for name in ["protected", "legacy", "slides", "truncated"] {
+        let name = format!("{}.docx", name);
+        std::fs::copy(fixtures.join(&name), dir.join("src").join(&name)).unwrap();
+    }
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |out: &str, password: &str| {
+        let report = dir.join(format!("{}.json", out));
+        let target = dir.join(out);
+        let output = run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                source.to_str().unwrap(),
+                "--report",
+                report.to_str().unwrap(),
+                "--docx-password",
+                password,
+                "translate",
+                target.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(output.status.code(), Some(2), "{:?}", output);
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+        let mut kinds: Vec<(String, String)> = report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| {
+                let source = Path::new(file["source"].as_str().unwrap());
+                let name = source.file_stem().unwrap().to_string_lossy().to_string();
+                (name, file["error_kind"].as_str().unwrap_or("").to_string())
+            })
+            .collect();
+        kinds.sort();
+        kinds
+    };
+    let kinds = translate("wrong", "guess");
+    assert_eq!(
+        kinds,
+        [
+            ("legacy".to_string(), "format_mismatch".to_string()),
+            ("protected".to_string(), "encrypted_input".to_string()),
+            ("slides".to_string(), "format_mismatch".to_string()),
+            ("truncated".to_string(), "truncated_input".to_string()),
+        ]
+    );
+    let kinds = translate("out", "secret");
+    assert!(kinds.contains(&("protected".to_string(), String::new())));
+    let text = std::fs::read_to_string(dir.join("out/protected.docx.txt")).unwrap();
+    assert_eq!(text.trim(), "Paragraph 1.");
+}
+
+/// Characters the batching server takes in one request.
+const BATCH_LIMIT: usize = 120;
+
+/// Sizes of the requests a server got and whether it refused them.
+type RequestLog = std::sync::Arc<std::sync::Mutex<Vec<(usize, bool)>>>;
+
+/// Translates batches like [`out_of_order`] but refuses those over [`BATCH_LIMIT`]
+/// characters like LibreTranslate does and takes 300 ms for those over 80. Logs the
+/// characters of each request and whether it was refused.
+fn serve_batches() -> (String, RequestLog) {
+    let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = log.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                answer(stream, &|request_line, body| {
+                    if !request_line.starts_with("POST /translate") {
+                        return ("404 Not Found", String::new());
+                    }
+                    let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    let batch = request["q"].as_array();
+                    let q: Vec<&str> = match batch {
+                        Some(q) => q.iter().map(|q| q.as_str().unwrap()).collect(),
+                        None => vec![request["q"].as_str().unwrap()],
+                    };
+                    let chars: usize = q.iter().map(|q| q.chars().count()).sum();
+                    let refused = chars > BATCH_LIMIT;
+                    requests.lock().unwrap().push((chars, refused));
+                    if refused {
+                        let error = format!(
+                            "Invalid request: request ({}) exceeds text limit ({})",
+                            chars, BATCH_LIMIT
+                        );
+                        return (
+                            "400 Bad Request",
+                            serde_json::json!({ "error": error }).to_string(),
+                        );
+                    }
+                    if chars > 80 {
+                        std::thread::sleep(Duration::from_millis(300));
+                    }
+                    let translated: Vec<String> = q
+                        .iter()
+                        .map(|q| q.replace("Абзац", "Paragraph").replace("слово", "word"))
+                        .collect();
+                    let response = match batch {
+                        Some(_) => serde_json::json!({ "translatedText": translated }),
+                        None => serde_json::json!({ "translatedText": translated[0] }),
+                    };
+                    ("200 OK", response.to_string())
+                })
+            });
+        }
+    });
+    (url, log)
+}
+
+#[test]
+fn batches_shrink_to_the_server_limits() {
+    let (url, requests) = serve_batches();
+    let dir = scratch("batching", &url);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("[batching]\nmax_chars = 200\ntarget_latency_ms = 100\n");
+    std::fs::write(&config, text).unwrap();
+    let paragraphs: Vec<String> = (1..=8)
+        .map(|n| format!("Абзац {} слово слово слово", n))
+        .collect();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            paragraphs.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--verbose",
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--translate-workers",
+            "1",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+    let segments: Vec<&str> = text.split("\n\n").filter(|s| !s.is_empty()).collect();
+    let expected: Vec<String> = (1..=8)
+        .map(|n| format!("Paragraph {} word word word", n))
+        .collect();
+    assert_eq!(segments, expected, "{}", text);
+    let requests = requests.lock().unwrap();
+    // the limit in the refusal is taken as it is
+    assert_eq!(requests.iter().filter(|(_, refused)| *refused).count(), 1);
+    assert!(requests[0].1, "{:?}", requests);
+    assert!(requests[1..].iter().all(|(chars, _)| *chars <= BATCH_LIMIT));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("batching: batches of up to 100 characters, server limit 120"),
+        "{}",
+        stderr
+    );
+}
+
+/// Sends one HTTP request to the daemon at `addr` and returns the status code and the
+/// JSON body of the response.
+fn request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> (u16, serde_json::Value) {
+    let (status, body) = request_text(addr, method, path, content_type, body);
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+/// The status code and body of the response to one HTTP request to the daemon at `addr`.
+fn request_text(
+    addr: &str,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        addr,
+        content_type,
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+/// Starts `serve` into the target dir `out` of `dir` with the further options `args` and
+/// returns it with the address it listens on.
+fn start_daemon(dir: &Path, args: &[&str]) -> (std::process::Child, String) {
+    let config = dir.join("conf/config.toml");
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_dir-translate"))
+        .current_dir(dir.join("cwd"))
+        .args(["--config", config.to_str().unwrap(), "-s"])
+        .arg(dir.join("src"))
+        .args(args)
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .arg(dir.join("out"))
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(daemon.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line
+        .trim()
+        .strip_prefix("listening on http://")
+        .unwrap_or_else(|| panic!("{}", line))
+        .to_string();
+    std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+    (daemon, addr)
+}
+
+/// Waits for job `id` of the daemon at `addr` to end and returns it.
+fn wait_for_job(addr: &str, id: u64) -> serde_json::Value {
+    for _ in 0..300 {
+        let (status, job) = request(addr, "GET", &format!("/jobs/{}", id), "", b"");
+        assert_eq!(status, 200, "{}", job);
+        if !["queued", "running"].contains(&job["state"].as_str().unwrap()) {
+            return job;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    panic!("job {} never finished", id);
+}
+
+#[test]
+fn jobs_are_queued_and_followed_over_http() {
+    let url = serve_out_of_order();
+    let dir = scratch("serve", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    let eml = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n";
+    std::fs::write(dir.join("src/letters/note.eml"), eml).unwrap();
+    let (mut daemon, addr) = start_daemon(&dir, &[]);
+    let wait = |id: u64| wait_for_job(&addr, id);
+
+    let body = br#"{"source": "letters", "target": "letters-en"}"#;
+    let (status, job) = request(&addr, "POST", "/jobs", "application/json", body);
+    assert_eq!(status, 201, "{}", job);
+    let job = wait(job["id"].as_u64().unwrap());
+    assert_eq!(job["state"], "done", "{}", job);
+    assert_eq!(job["exit_code"], 0, "{}", job);
+    assert_eq!(job["files"][0]["handler"], "email", "{}", job);
+    assert!(
+        job["files"][0]["report"]["outputs"][0].is_string(),
+        "{}",
+        job
+    );
+    assert_eq!(job["report"]["summary"]["processed"], 1, "{}", job);
+    let text = std::fs::read_to_string(dir.join("out/letters-en/note.eml.txt")).unwrap();
+    assert_eq!(text.trim(), "Paragraph 1");
+    let (status, _) = request(&addr, "DELETE", "/jobs/1", "", b"");
+    assert_eq!(status, 409);
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file("upload/note.eml", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(eml.as_bytes()).unwrap();
+    let upload = zip.finish().unwrap().into_inner();
+    let path = "/jobs?target=uploaded&arg=--keep-source-text";
+    let (status, job) = request(&addr, "POST", path, "application/zip", &upload);
+    assert_eq!(status, 201, "{}", job);
+    let job = wait(job["id"].as_u64().unwrap());
+    assert_eq!(job["state"], "done", "{}", job);
+    assert!(dir.join("out/uploaded/upload/note.eml.txt").exists());
+    assert!(dir.join("out/uploaded/upload/note.eml.src.txt").exists());
+
+    let body = br#"{"source": "../conf", "target": "x"}"#;
+    let (status, error) = request(&addr, "POST", "/jobs", "application/json", body);
+    assert_eq!(status, 400, "{}", error);
+    // options naming files could write anywhere
+    for arg in [
+        "--output-archive=/tmp/x.zip",
+        "--font-path",
+        "--report",
+        "-s",
+    ] {
+        let body =
+            serde_json::json!({ "source": "letters", "target": "x", "args": [arg, "/tmp/x"] });
+        let body = body.to_string();
+        let (status, error) = request(&addr, "POST", "/jobs", "application/json", body.as_bytes());
+        assert_eq!(status, 400, "{}", error);
+    }
+    let path = "/jobs?target=uploaded&arg=--low-confidence-report&arg=/tmp/x.csv";
+    let (status, error) = request(&addr, "POST", path, "application/zip", &upload);
+    assert_eq!(status, 400, "{}", error);
+    let (status, _) = request(&addr, "GET", "/jobs/99", "", b"");
+    assert_eq!(status, 404);
+    let (status, _) = request(&addr, "GET", "/metrics", "", b"");
+    assert_eq!(status, 404);
+    // refused from the header alone, before the body is read
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    write!(
+        stream,
+        "POST /jobs HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        2 << 20
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn the_metrics_of_jobs_are_served() {
+    let url = serve_out_of_order();
+    let dir = scratch("serve-metrics", &url);
+    std::fs::create_dir_all(dir.join("src/letters")).unwrap();
+    let eml = "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\nАбзац 2\r\n";
+    std::fs::write(dir.join("src/letters/note.eml"), eml).unwrap();
+    let (mut daemon, addr) = start_daemon(&dir, &["--metrics"]);
+    let (status, metrics) = request_text(&addr, "GET", "/metrics", "", b"");
+    assert_eq!(status, 200, "{}", metrics);
+    assert!(
+        metrics.contains("dir_translate_segments_total 0\n"),
+        "{}",
+        metrics
+    );
+
+    for target in ["first", "second"] {
+        let body = serde_json::json!({ "source": "letters", "target": target }).to_string();
+        let (status, job) = request(&addr, "POST", "/jobs", "application/json", body.as_bytes());
+        assert_eq!(status, 201, "{}", job);
+        let job = wait_for_job(&addr, job["id"].as_u64().unwrap());
+        assert_eq!(job["state"], "done", "{}", job);
+    }
+    let (status, metrics) = request_text(&addr, "GET", "/metrics", "", b"");
+    assert_eq!(status, 200, "{}", metrics);
+    // both paragraphs of both jobs
+    assert!(
+        metrics.contains("dir_translate_segments_total 4\n"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("dir_translate_backend_request_seconds_count 4\n"),
+        "{}",
+        metrics
+    );
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
+
+#[test]
+fn lookalike_letters_are_fixed_before_translation() {
+    let url = serve_out_of_order();
+    let dir = scratch("normalize", &url);
+    // Latin letters that look like Cyrillic ones, the other way round
+    let confusables = [
+        ('A', 'А'),
+        ('B', 'В'),
+        ('C', 'С'),
+        ('E', 'Е'),
+        ('H', 'Н'),
+        ('K', 'К'),
+        ('M', 'М'),
+        ('O', 'О'),
+        ('P', 'Р'),
+        ('T', 'Т'),
+        ('X', 'Х'),
+        ('Y', 'У'),
+        ('a', 'а'),
+        ('c', 'с'),
+        ('e', 'е'),
+        ('o', 'о'),
+        ('p', 'р'),
+        ('x', 'х'),
+        ('y', 'у'),
+    ];
+    let mut paragraphs: Vec<String> = confusables
+        .iter()
+        .map(|(latin, cyrillic)| format!("Абзац д{}м w{}rd", latin, cyrillic))
+        .collect();
+    let mut expected: Vec<String> = confusables
+        .iter()
+        .map(|(latin, cyrillic)| format!("Paragraph д{}м w{}rd", cyrillic, latin))
+        .collect();
+    // model numbers keep their letters, lookalikes only word takes the segment's script
+    paragraphs.push("Абзац СУ-27M CY-27M Aх".to_string());
+    expected.push("Paragraph СУ-27M CY-27M Ах".to_string());
+    paragraphs.push("Абзац\u{200B}  of \u{7}  spaces".to_string());
+    expected.push("Paragraph of spaces".to_string());
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            paragraphs.join("\r\n\r\n")
+        ),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |out: &str, extra: &[&str]| {
+        let target = dir.join(out);
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            target.to_str().unwrap(),
+        ];
+        let output = run(&dir.join("cwd"), &[&args[..], extra].concat());
+        assert!(output.status.success(), "{:?}", output);
+        std::fs::read_to_string(target.join("letter.eml.txt")).unwrap()
+    };
+    let text = translate("out", &[]);
+    let segments: Vec<&str> = text
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    assert_eq!(segments, expected, "{}", text);
+    let text = translate("raw", &["--no-normalize"]);
+    assert!(text.contains("Paragraph дAм"), "{}", text);
+}
+
+#[test]
+fn interrupted_renames_are_resumed_or_rolled_back() {
+    let dir = scratch("rename-journal", NO_SERVER);
+    let source = dir.join("src");
+    for name in ["один.txt", "два.txt", "три.txt"] {
+        std::fs::write(source.join(name), name).unwrap();
+    }
+    let entry = |from: &str, to: &str| {
+        let metadata = std::fs::metadata(source.join(from)).unwrap();
+        let modified = metadata.modified().unwrap();
+        serde_json::json!({
+            "source": source.join(from),
+            "target": source.join(to),
+            "size": metadata.len(),
+            "modified": modified.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            "issues": "",
+        })
+    };
+    let plan = dir.join("plan.json");
+    let entries = [
+        entry("один.txt", "one.txt"),
+        entry("два.txt", "blocker/two.txt"),
+        entry("три.txt", "three.txt"),
+    ];
+    std::fs::write(&plan, serde_json::to_string(&entries).unwrap()).unwrap();
+    // a file where the second rename needs a directory stops it halfway
+    std::fs::write(source.join("blocker"), "").unwrap();
+    let config = dir.join("conf/config.toml");
+    let apply = |extra: &[&str]| {
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "apply-plan",
+        ];
+        run(&dir.join("cwd"), &[&args[..], extra].concat())
+    };
+    let journal = source.join(".rename-journal.json");
+    let output = apply(&[plan.to_str().unwrap()]);
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(source.join("one.txt").exists() && journal.exists());
+
+    let output = apply(&[plan.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("stopped after 1 of 3 renames"),
+        "{}",
+        stderr
+    );
+
+    let output = apply(&["--rollback-rename"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(source.join("один.txt").exists() && !source.join("one.txt").exists());
+    assert!(!journal.exists());
+
+    let output = apply(&[plan.to_str().unwrap()]);
+    assert!(!output.status.success());
+    std::fs::remove_file(source.join("blocker")).unwrap();
+    std::fs::rename(source.join("три.txt"), source.join("moved.txt")).unwrap();
+    let output = apply(&["--resume-rename"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("три.txt: externally modified"),
+        "{}",
+        stderr
+    );
+    assert!(source.join("blocker/two.txt").exists());
+    assert!(source.join("moved.txt").exists() && !source.join("three.txt").exists());
+    assert!(!journal.exists());
+}
+
+#[test]
+fn presets_are_listed_with_what_they_lack() {
+    let dir = scratch("presets", NO_SERVER);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str(
+        "[presets.serbian]\nsource_lang = \"sr\"\nocr_lang = \"srp_latn\"\nscript = \"latin\"\n",
+    );
+    std::fs::write(&config, text).unwrap();
+    std::fs::create_dir_all(dir.join("conf/tessdata")).unwrap();
+    for lang in ["ukr", "srp_latn"] {
+        std::fs::write(dir.join(format!("conf/tessdata/{}.traineddata", lang)), "").unwrap();
+    }
+    let source = dir.join("src");
+    let languages = |preset: &str| {
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "--preset",
+            preset,
+            "languages",
+        ];
+        run(&dir.join("cwd"), &args)
+    };
+    let output = languages("ukrainian");
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[ok]   ukrainian: uk, ukr\n"), "{}", stdout);
+    assert!(
+        stdout.contains("[ok]   serbian: sr, srp_latn\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("[FAIL] kazakh: kk, kaz: no kaz traineddata in"),
+        "{}",
+        stdout
+    );
+    let output = languages("latvian");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no preset latvian"));
+}
+
+#[test]
+fn missing_tesseract_data_and_pdfium_are_explained() {
+    let url = serve_out_of_order();
+    let dir = scratch("missing-dependencies", &url);
+    let config = dir.join("conf/config.toml");
+    let mut text = std::fs::read_to_string(&config).unwrap();
+    text.push_str("pdfium_path = \"nowhere/libpdfium.so\"\n");
+    std::fs::write(&config, text).unwrap();
+    std::fs::create_dir_all(dir.join("conf/tessdata")).unwrap();
+    std::fs::write(dir.join("conf/tessdata/eng.traineddata"), "").unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two-columns.png"),
+        dir.join("src/scan.png"),
+    )
+    .unwrap();
+    let source = dir.join("src");
+    let command = |extra: &[&str]| {
+        let args = [
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+        ];
+        run(&dir.join("cwd"), &[&args[..], extra].concat())
+    };
+    let output = command(&["translate", dir.join("out").to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("tesseract could not be started with rus"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("which holds eng"), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&command(&["check"]).stdout).to_string();
+    assert!(
+        stdout.contains("pdfium could not be bound from pdfium_path"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn emails_and_docx_files_keep_their_layout() {
+    let url = serve_out_of_order();
+    let dir = scratch("layout", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "From: Абзац 9 <a@example.com>\r\n\
+         To: b@example.com, Абзац сбой <c@example.com>\r\n\
+         Subject: Абзац 3\r\n\
+         Date: Mon, 1 Jan 2024 10:00:00 +0000\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\n\
+         Абзац 1\r\n\r\nсбой\r\n\r\nАбзац 2\r\n",
+    )
+    .unwrap();
+    let mut docx = zip::ZipWriter::new(std::fs::File::create(dir.join("src/memo.docx")).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    docx.start_file("[Content_Types].xml", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+          <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+          <Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\
+          </Types>",
+    )
+    .unwrap();
+    docx.start_file("_rels/.rels", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+          <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\
+          </Relationships>",
+    )
+    .unwrap();
+    docx.start_file("word/document.xml", options).unwrap();
+    docx.write_all(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"><w:body>\
+         <w:p><w:r><w:t>Абзац 1. Абзац 2 от 1998 г. сбой.</w:t></w:r></w:p>\
+         <w:tbl><w:tblGrid><w:gridCol w:w=\"2000\"/><w:gridCol w:w=\"2000\"/></w:tblGrid><w:tr><w:tc><w:p><w:r><w:t>Абзац 5</w:t></w:r></w:p></w:tc>\
+         <w:tc><w:p><w:r><w:t>сбой</w:t></w:r></w:p></w:tc></w:tr></w:tbl>\
+         <w:p><w:r><w:t>Абзац 4.</w:t></w:r></w:p>\
+         </w:body></w:document>"
+            .as_bytes(),
+    )
+    .unwrap();
+    docx.finish().unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--keep-source-text",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let read = |name: &str| std::fs::read_to_string(dir.join("out").join(name)).unwrap();
+    assert_eq!(
+        read("letter.eml.txt"),
+        "From: Paragraph 9 <a@example.com>\n\
+         To: b@example.com, Абзац сбой <c@example.com>\n\
+         Subject: Paragraph 3\n\
+         Date: Mon, 1 Jan 2024 10:00:00 +0000\n\n\
+         Paragraph 1\n\n[[dir-translate: failed segment 5]]\n\nParagraph 2\n\n"
+    );
+    assert_eq!(
+        read("letter.eml.src.txt"),
+        "From: Абзац 9 <a@example.com>\n\
+         To: b@example.com, Абзац сбой <c@example.com>\n\
+         Subject: Абзац 3\n\
+         Date: Mon, 1 Jan 2024 10:00:00 +0000\n\n\
+         Абзац 1\n\nсбой\n\nАбзац 2\n\n"
+    );
+    // the abbreviation doesn't end the sentence, which fails as a whole
+    assert_eq!(
+        read("memo.docx.txt"),
+        "Paragraph 1.\n\n[[dir-translate: failed segment 1]]\n\n\
+         | Paragraph 5 | сбой |\n| --- | --- |\n\nParagraph 4.\n\n"
+    );
+    assert_eq!(
+        read("memo.docx.src.txt"),
+        "Абзац 1.\n\nАбзац 2 от 1998 г. сбой.\n\n\
+         | Абзац 5 | сбой |\n| --- | --- |\n\nАбзац 4.\n\n"
+    );
+}
+
+#[test]
+fn previews_translate_the_first_paragraph_only() {
+    let url = serve_out_of_order();
+    let dir = scratch("preview", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "From: a@example.com\r\n\
+         Subject: Абзац 3\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\n\
+         Абзац 1\r\n\r\nАбзац 2\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--preview",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out/_preview/letter.eml.txt")).unwrap(),
+        "From: a@example.com\nSubject: Paragraph 3\n\nParagraph 1\n\n"
+    );
+    assert!(!dir.join("out/letter.eml.txt").exists());
+    assert!(!dir.join("out/_preview/manifest.jsonl").exists());
+}
+
+#[test]
+fn fine_print_is_dropped() {
+    let url = serve_out_of_order();
+    let dir = scratch("fine-print", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fine-print.png"),
+        dir.join("src/fine-print.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--keep-source-text",
+        ],
+    );
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    let text = std::fs::read_to_string(dir.join("out/fine-print.png.src.txt"))
+        .unwrap_or_else(|e| panic!("{}: {:?}", e, output));
+    assert!(text.contains("Договор"), "{}", text);
+    assert!(!text.contains("4312"), "{}", text);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("dropped for their size"), "{}", stderr);
+}
+
+#[test]
+fn pictures_of_docx_files_are_read_on_request() {
+    let url = serve_out_of_order();
+    let dir = scratch("docx-images", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    let mut docx = zip::ZipWriter::new(std::fs::File::create(dir.join("src/memo.docx")).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    docx.start_file("[Content_Types].xml", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+          <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+          <Default Extension=\"png\" ContentType=\"image/png\"/>\
+          <Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\
+          </Types>",
+    )
+    .unwrap();
+    docx.start_file("_rels/.rels", options).unwrap();
+    docx.write_all(
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+          <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+          <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\
+          </Relationships>",
+    )
+    .unwrap();
+    docx.start_file("word/document.xml", options).unwrap();
+    docx.write_all(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"><w:body>\
+         <w:p><w:r><w:t>Абзац 1.</w:t></w:r></w:p>\
+         </w:body></w:document>"
+            .as_bytes(),
+    )
+    .unwrap();
+    docx.start_file("word/media/image1.png", options).unwrap();
+    docx.write_all(
+        &std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fine-print.png"))
+            .unwrap(),
+    )
+    .unwrap();
+    // a bullet, too small to hold text
+    docx.start_file("word/media/image2.png", options).unwrap();
+    let mut bullet = Vec::new();
+    image::DynamicImage::new_luma8(16, 16)
+        .write_to(
+            &mut std::io::Cursor::new(&mut bullet),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    docx.write_all(&bullet).unwrap();
+    docx.finish().unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--keep-source-text",
+            "--docx-ocr-images",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if skip_ocr(stderr.contains("images not read")) {
+        return;
+    }
+    let text = std::fs::read_to_string(dir.join("out/memo.docx.src.txt")).unwrap();
+    assert!(text.starts_with("Абзац 1."), "{}", text);
+    assert!(text.contains("## Images"), "{}", text);
+    assert!(text.contains("### word/media/image1.png"), "{}", text);
+    assert!(text.contains("Договор"), "{}", text);
+    assert!(!text.contains("image2.png"), "{}", text);
+}
+
+#[test]
+fn photos_are_not_recognized() {
+    let url = serve_out_of_order();
+    let dir = scratch("photos", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    // a sky over a field, brightness all over the range
+    let photo = image::RgbImage::from_fn(600, 400, |x, y| {
+        let noise = (x * 7919 + y * 104_729) % 23;
+        let level = (y * 255 / 400 + noise) as u8;
+        image::Rgb([level / 2, level, 255 - level])
+    });
+    photo.save(dir.join("src/field.png")).unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fine-print.png"),
+        dir.join("src/contract.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let (src, out) = (dir.join("src"), dir.join("out"));
+    let translate = |force: bool| {
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            src.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            out.to_str().unwrap(),
+            "--orientation",
+            "none",
+        ];
+        if force {
+            args.push("--force-ocr-images");
+        }
+        run(&dir.join("cwd"), &args)
+    };
+    let output = translate(false);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("out/contract.png.txt").exists());
+    assert!(!dir.join("out/field.png.txt").exists());
+    let text = std::fs::read_to_string(&report).unwrap();
+    assert!(text.contains("photo, no text detected"), "{}", text);
+    let output = translate(true);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("out/field.png.txt").exists());
+}
+
+#[test]
+fn words_hyphenated_across_pages_are_joined() {
+    let url = serve_out_of_order();
+    let dir = scratch("hyphenated", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hyphenated.pdf"),
+        dir.join("src/hyphenated.pdf"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--output-format",
+            "markdown",
+            "--keep-source-text",
+        ],
+    );
+    let text = match std::fs::read_to_string(dir.join("out/hyphenated.pdf.src.md")) {
+        Ok(text) => text,
+        Err(_) if skip_ocr(output.status.code() == Some(5)) => return,
+        Err(e) => panic!("{}: {:?}", e, output),
+    };
+    let (first, second) = text.split_once("## Page 2").unwrap();
+    assert!(first.contains("обязательств каждой из сторон."), "{}", text);
+    assert!(!second.contains("тельств"), "{}", text);
+    assert!(second.contains("Все споры"), "{}", text);
+}
+
+#[test]
+fn render_workers_give_the_same_pages() {
+    let url = serve_out_of_order();
+    let dir = scratch("render-workers", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hyphenated.pdf"),
+        dir.join("src/hyphenated.pdf"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let source = dir.join("src");
+    let translate = |out: &str, extra: &[&str]| {
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "translate",
+            "--orientation",
+            "none",
+            "--no-ocr-cache",
+        ];
+        args.extend(extra);
+        let out = dir.join(out);
+        args.push(out.to_str().unwrap());
+        run(&dir.join("cwd"), &args)
+    };
+    let output = translate("one", &[]);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert!(output.status.success(), "{:?}", output);
+    let output = translate("three", &["--render-workers", "3"]);
+    assert!(output.status.success(), "{:?}", output);
+    for page in ["hyphenated-page-0.txt", "hyphenated-page-1.txt"] {
+        let one = std::fs::read_to_string(dir.join("one").join(page)).unwrap();
+        let three = std::fs::read_to_string(dir.join("three").join(page)).unwrap();
+        assert_eq!(one, three, "{}", page);
+    }
+}
+
+#[test]
+fn page_outputs_keep_the_stem_of_the_pdf() {
+    let url = serve_out_of_order();
+    let dir = scratch("pdf-names", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/hyphenated.pdf"),
+        dir.join("src/Отчёт.PDF.pdf"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let source = dir.join("src");
+    let translate = |out: &str, extra: &[&str]| {
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            source.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            "--orientation",
+            "none",
+        ];
+        args.extend(extra);
+        let out = dir.join(out);
+        args.push(out.to_str().unwrap());
+        run(&dir.join("cwd"), &args)
+    };
+    let output = translate("new", &[]);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("new/Отчёт.PDF-page-1.txt").exists());
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(summary["summary"]["renamed_outputs"], 1, "{}", summary);
+
+    let output = translate("legacy", &["--legacy-names"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(dir.join("legacy/отчёт-page-1.txt-page-1.txt").exists());
+}
+
+#[test]
+fn pages_read_with_low_confidence_are_listed_for_a_rescan() {
+    let url = serve_out_of_order();
+    let dir = scratch("low-confidence", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fine-print.png"),
+        dir.join("src/fine-print.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let (report, low) = (dir.join("report.json"), dir.join("rescan.csv"));
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--low-confidence-report",
+            low.to_str().unwrap(),
+            // every page is below it
+            "--rescan-below",
+            "101",
+        ],
+    );
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let file = &report["files"][0];
+    let mean = file["confidence"]["mean"].as_f64().unwrap();
+    let p10 = file["confidence"]["p10"].as_f64().unwrap();
+    assert!(p10 <= mean && mean <= 100.0, "{}", file);
+    assert_eq!(file["page_confidence"][0]["page"], 1, "{}", file);
+    assert_eq!(report["summary"]["low_confidence_pages"], 1);
+    let csv = std::fs::read_to_string(&low).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "source,page,mean,p10");
+    assert!(lines[1].contains("fine-print.png,1,"), "{}", csv);
+    assert_eq!(lines.len(), 2, "{}", csv);
+}
+
+#[test]
+fn tables_of_contents_keep_their_page_numbers() {
+    let url = serve_out_of_order();
+    let dir = scratch("table-of-contents", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/book-contents.png"),
+        dir.join("src/book-contents.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let translate = |out: &str, handling: &str| {
+        run(
+            &dir.join("cwd"),
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "-s",
+                dir.join("src").to_str().unwrap(),
+                "--report",
+                report.to_str().unwrap(),
+                "translate",
+                dir.join(out).to_str().unwrap(),
+                "--orientation",
+                "none",
+                "--toc-handling",
+                handling,
+            ],
+        )
+    };
+    let output = translate("out", "translate");
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/book-contents.png.txt")).unwrap();
+    let entries: Vec<&str> = text.lines().filter(|l| l.contains(" . ")).collect();
+    assert_eq!(entries.len(), 7, "{}", text);
+    for page in ["5", "12", "27", "41", "58", "73", "80"] {
+        assert!(
+            entries.iter().any(|e| e.ends_with(&format!(" {}", page))),
+            "{}",
+            text
+        );
+    }
+    // the page numbers are right aligned in one column
+    let width = entries[0].chars().count();
+    assert!(
+        entries.iter().all(|e| e.chars().count() == width),
+        "{}",
+        text
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["toc_pages"], 1);
+
+    let output = translate("skipped", "skip");
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("skipped/book-contents.png.txt")).unwrap();
+    assert_eq!(text.trim(), "[table of contents or index, not translated]");
+}
+
+#[test]
+fn scans_and_other_files_are_taken_turn_about() {
+    let url = serve_out_of_order();
+    let dir = scratch("turn-about", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    for name in ["a.eml", "b.eml"] {
+        std::fs::write(
+            dir.join("src").join(name),
+            "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n",
+        )
+        .unwrap();
+    }
+    for (fixture, name) in [("fine-print.png", "c.png"), ("book-contents.png", "d.png")] {
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(fixture),
+            dir.join("src").join(name),
+        )
+        .unwrap();
+    }
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let translate = |out: &str, reorder: bool| {
+        let (src, out) = (dir.join("src"), dir.join(out));
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            src.to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            out.to_str().unwrap(),
+            "--orientation",
+            "none",
+        ];
+        if !reorder {
+            args.push("--no-reorder");
+        }
+        let output = run(&dir.join("cwd"), &args);
+        let report: serde_json::Value = std::fs::read_to_string(&report)
+            .map_or(serde_json::Value::Null, |r| {
+                serde_json::from_str(&r).unwrap()
+            });
+        (output, report)
+    };
+    let (output, report) = translate("out", true);
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let sources = |report: &serde_json::Value| -> Vec<String> {
+        report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                let source = Path::new(f["source"].as_str().unwrap());
+                source.file_name().unwrap().to_string_lossy().to_string()
+            })
+            .collect()
+    };
+    assert_eq!(sources(&report), ["a.eml", "c.png", "b.eml", "d.png"]);
+    assert_eq!(report["summary"]["reordered_files"], 2);
+    for resource in ["ocr_utilization", "translation_utilization"] {
+        let share = report["summary"][resource].as_f64().unwrap();
+        assert!(share > 0.0 && share <= 1.0, "{}", report["summary"]);
+    }
+
+    let (output, report) = translate("in-order", false);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert_eq!(sources(&report), ["a.eml", "b.eml", "c.png", "d.png"]);
+    assert_eq!(report["summary"]["reordered_files"], 0);
+}
+
+#[test]
+fn long_blocks_are_split_into_paragraphs() {
+    let url = serve_out_of_order();
+    let dir = scratch("dense", &url);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = {:?}\nlibretranslate_url = \"{}\"\nmax_block_chars = 200\n",
+            system_tessdata(),
+            url
+        ),
+    )
+    .unwrap();
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dense-page.png"),
+        dir.join("src/dense-page.png"),
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--orientation",
+            "none",
+            "--keep-source-text",
+        ],
+    );
+    if skip_ocr(output.status.code() == Some(5)) {
+        return;
+    }
+    let text = std::fs::read_to_string(dir.join("out/dense-page.png.src.txt"))
+        .unwrap_or_else(|e| panic!("{}: {:?}", e, output));
+    let segments: Vec<&str> = text
+        .split("\n\n")
+        .filter(|s| !s.trim().is_empty())
+        .collect();
+    for (first, last) in [
+        ("Первый", "заканчивает."),
+        ("Второй", "текста."),
+        ("Третий", "учреждения."),
+        ("Последний", "году."),
+    ] {
+        assert!(
+            segments
+                .iter()
+                .any(|s| s.trim_start().starts_with(first) && s.trim_end().ends_with(last)),
+            "{} … {} not a segment of {:?}",
+            first,
+            last,
+            segments
+        );
+    }
+}
+
+#[test]
+fn finished_folders_get_a_marker() {
+    let url = serve_out_of_order();
+    let dir = scratch("markers", &url);
+    let email = |body: &str| {
+        format!(
+            "From: a@example.com\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            body
+        )
+    };
+    std::fs::create_dir_all(dir.join("src/case-1/scans")).unwrap();
+    std::fs::create_dir_all(dir.join("src/case-2")).unwrap();
+    std::fs::write(dir.join("src/case-1/a.eml"), email("Абзац 1")).unwrap();
+    std::fs::write(dir.join("src/case-1/scans/b.eml"), email("Абзац 2")).unwrap();
+    std::fs::write(dir.join("src/case-2/c.eml"), email("Абзац 3\r\n\r\nсбой")).unwrap();
+    std::fs::write(dir.join("src/loose.eml"), email("сбой")).unwrap();
+    std::fs::create_dir_all(dir.join("out/case-2")).unwrap();
+    std::fs::write(dir.join("out/case-2/_TRANSLATED.ok"), "{}").unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+            "--per-folder-markers",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let ok = std::fs::read_to_string(dir.join("out/case-1/_TRANSLATED.ok")).unwrap();
+    assert!(ok.contains("\"files\": 2"), "{}", ok);
+    assert!(!dir.join("out/case-1/scans/_TRANSLATED.ok").exists());
+    assert!(!dir.join("out/case-2/_TRANSLATED.ok").exists());
+    let partial = std::fs::read_to_string(dir.join("out/case-2/_TRANSLATED.partial.json")).unwrap();
+    assert!(
+        partial.contains("c.eml") && partial.contains("\"failed_segments\": 1"),
+        "{}",
+        partial
+    );
+    assert!(!dir.join("out/_TRANSLATED.partial.json").exists());
+}
+
+#[test]
+fn a_suspect_translation_is_kept_when_its_retry_fails() {
+    // a server answering with the input first and failing the retry
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                answer(stream.unwrap(), &|request_line, body| {
+                    if !request_line.starts_with("POST /translate") {
+                        return ("404 Not Found", String::new());
+                    }
+                    if requests.fetch_add(1, Ordering::SeqCst) > 0 {
+                        return ("500 Internal Server Error", String::new());
+                    }
+                    let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    let text = request["q"].as_str().unwrap();
+                    (
+                        "200 OK",
+                        serde_json::json!({ "translatedText": text }).to_string(),
+                    )
+                })
+            });
+        }
+    });
+    let dir = scratch("suspect-retry", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац первый, довольно длинный\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap(),
+        "[suspect] Абзац первый, довольно длинный\n\n"
+    );
+}
+
+#[test]
+fn id_numbers_the_translation_alters_are_kept() {
+    // a server that writes SNILS numbers with spaces instead of hyphens
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            std::thread::spawn(move || {
+                answer(stream.unwrap(), &|request_line, body| {
+                    if !request_line.starts_with("POST /translate") {
+                        return ("404 Not Found", String::new());
+                    }
+                    let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+                    let q = request["q"].as_str().unwrap();
+                    let text = q.replace("Абзац", "Paragraph").replace('-', " ");
+                    (
+                        "200 OK",
+                        serde_json::json!({ "translatedText": text }).to_string(),
+                    )
+                })
+            });
+        }
+    });
+    let dir = scratch("pii", &url);
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\n\
+         Абзац 1, паспорт 45 08 123456\r\n\r\n\
+         Абзац 2, СНИЛС 123-456-789 01\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("translation altered snils"));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap(),
+        "Paragraph 1, паспорт 45 08 123456\n\n\
+         [pii-preserved] Абзац 2, СНИЛС 123-456-789 01\n\n"
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["pii_preserved_segments"], 1);
+}
+
+#[test]
+fn numbers_and_dates_are_written_the_english_way() {
+    let (url, _) = serve_fake(Behavior::FailEveryNth(usize::MAX));
+    let dir = scratch("locale", &url);
+    let cases = [
+        ("1 234,56 руб.", "1,234.56 rub."),
+        ("1\u{a0}234\u{a0}567", "1,234,567"),
+        ("1 234 567", "1,234,567"),
+        ("3,5%", "3.5%"),
+        ("-2,5", "-2.5"),
+        ("0,125", "0.125"),
+        ("12 300 человек", "12 300 chelovek"),
+        ("1,234", "1,234"),
+        ("1,5,7", "1,5,7"),
+        ("12.03.2021.", "2021-03-12."),
+        ("01.02.03", "01.02.03"),
+        ("31.02.2021", "31.02.2021"),
+        ("A-1234,56", "A-1234,56"),
+        ("v2,5", "v2,5"),
+        ("/data/1,5", "/data/1,5"),
+        ("`x = 2,5`", "`x = 2,5`"),
+        ("паспорт 45 08 123456", "pasport 45 08 123456"),
+    ];
+    let mut email = String::from("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+    for (source, _) in &cases {
+        email.push_str(&format!("Абзац: {}\r\n\r\n", source));
+    }
+    std::fs::write(dir.join("src/letter.eml"), email).unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "--normalize-numbers",
+            "--normalize-dates",
+            "iso",
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let text = std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap();
+    let paragraphs: Vec<&str> = text.split("\n\n").filter(|p| !p.is_empty()).collect();
+    assert_eq!(paragraphs.len(), cases.len(), "{}", text);
+    for ((source, expected), paragraph) in cases.iter().zip(paragraphs) {
+        assert_eq!(paragraph, format!("Abzac: {}", expected), "{}", source);
+    }
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["summary"]["converted_numbers"], 6);
+    assert_eq!(report["summary"]["converted_dates"], 1);
+}
+
+#[test]
+fn requests_avoid_a_server_that_is_down() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let down = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            std::thread::spawn(move || {
+                answer(stream.unwrap(), &|_, _| {
+                    ("503 Service Unavailable", String::new())
+                })
+            });
+        }
+    });
+    let up = serve_out_of_order();
+    let dir = scratch("instances", &up);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = \"tessdata\"\nlibretranslate_url = [\"{}\", \"{}\"]\n",
+            down, up
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\nАбзац 2\r\n\r\nАбзац 3\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("{} left out", down)));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap(),
+        "Paragraph 1\n\nParagraph 2\n\nParagraph 3\n\n"
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let instances = report["summary"]["instances"].as_array().unwrap();
+    assert_eq!(instances[0]["url"], down.as_str());
+    assert!(instances[0]["errors"].as_u64().unwrap() >= 1);
+    assert_eq!(instances[0]["errors"], instances[0]["requests"]);
+    assert_eq!(instances[1]["errors"], 0);
+    assert!(instances[1]["requests"].as_u64().unwrap() >= 3);
+}
+
+#[test]
+fn a_server_that_never_answers_times_out_and_is_left_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let silent = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        // connections are taken and kept open, nothing is ever read or written
+        let streams: Vec<TcpStream> = listener.incoming().map(Result::unwrap).collect();
+        drop(streams);
+    });
+    let up = serve_out_of_order();
+    let dir = scratch("timeout", &up);
+    std::fs::write(
+        dir.join("conf/config.toml"),
+        format!(
+            "tesserac_data = \"tessdata\"\nlibretranslate_url = [\"{}\", \"{}\"]\nrequest_timeout_secs = 1\n",
+            silent, up
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("src/letter.eml"),
+        "Content-Type: text/plain; charset=utf-8\r\n\r\nАбзац 1\r\n\r\nАбзац 2\r\n",
+    )
+    .unwrap();
+    let config = dir.join("conf/config.toml");
+    let report = dir.join("report.json");
+    let started = std::time::Instant::now();
+    let output = run(
+        &dir.join("cwd"),
+        &[
+            "--config",
+            config.to_str().unwrap(),
+            "-s",
+            dir.join("src").to_str().unwrap(),
+            "--report",
+            report.to_str().unwrap(),
+            "translate",
+            dir.join("out").to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("{} left out", silent)));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out/letter.eml.txt")).unwrap(),
+        "Paragraph 1\n\nParagraph 2\n\n"
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let instances = report["summary"]["instances"].as_array().unwrap();
+    assert_eq!(instances[0]["url"], silent.as_str());
+    assert_eq!(instances[0]["ejections"], 1);
+    assert_eq!(instances[1]["errors"], 0);
+}